@@ -0,0 +1,66 @@
+//! Generates the man pages of candycorn and its subcommands from its command
+//! line into `$OUT_DIR/man`, where packaging picks them up
+
+use clap::CommandFactory;
+use std::path::{Path, PathBuf};
+
+#[allow(dead_code)]
+#[path = "src/cli.rs"]
+mod cli;
+#[allow(dead_code)]
+#[path = "src/color.rs"]
+mod color;
+#[allow(dead_code)]
+#[path = "src/csv.rs"]
+mod csv;
+#[allow(dead_code)]
+#[path = "src/exit.rs"]
+mod exit;
+#[allow(dead_code)]
+#[path = "src/filter.rs"]
+mod filter;
+
+/// Value parsers of modules that can not be built on their own. Their values
+/// never show in the man pages
+mod checksum {
+    pub fn parse_sha256(s: &str) -> Result<String, String> {
+        Ok(s.to_string())
+    }
+}
+
+mod mirror {
+    pub type Kernel = String;
+
+    pub fn parse_kernel(s: &str) -> Result<Kernel, String> {
+        Ok(s.to_string())
+    }
+}
+
+/// Writes the page of a command, then those of its visible subcommands other
+/// than `help`, named after the command path as in `candycorn-db-import.1`
+fn write_pages(dir: &Path, name: &str, command: clap::Command) -> std::io::Result<()> {
+    let command = command.name(name);
+    let mut page = Vec::new();
+    clap_mangen::Man::new(command.clone()).render(&mut page)?;
+    std::fs::write(dir.join(format!("{}.1", name)), page)?;
+
+    let visible = |sub: &&clap::Command| !sub.is_hide_set() && sub.get_name() != "help";
+    for subcommand in command.get_subcommands().filter(visible) {
+        let sub_name = format!("{}-{}", name, subcommand.get_name());
+        write_pages(dir, &sub_name, subcommand.clone())?;
+    }
+    Ok(())
+}
+
+fn main() -> std::io::Result<()> {
+    for file in ["cli", "color", "csv", "exit", "filter"] {
+        println!("cargo:rerun-if-changed=src/{}.rs", file);
+    }
+
+    let dir = PathBuf::from(std::env::var_os("OUT_DIR").unwrap()).join("man");
+    std::fs::create_dir_all(&dir)?;
+
+    let mut command = cli::Args::command();
+    command.build();
+    write_pages(&dir, "candycorn", command)
+}
@@ -0,0 +1,127 @@
+//! Writing of patched modules, shared by the `candycorn` command and its C
+//! API so both replace modules the same way
+
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of temporary files created by this process so far, telling apart
+/// those of threads writing next to the same destination
+static TMP_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Attempts at creating a temporary file whose name is not taken, as one left
+/// behind by a crashed process of the same pid may be
+const TMP_ATTEMPTS: usize = 16;
+
+/// Writes a patched module atomically. The data is written to a temporary file
+/// next to the destination, synced and then renamed over the destination so a
+/// crash never leaves a truncated module behind
+///
+/// # Arguments
+/// * `path` - Destination of the patched module
+/// * `data` - Content of the patched module
+/// * `original` - File whose permissions, owner and timestamps are copied to
+///   the destination, where possible
+pub fn write_atomic(path: &Path, data: &[u8], original: Option<&Path>)
+    -> std::io::Result<()> {
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut attempt = 0;
+    let tmp_path = loop {
+        let mut tmp_name = std::ffi::OsString::from(".");
+        tmp_name.push(path.file_name().unwrap_or_default());
+        tmp_name.push(format!(".{}.{}.tmp", std::process::id(),
+                              TMP_COUNT.fetch_add(1, Ordering::Relaxed)));
+        let tmp_path = dir.join(tmp_name);
+        match write_synced(&tmp_path, data, original) {
+            Ok(()) => break tmp_path,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists
+                        && attempt + 1 < TMP_ATTEMPTS => attempt += 1,
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::AlreadyExists {
+                    let _ = std::fs::remove_file(&tmp_path);
+                }
+                return Err(e);
+            },
+        }
+    };
+
+    if let Err(e) = std::fs::rename(&tmp_path, path) {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+
+    // Sync the directory so the rename itself survives a crash
+    std::fs::File::open(dir)?.sync_all()
+}
+
+/// Writes a new file and syncs it to disk. Fails with `AlreadyExists` if the
+/// file exists, leaving it untouched
+fn write_synced(path: &Path, data: &[u8], original: Option<&Path>)
+    -> std::io::Result<()> {
+
+    let mut file = std::fs::OpenOptions::new().write(true).create_new(true)
+                    .open(path)?;
+    file.write_all(data)?;
+    if let Some(metadata) = original.and_then(|p| std::fs::metadata(p).ok()) {
+        copy_metadata(&file, &metadata)?;
+    }
+    file.sync_all()
+}
+
+/// Copies permissions, owner and timestamps to a file. Changing the owner
+/// requires privileges so failing to do so is not an error
+fn copy_metadata(file: &std::fs::File, metadata: &std::fs::Metadata)
+    -> std::io::Result<()> {
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let _ = std::os::unix::fs::fchown(file, Some(metadata.uid()),
+                                          Some(metadata.gid()));
+    }
+    file.set_permissions(metadata.permissions())?;
+
+    let mut times = std::fs::FileTimes::new();
+    if let Ok(accessed) = metadata.accessed() {
+        times = times.set_accessed(accessed);
+    }
+    if let Ok(modified) = metadata.modified() {
+        times = times.set_modified(modified);
+    }
+    file.set_times(times)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concurrent_writes_do_not_collide() {
+        let dir = std::env::temp_dir().join(format!("candycorn-core-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("target.ko");
+        std::fs::write(&path, b"original").unwrap();
+
+        // A temporary file left behind under a name this process could pick
+        let stale = dir.join(format!(".target.ko.{}.0.tmp", std::process::id()));
+        std::fs::write(&stale, b"stale").unwrap();
+
+        std::thread::scope(|scope| {
+            for idx in 0 .. 8u8 {
+                let path = &path;
+                scope.spawn(move || write_atomic(path, &[idx; 64], Some(path)).unwrap());
+            }
+        });
+        let data = std::fs::read(&path).unwrap();
+        assert!(data.len() == 64 && data.iter().all(|&b| b == data[0]));
+        assert_eq!(std::fs::read(&stale).unwrap(), b"stale");
+
+        // Only the module and the stale file, no temporary file of a write
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
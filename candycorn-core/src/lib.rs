@@ -0,0 +1,354 @@
+//! Parsing of the symbol version sections of kernel modules, shared by the
+//! `candycorn` command, its C API and its WebAssembly build. Modules carry
+//! their versions in `__versions`, an array of `modversion_info` entries, and
+//! on kernels with `CONFIG_EXTENDED_MODVERSIONS` in a pair of sections
+//! holding the CRCs and the names separately
+
+use goblin::elf::section_header::{SectionHeader, SHT_NOBITS};
+use std::ops::Range;
+
+pub mod fs;
+
+/// Size of a `modversion_info` entry
+pub const ENTRY_SIZE: usize = 64;
+
+/// Sections of kernels with `CONFIG_EXTENDED_MODVERSIONS`, holding the CRCs
+/// and null terminated names of the versioned symbols in the same order.
+/// Names are not limited in length
+pub const EXT_CRCS: &str = "__version_ext_crcs";
+pub const EXT_NAMES: &str = "__version_ext_names";
+
+/// Size of a CRC in `__version_ext_crcs`
+pub const EXT_CRC_SIZE: usize = 4;
+
+/// Marker appended after the signature of a signed kernel module
+pub const MODULE_SIG_STRING: &[u8] = b"~Module signature appended~\n";
+
+/// Errors reading the version sections of a module
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("`{section}` section not found")]
+    MissingSection { section: String },
+
+    #[error("`{section}` section at offset {offset:#x} is malformed: {reason}")]
+    MalformedSection {
+        section: String,
+        offset: usize,
+        reason: String,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Size of the CRC field at the start of a `modversion_info` entry, an
+/// `unsigned long`
+pub fn crc_size(is_64: bool) -> usize {
+    if is_64 { 8 } else { 4 }
+}
+
+/// Finds a section header by name
+pub fn find_section<'a>(elf: &'a goblin::elf::Elf, name: &str)
+    -> Option<&'a SectionHeader> {
+
+    elf.section_headers.iter()
+        .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(name))
+}
+
+/// Returns the file range of a section's content, failing if the section has
+/// no content in the file or extends past its end
+///
+/// # Arguments
+/// * `sh` - Header of the section
+/// * `name` - Name of the section, used for errors
+/// * `len` - Size of the file
+pub fn section_range(sh: &SectionHeader, name: &str, len: usize) -> Result<Range<usize>> {
+    let malformed = |reason: String| Error::MalformedSection {
+        section: name.to_string(),
+        offset: sh.sh_offset as usize,
+        reason,
+    };
+
+    if sh.sh_type == SHT_NOBITS {
+        return Err(malformed("section has no content in file (SHT_NOBITS)"
+                                .to_string()));
+    }
+
+    let start = usize::try_from(sh.sh_offset).ok();
+    let end = start.zip(usize::try_from(sh.sh_size).ok())
+                .and_then(|(start, size)| start.checked_add(size));
+    match (start, end) {
+        (Some(start), Some(end)) if end <= len => Ok(start .. end),
+        _ => Err(malformed(format!("section of {:#x} bytes extends past end of \
+                                    file ({:#x} bytes)", sh.sh_size, len))),
+    }
+}
+
+/// Returns the content of a section, failing like `section_range`
+pub fn section_data<'a>(sh: &SectionHeader, name: &str, data: &'a [u8])
+    -> Result<&'a [u8]> {
+
+    section_range(sh, name, data.len()).map(|range| &data[range])
+}
+
+/// Reads a CRC field of 4 or 8 bytes
+pub fn read_crc(field: &[u8], little_endian: bool) -> u64 {
+    let mut bytes = [0u8; 8];
+    if little_endian {
+        bytes[.. field.len()].copy_from_slice(field);
+        u64::from_le_bytes(bytes)
+    } else {
+        bytes[8 - field.len() ..].copy_from_slice(field);
+        u64::from_be_bytes(bytes)
+    }
+}
+
+/// Checks if a CRC fits a field of `size` bytes
+pub fn crc_fits(crc: u64, size: usize) -> bool {
+    size >= 8 || crc >> (size * 8) == 0
+}
+
+/// Writes a CRC to a field of 4 or 8 bytes. The CRC must fit the field
+pub fn write_crc(field: &mut [u8], crc: u64, little_endian: bool) {
+    let size = field.len();
+    if little_endian {
+        field.copy_from_slice(&crc.to_le_bytes()[.. size]);
+    } else {
+        field.copy_from_slice(&crc.to_be_bytes()[8 - size ..]);
+    }
+}
+
+/// Length of the part of a names section holding the first `count` names.
+/// Returns `None` if there are fewer names
+pub fn names_len(names: &[u8], count: usize) -> Option<usize> {
+    let mut len = 0;
+    for _ in 0 .. count {
+        len += names.get(len ..)?.iter().position(|&b| b == 0)? + 1;
+    }
+    Some(len)
+}
+
+/// Symbol version entry of `__versions` or the extended version sections
+#[derive(Debug)]
+pub struct Entry {
+    pub name: String,
+    pub crc: u64,
+    /// File offset of the entry's CRC
+    pub offset: usize,
+    /// Entry is in the extended sections, whose CRCs are always 32-bit
+    pub extended: bool,
+}
+
+/// Reads the entries of a module's `__versions` section in order. Duplicate
+/// names are kept. Returns `None` if the module has no `__versions` section
+pub fn basic_entries(elf: &goblin::elf::Elf, data: &[u8]) -> Result<Option<Vec<Entry>>> {
+    let sh = match find_section(elf, "__versions") {
+        Some(sh) => sh,
+        None => return Ok(None),
+    };
+    let start = sh.sh_offset as usize;
+    let content = section_data(sh, "__versions", data)?;
+    if !content.len().is_multiple_of(ENTRY_SIZE) {
+        return Err(Error::MalformedSection {
+            section: "__versions".to_string(),
+            offset: start,
+            reason: format!("size {:#x} is not a multiple of {}", content.len(),
+                            ENTRY_SIZE),
+        });
+    }
+
+    let crc_size = crc_size(elf.is_64);
+    let entries = content.chunks_exact(ENTRY_SIZE).enumerate().map(|(idx, entry)| {
+        let name = &entry[crc_size ..];
+        let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+        Entry {
+            name: String::from_utf8_lossy(&name[.. len]).into_owned(),
+            crc: read_crc(&entry[.. crc_size], elf.little_endian),
+            offset: start + idx * ENTRY_SIZE,
+            extended: false,
+        }
+    });
+    Ok(Some(entries.collect()))
+}
+
+/// Finds the extended version sections of a module. Returns `None` if the
+/// module has neither and fails if it has only one of them
+pub fn find_extended<'a>(elf: &'a goblin::elf::Elf)
+    -> Result<Option<(&'a SectionHeader, &'a SectionHeader)>> {
+
+    match (find_section(elf, EXT_CRCS), find_section(elf, EXT_NAMES)) {
+        (Some(crcs), Some(names)) => Ok(Some((crcs, names))),
+        (None, None) => Ok(None),
+        (Some(_), None) => {
+            Err(Error::MissingSection { section: EXT_NAMES.to_string() })
+        },
+        (None, Some(_)) => {
+            Err(Error::MissingSection { section: EXT_CRCS.to_string() })
+        },
+    }
+}
+
+/// Reads the entries of a module's extended version sections in order.
+/// Returns `None` if the module does not have them
+pub fn extended_entries(elf: &goblin::elf::Elf, data: &[u8])
+    -> Result<Option<Vec<Entry>>> {
+
+    let (crcs_sh, names_sh) = match find_extended(elf)? {
+        Some(sections) => sections,
+        None => return Ok(None),
+    };
+    let crcs = section_data(crcs_sh, EXT_CRCS, data)?;
+    let names = section_data(names_sh, EXT_NAMES, data)?;
+    let malformed = |reason: String| Error::MalformedSection {
+        section: EXT_CRCS.to_string(),
+        offset: crcs_sh.sh_offset as usize,
+        reason,
+    };
+
+    if !crcs.len().is_multiple_of(EXT_CRC_SIZE) {
+        return Err(malformed(format!("size {:#x} is not a multiple of {}",
+                                     crcs.len(), EXT_CRC_SIZE)));
+    }
+    let count = crcs.len() / EXT_CRC_SIZE;
+    if names_len(names, count).is_none() {
+        return Err(malformed(format!("{} CRCs but fewer names in `{}`", count,
+                                     EXT_NAMES)));
+    }
+
+    let start = crcs_sh.sh_offset as usize;
+    let entries = crcs.chunks_exact(EXT_CRC_SIZE).zip(names.split(|&b| b == 0))
+        .enumerate()
+        .map(|(idx, (crc, name))| Entry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            crc: read_crc(crc, elf.little_endian),
+            offset: start + idx * EXT_CRC_SIZE,
+            extended: true,
+        });
+    Ok(Some(entries.collect()))
+}
+
+/// Reads the entries of `__versions` followed by those of the extended
+/// version sections. Returns `None` if the module has neither
+pub fn entries(elf: &goblin::elf::Elf, data: &[u8]) -> Result<Option<Vec<Entry>>> {
+    let basic = basic_entries(elf, data)?;
+    let extended = extended_entries(elf, data)?;
+    if basic.is_none() && extended.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(basic.into_iter().chain(extended).flatten().collect()))
+}
+
+/// Single `key=value` string of a module's `.modinfo` section
+#[derive(Debug)]
+pub struct ModInfoEntry {
+    pub key: String,
+    pub value: String,
+    /// File offset of the first byte of the entry
+    pub offset: usize,
+    /// Number of bytes the entry may occupy. This covers the entry's null
+    /// terminator and any null padding before the next entry
+    pub capacity: usize,
+}
+
+/// Splits the content of a `.modinfo` section into its entries in order,
+/// skipping null padding between them
+///
+/// # Arguments
+/// * `content` - Content of the section
+/// * `start` - File offset of the section
+pub fn modinfo_entries(content: &[u8], start: usize) -> Vec<ModInfoEntry> {
+    let mut entries = Vec::new();
+    let mut idx = 0;
+    while idx < content.len() {
+        // Skip over null padding between entries
+        if content[idx] == 0 {
+            idx += 1;
+            continue;
+        }
+
+        let len = content[idx ..].iter().position(|&b| b == 0)
+                    .unwrap_or(content.len() - idx);
+        let raw = String::from_utf8_lossy(&content[idx .. idx + len]);
+        let (key, value) = raw.split_once('=').unwrap_or((&raw, ""));
+
+        // Entry may grow into its terminator and trailing padding
+        let mut next = idx + len;
+        while next < content.len() && content[next] == 0 {
+            next += 1;
+        }
+
+        entries.push(ModInfoEntry {
+            key: key.to_string(),
+            value: value.to_string(),
+            offset: start + idx,
+            capacity: next - idx,
+        });
+        idx = next;
+    }
+    entries
+}
+
+/// Reads the entries of a module's `.modinfo` section in order. Returns
+/// `None` if the module has no `.modinfo` section
+pub fn modinfo(elf: &goblin::elf::Elf, data: &[u8]) -> Result<Option<Vec<ModInfoEntry>>> {
+    let sh = match find_section(elf, ".modinfo") {
+        Some(sh) => sh,
+        None => return Ok(None),
+    };
+    let content = section_data(sh, ".modinfo", data)?;
+    Ok(Some(modinfo_entries(content, sh.sh_offset as usize)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crcs_round_trip_in_both_byte_orders() {
+        for little_endian in [true, false] {
+            for (size, crc) in [(4, 0x1234_5678), (8, 0x1234_5678_9abc_def0)] {
+                let mut field = vec![0u8; size];
+                write_crc(&mut field, crc, little_endian);
+                assert_eq!(read_crc(&field, little_endian), crc);
+            }
+        }
+        let mut field = [0u8; 4];
+        write_crc(&mut field, 0x1234_5678, false);
+        assert_eq!(field, [0x12, 0x34, 0x56, 0x78]);
+    }
+
+    #[test]
+    fn crc_width_is_checked() {
+        assert!(crc_fits(0xffff_ffff, 4));
+        assert!(!crc_fits(0x1_0000_0000, 4));
+        assert!(crc_fits(u64::MAX, 8));
+    }
+
+    #[test]
+    fn names_len_counts_whole_names() {
+        assert_eq!(names_len(b"printk\0kfree\0\0\0", 2), Some(13));
+        assert_eq!(names_len(b"printk\0kfree", 2), None);
+        assert_eq!(names_len(b"", 0), Some(0));
+    }
+
+    #[test]
+    fn section_range_is_bounded() {
+        let sh = SectionHeader { sh_offset: 8, sh_size: 8, ..Default::default() };
+        assert_eq!(section_range(&sh, "test", 16).unwrap(), 8 .. 16);
+        assert!(section_range(&sh, "test", 15).is_err());
+        let sh = SectionHeader { sh_offset: u64::MAX, sh_size: 2, ..Default::default() };
+        assert!(section_range(&sh, "test", 16).is_err());
+        let sh = SectionHeader { sh_type: SHT_NOBITS, ..Default::default() };
+        assert!(section_range(&sh, "test", 16).is_err());
+    }
+
+    #[test]
+    fn modinfo_entries_own_their_padding() {
+        let entries = modinfo_entries(b"\0license=GPL\0\0\0intree=Y\0flag\0", 0x40);
+        let fields: Vec<_> = entries.iter().map(|entry| {
+            (entry.key.as_str(), entry.value.as_str(), entry.offset, entry.capacity)
+        }).collect();
+        assert_eq!(fields, [("license", "GPL", 0x41, 14), ("intree", "Y", 0x4f, 9),
+                            ("flag", "", 0x58, 5)]);
+        assert!(modinfo_entries(b"\0\0", 0).is_empty());
+    }
+}
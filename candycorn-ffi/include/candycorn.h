@@ -0,0 +1,128 @@
+#ifndef CANDYCORN_H
+#define CANDYCORN_H
+
+/* Generated by cbindgen from candycorn-ffi, regenerate with
+ * `cbindgen --config cbindgen.toml --output include/candycorn.h` */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Outcome of a call. Values match the exit codes of the `candycorn` command
+ */
+typedef enum CandycornStatus {
+  CANDYCORN_STATUS_OK = 0,
+  /**
+   * Invalid argument, such as a null pointer, an entry index out of range
+   * or a CRC too wide for its entry, or a file that could not be read
+   */
+  CANDYCORN_STATUS_FAILURE = 1,
+  /**
+   * The module is not a valid ELF file or has malformed version sections
+   */
+  CANDYCORN_STATUS_PARSE = 3,
+  /**
+   * The module has no `__versions` section
+   */
+  CANDYCORN_STATUS_MISSING_SECTION = 4,
+  /**
+   * The module could not be written
+   */
+  CANDYCORN_STATUS_WRITE = 6,
+} CandycornStatus;
+
+/**
+ * Kernel module opened with `candycorn_open`
+ */
+typedef struct CandycornModule CandycornModule;
+
+/**
+ * Symbol version entry of a module
+ */
+typedef struct CandycornEntry {
+  /**
+   * Null terminated symbol name, valid until the module is closed
+   */
+  const char *name;
+  uint64_t crc;
+  /**
+   * File offset of the entry's CRC
+   */
+  uintptr_t offset;
+} CandycornEntry;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Opens the kernel module at `path`, storing it in `module` to be closed
+ * with `candycorn_close`
+ *
+ * # Safety
+ * `path` must be a null terminated string and `module` must be valid for
+ * writes
+ */
+CandycornStatus candycorn_open(const char *path, CandycornModule **module);
+
+/**
+ * Returns the number of symbol version entries of a module, `__versions`
+ * entries followed by those of the extended version sections
+ *
+ * # Safety
+ * `module` must be a module returned by `candycorn_open` and not yet closed
+ */
+uintptr_t candycorn_version_count(const CandycornModule *module);
+
+/**
+ * Reads the symbol version entry at `index` into `entry`
+ *
+ * # Safety
+ * `module` must be a module returned by `candycorn_open` and not yet closed,
+ * and `entry` must be valid for writes
+ */
+CandycornStatus candycorn_get_entry(const CandycornModule *module,
+                                    uintptr_t index,
+                                    CandycornEntry *entry);
+
+/**
+ * Sets the CRC of the symbol version entry at `index`. The module is only
+ * changed in memory until written
+ *
+ * # Safety
+ * `module` must be a module returned by `candycorn_open` and not yet closed
+ */
+CandycornStatus candycorn_set_crc(CandycornModule *module, uintptr_t index, uint64_t crc);
+
+/**
+ * Writes a module to `path`, which may be the file it was opened from. The
+ * file is replaced atomically
+ *
+ * # Safety
+ * `module` must be a module returned by `candycorn_open` and not yet closed,
+ * and `path` a null terminated string
+ */
+CandycornStatus candycorn_write(const CandycornModule *module, const char *path);
+
+/**
+ * Closes a module, invalidating the names of its entries. Null is ignored
+ *
+ * # Safety
+ * `module` must be a module returned by `candycorn_open` and not yet closed
+ */
+void candycorn_close(CandycornModule *module);
+
+/**
+ * Returns a description of the last failure on the calling thread, valid
+ * until the next failing call on the thread
+ */
+const char *candycorn_last_error(void);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* CANDYCORN_H */
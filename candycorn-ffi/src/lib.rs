@@ -0,0 +1,184 @@
+//! C API for patching the symbol versions of kernel modules. A module is
+//! opened, its `__versions` entries read and their CRCs set, and the module
+//! written back. Functions return a `CandycornStatus`, with a description of
+//! the last failure on the calling thread given by `candycorn_last_error`
+//!
+//! The header in `include/candycorn.h` is generated with cbindgen
+
+mod module;
+
+use module::Module;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+
+/// Outcome of a call. Values match the exit codes of the `candycorn` command
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CandycornStatus {
+    Ok = 0,
+    /// Invalid argument, such as a null pointer, an entry index out of range
+    /// or a CRC too wide for its entry, or a file that could not be read
+    Failure = 1,
+    /// The module is not a valid ELF file or has malformed version sections
+    Parse = 3,
+    /// The module has no `__versions` section
+    MissingSection = 4,
+    /// The module could not be written
+    Write = 6,
+}
+
+/// Kernel module opened with `candycorn_open`
+pub struct CandycornModule {
+    module: Module,
+}
+
+/// Symbol version entry of a module
+#[repr(C)]
+pub struct CandycornEntry {
+    /// Null terminated symbol name, valid until the module is closed
+    pub name: *const c_char,
+    pub crc: u64,
+    /// File offset of the entry's CRC
+    pub offset: usize,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn fail(error: module::Error) -> CandycornStatus {
+    let message = CString::new(error.message.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+    error.status
+}
+
+fn invalid(message: &str) -> CandycornStatus {
+    fail(module::Error {
+        status: CandycornStatus::Failure,
+        message: message.to_string(),
+    })
+}
+
+/// Reads a null terminated path
+unsafe fn path<'a>(path: *const c_char) -> Option<&'a std::path::Path> {
+    use std::os::unix::ffi::OsStrExt;
+
+    if path.is_null() {
+        return None;
+    }
+    Some(std::path::Path::new(std::ffi::OsStr::from_bytes(CStr::from_ptr(path)
+                                                            .to_bytes())))
+}
+
+/// Opens the kernel module at `path`, storing it in `module` to be closed
+/// with `candycorn_close`
+///
+/// # Safety
+/// `path` must be a null terminated string and `module` must be valid for
+/// writes
+#[no_mangle]
+pub unsafe extern "C" fn candycorn_open(path: *const c_char,
+                                        module: *mut *mut CandycornModule)
+    -> CandycornStatus {
+
+    let path = match self::path(path) {
+        Some(path) if !module.is_null() => path,
+        _ => return invalid("null argument"),
+    };
+    match Module::open(path) {
+        Ok(opened) => {
+            *module = Box::into_raw(Box::new(CandycornModule { module: opened }));
+            CandycornStatus::Ok
+        },
+        Err(e) => fail(e),
+    }
+}
+
+/// Returns the number of symbol version entries of a module, `__versions`
+/// entries followed by those of the extended version sections
+///
+/// # Safety
+/// `module` must be a module returned by `candycorn_open` and not yet closed
+#[no_mangle]
+pub unsafe extern "C" fn candycorn_version_count(module: *const CandycornModule)
+    -> usize {
+
+    module.as_ref().map_or(0, |module| module.module.entries.len())
+}
+
+/// Reads the symbol version entry at `index` into `entry`
+///
+/// # Safety
+/// `module` must be a module returned by `candycorn_open` and not yet closed,
+/// and `entry` must be valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn candycorn_get_entry(module: *const CandycornModule,
+                                             index: usize, entry: *mut CandycornEntry)
+    -> CandycornStatus {
+
+    let module = match module.as_ref() {
+        Some(module) if !entry.is_null() => &module.module,
+        _ => return invalid("null argument"),
+    };
+    match module.entries.get(index) {
+        Some(found) => {
+            *entry = CandycornEntry {
+                name: found.name.as_ptr(),
+                crc: module.crc(found),
+                offset: found.offset,
+            };
+            CandycornStatus::Ok
+        },
+        None => invalid(&format!("no version entry {}", index)),
+    }
+}
+
+/// Sets the CRC of the symbol version entry at `index`. The module is only
+/// changed in memory until written
+///
+/// # Safety
+/// `module` must be a module returned by `candycorn_open` and not yet closed
+#[no_mangle]
+pub unsafe extern "C" fn candycorn_set_crc(module: *mut CandycornModule, index: usize,
+                                           crc: u64) -> CandycornStatus {
+    match module.as_mut() {
+        Some(module) => module.module.set_crc(index, crc)
+                            .map_or_else(fail, |_| CandycornStatus::Ok),
+        None => invalid("null argument"),
+    }
+}
+
+/// Writes a module to `path`, which may be the file it was opened from. The
+/// file is replaced atomically
+///
+/// # Safety
+/// `module` must be a module returned by `candycorn_open` and not yet closed,
+/// and `path` a null terminated string
+#[no_mangle]
+pub unsafe extern "C" fn candycorn_write(module: *const CandycornModule,
+                                         path: *const c_char) -> CandycornStatus {
+    match (module.as_ref(), self::path(path)) {
+        (Some(module), Some(path)) => module.module.write(path)
+                                        .map_or_else(fail, |_| CandycornStatus::Ok),
+        _ => invalid("null argument"),
+    }
+}
+
+/// Closes a module, invalidating the names of its entries. Null is ignored
+///
+/// # Safety
+/// `module` must be a module returned by `candycorn_open` and not yet closed
+#[no_mangle]
+pub unsafe extern "C" fn candycorn_close(module: *mut CandycornModule) {
+    if !module.is_null() {
+        drop(Box::from_raw(module));
+    }
+}
+
+/// Returns a description of the last failure on the calling thread, valid
+/// until the next failing call on the thread
+#[no_mangle]
+pub extern "C" fn candycorn_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
@@ -0,0 +1,96 @@
+use candycorn_core::EXT_CRC_SIZE;
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+
+/// Error of an operation on a module, along with the status reported for it
+pub struct Error {
+    pub status: crate::CandycornStatus,
+    pub message: String,
+}
+
+fn error(status: crate::CandycornStatus, message: impl Into<String>) -> Error {
+    Error { status, message: message.into() }
+}
+
+fn parse_error(message: impl Into<String>) -> Error {
+    error(crate::CandycornStatus::Parse, message)
+}
+
+/// `__versions` entry, or entry of the extended version sections
+pub struct Entry {
+    pub name: CString,
+    /// File offset of the entry's CRC
+    pub offset: usize,
+    /// Size of the CRC, following the ELF class for `__versions` entries
+    pub crc_size: usize,
+}
+
+/// Kernel module read into memory, whose CRCs are edited in place
+pub struct Module {
+    /// File the module was read from, whose metadata written copies keep
+    pub path: PathBuf,
+    pub data: Vec<u8>,
+    pub little_endian: bool,
+    pub entries: Vec<Entry>,
+}
+
+impl Module {
+    pub fn open(path: &Path) -> Result<Module, Error> {
+        let data = std::fs::read(path).map_err(|e| {
+            error(crate::CandycornStatus::Failure,
+                  format!("failed to read {}: {}", path.display(), e))
+        })?;
+        let elf = goblin::elf::Elf::parse(&data)
+                    .map_err(|e| parse_error(format!("{}: {}", path.display(), e)))?;
+        let entries = match candycorn_core::entries(&elf, &data) {
+            Ok(Some(entries)) => entries,
+            Ok(None) => {
+                return Err(error(crate::CandycornStatus::MissingSection,
+                                 "`__versions` section not found"));
+            },
+            Err(e @ candycorn_core::Error::MissingSection { .. }) => {
+                return Err(error(crate::CandycornStatus::MissingSection, e.to_string()));
+            },
+            Err(e) => return Err(parse_error(e.to_string())),
+        };
+        let crc_size = candycorn_core::crc_size(elf.is_64);
+        let entries = entries.into_iter().map(|entry| Entry {
+            name: CString::new(entry.name).unwrap_or_default(),
+            offset: entry.offset,
+            crc_size: if entry.extended { EXT_CRC_SIZE } else { crc_size },
+        }).collect();
+        let little_endian = elf.little_endian;
+        Ok(Module { path: path.to_path_buf(), data, little_endian, entries })
+    }
+
+    pub fn crc(&self, entry: &Entry) -> u64 {
+        let field = &self.data[entry.offset .. entry.offset + entry.crc_size];
+        candycorn_core::read_crc(field, self.little_endian)
+    }
+
+    /// Writes the CRC of an entry, failing if it does not fit the entry's
+    /// field
+    pub fn set_crc(&mut self, index: usize, crc: u64) -> Result<(), Error> {
+        let entry = self.entries.get(index).ok_or_else(|| {
+            error(crate::CandycornStatus::Failure,
+                  format!("no version entry {}", index))
+        })?;
+        if !candycorn_core::crc_fits(crc, entry.crc_size) {
+            return Err(error(crate::CandycornStatus::Failure,
+                             format!("CRC {:#x} of {:?} does not fit in {} bits", crc,
+                                     entry.name, entry.crc_size * 8)));
+        }
+        let field = &mut self.data[entry.offset .. entry.offset + entry.crc_size];
+        candycorn_core::write_crc(field, crc, self.little_endian);
+        Ok(())
+    }
+
+    /// Writes the module through a temporary file renamed over the
+    /// destination, so the destination is never left half written
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        candycorn_core::fs::write_atomic(path, &self.data, Some(&self.path)).map_err(|e| {
+            error(crate::CandycornStatus::Write,
+                  format!("failed to write {}: {}", path.display(), e))
+        })
+    }
+}
@@ -0,0 +1,83 @@
+//! WebAssembly build of module inspection and comparison, for pages that
+//! show a dropped module's versions, vermagic and signature status without a
+//! server. Built for `wasm32-unknown-unknown` and given module bytes rather
+//! than files
+//!
+//! Modules are copied into buffers allocated with `candycorn_alloc`. Calls
+//! return the length of a JSON result, read from `candycorn_output`, which is
+//! either the requested data or an object with an `error` message
+
+mod module;
+
+use std::cell::RefCell;
+
+thread_local! {
+    /// Result of the last call, kept until the next call
+    static OUTPUT: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+fn output<T: serde::Serialize>(result: Result<T, String>) -> usize {
+    let json = match result {
+        Ok(value) => serde_json::to_vec(&value),
+        Err(error) => serde_json::to_vec(&serde_json::json!({ "error": error })),
+    };
+    let json = json.unwrap_or_default();
+    let len = json.len();
+    OUTPUT.with(|output| *output.borrow_mut() = json);
+    len
+}
+
+/// Reads a buffer passed in from the host
+///
+/// # Safety
+/// `ptr` must point to `len` readable bytes, or be null with a zero `len`
+unsafe fn input<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+    if ptr.is_null() || len == 0 {
+        return &[];
+    }
+    std::slice::from_raw_parts(ptr, len)
+}
+
+/// Allocates a buffer of `len` bytes for the host to copy a module into
+#[no_mangle]
+pub extern "C" fn candycorn_alloc(len: usize) -> *mut u8 {
+    let mut buffer = std::mem::ManuallyDrop::new(vec![0u8; len]);
+    buffer.as_mut_ptr()
+}
+
+/// Frees a buffer allocated with `candycorn_alloc`
+///
+/// # Safety
+/// `ptr` must have been returned by `candycorn_alloc` for the same `len`
+#[no_mangle]
+pub unsafe extern "C" fn candycorn_free(ptr: *mut u8, len: usize) {
+    if !ptr.is_null() {
+        drop(Vec::from_raw_parts(ptr, len, len));
+    }
+}
+
+/// Returns the JSON result of the last call, whose length the call returned
+#[no_mangle]
+pub extern "C" fn candycorn_output() -> *const u8 {
+    OUTPUT.with(|output| output.borrow().as_ptr())
+}
+
+/// Summarizes a module: its ELF class, byte order and machine, whether it is
+/// signed, its vermagic, `__versions` entries and `.modinfo` entries
+///
+/// # Safety
+/// `ptr` must point to `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn candycorn_inspect(ptr: *const u8, len: usize) -> usize {
+    output(module::inspect(input(ptr, len)))
+}
+
+/// Compares the symbol versions of two modules
+///
+/// # Safety
+/// `a_ptr` and `b_ptr` must point to `a_len` and `b_len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn candycorn_diff(a_ptr: *const u8, a_len: usize,
+                                        b_ptr: *const u8, b_len: usize) -> usize {
+    output(module::diff(input(a_ptr, a_len), input(b_ptr, b_len)))
+}
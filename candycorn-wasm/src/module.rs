@@ -0,0 +1,120 @@
+use candycorn_core::MODULE_SIG_STRING;
+
+#[derive(serde::Serialize)]
+pub struct Version {
+    pub name: String,
+    pub crc: u64,
+    /// File offset of the entry's CRC
+    pub offset: usize,
+}
+
+#[derive(serde::Serialize)]
+pub struct ModInfo {
+    pub key: String,
+    pub value: String,
+}
+
+/// Summary of a module, as `candycorn inspect` prints it
+#[derive(serde::Serialize)]
+pub struct Inspection {
+    pub class: &'static str,
+    pub endian: &'static str,
+    pub machine: &'static str,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub signed: bool,
+    pub vermagic: Option<String>,
+    /// Entries of `__versions` followed by those of the extended version
+    /// sections, `None` if the module has neither
+    pub versions: Option<Vec<Version>>,
+    pub modinfo: Option<Vec<ModInfo>>,
+}
+
+fn versions(elf: &goblin::elf::Elf, data: &[u8]) -> Result<Option<Vec<Version>>, String> {
+    let entries = candycorn_core::entries(elf, data).map_err(|e| e.to_string())?;
+    Ok(entries.map(|entries| {
+        entries.into_iter().map(|entry| {
+            Version { name: entry.name, crc: entry.crc, offset: entry.offset }
+        }).collect()
+    }))
+}
+
+fn modinfo(elf: &goblin::elf::Elf, data: &[u8]) -> Result<Option<Vec<ModInfo>>, String> {
+    let entries = candycorn_core::modinfo(elf, data).map_err(|e| e.to_string())?;
+    Ok(entries.map(|entries| {
+        entries.into_iter().map(|entry| ModInfo { key: entry.key, value: entry.value })
+            .collect()
+    }))
+}
+
+pub fn inspect(data: &[u8]) -> Result<Inspection, String> {
+    let elf = goblin::elf::Elf::parse(data).map_err(|e| e.to_string())?;
+    let modinfo = modinfo(&elf, data)?;
+    let vermagic = modinfo.iter().flatten().find(|entry| entry.key == "vermagic")
+                    .map(|entry| entry.value.clone());
+    Ok(Inspection {
+        class: if elf.is_64 { "ELF64" } else { "ELF32" },
+        endian: if elf.little_endian { "little" } else { "big" },
+        machine: goblin::elf::header::machine_to_str(elf.header.e_machine),
+        kind: goblin::elf::header::et_to_str(elf.header.e_type),
+        signed: data.ends_with(MODULE_SIG_STRING),
+        vermagic,
+        versions: versions(&elf, data)?,
+        modinfo,
+    })
+}
+
+/// Symbol compared between two modules, with its CRC in each
+#[derive(serde::Serialize)]
+pub struct DiffRow {
+    pub symbol: String,
+    pub a: Option<u64>,
+    pub b: Option<u64>,
+}
+
+/// Comparison of the versions of two modules, as `candycorn diff` prints it
+#[derive(serde::Serialize)]
+pub struct Diff {
+    /// Symbols of the first module in ELF order, followed by symbols only in
+    /// the second module in ELF order
+    pub rows: Vec<DiffRow>,
+    pub differing: usize,
+    pub only_a: usize,
+    pub only_b: usize,
+    pub matching: usize,
+}
+
+pub fn diff(a: &[u8], b: &[u8]) -> Result<Diff, String> {
+    let versions = |data: &[u8], which: &str| {
+        let elf = goblin::elf::Elf::parse(data).map_err(|e| format!("{}: {}", which, e))?;
+        versions(&elf, data).map_err(|e| format!("{}: {}", which, e))?
+            .ok_or_else(|| format!("{}: `__versions` section not found", which))
+    };
+    let a_versions = versions(a, "first module")?;
+    let b_versions = versions(b, "second module")?;
+
+    // Duplicate names are compared by their first entry
+    let crc_of = |versions: &[Version], name: &str| {
+        versions.iter().find(|v| v.name == name).map(|v| v.crc)
+    };
+    let mut diff = Diff { rows: Vec::new(), differing: 0, only_a: 0, only_b: 0,
+                          matching: 0 };
+    for a_ver in &a_versions {
+        let b_crc = crc_of(&b_versions, &a_ver.name);
+        match b_crc {
+            Some(crc) if crc == a_ver.crc => diff.matching += 1,
+            Some(_) => diff.differing += 1,
+            None => diff.only_a += 1,
+        }
+        diff.rows.push(DiffRow { symbol: a_ver.name.clone(), a: Some(a_ver.crc),
+                                 b: b_crc });
+    }
+    for b_ver in &b_versions {
+        if crc_of(&a_versions, &b_ver.name).is_none() {
+            diff.only_b += 1;
+            diff.rows.push(DiffRow { symbol: b_ver.name.clone(), a: None,
+                                     b: Some(b_ver.crc) });
+        }
+    }
+    Ok(diff)
+}
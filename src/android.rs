@@ -0,0 +1,155 @@
+use crate::error::{Error, Result};
+use std::ops::Range;
+
+/// Magic of Android vendor boot images, header versions 3 and 4
+const VENDOR_BOOT_MAGIC: &[u8] = b"VNDRBOOT";
+
+/// Offsets of header fields
+const PAGE_SIZE: usize = 12;
+const VENDOR_RAMDISK_SIZE: usize = 24;
+const HEADER_SIZE: usize = 2096;
+const DTB_SIZE: usize = 2100;
+/// Fields added in version 4
+const TABLE_SIZE: usize = 2112;
+const TABLE_ENTRY_NUM: usize = 2116;
+const TABLE_ENTRY_SIZE: usize = 2120;
+const BOOTCONFIG_SIZE: usize = 2124;
+
+/// Checks if data starts with an Android vendor boot image
+pub fn is_vendor_boot(data: &[u8]) -> bool {
+    data.starts_with(VENDOR_BOOT_MAGIC)
+}
+
+fn malformed(reason: &str) -> Error {
+    Error::Image { kind: "vendor boot", reason: reason.to_string() }
+}
+
+/// Android vendor boot image, as used by GKI devices to carry vendor
+/// ramdisks holding the modules loaded in first stage init. Each section
+/// starts at a page boundary: the header, the vendor ramdisks, the device
+/// tree and, from version 4, the vendor ramdisk table and bootconfig
+pub struct VendorBoot<'a> {
+    data: &'a [u8],
+    page_size: usize,
+    header_size: usize,
+    /// Vendor ramdisks, relative to the start of the ramdisk section. Version
+    /// 3 images have a single ramdisk, version 4 images list them in the
+    /// ramdisk table
+    ramdisks: Vec<Range<usize>>,
+    ramdisk_section: usize,
+    /// Start of the device tree, vendor ramdisk table, bootconfig and any
+    /// signature, copied unchanged apart from the table's ramdisk sizes and
+    /// offsets
+    trailing: usize,
+    table: Option<Range<usize>>,
+    table_entry_size: usize,
+}
+
+impl<'a> VendorBoot<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<VendorBoot<'a>> {
+        let u32_at = |offset: usize| {
+            data.get(offset .. offset + 4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize)
+                .ok_or_else(|| malformed("truncated header"))
+        };
+        let version = u32_at(8)?;
+        if !(3 ..= 4).contains(&version) {
+            return Err(malformed("only header versions 3 and 4 are supported"));
+        }
+        let page_size = u32_at(PAGE_SIZE)?;
+        if page_size == 0 {
+            return Err(malformed("page size is zero"));
+        }
+        let pad = |size: usize| size.next_multiple_of(page_size);
+
+        let header_size = u32_at(HEADER_SIZE)?;
+        let ramdisk_size = u32_at(VENDOR_RAMDISK_SIZE)?;
+        let ramdisk_section = pad(header_size);
+        let trailing = ramdisk_section + pad(ramdisk_size);
+        let mut end = trailing + pad(u32_at(DTB_SIZE)?);
+
+        let mut ramdisks = Vec::new();
+        let mut table = None;
+        let mut table_entry_size = 0;
+        if version == 4 {
+            let table_size = u32_at(TABLE_SIZE)?;
+            let count = u32_at(TABLE_ENTRY_NUM)?;
+            table_entry_size = u32_at(TABLE_ENTRY_SIZE)?;
+            if table_entry_size < 8 || count * table_entry_size > table_size {
+                return Err(malformed("invalid vendor ramdisk table size"));
+            }
+
+            // Ramdisks are rebuilt in table order, so they must follow each
+            // other in that order
+            let mut next = 0;
+            for idx in 0 .. count {
+                let entry = end + idx * table_entry_size;
+                let (size, offset) = (u32_at(entry)?, u32_at(entry + 4)?);
+                if offset != next {
+                    return Err(malformed("vendor ramdisks are not contiguous"));
+                }
+                ramdisks.push(offset .. offset + size);
+                next = offset + size;
+            }
+            if next != ramdisk_size {
+                return Err(malformed("vendor ramdisk table does not cover the \
+                                      ramdisk section"));
+            }
+            table = Some(end .. end + table_size);
+            end += pad(table_size) + pad(u32_at(BOOTCONFIG_SIZE)?);
+        } else {
+            ramdisks.push(0 .. ramdisk_size);
+        }
+        if data.len() < end {
+            return Err(malformed("truncated image"));
+        }
+
+        Ok(VendorBoot {
+            data,
+            page_size,
+            header_size,
+            ramdisks,
+            ramdisk_section,
+            trailing,
+            table,
+            table_entry_size,
+        })
+    }
+
+    /// Content of each vendor ramdisk, usually a compressed cpio archive
+    pub fn ramdisks(&self) -> impl Iterator<Item = &'a [u8]> + '_ {
+        self.ramdisks.iter().map(|ramdisk| {
+            &self.data[self.ramdisk_section + ramdisk.start
+                       .. self.ramdisk_section + ramdisk.end]
+        })
+    }
+
+    /// Writes the image with its vendor ramdisks replaced, in order
+    pub fn rebuild(&self, ramdisks: &[Vec<u8>]) -> Vec<u8> {
+        let ramdisk_size: usize = ramdisks.iter().map(Vec::len).sum();
+        let mut out = self.data[.. self.header_size].to_vec();
+        out[VENDOR_RAMDISK_SIZE .. VENDOR_RAMDISK_SIZE + 4]
+            .copy_from_slice(&(ramdisk_size as u32).to_le_bytes());
+        out.resize(self.ramdisk_section, 0);
+        for ramdisk in ramdisks {
+            out.extend_from_slice(ramdisk);
+        }
+        out.resize(out.len().next_multiple_of(self.page_size), 0);
+
+        let trailing = out.len();
+        out.extend_from_slice(&self.data[self.trailing ..]);
+        if let Some(table) = &self.table {
+            let mut offset = 0;
+            for (idx, ramdisk) in ramdisks.iter().enumerate() {
+                let entry = trailing + table.start - self.trailing
+                            + idx * self.table_entry_size;
+                out[entry .. entry + 4]
+                    .copy_from_slice(&(ramdisk.len() as u32).to_le_bytes());
+                out[entry + 4 .. entry + 8]
+                    .copy_from_slice(&(offset as u32).to_le_bytes());
+                offset += ramdisk.len();
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,468 @@
+use crate::error::{Error, Result};
+use scroll::Pread;
+use std::collections::{HashMap, HashSet};
+
+/// Magic at the start of BTF data, read in the data's byte order
+const MAGIC: u16 = 0xeb9f;
+
+const KIND_INT: u32 = 1;
+const KIND_PTR: u32 = 2;
+const KIND_ARRAY: u32 = 3;
+const KIND_STRUCT: u32 = 4;
+const KIND_UNION: u32 = 5;
+const KIND_ENUM: u32 = 6;
+const KIND_FWD: u32 = 7;
+const KIND_TYPEDEF: u32 = 8;
+const KIND_VOLATILE: u32 = 9;
+const KIND_CONST: u32 = 10;
+const KIND_RESTRICT: u32 = 11;
+const KIND_FUNC: u32 = 12;
+const KIND_FUNC_PROTO: u32 = 13;
+const KIND_VAR: u32 = 14;
+const KIND_DATASEC: u32 = 15;
+const KIND_FLOAT: u32 = 16;
+const KIND_DECL_TAG: u32 = 17;
+const KIND_TYPE_TAG: u32 = 18;
+const KIND_ENUM64: u32 = 19;
+
+/// Named member of a struct or union, or parameter of a function prototype
+#[derive(Clone)]
+struct Member {
+    name: String,
+    type_id: u32,
+    /// Offset in bits. For bitfields, the bitfield size is in the top 8 bits
+    offset: u32,
+}
+
+#[derive(Clone)]
+enum Kind {
+    Void,
+    Int { size: u32, signed: bool, bits: u32 },
+    Float { size: u32 },
+    Ptr(u32),
+    Array { elem: u32, len: u32 },
+    Aggregate { union: bool, size: u32, bitfields: bool, members: Vec<Member> },
+    Enum { size: u32 },
+    Fwd { union: bool },
+    /// Typedefs, qualifiers and type tags, which do not change the layout of
+    /// the type they refer to
+    Alias(u32),
+    Func(u32),
+    FuncProto { ret: u32, params: Vec<Member> },
+    Other,
+}
+
+#[derive(Clone)]
+struct Type {
+    name: String,
+    kind: Kind,
+}
+
+/// Struct or union, identified by name
+type Aggregate = (bool, String);
+
+/// Types described by BTF data, indexed by type ID
+pub struct Btf {
+    types: Vec<Type>,
+    strings: Vec<u8>,
+    funcs: HashMap<String, u32>,
+    aggregates: HashMap<Aggregate, u32>,
+}
+
+impl Btf {
+    /// Parses BTF data. Modules carry split BTF which only holds the types
+    /// missing from the kernel's BTF, so the kernel's BTF must be given as a
+    /// base to resolve them
+    pub fn parse(data: &[u8], base: Option<&Btf>) -> Result<Btf> {
+        let malformed = |offset: usize, reason: &str| Error::MalformedSection {
+            section: ".BTF".to_string(),
+            offset,
+            reason: reason.to_string(),
+        };
+        let endian = [scroll::LE, scroll::BE].into_iter()
+            .find(|&endian| data.pread_with::<u16>(0, endian).ok() == Some(MAGIC))
+            .ok_or_else(|| malformed(0, "bad magic"))?;
+        let read = |offset: usize| -> Result<u32> {
+            data.pread_with(offset, endian)
+                .map_err(|_| malformed(offset, "truncated header"))
+        };
+
+        let hdr_len = read(4)? as usize;
+        let (type_off, type_len) = (read(8)? as usize, read(12)? as usize);
+        let (str_off, str_len) = (read(16)? as usize, read(20)? as usize);
+        let types_data = data.get(hdr_len + type_off .. hdr_len + type_off + type_len)
+                            .ok_or_else(|| malformed(hdr_len, "types out of bounds"))?;
+        let strings = data.get(hdr_len + str_off .. hdr_len + str_off + str_len)
+                        .ok_or_else(|| malformed(hdr_len, "strings out of bounds"))?;
+
+        let mut btf = match base {
+            Some(base) => Btf {
+                types: base.types.clone(),
+                strings: base.strings.clone(),
+                funcs: base.funcs.clone(),
+                aggregates: base.aggregates.clone(),
+            },
+            None => Btf {
+                types: vec![Type { name: String::new(), kind: Kind::Void }],
+                strings: Vec::new(),
+                funcs: HashMap::new(),
+                aggregates: HashMap::new(),
+            },
+        };
+        btf.strings.extend_from_slice(strings);
+
+        let read = |offset: usize| -> Result<u32> {
+            types_data.pread_with(offset, endian)
+                .map_err(|_| malformed(hdr_len + type_off + offset, "truncated type"))
+        };
+        let mut offset = 0;
+        while offset < types_data.len() {
+            let name = btf.string(read(offset)?);
+            let info = read(offset + 4)?;
+            let size_or_type = read(offset + 8)?;
+            let kind_id = (info >> 24) & 0x1f;
+            let vlen = (info & 0xffff) as usize;
+            let kind_flag = info >> 31 == 1;
+            offset += 12;
+
+            let members = |offset: usize, btf: &Btf, stride: usize|
+                -> Result<Vec<Member>> {
+                (0 .. vlen).map(|i| {
+                    let at = offset + i * stride;
+                    Ok(Member {
+                        name: btf.string(read(at)?),
+                        type_id: read(at + 4)?,
+                        offset: if stride == 12 { read(at + 8)? } else { 0 },
+                    })
+                }).collect()
+            };
+            let kind = match kind_id {
+                KIND_INT => {
+                    let encoding = read(offset)?;
+                    offset += 4;
+                    Kind::Int {
+                        size: size_or_type,
+                        signed: (encoding >> 24) & 1 == 1,
+                        bits: encoding & 0xff,
+                    }
+                },
+                KIND_PTR => Kind::Ptr(size_or_type),
+                KIND_ARRAY => {
+                    let kind = Kind::Array {
+                        elem: read(offset)?,
+                        len: read(offset + 8)?,
+                    };
+                    offset += 12;
+                    kind
+                },
+                KIND_STRUCT | KIND_UNION => {
+                    let members = members(offset, &btf, 12)?;
+                    offset += vlen * 12;
+                    Kind::Aggregate {
+                        union: kind_id == KIND_UNION,
+                        size: size_or_type,
+                        bitfields: kind_flag,
+                        members,
+                    }
+                },
+                KIND_ENUM => {
+                    offset += vlen * 8;
+                    Kind::Enum { size: size_or_type }
+                },
+                KIND_ENUM64 => {
+                    offset += vlen * 12;
+                    Kind::Enum { size: size_or_type }
+                },
+                KIND_FWD => Kind::Fwd { union: kind_flag },
+                KIND_TYPEDEF | KIND_VOLATILE | KIND_CONST | KIND_RESTRICT
+                    | KIND_TYPE_TAG => Kind::Alias(size_or_type),
+                KIND_FUNC => Kind::Func(size_or_type),
+                KIND_FUNC_PROTO => {
+                    let params = members(offset, &btf, 8)?;
+                    offset += vlen * 8;
+                    Kind::FuncProto { ret: size_or_type, params }
+                },
+                KIND_VAR | KIND_DECL_TAG => {
+                    offset += 4;
+                    Kind::Other
+                },
+                KIND_DATASEC => {
+                    offset += vlen * 12;
+                    Kind::Other
+                },
+                KIND_FLOAT => Kind::Float { size: size_or_type },
+                _ => return Err(malformed(hdr_len + type_off + offset - 12,
+                                          "unknown type kind")),
+            };
+
+            let id = btf.types.len() as u32;
+            match &kind {
+                Kind::Func(_) => {
+                    btf.funcs.entry(name.clone()).or_insert(id);
+                },
+                Kind::Aggregate { union, .. } if !name.is_empty() => {
+                    btf.aggregates.entry((*union, name.clone())).or_insert(id);
+                },
+                _ => {},
+            }
+            btf.types.push(Type { name, kind });
+        }
+        Ok(btf)
+    }
+
+    /// Looks up a string by its offset in the string section
+    fn string(&self, offset: u32) -> String {
+        let bytes = self.strings.get(offset as usize ..).unwrap_or_default();
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[.. len]).into_owned()
+    }
+
+    fn get(&self, id: u32) -> Option<&Type> {
+        self.types.get(id as usize)
+    }
+
+    /// Describes a type by its layout rather than its spelling, so typedefs
+    /// and qualifiers are looked through and integers are described by size
+    /// and signedness. Named structs and unions are described by name and
+    /// added to `reached` so their layouts can be compared separately
+    fn describe(&self, id: u32, reached: &mut Vec<Aggregate>, depth: usize)
+        -> String {
+
+        // Anonymous types can only nest so deep in sane data
+        if depth > 64 {
+            return "...".to_string();
+        }
+        let ty = match self.get(id) {
+            Some(ty) => ty,
+            None => return format!("<type {}>", id),
+        };
+        match &ty.kind {
+            Kind::Void => "void".to_string(),
+            Kind::Int { size, signed, bits } => {
+                let mut desc = format!("{}{}", if *signed { "s" } else { "u" },
+                                       size * 8);
+                if *bits != size * 8 {
+                    desc += &format!(":{}", bits);
+                }
+                desc
+            },
+            Kind::Float { size } => format!("f{}", size * 8),
+            Kind::Ptr(target) => format!("{} *", self.describe(*target, reached,
+                                                              depth + 1)),
+            Kind::Array { elem, len } => {
+                format!("{}[{}]", self.describe(*elem, reached, depth + 1), len)
+            },
+            Kind::Aggregate { union, .. } | Kind::Fwd { union }
+                if !ty.name.is_empty() => {
+                reached.push((*union, ty.name.clone()));
+                format!("{} {}", if *union { "union" } else { "struct" }, ty.name)
+            },
+            Kind::Aggregate { union, size, .. } => {
+                let layout = self.layout(id, reached, depth + 1);
+                format!("{} {{{}}}:{}", if *union { "union" } else { "struct" },
+                        layout.describe(), size)
+            },
+            Kind::Fwd { .. } => "opaque".to_string(),
+            Kind::Enum { size } if ty.name.is_empty() => format!("enum:{}", size),
+            Kind::Enum { size } => format!("enum {}:{}", ty.name, size),
+            Kind::Alias(target) | Kind::Func(target) => {
+                self.describe(*target, reached, depth + 1)
+            },
+            Kind::FuncProto { ret, params } => {
+                let params: Vec<String> = params.iter().map(|p| {
+                    // A trailing parameter of type void marks varargs
+                    if p.type_id == 0 {
+                        "...".to_string()
+                    } else {
+                        self.describe(p.type_id, reached, depth + 1)
+                    }
+                }).collect();
+                format!("{} ({})", self.describe(*ret, reached, depth + 1),
+                        params.join(", "))
+            },
+            Kind::Other => "?".to_string(),
+        }
+    }
+
+    /// Size and members of a struct or union
+    fn layout(&self, id: u32, reached: &mut Vec<Aggregate>, depth: usize)
+        -> Layout {
+
+        match self.get(id).map(|ty| &ty.kind) {
+            Some(Kind::Aggregate { size, bitfields, members, .. }) => {
+                let members = members.iter().map(|m| {
+                    let mut ty = self.describe(m.type_id, reached, depth);
+                    let offset = if *bitfields {
+                        if m.offset >> 24 != 0 {
+                            ty += &format!(":{}", m.offset >> 24);
+                        }
+                        m.offset & 0xffffff
+                    } else {
+                        m.offset
+                    };
+                    (m.name.clone(), offset, ty)
+                }).collect();
+                Layout { size: *size, members }
+            },
+            _ => Layout::default(),
+        }
+    }
+}
+
+/// Size of a struct or union with the name, bit offset and type description
+/// of each member
+#[derive(Default, PartialEq)]
+struct Layout {
+    size: u32,
+    members: Vec<(String, u32, String)>,
+}
+
+impl Layout {
+    /// Inline description of the members, for anonymous structs and unions
+    fn describe(&self) -> String {
+        let members: Vec<String> = self.members.iter()
+            .map(|(name, offset, ty)| format!("{}@{}: {}", name, offset, ty))
+            .collect();
+        members.join("; ")
+    }
+}
+
+/// Describes how the layouts of a struct or union differ, or `None` if they
+/// match. Only the first differing member is described
+fn layout_difference(target: &Layout, source: &Layout) -> Option<String> {
+    if target == source {
+        return None;
+    }
+    let mut details = Vec::new();
+    if target.size != source.size {
+        details.push(format!("size {} vs {} bytes", target.size, source.size));
+    }
+
+    let display = |name: &str| match name {
+        "" => "(anonymous)".to_string(),
+        name => format!("\"{}\"", name),
+    };
+    let changed = target.members.iter().zip(&source.members).enumerate()
+                    .find(|(_, (t, s))| t != s);
+    match changed {
+        Some((idx, (t, s))) if t.0 != s.0 => {
+            details.push(format!("member {} is {} vs {}", idx, display(&t.0),
+                                 display(&s.0)));
+        },
+        Some((_, (t, s))) if t.1 != s.1 => {
+            details.push(format!("member {} at bit {} vs {}", display(&t.0),
+                                 t.1, s.1));
+        },
+        // Types of anonymous members are too long to print
+        Some((_, (t, s))) if t.2.len() + s.2.len() <= 80 => {
+            details.push(format!("member {} is {} vs {}", display(&t.0), t.2,
+                                 s.2));
+        },
+        Some((_, (t, _))) => {
+            details.push(format!("member {} type differs", display(&t.0)));
+        },
+        None if target.members.len() != source.members.len() => {
+            details.push(format!("{} vs {} members", target.members.len(),
+                                 source.members.len()));
+        },
+        None => {},
+    }
+    Some(details.join(", "))
+}
+
+/// Result of comparing the types of a function in target and source BTF
+pub enum FunctionMatch {
+    /// The function is missing from either BTF
+    Unknown,
+    Matching,
+    Differing {
+        /// Prototypes, if they differ
+        prototypes: Option<(String, String)>,
+        /// Structs and unions reachable from the function whose layouts
+        /// differ
+        layouts: Vec<String>,
+    },
+}
+
+/// Compares functions of a target's and a source's BTF. Layouts of structs and
+/// unions are compared once and remembered across functions
+pub struct Comparison<'a> {
+    target: &'a Btf,
+    source: &'a Btf,
+    /// Difference and reachable structs and unions of each struct or union
+    layouts: HashMap<Aggregate, (Option<String>, Vec<Aggregate>)>,
+}
+
+impl<'a> Comparison<'a> {
+    pub fn new(target: &'a Btf, source: &'a Btf) -> Comparison<'a> {
+        Comparison { target, source, layouts: HashMap::new() }
+    }
+
+    /// Compares the prototype of a function and the layouts of all structs
+    /// and unions reachable from it, including through pointers
+    pub fn function(&mut self, name: &str) -> FunctionMatch {
+        let (t_id, s_id) = match (self.target.funcs.get(name),
+                                  self.source.funcs.get(name)) {
+            (Some(&t_id), Some(&s_id)) => (t_id, s_id),
+            _ => return FunctionMatch::Unknown,
+        };
+        let mut pending = Vec::new();
+        let t_proto = self.target.describe(t_id, &mut pending, 0);
+        let s_proto = self.source.describe(s_id, &mut pending, 0);
+
+        let mut layouts = Vec::new();
+        let mut seen = HashSet::new();
+        while let Some(key) = pending.pop() {
+            if !seen.insert(key.clone()) {
+                continue;
+            }
+            let (difference, reached) = self.aggregate(&key);
+            if difference.is_some() {
+                layouts.push(format!("{} {}", if key.0 { "union" } else { "struct" },
+                                     key.1));
+            }
+            pending.extend(reached.iter().cloned());
+        }
+
+        if t_proto == s_proto && layouts.is_empty() {
+            return FunctionMatch::Matching;
+        }
+        layouts.sort();
+        FunctionMatch::Differing {
+            prototypes: Some((t_proto, s_proto)).filter(|(t, s)| t != s),
+            layouts,
+        }
+    }
+
+    /// Compares the layouts of a struct or union. Structs and unions only
+    /// declared on either side cannot be compared and are taken to match
+    fn aggregate(&mut self, key: &Aggregate) -> &(Option<String>, Vec<Aggregate>) {
+        if !self.layouts.contains_key(key) {
+            let compared = match (self.target.aggregates.get(key),
+                                  self.source.aggregates.get(key)) {
+                (Some(&t_id), Some(&s_id)) => {
+                    let mut reached = Vec::new();
+                    let t_layout = self.target.layout(t_id, &mut reached, 0);
+                    let s_layout = self.source.layout(s_id, &mut reached, 0);
+                    (layout_difference(&t_layout, &s_layout), reached)
+                },
+                _ => (None, Vec::new()),
+            };
+            self.layouts.insert(key.clone(), compared);
+        }
+        &self.layouts[key]
+    }
+
+    /// Differences of all differing structs and unions compared so far, by
+    /// name
+    pub fn layout_differences(&self) -> Vec<(String, String)> {
+        let mut differences: Vec<(String, String)> = self.layouts.iter()
+            .filter_map(|((union, name), (difference, _))| {
+                let kind = if *union { "union" } else { "struct" };
+                difference.as_ref().map(|d| (format!("{} {}", kind, name), d.clone()))
+            })
+            .collect();
+        differences.sort();
+        differences
+    }
+}
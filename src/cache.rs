@@ -0,0 +1,63 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Set when `--no-cache` disables the donor cache
+pub static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Directory below which candycorn caches data, following the XDG base
+/// directory specification
+pub fn dir(name: &str) -> Option<PathBuf> {
+    let cache = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(cache) => PathBuf::from(cache),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+    Some(cache.join("candycorn").join(name))
+}
+
+/// Parsed symbol versions of a donor module
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Donor {
+    pub arch: crate::Arch,
+    pub versions: Vec<(String, u64)>,
+}
+
+/// Path of the cache entry of a donor module, given its file's content, or
+/// `None` if the cache is disabled. Entries are keyed by the SHA-256 of the
+/// module's file as stored, compressed or not, so copies of a module share an
+/// entry and a changed module misses. The candycorn version is part of the
+/// key as parsing may change between versions
+pub fn entry(data: &[u8]) -> Option<PathBuf> {
+    if DISABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    let key = format!("{}-{}", env!("CARGO_PKG_VERSION"), crate::checksum::sha256(data));
+    Some(dir("donors")?.join(key + ".json"))
+}
+
+/// Looks up the cached versions of a donor module
+pub fn get(entry: &Path) -> Option<Donor> {
+    let content = std::fs::read(entry).ok()?;
+    serde_json::from_slice(&content).ok()
+}
+
+/// Caches the versions of a donor module. Failing to write the cache is not
+/// an error, the module is parsed again next time
+pub fn put(entry: &Path, donor: &Donor) {
+    static TMP_ID: AtomicUsize = AtomicUsize::new(0);
+
+    let content = match serde_json::to_vec(donor) {
+        Ok(content) => content,
+        Err(_) => return,
+    };
+
+    // Entries are written to a unique file and renamed, so readers never see
+    // partial entries
+    let tmp = entry.with_extension(format!("{}-{}.tmp", std::process::id(),
+                                           TMP_ID.fetch_add(1, Ordering::Relaxed)));
+    let written = entry.parent().map_or(Ok(()), std::fs::create_dir_all)
+                    .and_then(|_| std::fs::write(&tmp, content))
+                    .and_then(|_| std::fs::rename(&tmp, entry));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+}
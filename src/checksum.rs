@@ -0,0 +1,61 @@
+use crate::error::{Error, Result};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// Computes the lowercase hexadecimal SHA-256 of the provided bytes
+pub fn sha256(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+/// Encodes bytes as lowercase hexadecimal
+pub fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Checks that a string is a hexadecimal SHA-256
+pub fn parse_sha256(s: &str) -> std::result::Result<String, String> {
+    if s.len() != 64 || !s.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("expected 64 hexadecimal digits, got \"{}\"", s));
+    }
+    Ok(s.to_ascii_lowercase())
+}
+
+/// File and SHA-256 recorded in a checksum manifest
+#[derive(serde::Serialize)]
+struct Entry<'a> {
+    path: &'a Path,
+    sha256: &'a str,
+}
+
+/// Sidecar manifest recording the checksums of a target before and after
+/// patching
+#[derive(serde::Serialize)]
+struct Manifest<'a> {
+    input: Entry<'a>,
+    output: Entry<'a>,
+}
+
+/// Writes a JSON checksum manifest next to the patched module, returning the
+/// path of the manifest
+///
+/// # Arguments
+/// * `target` - Path of the original target and its SHA-256
+/// * `output` - Path of the patched module and its SHA-256
+pub fn write_manifest(target: (&Path, &str), output: (&Path, &str))
+    -> Result<std::path::PathBuf> {
+
+    let manifest = Manifest {
+        input: Entry { path: target.0, sha256: target.1 },
+        output: Entry { path: output.0, sha256: output.1 },
+    };
+
+    let mut path = output.0.as_os_str().to_os_string();
+    path.push(".sha256.json");
+    let path = std::path::PathBuf::from(path);
+
+    let mut content = serde_json::to_string_pretty(&manifest)?;
+    content.push('\n');
+    std::fs::write(&path, content)
+        .map_err(|e| Error::Write { path: path.clone(), source: e })?;
+    Ok(path)
+}
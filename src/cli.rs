@@ -0,0 +1,903 @@
+//! Command line of candycorn, kept apart from the commands so the build
+//! script can include it to generate the man pages
+
+use crate::{checksum, color, csv, exit, filter, mirror};
+use clap::{Parser, Subcommand};
+
+#[derive(Parser)]
+#[clap(about, long_about = None, after_help = exit::HELP)]
+pub struct Args {
+    #[clap(subcommand)]
+    pub command: Commands,
+
+    /// Ignore `~/.config/candycorn.toml` and `.candycorn.toml`
+    // Read by `config::apply` before parsing
+    #[allow(dead_code)]
+    #[clap(long, global = true, env = "CANDYCORN_NO_CONFIG")]
+    pub no_config: bool,
+
+    /// Log more details, `-v` for lookup decisions and `-vv` for every
+    /// version entry's offset and bytes
+    #[clap(short, long, global = true, parse(from_occurrences))]
+    pub verbose: u64,
+
+    /// Only log errors
+    #[clap(short, long, global = true, conflicts_with("verbose"))]
+    pub quiet: bool,
+
+    /// Write all messages to stderr, leaving stdout to the requested output
+    #[clap(long, global = true, env = "CANDYCORN_PORCELAIN")]
+    pub porcelain: bool,
+
+    /// Color output: `auto` colors output to terminals unless `NO_COLOR` is
+    /// set
+    #[clap(long, global = true, arg_enum, value_name = "WHEN", default_value = "auto")]
+    pub color: color::When,
+
+    /// Make output byte-for-byte reproducible from the same inputs and
+    /// options: notes and reports record `SOURCE_DATE_EPOCH`, or zero if it
+    /// is not set, instead of the current time
+    #[clap(long, global = true, env = "CANDYCORN_REPRODUCIBLE")]
+    pub reproducible: bool,
+}
+
+#[derive(Subcommand)]
+pub enum Commands {
+    /// Patch the targets' symbol versions
+    Patch(Box<PatchArgs>),
+    /// Patch the targets listed in a TOML or JSON manifest, each entry with
+    /// its own sources, overrides, outputs and strictness
+    Run {
+        /// Manifest to run
+        #[clap(parse(from_os_str))]
+        manifest: std::path::PathBuf,
+    },
+    /// Patch the targets and install them into a kernel's module directory,
+    /// then run `depmod`
+    Install(Box<InstallArgs>),
+    /// Patch modules inside a SquashFS image and repack it, or write the
+    /// patched modules to an overlay directory. Requires squashfs-tools
+    Squashfs(Box<ImageArgs>),
+    /// Patch modules inside an EROFS image, such as Android's `vendor_dlkm`,
+    /// and repack it, or write the patched modules to an overlay directory.
+    /// Requires erofs-utils
+    Erofs(Box<ImageArgs>),
+    /// Patch the modules a DKMS build produced against the kernel's
+    /// `Module.symvers`, for use as a DKMS `POST_BUILD` script
+    Dkms(Box<DkmsArgs>),
+    /// Watch a build directory and patch every kernel module written below
+    /// it, for edit-build-load loops against a mismatched kernel. Runs until
+    /// interrupted
+    #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+    Watch(WatchArgs),
+    /// Manage the local database of reference kernels' symbol versions used
+    /// by `patch --src-db`
+    Db(DbArgs),
+    /// Serve the CRC database over HTTP for `patch --src-url`, at
+    /// `GET /crc/<kernel>` and `GET /crc/<kernel>/<symbol>`
+    Serve {
+        /// Database to serve. Defaults to `~/.local/share/candycorn/crcs.sqlite`
+        #[clap(long, parse(from_os_str))]
+        db: Option<std::path::PathBuf>,
+
+        /// Address and port to listen on
+        #[clap(long, value_parser, default_value = "127.0.0.1:8080")]
+        listen: String,
+    },
+    /// List the target's symbol versions
+    List {
+        /// Target kernel module to list
+        #[clap(parse(from_os_str))]
+        target: std::path::PathBuf,
+    },
+    /// Check the target's symbol versions and vermagic against the running
+    /// kernel
+    Verify {
+        /// Target kernel module to verify
+        #[clap(parse(from_os_str))]
+        target: std::path::PathBuf,
+    },
+    /// Run the checks the running kernel makes when loading the target, in
+    /// the kernel's order, without loading it
+    SimulateLoad {
+        /// Target kernel module to check
+        #[clap(parse(from_os_str))]
+        target: std::path::PathBuf,
+    },
+    /// Check whether the running system allows loading patched or force
+    /// loaded modules at all
+    Doctor,
+    /// Print a summary of the target's metadata
+    Inspect {
+        /// Target kernel module to inspect
+        #[clap(parse(from_os_str))]
+        target: std::path::PathBuf,
+
+        /// Output format. `csv` prints only the symbol versions, as
+        /// `symbol,crc` rows
+        #[clap(long, arg_enum, default_value = "text")]
+        format: csv::Format,
+    },
+    /// Restore the symbol versions and `.modinfo` entries the target had
+    /// before it was patched with `--note`
+    Revert {
+        /// Target kernel module to revert. `-` reads it from stdin and writes
+        /// the reverted module to stdout
+        #[clap(parse(from_os_str))]
+        target: std::path::PathBuf,
+
+        /// Write the reverted module to this path instead of over the target.
+        /// `-` writes to stdout
+        #[clap(short, long, parse(from_os_str))]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Remove the target's `__versions` section and extended version
+    /// sections, for kernels built without `CONFIG_MODVERSIONS`
+    StripVersions {
+        /// Target kernel module to strip. `-` reads it from stdin and writes
+        /// the stripped module to stdout
+        #[clap(parse(from_os_str))]
+        target: std::path::PathBuf,
+
+        /// Write the stripped module to this path instead of over the target.
+        /// `-` writes to stdout
+        #[clap(short, long, parse(from_os_str))]
+        output: Option<std::path::PathBuf>,
+
+        /// Leave the `modversions` flag in the target's vermagic
+        #[clap(long)]
+        keep_vermagic: bool,
+    },
+    /// Apply a patch file written by `patch --emit-patch` to the target
+    Apply {
+        /// Patch file to apply
+        #[clap(parse(from_os_str))]
+        patch: std::path::PathBuf,
+
+        /// Target kernel module to patch. `-` reads it from stdin and writes
+        /// the patched module to stdout
+        #[clap(parse(from_os_str))]
+        target: std::path::PathBuf,
+
+        /// Write the patched module to this path instead of over the target.
+        /// `-` writes to stdout
+        #[clap(short, long, parse(from_os_str))]
+        output: Option<std::path::PathBuf>,
+
+        /// Apply the patch to a target whose SHA-256 differs from the module
+        /// the patch was made from, as long as the bytes to replace match
+        #[clap(long)]
+        ignore_hash: bool,
+    },
+    /// Compare the symbol versions of two kernel modules
+    Diff {
+        /// First kernel module to compare
+        #[clap(parse(from_os_str))]
+        a: std::path::PathBuf,
+
+        /// Second kernel module to compare
+        #[clap(parse(from_os_str))]
+        b: std::path::PathBuf,
+
+        /// Hide symbols with matching versions
+        #[clap(long)]
+        hide_matching: bool,
+
+        /// Output format. `csv` prints `symbol,crc_a,crc_b` rows, leaving
+        /// the CRC of a module without the symbol empty
+        #[clap(long, arg_enum, default_value = "text")]
+        format: csv::Format,
+    },
+    /// Compare the exports of two kernels' `Module.symvers` files, to predict
+    /// which out-of-tree modules need patching after a kernel update
+    SymversDiff {
+        /// `Module.symvers` of the kernel the modules were built for
+        #[clap(parse(from_os_str))]
+        old: std::path::PathBuf,
+
+        /// `Module.symvers` of the kernel to update to
+        #[clap(parse(from_os_str))]
+        new: std::path::PathBuf,
+
+        /// Kernel module built against the old kernel, to report the changed
+        /// and removed exports it imports. Glob patterns are expanded. May be
+        /// repeated
+        #[clap(long, parse(from_os_str), multiple_occurrences(true))]
+        module: Vec<std::path::PathBuf>,
+
+        /// Also list exports with the same CRC in both kernels
+        #[clap(long)]
+        show_unchanged: bool,
+
+        /// Output format. `csv` prints `symbol,crc_old,crc_new` rows, leaving
+        /// the CRC of a kernel without the export empty
+        #[clap(long, arg_enum, default_value = "text")]
+        format: csv::Format,
+    },
+    /// Check every kernel module below a directory against a kernel's
+    /// `Module.symvers` and vermagic, and tell which load as they are, which
+    /// need patching and which patching can not fix
+    Plan {
+        /// Directory of kernel modules to check
+        #[clap(parse(from_os_str))]
+        dir: std::path::PathBuf,
+
+        /// `Module.symvers` of the kernel the modules are to load on
+        #[clap(long, parse(from_os_str))]
+        symvers: std::path::PathBuf,
+
+        /// Vermagic of the kernel the modules are to load on. Vermagic is not
+        /// checked without it
+        #[clap(long, value_parser)]
+        vermagic: Option<String>,
+
+        /// Output format. `csv` prints `module,status,crcs,vermagic,missing,
+        /// gpl_only` rows, with symbols separated by spaces
+        #[clap(long, arg_enum, default_value = "text")]
+        format: csv::Format,
+    },
+    /// Compare the BTF types of the functions the target imports between the
+    /// target's and a source's BTF, to find where patched versions hide
+    /// incompatible prototypes or struct layouts
+    BtfDiff {
+        /// Target kernel module whose imported functions to compare
+        #[clap(parse(from_os_str))]
+        target: std::path::PathBuf,
+
+        /// Source kernel module, kernel image or raw BTF file such as
+        /// `/sys/kernel/btf/vmlinux`
+        #[clap(parse(from_os_str))]
+        source: std::path::PathBuf,
+
+        /// BTF of the kernel the target was built for, as a kernel image or
+        /// raw BTF file. Used as the base of the target's own BTF if it has
+        /// any
+        #[clap(long, parse(from_os_str))]
+        target_base: Option<std::path::PathBuf>,
+
+        /// BTF of the kernel the source was built for, needed when the source
+        /// is a module
+        #[clap(long, parse(from_os_str))]
+        source_base: Option<std::path::PathBuf>,
+
+        /// Hide functions with matching types
+        #[clap(long)]
+        hide_matching: bool,
+    },
+    /// Write a synthetic kernel module with the given symbol versions and
+    /// `.modinfo` entries, for test fixtures and fuzzing corpora
+    Testgen(TestgenArgs),
+    /// Print the completion script of a shell
+    #[clap(hide = true)]
+    Completions {
+        #[clap(arg_enum)]
+        shell: clap_complete::Shell,
+    },
+}
+
+#[derive(clap::Args)]
+pub struct TestgenArgs {
+    /// Path to write the module to. `-` writes it to stdout
+    #[clap(parse(from_os_str))]
+    pub output: std::path::PathBuf,
+
+    /// Symbol version to add as SYMBOL=CRC, with the CRC in decimal or `0x`
+    /// prefixed hexadecimal. May be repeated
+    #[clap(long, value_name = "SYMBOL=CRC", value_parser = parse_override,
+           multiple_occurrences(true))]
+    pub symbol: Vec<(String, String)>,
+
+    /// `.modinfo` entry to add as KEY=VALUE. May be repeated
+    #[clap(long, value_parser = parse_modinfo, multiple_occurrences(true))]
+    pub modinfo: Vec<(String, String)>,
+
+    /// Module name written to `.gnu.linkonce.this_module`. Defaults to the
+    /// file name of the output without its extension
+    #[clap(long, value_parser)]
+    pub name: Option<String>,
+
+    /// Architecture written to the ELF header
+    #[clap(long, arg_enum, default_value = "x86-64")]
+    pub machine: Machine,
+
+    /// Write a 32-bit ELF module instead of a 64-bit one
+    #[clap(long)]
+    pub elf32: bool,
+
+    /// Write a big-endian module instead of a little-endian one
+    #[clap(long)]
+    pub big_endian: bool,
+
+    /// Write the symbol versions to the extended version sections of
+    /// `CONFIG_EXTENDED_MODVERSIONS` kernels instead of `__versions`
+    #[clap(long)]
+    pub extended: bool,
+
+    /// Append a placeholder module signature
+    #[clap(long)]
+    pub signed: bool,
+}
+
+#[derive(clap::Args)]
+#[clap(group(clap::ArgGroup::new("action").required(true).multiple(true)
+    .args(&["src", "src-dir", "src-kernel", "src-db", "auto-src", "symvers", "vmlinux",
+            "system-map", "symtypes", "module-layout-version", "vermagic",
+            "vermagic-from-running", "srcversion", "set-modinfo",
+            "delete-modinfo", "intree", "retpoline", "set-license", "strip-debug",
+            "strip-btf",
+            "rename-depend", "add-depend", "remove-depend", "rename-module",
+            "set", "add-version", "overrides", "hook"])))]
+#[clap(group(clap::ArgGroup::new("source").multiple(true)
+    .args(&["src", "src-dir", "src-kernel", "src-db", "auto-src", "symvers", "vmlinux",
+            "system-map", "symtypes"])))]
+pub struct PatchArgs {
+    /// Target kernel modules to patch, or initramfs cpio archives whose
+    /// modules to patch. Glob patterns are expanded. `-` reads a single
+    /// target from stdin and writes it to stdout
+    #[clap(required_unless_present("kbuild"), parse(from_os_str))]
+    pub targets: Vec<std::path::PathBuf>,
+
+    /// Kbuild output tree whose freshly built modules to patch, as listed in
+    /// its `modules.order`. Versions of symbols its `Module.symvers` says the
+    /// tree's own modules export are left alone, as they match between the
+    /// modules already
+    #[clap(long, parse(from_os_str), value_name = "BUILDDIR", requires("source"))]
+    pub kbuild: Option<std::path::PathBuf>,
+
+    /// Also patch the modules the targets depend on, and the modules those
+    /// depend on, so that the whole chain loads. Dependencies are read from
+    /// the closest `modules.dep` at or above a target, or else resolved from
+    /// the target's `depends` entry against the modules below its directory
+    #[clap(long)]
+    pub with_deps: bool,
+
+    /// Reference kernel module to use for obtaining symbol versions, or a
+    /// `.deb` or `.rpm` kernel package whose modules and symvers files to
+    /// use. May be repeated, in which case versions of later sources take
+    /// precedence
+    #[clap(short, long, parse(from_os_str), multiple_occurrences(true))]
+    pub src: Vec<std::path::PathBuf>,
+
+    /// Directory to search recursively for kernel modules, including
+    /// compressed modules, to use for obtaining symbol versions. May be
+    /// repeated. Versions of `--src` modules take precedence
+    #[clap(long, parse(from_os_str), multiple_occurrences(true))]
+    pub src_dir: Vec<std::path::PathBuf>,
+
+    /// Distribution kernel to download from the distribution's mirror and
+    /// use like a `--src` package, as DISTRO:RELEASE[:ARCH] with DISTRO one
+    /// of `ubuntu`, `debian` or `fedora` and RELEASE as `uname -r` prints
+    /// it, such as `ubuntu:5.15.0-91-generic`. Packages are cached in
+    /// `~/.cache/candycorn`. May be repeated
+    #[clap(long, value_name = "DISTRO:RELEASE", value_parser = mirror::parse_kernel,
+           multiple_occurrences(true))]
+    pub src_kernel: Vec<mirror::Kernel>,
+
+    /// Reference kernel imported with `candycorn db import` to use for
+    /// obtaining symbol versions, by the name given to its `--kernel`. May
+    /// be repeated. Versions of source modules take precedence
+    #[clap(long, value_name = "KERNEL", value_parser, multiple_occurrences(true))]
+    pub src_db: Vec<String>,
+
+    /// Database to read `--src-db` kernels from. Defaults to
+    /// `~/.local/share/candycorn/crcs.sqlite`
+    #[clap(long, parse(from_os_str), requires("src-db"))]
+    pub db: Option<std::path::PathBuf>,
+
+    /// URL of a `candycorn serve` lookup service to read `--src-db` kernels
+    /// from instead of the local database, such as `http://lab:8080`
+    #[clap(long, value_name = "URL", value_parser, requires("src-db"),
+           conflicts_with("db"))]
+    pub src_url: Option<String>,
+
+    /// Look up symbol versions not found in other sources in the modules
+    /// installed for the running kernel, or in the module directory given as
+    /// `--auto-src=<DIR>`. Uses `modules.dep` and `modules.symbols` to find
+    /// modules importing each symbol
+    #[clap(long, value_name = "DIR", value_parser, min_values(0),
+           require_equals(true))]
+    pub auto_src: Option<Option<std::path::PathBuf>>,
+
+    /// Parse every module of `--src-dir`, `--auto-src` and packages again
+    /// instead of reusing the versions cached in `~/.cache/candycorn/donors`
+    /// by earlier runs, and do not cache them
+    #[clap(long)]
+    pub no_cache: bool,
+
+    /// `Module.symvers` of the target kernel to use for obtaining symbol
+    /// versions. Versions of source modules take precedence
+    #[clap(long, parse(from_os_str), env = "CANDYCORN_SYMVERS")]
+    pub symvers: Option<std::path::PathBuf>,
+
+    /// Kernel image of the target kernel to use for obtaining symbol versions,
+    /// either a `vmlinux`, compressed or not, or an x86 `bzImage`. Versions
+    /// of `Module.symvers` and source modules take precedence
+    #[clap(long, parse(from_os_str), env = "CANDYCORN_VMLINUX")]
+    pub vmlinux: Option<std::path::PathBuf>,
+
+    /// `System.map` of the target kernel to use for obtaining symbol versions
+    /// from absolute `__crc_` symbols. Versions of all other sources take
+    /// precedence
+    #[clap(long, parse(from_os_str), env = "CANDYCORN_SYSTEM_MAP")]
+    pub system_map: Option<std::path::PathBuf>,
+
+    /// `.symtypes` file, or directory searched recursively for them, of the
+    /// target kernel's build to compute symbol versions from the way genksyms
+    /// does. Written by kbuild with `KBUILD_SYMTYPES=1`. Versions of all
+    /// other sources except `System.map` take precedence
+    #[clap(long, parse(from_os_str))]
+    pub symtypes: Option<std::path::PathBuf>,
+
+    /// Module layout version value to patch into target. May be decimal or
+    /// `0x` prefixed hexadecimal
+    #[clap(short, long, value_parser)]
+    pub module_layout_version: Option<String>,
+
+    /// Symbol version to patch into target as SYMBOL=CRC. CRC may be decimal
+    /// or `0x` prefixed hexadecimal. May be repeated
+    #[clap(long, value_parser = parse_override, multiple_occurrences(true))]
+    pub set: Vec<(String, String)>,
+
+    /// Symbol version to add to the target's `__versions` as SYMBOL=CRC if
+    /// the target lacks an entry for it, otherwise patched like `--set`. The
+    /// section is created if the target has none. May be repeated
+    #[clap(long, value_parser = parse_override, multiple_occurrences(true),
+           conflicts_with("emit-patch"))]
+    pub add_version: Vec<(String, String)>,
+
+    /// Interpret versions given on the command line without a `0x` prefix as
+    /// hexadecimal instead of decimal
+    #[clap(long)]
+    pub hex: bool,
+
+    /// TOML or JSON file mapping symbol names to versions to patch into
+    /// target. Applied after the source and before `--set`. Symbols a
+    /// target does not version are skipped for it
+    #[clap(long, parse(from_os_str))]
+    pub overrides: Option<std::path::PathBuf>,
+
+    /// Rhai script deciding the version to write for each symbol, for rules
+    /// that filters and overrides cannot express. It is run once per symbol
+    /// with its `name`, `target_crc`, `source_crc` and the target's `modinfo`
+    /// set, and evaluates to a CRC, `"skip"`, or `()` to keep the CRC about
+    /// to be written. Applied after the sources, overrides and `--set`
+    #[clap(long, parse(from_os_str))]
+    pub hook: Option<std::path::PathBuf>,
+
+    /// Keep the original target and write modified output to a new file
+    /// with a `.patch` suffix
+    #[clap(short, long)]
+    pub keep: bool,
+
+    /// Write the patched module to this path instead of over the target. `-`
+    /// writes to stdout. Only valid with a single target
+    #[clap(short, long, parse(from_os_str), conflicts_with("keep"))]
+    pub output: Option<std::path::PathBuf>,
+
+    /// Write the patched targets into this directory, created if needed,
+    /// under their file names instead of over the targets. For `patch`,
+    /// defaults to `CANDYCORN_OUTPUT_DIR` unless `--output`, `--keep`,
+    /// `--emit-patch` or `-` is given
+    #[clap(long, parse(from_os_str), conflicts_with_all(&["keep", "output"]))]
+    pub output_dir: Option<std::path::PathBuf>,
+
+    /// Write the changes to a portable JSON patch file instead of writing the
+    /// patched module. Only valid with a single target
+    #[clap(long, parse(from_os_str),
+           conflicts_with_all(&["keep", "output", "output-dir", "note",
+                                "checksum-manifest"]))]
+    pub emit_patch: Option<std::path::PathBuf>,
+
+    /// Write only the changed bytes into the targets with positioned writes
+    /// instead of writing patched copies over them, so that large modules
+    /// are neither copied nor rewritten whole. Changes that resize a target
+    /// fail, including adding and deleting `.modinfo` entries, and an
+    /// interrupted write can leave a target partly patched
+    #[clap(long, conflicts_with_all(&["keep", "output", "output-dir", "emit-patch",
+                                     "note", "strip-debug", "strip-btf",
+                                     "delete-modinfo", "import-ns", "rename-module"]))]
+    pub in_place: bool,
+
+    /// Before writing over a target, keep its original as `<target>.orig`,
+    /// which is never replaced, and what it held before each later patch as
+    /// `<target>.orig.1`, the most recent, up to `<target>.orig.<LIMIT>`.
+    /// LIMIT defaults to 5
+    #[clap(long, value_name = "LIMIT", value_parser, min_values(0),
+           require_equals(true),
+           conflicts_with_all(&["keep", "output", "output-dir", "emit-patch"]))]
+    pub backup: Option<Option<usize>>,
+
+    /// Fail targets with symbol versions not found in the source.
+    /// `CANDYCORN_STRICT` enables it for commands with sources
+    #[clap(long, requires("source"))]
+    pub require_all: bool,
+
+    /// Only patch versions of symbols matching this glob pattern, or regular
+    /// expression with a `re:` prefix, from the sources. May be repeated
+    #[clap(long, value_parser = filter::parse_pattern, multiple_occurrences(true),
+           requires("source"))]
+    pub only: Vec<filter::Pattern>,
+
+    /// Only patch the version of `module_layout` from the sources, leaving
+    /// all other versions untouched. Same as `--only module_layout`
+    #[clap(long, requires("source"),
+           conflicts_with_all(&["only", "module-layout-version"]))]
+    pub module_layout_from_src: bool,
+
+    /// Only patch versions of symbols the kernel log reports version errors
+    /// for, as printed when loading a module fails. Reads `/dev/kmsg`, or a
+    /// saved log given as `--from-dmesg=<FILE>`. Symbols the log reports as
+    /// missing a version are added to the target's `__versions`
+    #[clap(long, value_name = "FILE", value_parser, min_values(0),
+           require_equals(true), requires("source"), conflicts_with("only"))]
+    pub from_dmesg: Option<Option<std::path::PathBuf>>,
+
+    /// Leave versions of symbols matching this glob pattern, or regular
+    /// expression with a `re:` prefix, untouched by the sources. May be
+    /// repeated
+    #[clap(long, value_parser = filter::parse_pattern, multiple_occurrences(true),
+           requires("source"))]
+    pub exclude: Vec<filter::Pattern>,
+
+    /// TOML or JSON file mapping symbol names of the targets to the names the
+    /// symbols have in the sources, for symbols renamed between kernel
+    /// versions. Renamed names are looked up when the original is not found
+    #[clap(long, parse(from_os_str), requires("source"))]
+    pub rename_map: Option<std::path::PathBuf>,
+
+    /// Replace every CRC of the target's `__versions` with the source's,
+    /// requiring both to version the same set of symbols. Requires a single
+    /// `--src` and no other sources
+    #[clap(long, requires("src"),
+           conflicts_with_all(&["src-dir", "auto-src", "symvers", "vmlinux",
+                                "system-map", "symtypes"]))]
+    pub clone_versions: bool,
+
+    /// Give targets without a `__versions` section one listing their
+    /// undefined symbols that have a version in the sources
+    #[clap(long, conflicts_with_all(&["emit-patch", "clone-versions"]))]
+    pub create_versions: bool,
+
+    /// Print the SHA-256 of each target before and after patching
+    #[clap(long)]
+    pub checksum: bool,
+
+    /// Also write the checksums to a `.sha256.json` manifest next to each
+    /// patched module
+    #[clap(long, requires("checksum"))]
+    pub checksum_manifest: bool,
+
+    /// Refuse to patch targets whose SHA-256 does not match
+    #[clap(long, value_parser = checksum::parse_sha256)]
+    pub expect_sha256: Option<String>,
+
+    /// Record the candycorn version, source, time and patched symbols in a
+    /// `.note.candycorn` section appended to the target
+    #[clap(long)]
+    pub note: bool,
+
+    /// Write a Markdown report of the sources, every changed version and
+    /// `.modinfo` entry of each target and any warnings to this file, or an
+    /// HTML report if its name ends in `.html`
+    #[clap(long, parse(from_os_str))]
+    pub report_file: Option<std::path::PathBuf>,
+
+    /// Take symbol versions from source modules whose architecture, ELF class
+    /// or byte order differs from the target's, and patch targets that do
+    /// not look like kernel modules, such as executables and shared libraries
+    #[clap(long)]
+    pub force: bool,
+
+    /// Number of targets to patch in parallel. 0 uses one job per CPU
+    #[clap(short, long, value_parser, default_value_t = 1, env = "CANDYCORN_JOBS")]
+    pub jobs: usize,
+
+    /// Review each target's versions and proposed CRCs in a full screen view
+    /// on the terminal before writing it, toggling which versions to patch.
+    /// Targets are patched one at a time
+    #[clap(long)]
+    pub interactive: bool,
+
+    /// Ask on the terminal before each CRC is written, showing the symbol,
+    /// its current and new CRC and where the new CRC comes from. Answer `y`
+    /// to write it, `n` to leave it, `a` to write it and the target's
+    /// remaining CRCs or `q` to leave it and the remaining ones
+    #[clap(long, conflicts_with("interactive"))]
+    pub confirm: bool,
+
+    /// Print a hexdump of each patched `__versions` entry before and after
+    /// patching, with the changed bytes highlighted
+    #[clap(long)]
+    pub show_bytes: bool,
+
+    /// Vermagic string to patch into the target's `.modinfo`
+    #[clap(long, value_parser,
+           conflicts_with_all(&["vermagic-from-src", "vermagic-from-running"]))]
+    pub vermagic: Option<String>,
+
+    /// Patch the target's vermagic with the vermagic of the last source module
+    #[clap(long, requires("src"), conflicts_with("vermagic-from-running"))]
+    pub vermagic_from_src: bool,
+
+    /// Patch the target's vermagic with the vermagic of the running kernel
+    #[clap(long)]
+    pub vermagic_from_running: bool,
+
+    /// Srcversion string to patch into the target's `.modinfo`
+    #[clap(long, value_parser, conflicts_with("srcversion-from-src"))]
+    pub srcversion: Option<String>,
+
+    /// Patch the target's srcversion with the srcversion of the last source
+    /// module
+    #[clap(long, requires("src"))]
+    pub srcversion_from_src: bool,
+
+    /// `.modinfo` entry to set as KEY=VALUE, added if the target has no entry
+    /// with the key. Values longer than the existing entry rebuild the
+    /// section. May be repeated
+    #[clap(long, value_parser = parse_modinfo, multiple_occurrences(true))]
+    pub set_modinfo: Vec<(String, String)>,
+
+    /// Add `import_ns` `.modinfo` entries for namespaced symbols the target
+    /// imports without declaring their namespace. Namespaces are taken from
+    /// `--symvers`, `--vmlinux` and `--src` exports
+    #[clap(long, conflicts_with("emit-patch"))]
+    pub import_ns: bool,
+
+    /// Key of `.modinfo` entries to remove from the target. May be repeated
+    #[clap(long, value_parser, multiple_occurrences(true),
+           conflicts_with("emit-patch"))]
+    pub delete_modinfo: Vec<String>,
+
+    /// Add (`y`) or remove (`n`) the target's `intree=Y` `.modinfo` entry.
+    /// The kernel taints itself as out-of-tree when loading modules without
+    /// it
+    #[clap(long, value_parser = parse_flag, value_name = "y|n",
+           conflicts_with("emit-patch"))]
+    pub intree: Option<bool>,
+
+    /// Add (`y`) or remove (`n`) the target's `retpoline=Y` `.modinfo` entry.
+    /// Retpoline kernels warn about modules without it being vulnerable to
+    /// Spectre v2
+    #[clap(long, value_parser = parse_flag, value_name = "y|n",
+           conflicts_with("emit-patch"))]
+    pub retpoline: Option<bool>,
+
+    /// Set the target's `license` `.modinfo` entry. This changes what the
+    /// kernel believes, not the terms the module's code is under
+    #[clap(long, value_name = "LICENSE", value_parser)]
+    pub set_license: Option<String>,
+
+    /// Remove `.debug_*` sections and their relocations from the target
+    #[clap(long, conflicts_with("emit-patch"))]
+    pub strip_debug: bool,
+
+    /// Remove the `.BTF` and `.BTF.ext` sections from the target. Kernels
+    /// skip checking BTF of modules without any
+    #[clap(long, conflicts_with("emit-patch"))]
+    pub strip_btf: bool,
+
+    /// Replace a module in the target's `depends` list as OLD=NEW. May be
+    /// repeated
+    #[clap(long, value_parser = parse_rename, multiple_occurrences(true))]
+    pub rename_depend: Vec<(String, String)>,
+
+    /// Add a module to the target's `depends` list. May be repeated
+    #[clap(long, value_parser, multiple_occurrences(true))]
+    pub add_depend: Vec<String>,
+
+    /// Remove a module from the target's `depends` list. May be repeated
+    #[clap(long, value_parser, multiple_occurrences(true))]
+    pub remove_depend: Vec<String>,
+
+    /// Rename the module in its `struct module` and `.modinfo`, so that it
+    /// can be loaded alongside the module it was built as. Given as OLD=NEW,
+    /// only the target named OLD is renamed and other targets depending on it
+    /// have their `depends` list updated
+    #[clap(long, value_name = "[OLD=]NEW", value_parser = parse_module_rename)]
+    pub rename_module: Option<ModuleRename>,
+}
+
+/// Splits a `SYMBOL=CRC` symbol version override. The CRC is parsed later as
+/// its radix depends on other options
+fn parse_override(s: &str) -> std::result::Result<(String, String), String> {
+    let (name, crc) = s.split_once('=')
+                        .ok_or(format!("expected SYMBOL=CRC, got \"{}\"", s))?;
+    if name.is_empty() {
+        return Err(format!("missing symbol name in \"{}\"", s));
+    }
+    Ok((name.to_string(), crc.to_string()))
+}
+
+/// Parses a KEY=VALUE `.modinfo` entry
+fn parse_modinfo(s: &str) -> std::result::Result<(String, String), String> {
+    let (key, value) = s.split_once('=')
+                        .ok_or(format!("expected KEY=VALUE, got \"{}\"", s))?;
+    if key.is_empty() {
+        return Err(format!("missing key in \"{}\"", s));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Parses a module name, which the kernel compares as a null terminated string
+fn parse_module_name(s: &str) -> std::result::Result<String, String> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_graphic()) {
+        return Err(format!("invalid module name \"{}\"", s));
+    }
+    Ok(s.to_string())
+}
+
+/// Module rename given with `--rename-module`
+#[derive(Clone, Debug)]
+pub struct ModuleRename {
+    /// Module to rename, `None` to rename the target whatever its name
+    pub old: Option<String>,
+    pub new: String,
+}
+
+/// Parses a `[OLD=]NEW` module rename
+fn parse_module_rename(s: &str) -> std::result::Result<ModuleRename, String> {
+    match s.split_once('=') {
+        Some((old, new)) => Ok(ModuleRename { old: Some(parse_module_name(old)?),
+                                              new: parse_module_name(new)? }),
+        None => Ok(ModuleRename { old: None, new: parse_module_name(s)? }),
+    }
+}
+
+/// Parses the value of a `.modinfo` flag option
+fn parse_flag(s: &str) -> std::result::Result<bool, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "y" | "yes" => Ok(true),
+        "n" | "no" => Ok(false),
+        _ => Err(format!("expected y or n, got \"{}\"", s)),
+    }
+}
+
+/// Parses an OLD=NEW module rename
+fn parse_rename(s: &str) -> std::result::Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((old, new)) if !old.is_empty() && !new.is_empty() => {
+            Ok((old.to_string(), new.to_string()))
+        },
+        _ => Err(format!("expected OLD=NEW, got \"{}\"", s)),
+    }
+}
+
+#[derive(clap::Args)]
+pub struct InstallArgs {
+    #[clap(flatten)]
+    pub patch: PatchArgs,
+
+    /// Release of the kernel to install for. Defaults to the running kernel
+    #[clap(long, value_parser)]
+    pub kernel_release: Option<String>,
+
+    /// Root directory holding `lib/modules`, to install into another system
+    #[clap(long, parse(from_os_str), default_value = "/")]
+    pub root: std::path::PathBuf,
+
+    /// Directory within the kernel's module directory to install into.
+    /// Defaults to the directory under `updates` matching where a module of
+    /// the same name is installed under `kernel`, or `updates` itself
+    #[clap(long, parse(from_os_str))]
+    pub dir: Option<std::path::PathBuf>,
+
+    /// Compression of the installed modules. Defaults to the compression
+    /// most of the kernel's installed modules use
+    #[clap(long, value_parser = clap::builder::PossibleValuesParser::new(
+        ["none", "gzip", "xz", "zstd"]))]
+    pub compress: Option<String>,
+
+    /// Do not run `depmod` after installing
+    #[clap(long)]
+    pub no_depmod: bool,
+}
+
+#[derive(clap::Args)]
+pub struct ImageArgs {
+    /// Filesystem image holding the modules. Targets are paths within the
+    /// image
+    #[clap(long, parse(from_os_str))]
+    pub image: std::path::PathBuf,
+
+    /// Write the repacked image to this path instead of over the image
+    #[clap(long, parse(from_os_str))]
+    pub image_output: Option<std::path::PathBuf>,
+
+    /// Write the patched modules below this directory, at their paths in the
+    /// image, instead of repacking the image. The directory can be mounted
+    /// as an overlay over the image
+    #[clap(long, parse(from_os_str), conflicts_with("image-output"))]
+    pub overlay: Option<std::path::PathBuf>,
+
+    #[clap(flatten)]
+    pub patch: PatchArgs,
+}
+
+#[derive(clap::Args)]
+pub struct DkmsArgs {
+    /// Directory below which to patch every module. Defaults to the current
+    /// directory, the build directory DKMS runs `POST_BUILD` scripts in
+    #[clap(parse(from_os_str), default_value = ".")]
+    pub dir: std::path::PathBuf,
+
+    /// Release of the kernel the modules were built for. Defaults to DKMS's
+    /// `kernelver`, or the running kernel
+    #[clap(long, value_parser, env = "kernelver")]
+    pub kernel_release: Option<String>,
+
+    /// Kernel build tree holding `Module.symvers`. Defaults to DKMS's
+    /// `kernel_source_dir`, or `/lib/modules/<release>/build`
+    #[clap(long, parse(from_os_str), env = "kernel_source_dir")]
+    pub kernel_source_dir: Option<std::path::PathBuf>,
+
+    /// Further `patch` options, such as `--note`, given after `--`
+    #[clap(last = true, parse(from_os_str))]
+    pub patch_options: Vec<std::ffi::OsString>,
+}
+
+#[derive(clap::Args)]
+pub struct WatchArgs {
+    /// Directory below which to watch for modules
+    #[clap(parse(from_os_str))]
+    pub dir: std::path::PathBuf,
+
+    /// Time in milliseconds without writes to wait before patching, as build
+    /// steps such as BTF generation rewrite modules after linking them
+    #[clap(long, value_parser, default_value = "1000")]
+    pub settle: u64,
+
+    /// `patch` options to patch the modules with, such as `--symvers`
+    #[clap(allow_hyphen_values = true, parse(from_os_str))]
+    pub patch_options: Vec<std::ffi::OsString>,
+}
+
+#[derive(clap::Args)]
+pub struct DbArgs {
+    /// Database to use. Defaults to `~/.local/share/candycorn/crcs.sqlite`
+    #[clap(long, parse(from_os_str), global = true)]
+    pub db: Option<std::path::PathBuf>,
+
+    #[clap(subcommand)]
+    pub command: DbCommands,
+}
+
+#[derive(Subcommand)]
+pub enum DbCommands {
+    /// Parse the symbol versions of a reference kernel and store them in the
+    /// database, creating it if needed. Versions of earlier imports of the
+    /// same kernel are kept unless the sources have them too
+    Import {
+        /// `Module.symvers` files, kernel modules, kernel images, `.deb` or
+        /// `.rpm` kernel packages, or directories searched recursively for
+        /// kernel modules. Versions of later sources take precedence
+        #[clap(required(true), parse(from_os_str))]
+        sources: Vec<std::path::PathBuf>,
+
+        /// Name to store the versions under and to give `--src-db`, such as
+        /// the kernel's release
+        #[clap(long, value_parser)]
+        kernel: String,
+    },
+    /// List the reference kernels in the database
+    List,
+    /// Remove a reference kernel's versions from the database
+    Remove {
+        /// Name of the kernel to remove
+        #[clap(value_parser)]
+        kernel: String,
+    },
+}
+
+/// Architecture of a module generated by `testgen`
+#[derive(Clone, Copy, Debug, clap::ArgEnum)]
+pub enum Machine {
+    X86_64,
+    I386,
+    Aarch64,
+    Arm,
+    Ppc64,
+    S390,
+    Riscv,
+    Mips,
+}
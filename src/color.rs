@@ -0,0 +1,58 @@
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// When to color output, given with `--color`
+#[derive(Clone, Copy, clap::ArgEnum)]
+pub enum When {
+    /// Color output written to a terminal unless `NO_COLOR` is set
+    Auto,
+    Always,
+    Never,
+}
+
+/// Stream colored output is written to
+#[derive(Clone, Copy)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Clone, Copy)]
+pub enum Color {
+    Red,
+    Green,
+    Yellow,
+}
+
+static STDOUT: AtomicBool = AtomicBool::new(false);
+static STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Decides which streams are colored. Following https://no-color.org,
+/// a non-empty `NO_COLOR` disables color unless `--color=always` is given
+pub fn init(when: When) {
+    let enabled = |terminal: bool| match when {
+        When::Auto => terminal && std::env::var_os("NO_COLOR")
+                                    .is_none_or(|value| value.is_empty()),
+        When::Always => true,
+        When::Never => false,
+    };
+    STDOUT.store(enabled(std::io::stdout().is_terminal()), Ordering::Relaxed);
+    STDERR.store(enabled(std::io::stderr().is_terminal()), Ordering::Relaxed);
+}
+
+/// Colors text for a stream if output to it is colored
+pub fn paint(stream: Stream, color: Color, text: impl std::fmt::Display) -> String {
+    let enabled = match stream {
+        Stream::Stdout => &STDOUT,
+        Stream::Stderr => &STDERR,
+    };
+    if !enabled.load(Ordering::Relaxed) {
+        return text.to_string();
+    }
+    let code = match color {
+        Color::Red => 31,
+        Color::Green => 32,
+        Color::Yellow => 33,
+    };
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+}
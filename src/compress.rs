@@ -0,0 +1,184 @@
+use crate::error::{Error, Result};
+use std::io::Read;
+
+/// Compression formats kernel modules are commonly installed with and
+/// kernel images are commonly compressed with
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Format {
+    Gzip,
+    Xz,
+    Zstd,
+    /// Legacy LZ4 frame format used by the kernel
+    Lz4,
+}
+
+/// Magic of the legacy LZ4 frame format
+const LZ4_LEGACY_MAGIC: [u8; 4] = [0x02, 0x21, 0x4c, 0x18];
+
+/// Largest block the legacy LZ4 frame format decompresses to
+const LZ4_LEGACY_BLOCK: usize = 8 << 20;
+
+impl Format {
+    /// Detects the compression format from the leading magic bytes
+    pub fn detect(data: &[u8]) -> Option<Format> {
+        if data.starts_with(&[0x1f, 0x8b]) {
+            Some(Format::Gzip)
+        } else if data.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Format::Xz)
+        } else if data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Format::Zstd)
+        } else if data.starts_with(&LZ4_LEGACY_MAGIC) {
+            Some(Format::Lz4)
+        } else {
+            None
+        }
+    }
+
+    /// Looks up a format by its name
+    pub fn from_name(name: &str) -> Option<Format> {
+        [Format::Gzip, Format::Xz, Format::Zstd, Format::Lz4].into_iter()
+            .find(|format| format.name() == name)
+    }
+
+    /// Detects the compression format of a module from its file name
+    pub fn from_path(path: &std::path::Path) -> Option<Format> {
+        let ext = path.extension()?.to_str()?;
+        [Format::Gzip, Format::Xz, Format::Zstd, Format::Lz4].into_iter()
+            .find(|format| format.extension() == ext)
+    }
+
+    /// File name extension of modules compressed in the format
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Gzip => "gz",
+            Format::Xz => "xz",
+            Format::Zstd => "zst",
+            Format::Lz4 => "lz4",
+        }
+    }
+
+    fn error(self, reason: String) -> Error {
+        Error::Decompress { format: self.name(), reason }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Format::Gzip => "gzip",
+            Format::Xz => "xz",
+            Format::Zstd => "zstd",
+            Format::Lz4 => "lz4",
+        }
+    }
+}
+
+/// Decompresses data if it is compressed in a known format. Returns `None` if
+/// the data is not compressed
+pub fn decompress(data: &[u8]) -> Result<Option<Vec<u8>>> {
+    let format = match Format::detect(data) {
+        Some(format) => format,
+        None => return Ok(None),
+    };
+    let mut out = Vec::new();
+    match format {
+        Format::Gzip => {
+            flate2::read::MultiGzDecoder::new(data).read_to_end(&mut out)
+                .map_err(|e| format.error(e.to_string()))?;
+        },
+        _ => out = decompress_stream(format, data)?,
+    }
+    Ok(Some(out))
+}
+
+/// Decompresses the first stream of the provided format at the start of the
+/// data. Any data following the stream is ignored
+pub fn decompress_stream(format: Format, data: &[u8]) -> Result<Vec<u8>> {
+    let error = |reason: String| format.error(reason);
+
+    let mut out = Vec::new();
+    match format {
+        Format::Gzip => {
+            flate2::read::GzDecoder::new(data).read_to_end(&mut out)
+                .map_err(|e| error(e.to_string()))?;
+        },
+        Format::Xz => {
+            // The decoder only reports data following the stream after the
+            // stream has been decoded in full
+            match lzma_rs::xz_decompress(&mut std::io::BufReader::new(data), &mut out) {
+                Ok(()) => {},
+                Err(lzma_rs::error::Error::XzError(e))
+                    if e == "Unexpected data after last XZ block" => {},
+                Err(e) => return Err(error(e.to_string())),
+            }
+        },
+        Format::Zstd => {
+            ruzstd::decoding::StreamingDecoder::new(data)
+                .map_err(|e| error(e.to_string()))?
+                .read_to_end(&mut out)
+                .map_err(|e| error(e.to_string()))?;
+        },
+        Format::Lz4 => out = lz4_legacy(data).map_err(error)?,
+    }
+    Ok(out)
+}
+
+/// Decompresses the legacy LZ4 frame format: the magic followed by blocks,
+/// each prefixed with its compressed size. The frame ends at the end of the
+/// data or at a size no block can have
+fn lz4_legacy(data: &[u8]) -> std::result::Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    let mut buf = vec![0; LZ4_LEGACY_BLOCK];
+    let mut idx = LZ4_LEGACY_MAGIC.len();
+    while let Some(size) = data.get(idx .. idx + 4) {
+        let size = u32::from_le_bytes(size.try_into().unwrap()) as usize;
+        let block = match data.get(idx + 4 .. idx + 4 + size) {
+            Some(block) if size != 0 && size <= LZ4_LEGACY_BLOCK => block,
+            _ => break,
+        };
+
+        // Data following the frame may look like another block
+        let len = match lz4_flex::block::decompress_into(block, &mut buf) {
+            Ok(len) => len,
+            Err(_) if !out.is_empty() => break,
+            Err(e) => return Err(e.to_string()),
+        };
+        out.extend_from_slice(&buf[.. len]);
+        idx += 4 + size;
+    }
+    if out.is_empty() {
+        return Err("no blocks found".to_string());
+    }
+    Ok(out)
+}
+
+/// Compresses data in the provided format
+pub fn compress(format: Format, data: &[u8]) -> Result<Vec<u8>> {
+    use std::io::Write;
+
+    let error = |reason: String| Error::Compress { format: format.name(), reason };
+    let mut out = Vec::new();
+    match format {
+        Format::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(
+                &mut out, flate2::Compression::default());
+            encoder.write_all(data).and_then(|_| encoder.finish())
+                .map_err(|e| error(e.to_string()))?;
+        },
+        Format::Xz => {
+            lzma_rs::xz_compress(&mut std::io::BufReader::new(data), &mut out)
+                .map_err(|e| error(e.to_string()))?;
+        },
+        Format::Zstd => {
+            out = ruzstd::encoding::compress_to_vec(
+                data, ruzstd::encoding::CompressionLevel::Fastest);
+        },
+        Format::Lz4 => {
+            out.extend_from_slice(&LZ4_LEGACY_MAGIC);
+            for chunk in data.chunks(LZ4_LEGACY_BLOCK) {
+                let block = lz4_flex::block::compress(chunk);
+                out.extend_from_slice(&(block.len() as u32).to_le_bytes());
+                out.extend_from_slice(&block);
+            }
+        },
+    }
+    Ok(out)
+}
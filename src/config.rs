@@ -0,0 +1,197 @@
+use crate::error::{Error, Result};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Name of the project configuration file, looked for in the current
+/// directory and its parents
+const PROJECT_FILE: &str = ".candycorn.toml";
+
+/// Subcommands taking the options of `patch`, to which the `[patch]` section
+/// applies
+const PATCHING: &[&str] = &["patch", "install", "squashfs", "erofs"];
+
+/// Options whose values are paths. Relative paths are resolved against the
+/// directory of the file setting them
+const PATH_OPTIONS: &[&str] = &["src", "src-dir", "symvers", "vmlinux", "system-map",
+                                "symtypes", "overrides", "hook", "rename-map", "db",
+                                "root"];
+
+/// Configuration file and the directory its relative paths are resolved
+/// against
+struct File {
+    path: PathBuf,
+    dir: PathBuf,
+    table: toml::Table,
+}
+
+/// Paths of the configuration files in order of increasing precedence: the
+/// user's, following the XDG base directory specification, and the
+/// project's
+fn paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    let config = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(config) => Some(PathBuf::from(config)),
+        None => std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")),
+    };
+    if let Some(config) = config {
+        paths.push(config.join("candycorn.toml"));
+    }
+    if let Ok(cwd) = std::env::current_dir() {
+        if let Some(project) = cwd.ancestors().map(|dir| dir.join(PROJECT_FILE))
+                                .find(|path| path.is_file()) {
+            paths.push(project);
+        }
+    }
+    paths
+}
+
+fn load(path: &Path) -> Result<Option<File>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(Error::Read { path: path.into(), source: e }),
+    };
+    let table = toml::from_str(&content).map_err(|e| Error::from(e).in_file(path))?;
+    Ok(Some(File {
+        path: path.to_path_buf(),
+        dir: path.parent().unwrap_or(Path::new("")).to_path_buf(),
+        table,
+    }))
+}
+
+fn invalid(path: &Path, reason: String) -> Error {
+    Error::Config { reason }.in_file(path)
+}
+
+/// Converts a configuration value into the values of an option
+fn values(file: &File, option: &str, value: &toml::Value) -> Result<Vec<String>> {
+    let value = match value {
+        toml::Value::String(value) if PATH_OPTIONS.contains(&option) => {
+            file.dir.join(value).to_string_lossy().into_owned()
+        },
+        toml::Value::String(value) => value.clone(),
+        toml::Value::Integer(value) => value.to_string(),
+        toml::Value::Float(value) => value.to_string(),
+        toml::Value::Boolean(value) => value.to_string(),
+        toml::Value::Array(values) => {
+            let mut all = Vec::new();
+            for value in values {
+                all.extend(values_of(file, option, value)?);
+            }
+            return Ok(all);
+        },
+        _ => return Err(invalid(&file.path, format!("unsupported value for `{}`",
+                                                     option))),
+    };
+    Ok(vec![value])
+}
+
+/// Like `values`, for elements of arrays, which can not be arrays themselves
+fn values_of(file: &File, option: &str, value: &toml::Value) -> Result<Vec<String>> {
+    if value.is_array() {
+        return Err(invalid(&file.path, format!("nested array for `{}`", option)));
+    }
+    values(file, option, value)
+}
+
+/// Adds the options the configuration files set for the subcommand of a
+/// command line to it, unless the command line gives them itself. Sections
+/// are named after subcommands, with `[patch]` also applying to the other
+/// subcommands that patch. Options set by environment variables are not
+/// added. `--no-config` skips the configuration files
+pub fn apply(command: &clap::Command, mut argv: Vec<OsString>) -> Result<Vec<OsString>> {
+    // Options after `--` are positional
+    let given: Vec<&OsString> = argv.iter().skip(1).take_while(|arg| *arg != "--")
+                                    .collect();
+    if given.iter().any(|arg| *arg == "--no-config")
+        || crate::env_flag("CANDYCORN_NO_CONFIG") {
+        return Ok(argv);
+    }
+    let position = match given.iter().position(|arg| {
+        !arg.to_string_lossy().starts_with('-')
+    }) {
+        Some(position) => position,
+        None => return Ok(argv),
+    };
+    let name = given[position].to_string_lossy().into_owned();
+    let subcommand = match command.find_subcommand(&name) {
+        Some(subcommand) => subcommand,
+        None => return Ok(argv),
+    };
+
+    // Long names of the options given on the command line
+    let mut on_command_line = std::collections::HashSet::new();
+    for arg in &given[position + 1 ..] {
+        let arg = arg.to_string_lossy();
+        if let Some(long) = arg.strip_prefix("--") {
+            on_command_line.insert(long.split('=').next().unwrap_or(long).to_string());
+        } else if let Some(shorts) = arg.strip_prefix('-') {
+            // Only the first letter of short options with attached values
+            // names an option
+            for short in shorts.chars().take(1) {
+                let long = subcommand.get_arguments()
+                            .find(|opt| opt.get_short() == Some(short))
+                            .and_then(|opt| opt.get_long());
+                if let Some(long) = long {
+                    on_command_line.insert(long.to_string());
+                }
+            }
+        }
+    }
+
+    let mut sections = vec![name.as_str()];
+    if PATCHING.contains(&name.as_str()) && name != "patch" {
+        sections.insert(0, "patch");
+    }
+    // Later files and sections override the options of earlier ones
+    let mut options: Vec<(String, Vec<OsString>)> = Vec::new();
+    for path in paths() {
+        let file = match load(&path)? {
+            Some(file) => file,
+            None => continue,
+        };
+        for (section_name, section) in &file.table {
+            if !section.is_table() || command.find_subcommand(section_name).is_none() {
+                return Err(invalid(&file.path, format!("unknown section `{}`",
+                                                       section_name)));
+            }
+        }
+
+        let sections = sections.iter().filter_map(|name| file.table.get(*name))
+                        .filter_map(toml::Value::as_table);
+        for (option, value) in sections.flatten() {
+            let arg = subcommand.get_arguments()
+                        .find(|arg| arg.get_long() == Some(option))
+                        .ok_or_else(|| invalid(&file.path, format!("unknown option \
+                                                                    `{}` for `{}`",
+                                                                   option, name)))?;
+            let mut args = Vec::new();
+            for value in values(&file, option, value)? {
+                match (arg.is_takes_value_set(), value.as_str()) {
+                    (true, _) => args.push(format!("--{}={}", option, value).into()),
+                    (false, "true") => args.push(format!("--{}", option).into()),
+                    (false, "false") => {},
+                    (false, _) => return Err(invalid(&file.path, format!("`{}` takes \
+                                                                          `true` or \
+                                                                          `false`",
+                                                                         option))),
+                }
+            }
+            options.retain(|(name, _)| name != option);
+            options.push((option.clone(), args));
+        }
+    }
+
+    // Options set by environment variables take precedence as well
+    let from_env = |option: &str| subcommand.get_arguments()
+        .find(|arg| arg.get_long() == Some(option))
+        .and_then(|arg| arg.get_env())
+        .is_some_and(|name| std::env::var_os(name).is_some());
+    let added = options.into_iter()
+        .filter(|(option, _)| !on_command_line.contains(option) && !from_env(option))
+        .flat_map(|(_, args)| args);
+
+    // `given` starts after the program name
+    argv.splice(position + 2 .. position + 2, added);
+    Ok(argv)
+}
@@ -0,0 +1,201 @@
+use crate::error::{Error, Result};
+use std::collections::HashMap;
+
+/// Magic of the portable "newc" format the kernel unpacks initramfs from
+const NEWC_MAGIC: &[u8] = b"070701";
+
+/// Magic of the "newc" format with checksums of the file contents
+const CRC_MAGIC: &[u8] = b"070702";
+
+/// Size of an entry header: the magic followed by 13 fields of 8 hex digits
+const HEADER_LEN: usize = 110;
+
+/// Header fields holding the size of the file contents, the size of the
+/// name including its null terminator and the checksum
+const FILESIZE: usize = 6;
+const NAMESIZE: usize = 11;
+const CHECK: usize = 12;
+
+/// Name of the entry ending an archive
+const TRAILER: &[u8] = b"TRAILER!!!";
+
+/// Checks if data starts with a cpio archive in a format the kernel unpacks
+pub fn is_archive(data: &[u8]) -> bool {
+    data.starts_with(NEWC_MAGIC) || data.starts_with(CRC_MAGIC)
+}
+
+/// Entry of a cpio archive, located by offsets into the archive
+pub struct Entry {
+    /// Path of the entry, usually relative to the root
+    pub name: String,
+    header: usize,
+    data: std::ops::Range<usize>,
+}
+
+/// Parsed cpio archive. Only the entries are parsed, all other bytes are
+/// kept as they are when the archive is rebuilt
+pub struct Archive<'a> {
+    data: &'a [u8],
+    pub entries: Vec<Entry>,
+    /// Offset of the trailer entry
+    trailer: usize,
+    /// Offset following the padded trailer entry, where concatenated data
+    /// such as further archives starts
+    pub end: usize,
+}
+
+fn malformed(offset: usize, reason: &str) -> Error {
+    Error::Archive { offset, reason: reason.to_string() }
+}
+
+/// Rounds up to the 4 byte alignment of names and file contents
+fn pad(offset: usize) -> usize {
+    offset.next_multiple_of(4)
+}
+
+/// Reads a hexadecimal header field
+fn field(header: &[u8], idx: usize) -> Option<usize> {
+    let digits = header.get(6 + idx * 8 .. 14 + idx * 8)?;
+    usize::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()
+}
+
+/// Replaces a hexadecimal header field
+fn set_field(header: &mut [u8], idx: usize, value: u32) {
+    header[6 + idx * 8 .. 14 + idx * 8]
+        .copy_from_slice(format!("{:08X}", value).as_bytes());
+}
+
+impl<'a> Archive<'a> {
+    /// Parses the entries of the archive at the start of the data, up to and
+    /// including its trailer
+    pub fn parse(data: &'a [u8]) -> Result<Archive<'a>> {
+        let mut entries = Vec::new();
+        let mut idx = 0;
+        loop {
+            let header = data.get(idx .. idx + HEADER_LEN).filter(|h| is_archive(h))
+                            .ok_or_else(|| malformed(idx, "truncated or bad header"))?;
+            let (size, name_len) = field(header, FILESIZE).zip(field(header, NAMESIZE))
+                                    .ok_or_else(|| malformed(idx, "invalid header field"))?;
+            let name_start = idx + HEADER_LEN;
+            let name = data.get(name_start .. name_start + name_len)
+                        .ok_or_else(|| malformed(idx, "truncated name"))?;
+            let name = name.strip_suffix(&[0]).unwrap_or(name);
+            let start = pad(name_start + name_len);
+            if data.len() < start + size {
+                return Err(malformed(idx, "truncated file contents"));
+            }
+
+            let next = pad(start + size);
+            if name == TRAILER {
+                return Ok(Archive {
+                    data,
+                    entries,
+                    trailer: idx,
+                    end: next.min(data.len()),
+                });
+            }
+            entries.push(Entry {
+                name: String::from_utf8_lossy(name).into_owned(),
+                header: idx,
+                data: start .. start + size,
+            });
+            idx = next;
+        }
+    }
+
+    /// File contents of an entry
+    pub fn content(&self, entry: &Entry) -> &'a [u8] {
+        &self.data[entry.data.clone()]
+    }
+
+    /// Writes the archive with the contents of some entries, by index,
+    /// replaced. Headers are only changed in the size and checksum of
+    /// replaced contents
+    pub fn rebuild(&self, replaced: &HashMap<usize, Vec<u8>>) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.end);
+        for (idx, entry) in self.entries.iter().enumerate() {
+            let start = out.len();
+            out.extend_from_slice(&self.data[entry.header .. entry.data.start]);
+            let content = match replaced.get(&idx) {
+                Some(content) => {
+                    let header = &mut out[start .. start + HEADER_LEN];
+                    set_field(header, FILESIZE, content.len() as u32);
+                    if header.starts_with(CRC_MAGIC) {
+                        let sum = content.iter()
+                                    .fold(0u32, |sum, &b| sum.wrapping_add(b as u32));
+                        set_field(header, CHECK, sum);
+                    }
+                    content.as_slice()
+                },
+                None => self.content(entry),
+            };
+            out.extend_from_slice(content);
+            out.resize(pad(out.len()), 0);
+        }
+        out.extend_from_slice(&self.data[self.trailer .. self.end]);
+        out
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Writes a "newc" archive of regular files, with the given magic
+    pub fn archive(magic: &[u8], files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (idx, &(name, content)) in files.iter().chain(&[("TRAILER!!!", &b""[..])])
+                                            .enumerate() {
+            let sum = content.iter().fold(0u32, |sum, &b| sum.wrapping_add(b as u32));
+            let check = if magic == CRC_MAGIC { sum } else { 0 };
+            let fields = [idx as u32 + 1, 0o100644, 0, 0, 1, 0, content.len() as u32,
+                          0, 0, 0, 0, name.len() as u32 + 1, check];
+            out.extend_from_slice(magic);
+            for field in fields {
+                out.extend_from_slice(format!("{:08X}", field).as_bytes());
+            }
+            out.extend_from_slice(name.as_bytes());
+            out.push(0);
+            out.resize(pad(out.len()), 0);
+            out.extend_from_slice(content);
+            out.resize(pad(out.len()), 0);
+        }
+        out
+    }
+
+    #[test]
+    fn entries_are_parsed_up_to_the_trailer() {
+        let mut data = archive(NEWC_MAGIC, &[("init", b"#!/bin/sh\n"), ("lib", b"")]);
+        let end = data.len();
+        data.extend_from_slice(b"\x1f\x8b concatenated archive");
+
+        let archive = Archive::parse(&data).unwrap();
+        let entries: Vec<_> = archive.entries.iter().map(|entry| {
+            (entry.name.as_str(), archive.content(entry))
+        }).collect();
+        assert_eq!(entries, [("init", &b"#!/bin/sh\n"[..]), ("lib", b"")]);
+        assert_eq!(archive.end, end);
+        assert_eq!(archive.rebuild(&HashMap::new()), data[.. end]);
+    }
+
+    #[test]
+    fn replaced_contents_update_size_and_checksum() {
+        for magic in [NEWC_MAGIC, CRC_MAGIC] {
+            let data = archive(magic, &[("a.ko", b"abc"), ("init", b"#!/bin/sh\n")]);
+            let archive = Archive::parse(&data).unwrap();
+            let rebuilt = archive.rebuild(&HashMap::from([(0, b"abcdefgh".to_vec())]));
+            assert_eq!(rebuilt, tests::archive(magic, &[("a.ko", b"abcdefgh"),
+                                                        ("init", b"#!/bin/sh\n")]));
+        }
+    }
+
+    #[test]
+    fn truncated_archives_are_refused() {
+        let data = archive(NEWC_MAGIC, &[("a.ko", b"abcdefgh")]);
+        for len in [0, 50, HEADER_LEN + 3, HEADER_LEN + 10, data.len() - 4] {
+            assert!(matches!(Archive::parse(&data[.. len]), Err(Error::Archive { .. })),
+                    "{} bytes parsed", len);
+        }
+        assert!(is_archive(&data) && !is_archive(b"\x7fELF"));
+    }
+}
@@ -0,0 +1,27 @@
+/// Output format of version tables, given with `--format`
+#[derive(Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum Format {
+    Text,
+    /// Comma-separated values with a header row, for spreadsheets
+    Csv,
+}
+
+/// Quotes a field if it holds separators, quotes or line breaks, following
+/// RFC 4180
+fn field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Formats a row of fields, without its line break
+pub fn row<S: AsRef<str>>(fields: &[S]) -> String {
+    fields.iter().map(|f| field(f.as_ref())).collect::<Vec<_>>().join(",")
+}
+
+/// Formats a CRC as it appears in `Module.symvers`
+pub fn crc(crc: u64) -> String {
+    format!("0x{:08x}", crc)
+}
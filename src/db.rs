@@ -0,0 +1,238 @@
+use crate::error::{Error, Result};
+use rusqlite::{params, Connection, OpenFlags};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Tables of the database. Versions are keyed by kernel and symbol, so
+/// looking up a kernel's versions reads a single range of the index
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS kernels (
+    name TEXT PRIMARY KEY,
+    imported INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS versions (
+    kernel TEXT NOT NULL,
+    symbol TEXT NOT NULL,
+    crc INTEGER NOT NULL,
+    namespace TEXT,
+    PRIMARY KEY (kernel, symbol)
+) WITHOUT ROWID;
+";
+
+/// Symbol versions of a reference kernel stored in the database
+#[derive(Default)]
+pub struct KernelVersions {
+    pub crcs: HashMap<String, u64>,
+    /// Namespaces of the symbols exported into one
+    pub namespaces: HashMap<String, String>,
+}
+
+/// Reference kernel listed by `kernels`
+pub struct KernelEntry {
+    pub name: String,
+    pub symbols: usize,
+}
+
+/// Location of the database: `path` if given, otherwise `CANDYCORN_DB` or
+/// the data directory following the XDG base directory specification
+pub fn path(path: Option<PathBuf>) -> Result<PathBuf> {
+    let path = path.or_else(|| std::env::var_os("CANDYCORN_DB").map(Into::into));
+    if let Some(path) = path {
+        return Ok(path);
+    }
+    let data = match std::env::var_os("XDG_DATA_HOME") {
+        Some(data) => PathBuf::from(data),
+        None => match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".local/share"),
+            None => return Err(db_error(Path::new("crcs.sqlite"),
+                                        "no data directory, give the database \
+                                         with `--db`")),
+        },
+    };
+    Ok(data.join("candycorn").join("crcs.sqlite"))
+}
+
+fn db_error(path: &Path, reason: impl Into<String>) -> Error {
+    Error::Database { path: path.into(), reason: reason.into() }
+}
+
+/// Opens the database, creating it and its tables unless `read_only`
+fn open(path: &Path, read_only: bool) -> Result<Connection> {
+    let connection = if read_only {
+        check_exists(path)?;
+        Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+    } else {
+        Connection::open(path)
+    };
+    let connection = connection.map_err(|e| db_error(path, e.to_string()))?;
+    if !read_only {
+        connection.execute_batch(SCHEMA).map_err(|e| db_error(path, e.to_string()))?;
+    }
+    Ok(connection)
+}
+
+/// Checks that the database exists before reading from it, as opening it
+/// would otherwise fail with a less helpful error
+fn check_exists(path: &Path) -> Result<()> {
+    if !path.exists() {
+        return Err(db_error(path, "no such database, import a kernel with \
+                                   `candycorn db import` first"));
+    }
+    Ok(())
+}
+
+/// Stores the symbol versions of a reference kernel, replacing versions of
+/// the same symbols imported earlier. The database is created if it does
+/// not exist. Returns the number of symbols the kernel has afterwards
+pub fn import(path: &Path, kernel: &str,
+              versions: impl IntoIterator<Item = (String, u64, Option<String>)>)
+    -> Result<usize> {
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| Error::Write { path: dir.into(), source: e })?;
+    }
+
+    let sql_err = |e: rusqlite::Error| db_error(path, e.to_string());
+    let mut connection = open(path, false)?;
+    let transaction = connection.transaction().map_err(sql_err)?;
+    transaction.execute("INSERT OR REPLACE INTO kernels VALUES (?1, strftime('%s', \
+                         'now'))", params![kernel])
+        .map_err(sql_err)?;
+    {
+        let mut insert = transaction.prepare("INSERT OR REPLACE INTO versions VALUES \
+                                              (?1, ?2, ?3, ?4)")
+                            .map_err(sql_err)?;
+        for (name, crc, namespace) in versions {
+            insert.execute(params![kernel, name, crc as i64, namespace])
+                .map_err(sql_err)?;
+        }
+    }
+    let count: i64 = transaction.query_row("SELECT COUNT(*) FROM versions WHERE \
+                                            kernel = ?1", params![kernel],
+                                           |row| row.get(0))
+                        .map_err(sql_err)?;
+    transaction.commit().map_err(sql_err)?;
+    Ok(count as usize)
+}
+
+/// Reads the symbol versions of a reference kernel, or only the version of
+/// one symbol. Kernels not in the database have no versions
+pub fn query(path: &Path, kernel: &str, symbol: Option<&str>) -> Result<KernelVersions> {
+    let sql_err = |e: rusqlite::Error| db_error(path, e.to_string());
+    let connection = open(path, true)?;
+    let mut statement = connection.prepare("SELECT symbol, crc, namespace FROM versions \
+                                            WHERE kernel = ?1 AND (?2 IS NULL OR \
+                                            symbol = ?2)")
+                            .map_err(sql_err)?;
+    let rows = statement.query_map(params![kernel, symbol], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?,
+            row.get::<_, Option<String>>(2)?))
+    }).map_err(sql_err)?;
+
+    let mut versions = KernelVersions::default();
+    for row in rows {
+        let (name, crc, namespace) = row.map_err(sql_err)?;
+        versions.crcs.insert(name.clone(), crc as u64);
+        if let Some(namespace) = namespace.filter(|ns| !ns.is_empty()) {
+            versions.namespaces.insert(name, namespace);
+        }
+    }
+    Ok(versions)
+}
+
+/// Reads the symbol versions of a reference kernel
+pub fn load(path: &Path, kernel: &str) -> Result<KernelVersions> {
+    let versions = query(path, kernel, None)?;
+    if versions.crcs.is_empty() {
+        return Err(db_error(path, format!("no versions of kernel \"{}\", import \
+                                           them with `candycorn db import`",
+                                          kernel)));
+    }
+    Ok(versions)
+}
+
+/// Reads the version of a single symbol of a reference kernel, along with
+/// its namespace
+pub fn lookup(path: &Path, kernel: &str, symbol: &str)
+    -> Result<Option<(u64, Option<String>)>> {
+
+    let mut versions = query(path, kernel, Some(symbol))?;
+    Ok(versions.crcs.remove(symbol)
+        .map(|crc| (crc, versions.namespaces.remove(symbol))))
+}
+
+/// Lists the reference kernels in the database
+pub fn kernels(path: &Path) -> Result<Vec<KernelEntry>> {
+    let sql_err = |e: rusqlite::Error| db_error(path, e.to_string());
+    let connection = open(path, true)?;
+    let mut statement = connection.prepare("SELECT name, (SELECT COUNT(*) FROM versions \
+                                            WHERE kernel = name) FROM kernels ORDER \
+                                            BY name")
+                            .map_err(sql_err)?;
+    let rows = statement.query_map([], |row| {
+        Ok(KernelEntry { name: row.get(0)?, symbols: row.get::<_, i64>(1)? as usize })
+    }).map_err(sql_err)?;
+    rows.map(|row| row.map_err(sql_err)).collect()
+}
+
+/// Removes a reference kernel and its versions. Returns whether the kernel
+/// was in the database
+pub fn remove(path: &Path, kernel: &str) -> Result<bool> {
+    let sql_err = |e: rusqlite::Error| db_error(path, e.to_string());
+    check_exists(path)?;
+    let mut connection = open(path, false)?;
+    let transaction = connection.transaction().map_err(sql_err)?;
+    transaction.execute("DELETE FROM versions WHERE kernel = ?1", params![kernel])
+        .map_err(sql_err)?;
+    let removed = transaction.execute("DELETE FROM kernels WHERE name = ?1",
+                                      params![kernel])
+                    .map_err(sql_err)?;
+    transaction.commit().map_err(sql_err)?;
+    Ok(removed != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(name: &str, crc: u64, namespace: Option<&str>)
+        -> (String, u64, Option<String>) {
+
+        (name.to_string(), crc, namespace.map(str::to_string))
+    }
+
+    #[test]
+    fn versions_round_trip() {
+        let dir = crate::tempdir::create("test").unwrap();
+        let path = dir.path().join("data").join("crcs.sqlite");
+        assert!(matches!(kernels(&path), Err(Error::Database { .. })));
+        assert!(matches!(remove(&path, "6.1.0"), Err(Error::Database { .. })));
+
+        let versions = [version("printk", 0xaa, None),
+                        version("kfree", u64::MAX, Some("NS")),
+                        version("o'brien", 0x1, Some(""))];
+        assert_eq!(import(&path, "6.1.0", versions).unwrap(), 3);
+        assert_eq!(import(&path, "6.1.0", [version("printk", 0xbb, None)]).unwrap(), 3);
+        assert_eq!(import(&path, "5.15.0", [version("printk", 0x1, None)]).unwrap(), 1);
+
+        let versions = load(&path, "6.1.0").unwrap();
+        assert_eq!(versions.crcs, HashMap::from([("printk".to_string(), 0xbb),
+                                                 ("kfree".to_string(), u64::MAX),
+                                                 ("o'brien".to_string(), 0x1)]));
+        assert_eq!(versions.namespaces,
+                   HashMap::from([("kfree".to_string(), "NS".to_string())]));
+        assert_eq!(lookup(&path, "6.1.0", "kfree").unwrap(),
+                   Some((u64::MAX, Some("NS".to_string()))));
+        assert_eq!(lookup(&path, "5.15.0", "printk").unwrap(), Some((0x1, None)));
+        assert_eq!(lookup(&path, "6.1.0", "kmalloc").unwrap(), None);
+
+        let listed: Vec<_> = kernels(&path).unwrap().into_iter()
+                                .map(|kernel| (kernel.name, kernel.symbols)).collect();
+        assert_eq!(listed, [("5.15.0".to_string(), 1), ("6.1.0".to_string(), 3)]);
+        assert!(remove(&path, "5.15.0").unwrap());
+        assert!(!remove(&path, "5.15.0").unwrap());
+        assert!(matches!(load(&path, "5.15.0"), Err(Error::Database { .. })));
+        assert_eq!(kernels(&path).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,104 @@
+use crate::error::{Error, Result};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// Symbols named in the kernel's errors about symbol versions of modules it
+/// refused to load
+#[derive(Default)]
+pub struct VersionErrors {
+    /// Symbols whose version disagrees with the kernel's
+    pub mismatched: BTreeSet<String>,
+    /// Symbols a module's `__versions` lacks
+    pub missing: BTreeSet<String>,
+}
+
+impl VersionErrors {
+    pub fn is_empty(&self) -> bool {
+        self.mismatched.is_empty() && self.missing.is_empty()
+    }
+}
+
+/// Collects the symbols of version errors from kernel log messages, as
+/// printed by `dmesg` or read from `/dev/kmsg`. Errors have the form
+/// `<module>: disagrees about version of symbol <symbol>` or
+/// `<module>: no symbol version for <symbol>`
+pub fn parse(log: &str) -> VersionErrors {
+    let mut errors = VersionErrors::default();
+    for line in log.lines() {
+        let symbol = |marker: &str| {
+            line.split_once(marker)
+                .and_then(|(_, rest)| rest.split_whitespace().next())
+                .map(str::to_string)
+        };
+        if let Some(symbol) = symbol("disagrees about version of symbol ") {
+            errors.mismatched.insert(symbol);
+        } else if let Some(symbol) = symbol("no symbol version for ") {
+            errors.missing.insert(symbol);
+        }
+    }
+    errors
+}
+
+/// Reads all messages currently in the kernel log buffer. Reading `/dev/kmsg`
+/// returns one message per read and blocks once all are read unless opened
+/// non-blocking
+fn read_kmsg() -> Result<String> {
+    use std::io::Read;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let path = Path::new("/dev/kmsg");
+    let read_err = |e| Error::Read { path: path.into(), source: e };
+    let mut file = std::fs::OpenOptions::new().read(true)
+                    .custom_flags(libc::O_NONBLOCK).open(path).map_err(read_err)?;
+
+    let mut log = String::new();
+    let mut record = vec![0; 8192];
+    loop {
+        match file.read(&mut record) {
+            Ok(0) => break,
+            Ok(len) => {
+                // Records are `<prefix>;<message>`
+                let record = String::from_utf8_lossy(&record[.. len]);
+                if let Some((_, message)) = record.split_once(';') {
+                    log.push_str(message);
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+            // Messages overwritten while reading are skipped
+            Err(e) if e.raw_os_error() == Some(libc::EPIPE) => continue,
+            Err(e) => return Err(read_err(e)),
+        }
+    }
+    Ok(log)
+}
+
+/// Collects the symbols of version errors from a saved kernel log, or from
+/// `/dev/kmsg` if no path is given
+pub fn load(path: Option<&Path>) -> Result<VersionErrors> {
+    let log = match path {
+        Some(path) => std::fs::read_to_string(path)
+                        .map_err(|e| Error::Read { path: path.into(), source: e })?,
+        None => read_kmsg()?,
+    };
+    Ok(parse(&log))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_errors_are_collected() {
+        let errors = parse("[ 12.345678] foo: disagrees about version of symbol printk\n\
+                            [ 12.345679] foo: Unknown symbol printk (err -22)\n\
+                            [ 12.345680] foo: no symbol version for module_layout\n\
+                            bar: disagrees about version of symbol kfree\n\
+                            bar: disagrees about version of symbol printk\n\
+                            usb 1-1: new high-speed USB device number 2\n");
+        assert_eq!(errors.mismatched, BTreeSet::from(["kfree".to_string(),
+                                                      "printk".to_string()]));
+        assert_eq!(errors.missing, BTreeSet::from(["module_layout".to_string()]));
+        assert!(!errors.is_empty());
+        assert!(parse("usb 1-1: new high-speed USB device number 2\n").is_empty());
+    }
+}
@@ -0,0 +1,155 @@
+use crate::running;
+
+/// Outcome of a check of the running system
+pub enum Status {
+    Ok,
+    /// Force loading may work but could be hindered
+    Warning,
+    /// Force loading will fail
+    Blocked,
+    Unknown,
+}
+
+pub struct Check {
+    pub status: Status,
+    pub message: String,
+}
+
+fn check(status: Status, message: impl Into<String>) -> Check {
+    Check { status, message: message.into() }
+}
+
+/// Reads a small file from `/proc` or `/sys`, trimmed
+fn read(path: &str) -> std::io::Result<String> {
+    std::fs::read_to_string(path).map(|s| s.trim().to_string())
+}
+
+/// Variable holding the Secure Boot state, in the EFI global variable GUID
+const SECURE_BOOT: &str = "/sys/firmware/efi/efivars/\
+                           SecureBoot-8be4df61-93ca-11d2-aa0d-00e098032b8c";
+
+/// Checks whether loading modules is disabled until reboot
+fn modules_disabled() -> Check {
+    match read("/proc/sys/kernel/modules_disabled").as_deref() {
+        Ok("0") => check(Status::Ok, "module loading is enabled"),
+        Ok(_) => check(Status::Blocked, "module loading is disabled until \
+                                         reboot (`kernel.modules_disabled`)"),
+        Err(e) => check(Status::Unknown, format!("unable to read \
+                                                 `kernel.modules_disabled`: {}", e)),
+    }
+}
+
+/// Checks the kernel lockdown mode. Lockdown only allows loading signed
+/// modules
+fn lockdown() -> Check {
+    let modes = match read("/sys/kernel/security/lockdown") {
+        Ok(modes) => modes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return check(Status::Ok, "kernel lockdown is not available");
+        },
+        Err(e) => return check(Status::Unknown,
+                               format!("unable to read kernel lockdown mode: {}", e)),
+    };
+
+    // Modes are listed with the active one in brackets
+    let mode = modes.split_whitespace()
+                .find_map(|m| m.strip_prefix('[')?.strip_suffix(']'))
+                .unwrap_or("none");
+    if mode == "none" {
+        check(Status::Ok, "kernel lockdown is off")
+    } else {
+        check(Status::Blocked, format!("kernel lockdown is in {} mode, only \
+                                        validly signed modules load", mode))
+    }
+}
+
+/// Checks whether module signatures are enforced. Patching a signed module
+/// invalidates its signature
+fn sig_enforce(config: Option<&str>) -> Check {
+    match read("/sys/module/module/parameters/sig_enforce").as_deref() {
+        Ok("Y") => check(Status::Blocked, "module signatures are enforced, \
+                                           patched modules are rejected"),
+        Ok(_) => check(Status::Ok, "module signatures are not enforced, \
+                                    loading unsigned modules taints the kernel"),
+        // Without module signing support the parameter does not exist
+        Err(_) => match config.map(|c| {
+            running::config_option(c, "CONFIG_MODULE_SIG_FORCE")
+        }) {
+            Some(Some("y")) => check(Status::Blocked, "module signatures are \
+                                                       enforced, patched \
+                                                       modules are rejected"),
+            Some(_) => check(Status::Ok, "module signatures are not checked"),
+            None => check(Status::Unknown, "unable to determine whether module \
+                                            signatures are enforced"),
+        },
+    }
+}
+
+/// Checks the EFI Secure Boot state. Distributions commonly enable lockdown
+/// and signature enforcement when booted with Secure Boot
+fn secure_boot() -> Check {
+    if !std::path::Path::new("/sys/firmware/efi").exists() {
+        return check(Status::Ok, "system did not boot with EFI, Secure Boot \
+                                  is off");
+    }
+    // The variable's 4 byte attributes precede its value
+    match std::fs::read(SECURE_BOOT).map(|data| data.get(4).copied()) {
+        Ok(Some(1)) => check(Status::Warning, "Secure Boot is enabled, which \
+                                               often comes with lockdown and \
+                                               signature enforcement"),
+        Ok(_) => check(Status::Ok, "Secure Boot is disabled"),
+        Err(e) => check(Status::Unknown,
+                        format!("unable to read Secure Boot state: {}", e)),
+    }
+}
+
+/// Checks the kernel options deciding how strictly versions are checked
+fn versioning(config: Option<&str>) -> Vec<Check> {
+    let config = match config {
+        Some(config) => config,
+        None => return vec![check(Status::Unknown, "kernel configuration not \
+                                                    found, unable to check \
+                                                    `CONFIG_MODULE_FORCE_LOAD`")],
+    };
+
+    let mut checks = Vec::new();
+    if running::config_option(config, "CONFIG_MODVERSIONS") != Some("y") {
+        checks.push(check(Status::Ok, "symbol versions are not checked \
+                                       (`CONFIG_MODVERSIONS` is not set)"));
+    }
+    if running::config_option(config, "CONFIG_MODULE_FORCE_LOAD") == Some("y") {
+        checks.push(check(Status::Ok, "`modprobe --force` is supported \
+                                       (`CONFIG_MODULE_FORCE_LOAD`)"));
+    } else {
+        checks.push(check(Status::Warning, "`modprobe --force` is not \
+                                            supported, versions and vermagic \
+                                            must be patched to match"));
+    }
+    checks
+}
+
+/// Checks whether the kernel refuses to load any modules
+pub fn modules_blocked() -> bool {
+    matches!(modules_disabled().status, Status::Blocked)
+}
+
+/// Checks whether the kernel only loads validly signed modules, because of
+/// signature enforcement or lockdown
+pub fn signature_required() -> bool {
+    matches!(lockdown().status, Status::Blocked)
+        || matches!(sig_enforce(running::config().as_deref()).status, Status::Blocked)
+}
+
+/// Checks whether anything about the running system prevents loading
+/// patched or force loaded modules
+pub fn checks() -> Vec<Check> {
+    let config = running::config();
+    let mut checks = vec![
+        modules_disabled(),
+        lockdown(),
+        sig_enforce(config.as_deref()),
+        secure_boot(),
+    ];
+    checks.extend(versioning(config.as_deref()));
+    checks
+}
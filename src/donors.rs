@@ -0,0 +1,301 @@
+use crate::error::{Error, Result};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Symbol versions supplied by a donor module
+pub struct Donor {
+    pub path: PathBuf,
+    pub arch: crate::Arch,
+    pub versions: Vec<(String, u64)>,
+}
+
+/// Version of a symbol found in an installed module
+pub struct Resolved {
+    pub crc: u64,
+    pub path: PathBuf,
+    pub arch: crate::Arch,
+}
+
+/// Collects the versions a module can donate: those of the symbols it imports
+/// from its `__versions` section and those of the symbols it exports from its
+/// export tables
+pub fn module_versions(ko: &crate::KernelModule) -> Result<Vec<(String, u64)>> {
+    let info = crate::parse_elf(&ko.buffer)?;
+    let mut versions: Vec<_> = crate::exports::crcs(&info, &ko.buffer)?
+                                .into_iter().collect();
+    versions.sort();
+    if let Some(imports) = &ko.versions {
+        versions.extend(crate::versions_by_name(imports).into_iter()
+                            .map(|(name, ver)| (name.to_string(), ver.crc)));
+    }
+    Ok(versions)
+}
+
+/// Reads the architecture and donatable versions of a module, taking them
+/// from the donor cache if the module was read before
+fn read_versions(path: &Path) -> Result<(crate::Arch, Vec<(String, u64)>)> {
+    let mapped = crate::map_file(path)?;
+    let entry = crate::cache::entry(&mapped);
+    if let Some(donor) = entry.as_deref().and_then(crate::cache::get) {
+        log::trace!("Using cached versions of {}", path.display());
+        return Ok((donor.arch, donor.versions));
+    }
+
+    let ko = crate::parse_module(crate::decompress_module(path, mapped)?)
+                .map_err(|e| e.in_file(path))?;
+    let versions = module_versions(&ko).map_err(|e| e.in_file(path))?;
+    let donor = crate::cache::Donor { arch: ko.arch, versions };
+    if let Some(entry) = &entry {
+        crate::cache::put(entry, &donor);
+    }
+    Ok((donor.arch, donor.versions))
+}
+
+/// Checks if a file name is that of a kernel module, possibly compressed.
+/// Backups such as `.ko.orig` are not modules to use
+pub fn is_module_name(name: &std::ffi::OsStr) -> bool {
+    let name = name.to_string_lossy();
+    name.ends_with(".ko") || name.rsplit_once(".ko.").is_some_and(|(_, ext)| {
+        crate::compress::Format::from_path(std::path::Path::new(name.as_ref()))
+            .is_some_and(|format| format.extension() == ext)
+    })
+}
+
+/// Recursively finds kernel modules below a directory in path order. Symbolic
+/// links to directories are not followed
+pub fn find_modules(dir: &Path) -> Result<Vec<PathBuf>> {
+    find_files(dir, is_module_name)
+}
+
+/// Recursively finds files below a directory whose names match, in path
+/// order. Symbolic links to directories are not followed
+pub fn find_files(dir: &Path, matches: impl Fn(&std::ffi::OsStr) -> bool)
+    -> Result<Vec<PathBuf>> {
+
+    let mut modules = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let read_err = |e| Error::Read { path: dir.clone(), source: e };
+        for entry in std::fs::read_dir(&dir).map_err(read_err)? {
+            let entry = entry.map_err(read_err)?;
+            let file_type = entry.file_type().map_err(read_err)?;
+            if file_type.is_dir() {
+                dirs.push(entry.path());
+            } else if matches(&entry.file_name()) {
+                modules.push(entry.path());
+            }
+        }
+    }
+    modules.sort();
+    Ok(modules)
+}
+
+/// Collects the symbol versions of all kernel modules below a directory.
+/// When modules disagree on a version, the module first in path order wins
+/// and a warning is printed. Each donor only lists the versions it supplies
+/// and modules that can not be read are skipped
+pub fn from_dir(dir: &Path) -> Result<Vec<Donor>> {
+    let modules = find_modules(dir)?;
+    let bar = crate::progress::start(format!("Reading {}", dir.display()), modules.len());
+    let loaded: Vec<_> = modules.par_iter()
+        .map(|path| {
+            let result = read_versions(path);
+            bar.inc(path.strip_prefix(dir).unwrap_or(path).display());
+            (path, result)
+        })
+        .collect();
+    drop(bar);
+
+    let mut supplied: HashMap<String, (u64, &Path)> = HashMap::new();
+    let mut donors = Vec::new();
+    for (path, result) in loaded {
+        let (arch, versions) = match result {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                log::warn!("Skipping {}", e);
+                continue;
+            }
+        };
+
+        let mut donated = Vec::new();
+        for (name, crc) in versions {
+            match supplied.get(&name) {
+                Some(&(other_crc, _)) if other_crc == crc => {},
+                Some(&(other_crc, other)) => {
+                    log::warn!("\"{}\" is 0x{:x} in {} but 0x{:x} in {}",
+                                 name, other_crc, other.display(), crc,
+                                 path.display());
+                },
+                None => {
+                    supplied.insert(name.clone(), (crc, path));
+                    donated.push((name, crc));
+                },
+            }
+        }
+        if !donated.is_empty() {
+            donors.push(Donor { path: path.clone(), arch, versions: donated });
+        }
+    }
+    Ok(donors)
+}
+
+/// Computes a SHA-256 identifying the combined versions of a set of donors
+pub fn digest(donors: &[Donor]) -> String {
+    let mut lines: Vec<String> = donors.iter()
+        .flat_map(|donor| donor.versions.iter().map(move |(name, crc)| {
+            format!("0x{:08x}\t{}\t{}\n", crc, name, donor.path.display())
+        }))
+        .collect();
+    lines.sort();
+    crate::checksum::sha256(lines.concat().as_bytes())
+}
+
+/// Number of installed modules read at once while looking for versions
+const BATCH: usize = 64;
+
+/// Index of the modules installed for a kernel, used to look up versions of
+/// symbols on demand. Modules are only read when a symbol they may import is
+/// looked up and their versions are kept for later lookups
+pub struct Installed {
+    dir: PathBuf,
+    /// SHA-256 of `modules.dep`, identifying the set of installed modules
+    pub sha256: String,
+    /// Module paths relative to `dir`, in `modules.dep` order
+    modules: Vec<PathBuf>,
+    /// Index of each module, by module name
+    by_name: HashMap<String, usize>,
+    /// Indices of the modules depending on each module, by module name
+    dependents: HashMap<String, Vec<usize>>,
+    /// Name of the module exporting each symbol
+    exporters: HashMap<String, String>,
+    /// Architecture and versions of modules read so far, by module index.
+    /// `None` for modules that could not be read
+    read: std::sync::Mutex<HashMap<usize, Option<ReadModule>>>,
+}
+
+type ReadModule = (crate::Arch, HashMap<String, u64>);
+
+/// Derives the name of a module from its path, as used in `modules.symbols`
+fn module_name(path: &str) -> String {
+    let file = path.rsplit('/').next().unwrap_or(path);
+    let name = file.find(".ko").map_or(file, |idx| &file[.. idx]);
+    name.replace('-', "_")
+}
+
+impl Installed {
+    /// Reads the `modules.dep` and `modules.symbols` indices of a kernel's
+    /// module directory. `modules.symbols` is optional
+    pub fn open(dir: &Path) -> Result<Installed> {
+        let dep_path = dir.join("modules.dep");
+        let deps = std::fs::read_to_string(&dep_path)
+                    .map_err(|e| Error::Read { path: dep_path, source: e })?;
+
+        // `modules.dep` lines are "<path>: <dependency paths>"
+        let mut modules = Vec::new();
+        let mut by_name = HashMap::new();
+        let mut dependents: HashMap<String, Vec<usize>> = HashMap::new();
+        for line in deps.lines() {
+            let (path, line_deps) = match line.split_once(':') {
+                Some(split) => split,
+                None => continue,
+            };
+            for dep in line_deps.split_whitespace() {
+                dependents.entry(module_name(dep)).or_default()
+                    .push(modules.len());
+            }
+            by_name.insert(module_name(path), modules.len());
+            modules.push(PathBuf::from(path));
+        }
+
+        // `modules.symbols` lines are "alias symbol:<symbol> <module>"
+        let symbols = std::fs::read_to_string(dir.join("modules.symbols"))
+                        .unwrap_or_default();
+        let exporters = symbols.lines()
+            .filter_map(|l| l.strip_prefix("alias symbol:"))
+            .filter_map(|l| l.split_once(' '))
+            .map(|(symbol, module)| (symbol.to_string(), module.to_string()))
+            .collect();
+
+        Ok(Installed {
+            dir: dir.to_path_buf(),
+            sha256: crate::checksum::sha256(deps.as_bytes()),
+            modules,
+            by_name,
+            dependents,
+            exporters,
+            read: Default::default(),
+        })
+    }
+
+    /// Checks if an installed module exports a symbol
+    pub fn exports(&self, name: &str) -> bool {
+        self.exporters.contains_key(name)
+    }
+
+    /// Looks up the versions of symbols in the installed modules, returning
+    /// the version of each symbol found. A symbol's exporter is read first,
+    /// followed by the modules depending on it. Symbols exported by the
+    /// kernel itself are searched for in all modules
+    pub fn resolve(&self, names: &[&str]) -> HashMap<String, Resolved> {
+        let mut found = HashMap::new();
+        let mut wanted: Vec<&str> = names.to_vec();
+
+        let mut candidates: Vec<usize> = names.iter()
+            .filter_map(|name| self.exporters.get(*name))
+            .flat_map(|module| {
+                let exporter = self.by_name.get(module).copied();
+                let dependents = self.dependents.get(module).into_iter().flatten();
+                exporter.into_iter().chain(dependents.copied())
+            })
+            .collect();
+        candidates.extend(0 .. self.modules.len());
+        let mut seen = std::collections::HashSet::new();
+        candidates.retain(|idx| seen.insert(*idx));
+
+        let bar = crate::progress::start(format!("Searching {}", self.dir.display()),
+                                         self.modules.len());
+        for batch in candidates.chunks(BATCH) {
+            if wanted.is_empty() {
+                break;
+            }
+            self.read_batch(batch, &bar);
+
+            let read = self.read.lock().unwrap();
+            wanted.retain(|name| {
+                let hit = batch.iter().find_map(|idx| {
+                    let (arch, versions) = read[idx].as_ref()?;
+                    Some((*versions.get(*name)?, *arch, *idx))
+                });
+                match hit {
+                    Some((crc, arch, idx)) => {
+                        let path = self.dir.join(&self.modules[idx]);
+                        log::debug!("Found \"{}\" in installed module {}", name,
+                                    path.display());
+                        found.insert(name.to_string(), Resolved { crc, path, arch });
+                        false
+                    },
+                    None => true,
+                }
+            });
+        }
+        found
+    }
+
+    /// Reads the versions of any modules of a batch not read before
+    fn read_batch(&self, batch: &[usize], bar: &crate::progress::Bar) {
+        let unread: Vec<usize> = {
+            let read = self.read.lock().unwrap();
+            batch.iter().filter(|idx| !read.contains_key(idx)).copied().collect()
+        };
+        let versions: Vec<_> = unread.par_iter().map(|&idx| {
+            let path = self.dir.join(&self.modules[idx]);
+            let module = read_versions(&path).ok().map(|(arch, versions)| {
+                (arch, versions.into_iter().collect())
+            });
+            bar.inc(self.modules[idx].display());
+            (idx, module)
+        }).collect();
+        self.read.lock().unwrap().extend(versions);
+    }
+}
@@ -0,0 +1,456 @@
+use crate::error::{Error, Result};
+use crate::{parse_elf, section_data};
+use goblin::elf::section_header::{SectionHeader, SHF_INFO_LINK, SHN_ABS,
+                                  SHN_LORESERVE, SHN_XINDEX, SHT_GROUP,
+                                  SHT_NOBITS, SHT_REL, SHT_RELA, SHT_SYMTAB,
+                                  SHT_SYMTAB_SHNDX};
+use scroll::{Cwrite, Pread};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Rounds a value up to a multiple of `align`
+pub fn align_up(value: usize, align: usize) -> usize {
+    value.div_ceil(align.max(1)) * align.max(1)
+}
+
+/// Edits the sections of a module. Sections may be added, removed or given
+/// new content. Content of unchanged sections stays where it is so file
+/// offsets into them remain valid. Sections whose content changes size are
+/// moved to the end of the file, as is the section header table. Content of
+/// removed sections is cleared, unless the module is compacted
+pub struct Editor<'a> {
+    data: &'a [u8],
+    ctx: goblin::container::Ctx,
+    headers: Vec<SectionHeader>,
+    /// New content of sections, by section index
+    content: BTreeMap<usize, Vec<u8>>,
+    removed: BTreeSet<usize>,
+    /// Content of the section name table, if names were added
+    names: Option<Vec<u8>>,
+    shstrndx: usize,
+    /// Number of sections in the unedited module
+    original: usize,
+    compact: bool,
+}
+
+impl<'a> Editor<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Editor<'a>> {
+        let elf = parse_elf(data)?;
+        let header = elf.header;
+        let ctx = goblin::container::Ctx::new(header.container()?,
+                                              header.endianness()?);
+
+        let shstrndx = header.e_shstrndx as usize;
+        if header.e_shnum == 0 || shstrndx == SHN_XINDEX as usize
+            || shstrndx >= elf.section_headers.len() {
+            return Err(Error::Unsupported {
+                reason: "extended section numbering is not supported".to_string(),
+            });
+        }
+
+        Ok(Editor {
+            data,
+            ctx,
+            original: elf.section_headers.len(),
+            headers: elf.section_headers,
+            content: BTreeMap::new(),
+            removed: BTreeSet::new(),
+            names: None,
+            shstrndx,
+            compact: false,
+        })
+    }
+
+    /// Byte order of the module
+    pub fn endian(&self) -> scroll::Endian {
+        self.ctx.le
+    }
+
+    /// Whether the module is 64-bit
+    pub fn is_64(&self) -> bool {
+        self.ctx.container.is_big()
+    }
+
+    /// Returns the name table, copying it on first use
+    fn names(&mut self) -> Result<&mut Vec<u8>> {
+        if self.names.is_none() {
+            let sh = &self.headers[self.shstrndx];
+            self.names = Some(section_data(sh, ".shstrtab", self.data)?.to_vec());
+        }
+        Ok(self.names.as_mut().unwrap())
+    }
+
+    /// Finds a section that has not been removed by name
+    pub fn find(&self, name: &str) -> Option<usize> {
+        let names = match &self.names {
+            Some(names) => &names[..],
+            None => section_data(&self.headers[self.shstrndx], ".shstrtab",
+                                 self.data).ok()?,
+        };
+        (0 .. self.headers.len()).find(|idx| {
+            !self.removed.contains(idx)
+                && names.get(self.headers[*idx].sh_name ..)
+                    .and_then(|n| n.split(|&b| b == 0).next())
+                    == Some(name.as_bytes())
+        })
+    }
+
+    /// Current content of a section
+    pub fn content(&self, idx: usize) -> Result<&[u8]> {
+        match self.content.get(&idx) {
+            Some(content) => Ok(content),
+            None => section_data(&self.headers[idx], "section", self.data),
+        }
+    }
+
+    /// Replaces the content of a section
+    pub fn set_content(&mut self, idx: usize, content: Vec<u8>) {
+        self.content.insert(idx, content);
+    }
+
+    /// Adds a section with the provided name and content. The header's name,
+    /// offset and size are filled in. Returns the index of the new section
+    pub fn add(&mut self, name: &str, header: SectionHeader, content: Vec<u8>)
+        -> Result<usize> {
+
+        let names = self.names()?;
+        let sh_name = names.len();
+        names.extend_from_slice(name.as_bytes());
+        names.push(0);
+
+        self.headers.push(SectionHeader { sh_name, ..header });
+        let idx = self.headers.len() - 1;
+        self.content.insert(idx, content);
+        Ok(idx)
+    }
+
+    /// Removes a section. Relocation sections applying to it are removed too
+    pub fn remove(&mut self, idx: usize) {
+        self.removed.insert(idx);
+        for (rel_idx, sh) in self.headers.iter().enumerate() {
+            if (sh.sh_type == SHT_REL || sh.sh_type == SHT_RELA)
+                && sh.sh_info as usize == idx {
+                self.removed.insert(rel_idx);
+            }
+        }
+    }
+
+    /// Lays all sections out again in file order when finishing, leaving no
+    /// gaps. File offsets into the module are not kept
+    pub fn compact(&mut self) {
+        self.compact = true;
+    }
+
+    /// Produces the edited module
+    pub fn finish(mut self) -> Result<Vec<u8>> {
+        if let Some(names) = self.names.take() {
+            self.content.insert(self.shstrndx, names);
+        }
+
+        // Sections are renumbered around removed sections
+        let mut new_idx = Vec::with_capacity(self.headers.len());
+        let mut next = 0;
+        for idx in 0 .. self.headers.len() {
+            if self.removed.contains(&idx) {
+                new_idx.push(None);
+            } else {
+                new_idx.push(Some(next));
+                next += 1;
+            }
+        }
+        if !self.removed.is_empty() {
+            self.renumber_content(&new_idx)?;
+        }
+
+        let out = if self.compact {
+            self.layout_compact()?
+        } else {
+            self.layout_in_place()
+        };
+        self.finish_headers(out, &new_idx)
+    }
+
+    /// Appends content to the unedited module, keeping unchanged sections in
+    /// place
+    fn layout_in_place(&mut self) -> Vec<u8> {
+        let mut out = self.data.to_vec();
+        for &idx in &self.removed {
+            let sh = &self.headers[idx];
+            if sh.sh_type != SHT_NOBITS {
+                let start = sh.sh_offset as usize;
+                let end = start.checked_add(sh.sh_size as usize);
+                if let Some(bytes) = end.and_then(|end| out.get_mut(start .. end)) {
+                    bytes.fill(0);
+                }
+            }
+        }
+
+        // Content of the same size is written in place, all other content
+        // is appended
+        for (&idx, content) in &self.content {
+            if self.removed.contains(&idx) {
+                continue;
+            }
+            let sh = &mut self.headers[idx];
+            let start = sh.sh_offset as usize;
+            let in_place = sh.sh_type != SHT_NOBITS && sh.sh_offset != 0
+                && sh.sh_size as usize == content.len()
+                && start.checked_add(content.len())
+                        .is_some_and(|end| end <= self.data.len());
+            if in_place {
+                out[start .. start + content.len()].copy_from_slice(content);
+                continue;
+            }
+            out.resize(align_up(out.len(), sh.sh_addralign as usize), 0);
+            sh.sh_offset = out.len() as u64;
+            sh.sh_size = content.len() as u64;
+            out.extend_from_slice(content);
+        }
+        out
+    }
+
+    /// Copies everything before the first section, then all remaining
+    /// sections in file order. Added sections go last
+    fn layout_compact(&mut self) -> Result<Vec<u8>> {
+        let mut order: Vec<usize> = (1 .. self.headers.len())
+            .filter(|idx| !self.removed.contains(idx)
+                          && self.headers[*idx].sh_type != SHT_NOBITS)
+            .collect();
+        order.sort_by_key(|&idx| {
+            (idx >= self.original, self.headers[idx].sh_offset, idx)
+        });
+
+        let start = order.iter()
+            .filter(|&&idx| idx < self.original)
+            .map(|&idx| self.headers[idx].sh_offset as usize)
+            .min()
+            .unwrap_or(self.data.len())
+            .min(self.data.len());
+        let mut out = self.data[.. start].to_vec();
+        for idx in order {
+            let content = match self.content.get(&idx) {
+                Some(content) => content,
+                None => section_data(&self.headers[idx], "section", self.data)?,
+            };
+            let sh = &mut self.headers[idx];
+            out.resize(align_up(out.len(), sh.sh_addralign as usize), 0);
+            sh.sh_offset = out.len() as u64;
+            sh.sh_size = content.len() as u64;
+            out.extend_from_slice(content);
+        }
+        Ok(out)
+    }
+
+    /// Appends the section header table and points the ELF header at it
+    fn finish_headers(&self, mut out: Vec<u8>, new_idx: &[Option<usize>])
+        -> Result<Vec<u8>> {
+
+        let endian = self.endian();
+        let is_64 = self.is_64();
+
+        // Links to other sections follow the renumbering
+        let mut headers = Vec::new();
+        for (idx, sh) in self.headers.iter().enumerate() {
+            if self.removed.contains(&idx) {
+                continue;
+            }
+            let mut sh = sh.clone();
+            let remap = |link: u32| {
+                new_idx.get(link as usize).copied().flatten().unwrap_or(0) as u32
+            };
+            sh.sh_link = remap(sh.sh_link);
+            if sh.sh_type == SHT_REL || sh.sh_type == SHT_RELA
+                || sh.sh_flags & SHF_INFO_LINK as u64 != 0 {
+                sh.sh_info = remap(sh.sh_info);
+            }
+            headers.push(sh);
+        }
+
+        // Section header table, aligned for the ELF class
+        let (entry_size, word) = if is_64 { (64, 8) } else { (40, 4) };
+        out.resize(align_up(out.len(), word), 0);
+        let table_offset = out.len();
+        out.resize(table_offset + headers.len() * entry_size, 0);
+        for (idx, sh) in headers.iter().enumerate() {
+            out.cwrite_with(sh.clone(), table_offset + idx * entry_size, self.ctx);
+        }
+
+        // Point the ELF header at the new table
+        let shstrndx = new_idx[self.shstrndx].unwrap_or(0) as u16;
+        if is_64 {
+            out.cwrite_with(table_offset as u64, 0x28, endian);
+            out.cwrite_with(headers.len() as u16, 0x3c, endian);
+            out.cwrite_with(shstrndx, 0x3e, endian);
+        } else {
+            out.cwrite_with(table_offset as u32, 0x20, endian);
+            out.cwrite_with(headers.len() as u16, 0x30, endian);
+            out.cwrite_with(shstrndx, 0x32, endian);
+        }
+        Ok(out)
+    }
+
+    /// Rewrites section indices held in symbol tables and section groups.
+    /// Symbols defined in removed sections become absolute symbols
+    fn renumber_content(&mut self, new_idx: &[Option<usize>]) -> Result<()> {
+        let endian = self.endian();
+        let is_64 = self.is_64();
+        for idx in 0 .. self.headers.len() {
+            if self.removed.contains(&idx) {
+                continue;
+            }
+            let sh_type = self.headers[idx].sh_type;
+            if sh_type == SHT_SYMTAB_SHNDX {
+                return Err(Error::Unsupported {
+                    reason: "extended symbol section indices are not supported"
+                                .to_string(),
+                });
+            }
+            if sh_type != SHT_SYMTAB && sh_type != SHT_GROUP {
+                continue;
+            }
+
+            let mut content = self.content(idx)?.to_vec();
+            if sh_type == SHT_GROUP {
+                // A flag word followed by the indices of the members
+                for offset in (4 .. content.len()).step_by(4) {
+                    let member: u32 = content.pread_with(offset, endian)
+                                        .map_err(goblin::error::Error::from)?;
+                    let member = new_idx.get(member as usize).copied().flatten()
+                                    .unwrap_or(0) as u32;
+                    content.cwrite_with(member, offset, endian);
+                }
+            } else {
+                let (sym_size, shndx_at, value_at) = if is_64 {
+                    (24, 6, 8)
+                } else {
+                    (16, 14, 4)
+                };
+                for sym in (0 .. content.len() / sym_size).map(|i| i * sym_size) {
+                    let shndx: u16 = content.pread_with(sym + shndx_at, endian)
+                                        .map_err(goblin::error::Error::from)?;
+                    if shndx == 0 || shndx >= SHN_LORESERVE as u16 {
+                        continue;
+                    }
+                    match new_idx.get(shndx as usize).copied().flatten() {
+                        Some(new) => {
+                            content.cwrite_with(new as u16, sym + shndx_at, endian);
+                        },
+                        None => {
+                            content.cwrite_with(SHN_ABS as u16, sym + shndx_at, endian);
+                            let zero = vec![0; if is_64 { 8 } else { 4 }];
+                            content[sym + value_at .. sym + value_at + zero.len()]
+                                .copy_from_slice(&zero);
+                        },
+                    }
+                }
+            }
+            self.content.insert(idx, content);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testgen;
+
+    /// Content of a named section of a module
+    fn content<'a>(data: &'a [u8], name: &str) -> Option<&'a [u8]> {
+        let elf = crate::parse_elf(data).unwrap();
+        let sh = crate::find_section(&elf, name)?;
+        Some(crate::section_data(sh, name, data).unwrap())
+    }
+
+    fn offset(data: &[u8], name: &str) -> u64 {
+        let elf = crate::parse_elf(data).unwrap();
+        crate::find_section(&elf, name).unwrap().sh_offset
+    }
+
+    #[test]
+    fn resized_section_is_moved() {
+        for module in testgen::fixtures(&[("printk", 0x1234)], false) {
+            let mut editor = Editor::new(&module).unwrap();
+            let idx = editor.find(".modinfo").unwrap();
+            editor.set_content(idx, b"license=GPL\0author=someone\0".to_vec());
+            let edited = editor.finish().unwrap();
+
+            assert_eq!(content(&edited, ".modinfo").unwrap(),
+                       b"license=GPL\0author=someone\0");
+            assert!(offset(&edited, ".modinfo") >= module.len() as u64);
+            assert_eq!(offset(&edited, "__versions"), offset(&module, "__versions"));
+            assert_eq!(content(&edited, "__versions"), content(&module, "__versions"));
+        }
+    }
+
+    #[test]
+    fn same_size_content_stays_in_place() {
+        for module in testgen::fixtures(&[("printk", 0x1234)], false) {
+            let mut editor = Editor::new(&module).unwrap();
+            let idx = editor.find(".modinfo").unwrap();
+            editor.set_content(idx, b"license=BSD\0".to_vec());
+            let edited = editor.finish().unwrap();
+
+            assert_eq!(content(&edited, ".modinfo").unwrap(), b"license=BSD\0");
+            assert_eq!(offset(&edited, ".modinfo"), offset(&module, ".modinfo"));
+        }
+    }
+
+    #[test]
+    fn added_section_is_found() {
+        for module in testgen::fixtures(&[("printk", 0x1234)], false) {
+            let mut editor = Editor::new(&module).unwrap();
+            let header = SectionHeader {
+                sh_type: goblin::elf::section_header::SHT_PROGBITS,
+                sh_addralign: 8,
+                ..Default::default()
+            };
+            editor.add(".added", header, vec![1, 2, 3]).unwrap();
+            let edited = editor.finish().unwrap();
+
+            assert_eq!(content(&edited, ".added").unwrap(), [1, 2, 3]);
+            assert_eq!(offset(&edited, ".added") % 8, 0);
+            assert_eq!(content(&edited, ".modinfo"), content(&module, ".modinfo"));
+        }
+    }
+
+    #[test]
+    fn removed_section_is_cleared() {
+        for module in testgen::fixtures(&[("printk", 0x1234)], false) {
+            let start = offset(&module, "__versions") as usize;
+            let mut editor = Editor::new(&module).unwrap();
+            let idx = editor.find("__versions").unwrap();
+            editor.remove(idx);
+            let edited = editor.finish().unwrap();
+
+            assert!(content(&edited, "__versions").is_none());
+            let entry = start .. start + crate::modversions::ENTRY_SIZE;
+            assert!(edited[entry].iter().all(|&b| b == 0));
+            assert_eq!(content(&edited, ".modinfo"), content(&module, ".modinfo"));
+            // Symbols still refer to `.gnu.linkonce.this_module` by its new index
+            let elf = crate::parse_elf(&edited).unwrap();
+            let (syms, strtab) = crate::read_symtab(&elf, &edited).unwrap().unwrap();
+            let this_module = syms.iter().find(|sym| {
+                strtab.get_at(sym.st_name) == Some("__this_module")
+            }).unwrap();
+            let sh = &elf.section_headers[this_module.st_shndx];
+            assert_eq!(elf.shdr_strtab.get_at(sh.sh_name),
+                       Some(".gnu.linkonce.this_module"));
+        }
+    }
+
+    #[test]
+    fn oversized_sections_do_not_overflow() {
+        for module in testgen::fixtures(&[("printk", 0x1234)], false) {
+            let mut editor = Editor::new(&module).unwrap();
+            let removed = editor.find("__versions").unwrap();
+            editor.headers[removed].sh_size = u64::MAX;
+            editor.remove(removed);
+            let changed = editor.find(".modinfo").unwrap();
+            editor.headers[changed].sh_offset = u64::MAX;
+            editor.set_content(changed, b"license=BSD\0".to_vec());
+            let edited = editor.finish().unwrap();
+
+            assert_eq!(content(&edited, ".modinfo").unwrap(), b"license=BSD\0");
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use crate::error::{Error, Result};
+use crate::tools::run;
+use std::path::Path;
+use std::process::Command;
+
+/// Offset of the superblock, following the boot sector
+const SUPERBLOCK: usize = 1024;
+
+/// Magic at the start of the superblock
+const MAGIC: u32 = 0xe0f5e1e2;
+
+/// Incompatible features marking compressed images. Images with
+/// configurable compressors list them in the superblock, older ones only
+/// use LZ4
+const ZERO_PADDING: u32 = 0x1;
+const COMPR_CFGS: u32 = 0x2;
+
+/// Settings of an image that are kept when it is repacked
+pub struct Superblock {
+    /// Creation time, in seconds since the Unix epoch
+    pub build_time: u64,
+    pub uuid: [u8; 16],
+    /// Compressor name as `mkfs.erofs -z` takes it, if the image is
+    /// compressed
+    pub compression: Option<&'static str>,
+}
+
+impl Superblock {
+    fn uuid(&self) -> String {
+        let hex: String = self.uuid.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("{}-{}-{}-{}-{}", &hex[.. 8], &hex[8 .. 12], &hex[12 .. 16],
+                &hex[16 .. 20], &hex[20 ..])
+    }
+}
+
+/// Reads the superblock of an EROFS image
+pub fn superblock(path: &Path) -> Result<Superblock> {
+    use std::io::Read;
+
+    let mut header = [0; SUPERBLOCK + 128];
+    std::fs::File::open(path).and_then(|mut file| file.read_exact(&mut header))
+        .map_err(|e| Error::Read { path: path.into(), source: e })?;
+    let sb = &header[SUPERBLOCK ..];
+    let u32_at = |offset: usize| {
+        u32::from_le_bytes(sb[offset .. offset + 4].try_into().unwrap())
+    };
+    if u32_at(0) != MAGIC {
+        return Err(Error::Image {
+            kind: "EROFS",
+            reason: "bad magic".to_string(),
+        }.in_file(path));
+    }
+
+    // Android images are compressed with LZ4HC unless configured otherwise
+    let features = u32_at(80);
+    let compression = if features & COMPR_CFGS != 0 {
+        let algorithms = u16::from_le_bytes([sb[84], sb[85]]);
+        [(0x1, "lz4hc"), (0x2, "lzma"), (0x4, "deflate"), (0x8, "zstd")].into_iter()
+            .find(|(bit, _)| algorithms & bit != 0)
+            .map(|(_, name)| name)
+    } else if features & ZERO_PADDING != 0 {
+        Some("lz4hc")
+    } else {
+        None
+    };
+    Ok(Superblock {
+        build_time: u64::from_le_bytes(sb[24 .. 32].try_into().unwrap()),
+        uuid: sb[48 .. 64].try_into().unwrap(),
+        compression,
+    })
+}
+
+/// Extracts an EROFS image, including the extended attributes holding
+/// SELinux labels, into a directory that does not exist yet
+pub fn extract(image: &Path, dir: &Path) -> Result<()> {
+    let mut extract = std::ffi::OsString::from("--extract=");
+    extract.push(dir);
+    run(Command::new("fsck.erofs").arg(extract).arg("--xattrs").arg(image))
+}
+
+/// Packs a directory into a new EROFS image with the settings of an existing
+/// image
+pub fn pack(dir: &Path, image: &Path, settings: &Superblock) -> Result<()> {
+    let mut command = Command::new("mkfs.erofs");
+    if let Some(compression) = settings.compression {
+        command.arg("-z").arg(compression);
+    }
+    run(command.arg("-T").arg(settings.build_time.to_string())
+            .arg("-U").arg(settings.uuid()).arg(image).arg(dir))
+}
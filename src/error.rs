@@ -0,0 +1,290 @@
+use crate::exit::ExitCode;
+use std::path::PathBuf;
+
+/// Errors surfaced by candycorn. Errors about the content of a file are
+/// wrapped in `InFile` to name the file they occurred in
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{}: {source}", path.display())]
+    InFile {
+        path: PathBuf,
+        #[source]
+        source: Box<Error>,
+    },
+
+    #[error("failed to read {}: {source}", path.display())]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to write {}: {source}", path.display())]
+    Write {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to parse ELF: {0}")]
+    Elf(#[from] goblin::error::Error),
+
+    #[error("`{section}` section not found")]
+    MissingSection { section: String },
+
+    #[error("`{section}` section at offset {offset:#x} is malformed: {reason}")]
+    MalformedSection {
+        section: String,
+        offset: usize,
+        reason: String,
+    },
+
+    #[error("symbol version \"{symbol}\" not found")]
+    MissingSymbol { symbol: String },
+
+    #[error("{count} target versions not found in source")]
+    MissingVersions { count: usize },
+
+    #[error("symbol versions differ, {only_target} only in target and \
+             {only_source} only in source")]
+    SymbolSetMismatch { only_target: usize, only_source: usize },
+
+    #[error("`.modinfo` entry \"{key}\" not found")]
+    MissingModInfo { key: String },
+
+    #[error("`.modinfo` entry \"{key}\" at offset {offset:#x}: value \"{value}\" \
+             is {} bytes but only {available} bytes are available", value.len())]
+    ModInfoTooLong {
+        key: String,
+        value: String,
+        offset: usize,
+        available: usize,
+    },
+
+    #[error("\"{symbol}\" at offset {offset:#x}: CRC {crc:#x} does not fit in \
+             {bits}-bit CRC field")]
+    CrcWidth {
+        symbol: String,
+        offset: usize,
+        crc: u64,
+        bits: usize,
+    },
+
+    #[error("module name \"{name}\" is longer than the {max} bytes `struct module` \
+             holds")]
+    ModuleNameTooLong { name: String, max: usize },
+
+    #[error("invalid CRC \"{value}\": {source}")]
+    InvalidCrc {
+        value: String,
+        #[source]
+        source: std::num::ParseIntError,
+    },
+
+    #[error("\"{symbol}\": {source}")]
+    Override {
+        symbol: String,
+        #[source]
+        source: Box<Error>,
+    },
+
+    #[error("SHA-256 {actual} does not match expected {expected}")]
+    ChecksumMismatch { expected: String, actual: String },
+
+    #[error("module is {actual} bytes, expected {expected} bytes")]
+    SizeMismatch { expected: usize, actual: usize },
+
+    #[error("bytes at offset {offset:#x} are {actual}, expected {expected}")]
+    PatchMismatch {
+        offset: usize,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("patched module failed verification: {reason}")]
+    Verify { reason: String },
+
+    #[error("hook script failed: {reason}")]
+    Hook { reason: String },
+
+    #[error("invalid patch file: {reason}")]
+    InvalidPatch { reason: String },
+
+    #[error("malformed cpio archive at offset {offset:#x}: {reason}")]
+    Archive { offset: usize, reason: String },
+
+    #[error("failed to decompress {format} data: {reason}")]
+    Decompress { format: &'static str, reason: String },
+
+    #[error("failed to compress {format} data: {reason}")]
+    Compress { format: &'static str, reason: String },
+
+    #[error("failed to run {program}: {reason}")]
+    Command { program: String, reason: String },
+
+    #[error("invalid TOML: {0}")]
+    Toml(#[from] toml::de::Error),
+
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("line {line}: {reason}")]
+    Symvers { line: usize, reason: String },
+
+    #[error("line {line}: {reason}")]
+    SystemMap { line: usize, reason: String },
+
+    #[error("invalid pattern: {0}")]
+    Pattern(#[from] glob::PatternError),
+
+    #[error("failed to create worker pool: {0}")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+
+    #[error("unsupported module: {reason}")]
+    Unsupported { reason: String },
+
+    #[error("being written by another candycorn process, which holds {}",
+             lock.display())]
+    Locked { lock: PathBuf },
+
+    #[error("not a kernel module: {reason}, use `--force` to patch it anyway")]
+    NotAModule { reason: String },
+
+    #[error("unsupported kernel image: {reason}")]
+    KernelImage { reason: String },
+
+    #[error("unable to fetch reference kernel {kernel}: {reason}")]
+    Mirror { kernel: String, reason: String },
+
+    #[error("CRC database {}: {reason}", path.display())]
+    Database { path: PathBuf, reason: String },
+
+    #[error("CRC lookup service {url}: {reason}")]
+    Lookup { url: String, reason: String },
+
+    #[error("invalid configuration: {reason}")]
+    Config { reason: String },
+
+    #[error("invalid manifest: {reason}")]
+    Manifest { reason: String },
+
+    #[error("malformed package: {reason}")]
+    Package { reason: String },
+
+    #[error("unsupported {kind} image: {reason}")]
+    Image { kind: &'static str, reason: String },
+
+    #[error("unable to determine running kernel vermagic")]
+    RunningVermagic,
+
+    #[error("unable to determine running kernel release")]
+    RunningRelease,
+
+    #[error("nothing to do, {reason}")]
+    NothingToDo { reason: String },
+
+    #[error("source {} is {donor_arch} but target is {arch}", donor.display())]
+    ArchMismatch {
+        donor: PathBuf,
+        donor_arch: String,
+        arch: String,
+    },
+
+    #[error("{failed} of {total} targets failed")]
+    TargetsFailed {
+        failed: usize,
+        total: usize,
+        code: ExitCode,
+    },
+
+    #[error("{failed} of {total} manifest entries failed")]
+    EntriesFailed {
+        failed: usize,
+        total: usize,
+        code: ExitCode,
+    },
+
+    #[error("{count} entries would cause insmod to fail")]
+    CheckFailed { count: usize },
+
+    #[error("{count} functions have differing types")]
+    AbiMismatch { count: usize },
+}
+
+impl From<candycorn_core::Error> for Error {
+    fn from(error: candycorn_core::Error) -> Error {
+        match error {
+            candycorn_core::Error::MissingSection { section } => {
+                Error::MissingSection { section }
+            },
+            candycorn_core::Error::MalformedSection { section, offset, reason } => {
+                Error::MalformedSection { section, offset, reason }
+            },
+        }
+    }
+}
+
+impl Error {
+    /// Wraps the error with the name of the file it occurred in
+    pub fn in_file(self, path: impl Into<PathBuf>) -> Error {
+        Error::InFile { path: path.into(), source: Box::new(self) }
+    }
+
+    /// Exit code the process should produce for the error
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            Error::InFile { source, .. } => source.exit_code(),
+            Error::Override { source, .. } => source.exit_code(),
+            Error::Read { .. } => ExitCode::Failure,
+            Error::Write { .. } => ExitCode::Write,
+            Error::Elf(_) => ExitCode::Parse,
+            Error::MissingSection { .. } => ExitCode::MissingSection,
+            Error::MalformedSection { .. } => ExitCode::Parse,
+            Error::MissingSymbol { .. } => ExitCode::MissingSymbol,
+            Error::MissingVersions { .. } => ExitCode::MissingSymbol,
+            Error::SymbolSetMismatch { .. } => ExitCode::MissingSymbol,
+            Error::MissingModInfo { .. } => ExitCode::MissingSymbol,
+            Error::ModInfoTooLong { .. } => ExitCode::Failure,
+            Error::CrcWidth { .. } => ExitCode::Failure,
+            Error::ModuleNameTooLong { .. } => ExitCode::Failure,
+            Error::InvalidCrc { .. } => ExitCode::Parse,
+            Error::ChecksumMismatch { .. } => ExitCode::Failure,
+            Error::SizeMismatch { .. } => ExitCode::Failure,
+            Error::PatchMismatch { .. } => ExitCode::Failure,
+            Error::Verify { .. } => ExitCode::Write,
+            Error::Hook { .. } => ExitCode::Parse,
+            Error::InvalidPatch { .. } => ExitCode::Parse,
+            Error::Archive { .. } => ExitCode::Parse,
+            Error::Decompress { .. } => ExitCode::Parse,
+            Error::Compress { .. } => ExitCode::Write,
+            Error::Command { .. } => ExitCode::Failure,
+            Error::Toml(_) => ExitCode::Parse,
+            Error::Json(_) => ExitCode::Parse,
+            Error::Symvers { .. } => ExitCode::Parse,
+            Error::SystemMap { .. } => ExitCode::Parse,
+            Error::Pattern(_) => ExitCode::Failure,
+            Error::ThreadPool(_) => ExitCode::Failure,
+            Error::Unsupported { .. } => ExitCode::Failure,
+            Error::NotAModule { .. } => ExitCode::Failure,
+            Error::Locked { .. } => ExitCode::Failure,
+            Error::Mirror { .. } => ExitCode::Failure,
+            Error::Database { .. } => ExitCode::Failure,
+            Error::Lookup { .. } => ExitCode::Failure,
+            Error::Config { .. } => ExitCode::Usage,
+            Error::Manifest { .. } => ExitCode::Parse,
+            Error::Package { .. } => ExitCode::Parse,
+            Error::Image { .. } => ExitCode::Parse,
+            Error::KernelImage { .. } => ExitCode::Failure,
+            Error::RunningVermagic => ExitCode::Failure,
+            Error::RunningRelease => ExitCode::Failure,
+            Error::NothingToDo { .. } => ExitCode::NothingToDo,
+            Error::ArchMismatch { .. } => ExitCode::Failure,
+            Error::TargetsFailed { code, .. } => *code,
+            Error::EntriesFailed { code, .. } => *code,
+            Error::CheckFailed { .. } => ExitCode::CheckFailed,
+            Error::AbiMismatch { .. } => ExitCode::CheckFailed,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,45 @@
+/// Process exit codes. Scripts may branch on these so existing values must
+/// not change. Success and usage errors are not produced through `exit` so
+/// are only listed for completeness
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitCode {
+    Success = 0,
+    /// Error not covered by a more specific code, such as failing to read a
+    /// file
+    Failure = 1,
+    /// Invalid command line, reported by clap
+    Usage = 2,
+    /// Input file could not be parsed
+    Parse = 3,
+    /// Required ELF section is missing
+    MissingSection = 4,
+    /// Required symbol version or `.modinfo` entry is missing
+    MissingSymbol = 5,
+    /// Output could not be written or failed verification
+    Write = 6,
+    /// Targets did not need to be patched
+    NothingToDo = 7,
+    /// Target would be rejected by the running kernel
+    CheckFailed = 8,
+}
+
+/// Exit code documentation appended to `--help`
+pub const HELP: &str = "EXIT CODES:
+    0    Success
+    1    General failure
+    2    Invalid command line
+    3    Input file could not be parsed
+    4    Required ELF section is missing
+    5    Required symbol version or `.modinfo` entry is missing
+    6    Output could not be written or failed verification
+    7    Nothing to do, no target needed to be patched
+    8    Target would be rejected by the running kernel
+
+When patching multiple targets and targets fail with different codes, 1 is \
+used.";
+
+/// Exits the process with the provided code
+pub fn exit(code: ExitCode) -> ! {
+    std::process::exit(code as i32)
+}
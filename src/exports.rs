@@ -0,0 +1,266 @@
+use crate::error::{Error, Result};
+use crate::{find_section, section_data};
+use goblin::elf::header::ET_REL;
+use goblin::elf::section_header::{SectionHeader, SHF_ALLOC, SHN_ABS, SHT_NOBITS,
+                                  SHT_REL, SHT_RELA};
+use goblin::elf::sym::Sym;
+use scroll::Pread;
+use std::collections::{HashMap, HashSet};
+
+/// Export tables and the CRC tables listing the CRCs of their symbols in the
+/// same order
+const TABLES: &[(&str, &str)] = &[
+    ("__ksymtab", "__kcrctab"),
+    ("__ksymtab_gpl", "__kcrctab_gpl"),
+];
+
+/// Layout of the entries of an export table and its CRC table
+struct EntryLayout {
+    count: usize,
+    /// Size of a `struct kernel_symbol`
+    entry_size: usize,
+    /// Size of a CRC
+    crc_size: usize,
+    /// Entries hold 32-bit offsets relative to themselves rather than
+    /// pointers (`CONFIG_HAVE_ARCH_PREL32_RELOCATIONS`)
+    prel32: bool,
+}
+
+impl EntryLayout {
+    /// Derives the layout from the sizes of the tables. The number of entries
+    /// is the same in both tables, and entries are either pointers or 32-bit
+    /// offsets to the symbol's value, name and, in newer kernels, namespace
+    fn detect(ksymtab_len: usize, kcrctab_len: usize, is_64: bool)
+        -> Option<EntryLayout> {
+
+        let crc_sizes: &[usize] = if is_64 { &[4, 8] } else { &[4] };
+        for &crc_size in crc_sizes {
+            if kcrctab_len == 0 || !kcrctab_len.is_multiple_of(crc_size) {
+                continue;
+            }
+            let count = kcrctab_len / crc_size;
+            if !ksymtab_len.is_multiple_of(count) {
+                continue;
+            }
+            let entry_size = ksymtab_len / count;
+            let prel32 = match (entry_size, is_64) {
+                (8 | 12, true) => true,
+                (16 | 24, true) | (8 | 12, false) => false,
+                _ => continue,
+            };
+            return Some(EntryLayout { count, entry_size, crc_size, prel32 });
+        }
+        None
+    }
+}
+
+/// Section of a kernel image or module along with the relocations applying
+/// to it. Linked images hold the final value of each field in place while
+/// relocatable modules leave most fields to relocations
+struct Fields<'a> {
+    sh: &'a SectionHeader,
+    data: &'a [u8],
+    /// Symbol and explicit addend of the relocation of each field, by offset
+    /// within the section
+    relocs: HashMap<u64, (Sym, Option<i64>)>,
+    endian: scroll::Endian,
+}
+
+impl<'a> Fields<'a> {
+    fn new(info: &goblin::elf::Elf, data: &'a [u8], sh: &'a SectionHeader,
+           name: &str) -> Result<Fields<'a>> {
+
+        let ctx = goblin::container::Ctx::new(info.header.container()?,
+                                              info.header.endianness()?);
+        let target = info.section_headers.iter()
+                        .position(|s| std::ptr::eq(s, sh));
+
+        let mut relocs = HashMap::new();
+        for rel_sh in &info.section_headers {
+            let is_rela = rel_sh.sh_type == SHT_RELA;
+            if !is_rela && rel_sh.sh_type != SHT_REL
+                || Some(rel_sh.sh_info as usize) != target {
+                continue;
+            }
+            let symtab_sh = match info.section_headers.get(rel_sh.sh_link as usize) {
+                Some(symtab_sh) => symtab_sh,
+                None => continue,
+            };
+            let sym_data = section_data(symtab_sh, ".symtab", data)?;
+            let syms = goblin::elf::Symtab::parse(
+                sym_data, 0, sym_data.len() / Sym::size(ctx.container), ctx)?;
+            let rel_data = section_data(rel_sh, name, data)?;
+            let section = goblin::elf::RelocSection::parse(
+                rel_data, 0, rel_data.len(), is_rela, ctx)?;
+            for reloc in section.iter() {
+                if let Some(sym) = syms.get(reloc.r_sym) {
+                    relocs.insert(reloc.r_offset, (sym, reloc.r_addend));
+                }
+            }
+        }
+
+        Ok(Fields {
+            sh,
+            data: section_data(sh, name, data)?,
+            relocs,
+            endian: ctx.le,
+        })
+    }
+
+    /// Reads the value held in place by a field of 4 or 8 bytes, sign
+    /// extending 4 byte values if requested
+    fn raw(&self, offset: usize, size: usize, signed: bool) -> Option<u64> {
+        if size == 8 {
+            return self.data.pread_with::<u64>(offset, self.endian).ok();
+        }
+        let value: u32 = self.data.pread_with(offset, self.endian).ok()?;
+        Some(if signed { value as i32 as i64 as u64 } else { value as u64 })
+    }
+
+    /// Resolves a pointer, or with `relative` an offset relative to the field
+    /// itself, to the file offset of the data it points to
+    fn target(&self, info: &goblin::elf::Elf, offset: usize, size: usize,
+              relative: bool) -> Option<usize> {
+
+        if let Some((sym, addend)) = self.relocs.get(&(offset as u64)) {
+            // Relative relocations are computed against the field, so the
+            // symbol and addend alone give the target
+            let addend = addend.or_else(|| {
+                Some(self.raw(offset, size, relative)? as i64)
+            })?;
+            let sh = info.section_headers.get(sym.st_shndx)?;
+            let target = sh.sh_offset.checked_add(sym.st_value)?
+                            .checked_add_signed(addend)?;
+            return usize::try_from(target).ok();
+        }
+        if info.header.e_type == ET_REL {
+            return None;
+        }
+
+        let value = self.raw(offset, size, relative)?;
+        let addr = if relative {
+            (self.sh.sh_addr + offset as u64).wrapping_add(value)
+        } else {
+            value
+        };
+        file_offset(info, addr)
+    }
+
+    /// Reads a CRC. Relocatable modules of kernels using absolute CRCs carry
+    /// the CRC in the value of the absolute `__crc_` symbol a field is
+    /// relocated against
+    fn crc(&self, offset: usize, size: usize) -> Option<u64> {
+        match self.relocs.get(&(offset as u64)) {
+            Some((sym, addend)) if sym.st_shndx == SHN_ABS as usize => {
+                let addend = addend.unwrap_or(self.raw(offset, size, false)? as i64);
+                Some(sym.st_value.wrapping_add_signed(addend))
+            },
+            Some(_) => None,
+            None => self.raw(offset, size, false),
+        }
+    }
+}
+
+/// Converts a virtual address to a file offset using the section containing
+/// the address
+fn file_offset(info: &goblin::elf::Elf, addr: u64) -> Option<usize> {
+    let sh = info.section_headers.iter().find(|sh| {
+        sh.sh_flags & SHF_ALLOC as u64 != 0 && sh.sh_type != SHT_NOBITS
+            && sh.sh_addr <= addr && addr - sh.sh_addr < sh.sh_size
+    })?;
+    usize::try_from(sh.sh_offset + (addr - sh.sh_addr)).ok()
+}
+
+/// Reads a null terminated string at a file offset
+fn str_at(data: &[u8], offset: usize) -> Option<String> {
+    let bytes = data.get(offset ..)?;
+    let len = bytes.iter().position(|&b| b == 0)?;
+    String::from_utf8(bytes[.. len].to_vec()).ok()
+}
+
+/// Symbol exported by a kernel image or module
+struct Export {
+    name: String,
+    crc: Option<u64>,
+    namespace: Option<String>,
+    /// Exported with `EXPORT_SYMBOL_GPL`
+    gpl_only: bool,
+}
+
+/// Reads the entries of the `__ksymtab` export tables of a kernel image or
+/// module along with their CRCs from the `__kcrctab` tables alongside them.
+/// Relocations of relocatable modules are resolved
+fn exports(info: &goblin::elf::Elf, data: &[u8]) -> Result<Vec<Export>> {
+    let mut exports = Vec::new();
+    for (ksymtab_name, kcrctab_name) in TABLES {
+        let (ksymtab, kcrctab) = match (find_section(info, ksymtab_name),
+                                        find_section(info, kcrctab_name)) {
+            (Some(ksymtab), Some(kcrctab)) => (ksymtab, kcrctab),
+            _ => continue,
+        };
+        let ksymtab = Fields::new(info, data, ksymtab, ksymtab_name)?;
+        let kcrctab = Fields::new(info, data, kcrctab, kcrctab_name)?;
+        let layout = EntryLayout::detect(ksymtab.data.len(), kcrctab.data.len(),
+                                         info.is_64)
+            .ok_or_else(|| Error::MalformedSection {
+                section: ksymtab_name.to_string(),
+                offset: ksymtab.sh.sh_offset as usize,
+                reason: format!("size {:#x} does not match `{}` size {:#x}",
+                                ksymtab.data.len(), kcrctab_name,
+                                kcrctab.data.len()),
+            })?;
+
+        // The name follows the symbol's value, and the namespace the name in
+        // kernels with namespaces
+        let field_size = if layout.prel32 { 4 } else if info.is_64 { 8 } else { 4 };
+        let has_namespace = layout.entry_size == 3 * field_size;
+        for idx in 0 .. layout.count {
+            let name_field = idx * layout.entry_size + field_size;
+            let name = match ksymtab.target(info, name_field, field_size,
+                                            layout.prel32)
+                            .and_then(|offset| str_at(data, offset)) {
+                Some(name) => name,
+                None => continue,
+            };
+            let namespace = has_namespace.then(|| {
+                ksymtab.target(info, name_field + field_size, field_size,
+                               layout.prel32)
+                    .and_then(|offset| str_at(data, offset))
+            }).flatten().filter(|ns| !ns.is_empty());
+            exports.push(Export {
+                name,
+                crc: kcrctab.crc(idx * layout.crc_size, layout.crc_size),
+                namespace,
+                gpl_only: *ksymtab_name == "__ksymtab_gpl",
+            });
+        }
+    }
+    Ok(exports)
+}
+
+/// Collects the CRCs of the symbols exported by a kernel image or module from
+/// its export tables. Returns an empty map if nothing versioned is exported
+pub fn crcs(info: &goblin::elf::Elf, data: &[u8]) -> Result<HashMap<String, u64>> {
+    Ok(exports(info, data)?.into_iter()
+        .filter_map(|e| Some((e.name, e.crc?)))
+        .collect())
+}
+
+/// Collects the namespaces of the symbols exported by a kernel image or
+/// module into namespaces from its export tables
+pub fn namespaces(info: &goblin::elf::Elf, data: &[u8])
+    -> Result<HashMap<String, String>> {
+
+    Ok(exports(info, data)?.into_iter()
+        .filter_map(|e| Some((e.name, e.namespace?)))
+        .collect())
+}
+
+/// Collects the symbols a kernel image or module exports to GPL-compatible
+/// modules only, from its `__ksymtab_gpl` export table
+pub fn gpl_only(info: &goblin::elf::Elf, data: &[u8]) -> Result<HashSet<String>> {
+    Ok(exports(info, data)?.into_iter()
+        .filter(|e| e.gpl_only)
+        .map(|e| e.name)
+        .collect())
+}
@@ -0,0 +1,44 @@
+/// Pattern matching symbol names. Glob patterns must match the whole name
+/// while regular expressions, given with a `re:` prefix, may match any part
+#[derive(Clone, Debug)]
+pub enum Pattern {
+    Glob(glob::Pattern),
+    Regex(regex::Regex),
+    /// Name matched as is, for names not given by the user
+    Exact(String),
+}
+
+impl Pattern {
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            Pattern::Glob(pattern) => pattern.matches(name),
+            Pattern::Regex(regex) => regex.is_match(name),
+            Pattern::Exact(exact) => exact == name,
+        }
+    }
+}
+
+/// Parses a glob pattern, or a regular expression with a `re:` prefix
+pub fn parse_pattern(s: &str) -> std::result::Result<Pattern, String> {
+    match s.strip_prefix("re:") {
+        Some(regex) => regex::Regex::new(regex).map(Pattern::Regex)
+                        .map_err(|e| e.to_string()),
+        None => glob::Pattern::new(s).map(Pattern::Glob)
+                    .map_err(|e| e.to_string()),
+    }
+}
+
+/// Selects symbols by name. Without `only` patterns all symbols not excluded
+/// are selected
+#[derive(Default)]
+pub struct SymbolFilter {
+    pub only: Vec<Pattern>,
+    pub exclude: Vec<Pattern>,
+}
+
+impl SymbolFilter {
+    pub fn matches(&self, name: &str) -> bool {
+        (self.only.is_empty() || self.only.iter().any(|p| p.matches(name)))
+            && !self.exclude.iter().any(|p| p.matches(name))
+    }
+}
@@ -0,0 +1,48 @@
+use crate::color::{self, Color, Stream};
+use std::fmt::Write;
+
+/// Bytes shown per line
+const LINE_LEN: usize = 16;
+
+/// Formats one line of a hexdump, coloring the bytes that differ from `other`
+fn line(stream: Stream, offset: usize, bytes: &[u8], other: &[u8], prefix: char,
+        color: Color) -> String {
+
+    let mut out = format!("{}{:08x}: ", prefix, offset);
+    for idx in 0 .. LINE_LEN {
+        let hex = match bytes.get(idx) {
+            Some(byte) => format!("{:02x}", byte),
+            None => "  ".to_string(),
+        };
+        if bytes.get(idx) != other.get(idx) {
+            out.push_str(&color::paint(stream, color, hex));
+        } else {
+            out.push_str(&hex);
+        }
+        out.push(' ');
+    }
+    let ascii: String = bytes.iter()
+        .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
+        .collect();
+    let _ = write!(out, " {}", ascii);
+    out
+}
+
+/// Formats a region of a file before and after patching as a hexdump, in
+/// the style of a unified diff. Lines that changed are shown before, marked
+/// `-`, and after, marked `+`, with the changed bytes colored. Other lines
+/// are shown once
+pub fn diff(stream: Stream, offset: usize, before: &[u8], after: &[u8]) -> String {
+    let mut out = Vec::new();
+    for (idx, (old, new)) in before.chunks(LINE_LEN).zip(after.chunks(LINE_LEN))
+                                .enumerate() {
+        let offset = offset + idx * LINE_LEN;
+        if old == new {
+            out.push(line(stream, offset, old, new, ' ', Color::Red));
+        } else {
+            out.push(line(stream, offset, old, new, '-', Color::Red));
+            out.push(line(stream, offset, new, old, '+', Color::Green));
+        }
+    }
+    out.join("\n")
+}
@@ -0,0 +1,161 @@
+use crate::error::{Error, Result};
+use log::{debug, info};
+use rhai::{Array, Dynamic, Engine, Map, Scope, INT};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// Versioned symbol of a target, as passed to a hook
+pub struct Symbol<'a> {
+    pub name: &'a str,
+    /// CRC the target was built with
+    pub target_crc: u64,
+    /// CRC found in the sources, if any
+    pub source_crc: Option<u64>,
+    /// CRC about to be written, after the sources and overrides
+    pub crc: u64,
+}
+
+/// Input of a hook for a target
+pub struct Input<'a> {
+    pub target: String,
+    /// Values of the target's `.modinfo` entries by key, in order
+    pub modinfo: BTreeMap<&'a str, Vec<&'a str>>,
+    pub symbols: Vec<Symbol<'a>>,
+}
+
+/// What a hook decided for a symbol
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    /// Write this CRC
+    Crc(u64),
+    /// Leave the target's CRC untouched
+    Skip,
+}
+
+/// CRC as a script integer. 64-bit CRCs above `INT::MAX` come out negative
+/// and are read back unchanged
+fn crc_value(crc: u64) -> Dynamic {
+    Dynamic::from_int(crc as INT)
+}
+
+/// Builds the scope a hook script runs in for a symbol, with the symbol's
+/// fields set. `source_crc` is `()` when the sources do not have the symbol
+fn scope(input: &Input, modinfo: &Map, symbol: &Symbol) -> Scope<'static> {
+    let mut scope = Scope::new();
+    scope.push_constant("target", input.target.clone());
+    scope.push_constant("modinfo", modinfo.clone());
+    scope.push_constant("name", symbol.name.to_string());
+    scope.push_constant("target_crc", crc_value(symbol.target_crc));
+    scope.push_constant("source_crc", symbol.source_crc.map_or(Dynamic::UNIT, crc_value));
+    scope.push_constant("crc", crc_value(symbol.crc));
+    scope
+}
+
+/// Reads the decision of a hook for a symbol: a CRC, `"skip"`, or `()` to
+/// keep the CRC about to be written
+fn decision(name: &str, value: Dynamic) -> Result<Option<Decision>> {
+    if value.is_unit() {
+        return Ok(None);
+    }
+    if let Ok(crc) = value.as_int() {
+        return Ok(Some(Decision::Crc(crc as u64)));
+    }
+    match value.into_string() {
+        Ok(s) if s == "skip" => Ok(Some(Decision::Skip)),
+        Ok(s) => crate::parse_crc(&s, false).map(|crc| Some(Decision::Crc(crc)))
+                    .map_err(|e| Error::Override {
+                        symbol: name.to_string(),
+                        source: Box::new(e),
+                    }),
+        Err(kind) => Err(Error::Hook {
+            reason: format!("expected a CRC or \"skip\" for \"{}\", got {}", name, kind),
+        }),
+    }
+}
+
+/// Runs a hook script for each of the target's symbols, returning its
+/// decisions by symbol name. The script is run with `name`, `target_crc`,
+/// `source_crc`, `crc`, `modinfo` and `target` set and evaluates to a CRC or
+/// `"skip"`, or to `()` to keep the CRC about to be written
+///
+/// Example script:
+/// ```rhai
+/// if name == "printk" { 0xdeadbeef } else if source_crc == () { "skip" }
+/// ```
+pub fn run(hook: &Path, input: &Input) -> Result<HashMap<String, Decision>> {
+    let script = std::fs::read_to_string(hook)
+        .map_err(|e| Error::Read { path: hook.to_path_buf(), source: e })?;
+
+    // Output of `print` and `debug` goes to the log, keeping stdout to the
+    // command's output
+    let mut engine = Engine::new();
+    engine.on_print(|text| info!("{}", text));
+    engine.on_debug(|text, _, pos| debug!("{}: {}", pos, text));
+    let ast = engine.compile(&script).map_err(|e| Error::Hook { reason: e.to_string() })?;
+
+    let modinfo: Map = input.modinfo.iter().map(|(&key, values)| {
+        let values: Array = values.iter().map(|&value| value.into()).collect();
+        (key.into(), values.into())
+    }).collect();
+    let mut decisions = HashMap::new();
+    for symbol in &input.symbols {
+        let value = engine.eval_ast_with_scope(&mut scope(input, &modinfo, symbol), &ast)
+            .map_err(|e| Error::Hook {
+                reason: format!("for \"{}\": {}", symbol.name, e),
+            })?;
+        if let Some(decision) = decision(symbol.name, value)? {
+            decisions.insert(symbol.name.to_string(), decision);
+        }
+    }
+    Ok(decisions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decisions_are_read_from_values() {
+        let decide = |value: Dynamic| decision("printk", value);
+        assert_eq!(decide(Dynamic::from_int(0x12)).unwrap(), Some(Decision::Crc(0x12)));
+        assert_eq!(decide(Dynamic::from_int(-1)).unwrap(), Some(Decision::Crc(u64::MAX)));
+        assert_eq!(decide("0xdeadbeef".into()).unwrap(), Some(Decision::Crc(0xdeadbeef)));
+        assert_eq!(decide("42".into()).unwrap(), Some(Decision::Crc(42)));
+        assert_eq!(decide("skip".into()).unwrap(), Some(Decision::Skip));
+        assert_eq!(decide(Dynamic::UNIT).unwrap(), None);
+        assert!(matches!(decide("0xnope".into()), Err(Error::Override { .. })));
+        assert!(matches!(decide(Dynamic::from_bool(true)), Err(Error::Hook { .. })));
+        assert!(matches!(decide(Array::new().into()), Err(Error::Hook { .. })));
+    }
+
+    #[test]
+    fn script_runs_once_per_symbol() {
+        let dir = crate::tempdir::create("test").unwrap();
+        let hook = dir.path().join("rules.rhai");
+        std::fs::write(&hook, r#"
+            if modinfo.board[0] != "rev-a" { return; }
+            if name == "printk" { "0xaa" }
+            else if source_crc == () { target_crc + 1 }
+            else if name == "gpio_set" { "skip" }
+        "#).unwrap();
+
+        let symbol = |name, source_crc| Symbol { name, target_crc: 0x10, source_crc,
+                                                 crc: 0x20 };
+        let input = Input {
+            target: "target.ko".to_string(),
+            modinfo: BTreeMap::from([("board", vec!["rev-a"])]),
+            symbols: vec![symbol("printk", Some(0x30)), symbol("vendor_init", None),
+                          symbol("gpio_set", Some(0x30)), symbol("kfree", Some(0x30))],
+        };
+        let decisions = run(&hook, &input).unwrap();
+        assert_eq!(decisions, HashMap::from([
+            ("printk".to_string(), Decision::Crc(0xaa)),
+            ("vendor_init".to_string(), Decision::Crc(0x11)),
+            ("gpio_set".to_string(), Decision::Skip),
+        ]));
+
+        let modinfo = BTreeMap::from([("board", vec!["rev-b"])]);
+        let input = Input { modinfo, ..input };
+        assert!(run(&hook, &input).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,47 @@
+use crate::error::{Error, Result};
+use std::path::{Path, PathBuf};
+
+/// Modules of a Kbuild output tree and the symbols they export to each other
+pub struct Tree {
+    /// Modules in build order
+    pub modules: Vec<PathBuf>,
+    /// Symbols exported by the tree's modules rather than by the kernel
+    pub exports: Vec<String>,
+}
+
+/// Reads the modules a Kbuild output tree built from its `modules.order`,
+/// and the symbols they export from its `Module.symvers`. Kernels since 6.2
+/// list objects rather than modules, and kernels before 2.6.30 prefixed
+/// paths with `kernel/`
+pub fn read(dir: &Path) -> Result<Tree> {
+    let order_path = dir.join("modules.order");
+    let order = std::fs::read_to_string(&order_path)
+                    .map_err(|e| Error::Read { path: order_path.clone(), source: e })?;
+    let modules = order.lines().map(str::trim).filter(|line| !line.is_empty())
+        .map(|line| {
+            let line = match line.strip_suffix(".o") {
+                Some(object) => format!("{}.ko", object),
+                None => line.to_string(),
+            };
+            let path = dir.join(&line);
+            match line.strip_prefix("kernel/") {
+                Some(stripped) if !path.exists() => dir.join(stripped),
+                _ => path,
+            }
+        })
+        .collect();
+
+    // Trees built without modules exporting symbols may have no
+    // `Module.symvers`
+    let symvers_path = dir.join("Module.symvers");
+    let exports = if symvers_path.exists() {
+        crate::symvers::load_exports(&symvers_path)?.modules.into_iter()
+            .filter(|(_, module)| module != "vmlinux")
+            .map(|(name, _)| name)
+            .collect()
+    } else {
+        log::warn!("No `Module.symvers` in {}", dir.display());
+        Vec::new()
+    };
+    Ok(Tree { modules, exports })
+}
@@ -0,0 +1,65 @@
+use crate::compress::{self, Format};
+use crate::error::{Error, Result};
+
+/// Magic of the x86 boot protocol setup header
+const BOOT_MAGIC: &[u8] = b"HdrS";
+
+/// Locates the compressed kernel within an x86 `bzImage` using the boot
+/// protocol's setup header
+fn bzimage_payload(data: &[u8]) -> Option<&[u8]> {
+    if data.get(0x202 .. 0x206)? != BOOT_MAGIC {
+        return None;
+    }
+    let read_u32 = |offset: usize| -> Option<usize> {
+        let bytes = data.get(offset .. offset + 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().ok()?) as usize)
+    };
+
+    // Protected mode code follows the boot sector and setup sectors. A count
+    // of 0 means 4 setup sectors
+    let setup_sects = match *data.get(0x1f1)? {
+        0 => 4,
+        sects => sects as usize,
+    };
+    let start = (setup_sects + 1) * 512 + read_u32(0x248)?;
+    data.get(start .. start + read_u32(0x24c)?)
+}
+
+/// Finds offsets of the magic of each supported compression format, in file
+/// order
+fn candidates(data: &[u8]) -> impl Iterator<Item = (usize, Format)> + '_ {
+    (0 .. data.len()).filter_map(|idx| Some((idx, Format::detect(&data[idx ..])?)))
+}
+
+/// Extracts the ELF kernel from an x86 `bzImage` or a compressed `vmlinux`.
+/// The compressed kernel is located through the `bzImage` setup header if
+/// present, otherwise the first stream of a supported format that
+/// decompresses to an ELF is used. ARM `zImage` and arm64 `Image.gz` hold a
+/// raw binary kernel and are rejected
+pub fn unwrap(data: &[u8]) -> Result<Vec<u8>> {
+    if let Some(payload) = bzimage_payload(data) {
+        if let Some(format) = Format::detect(payload) {
+            let kernel = compress::decompress_stream(format, payload)?;
+            if is_elf(&kernel) {
+                return Ok(kernel);
+            }
+        }
+    }
+
+    for (offset, format) in candidates(data) {
+        match compress::decompress_stream(format, &data[offset ..]) {
+            Ok(kernel) if is_elf(&kernel) => return Ok(kernel),
+            _ => {},
+        }
+    }
+    Err(Error::KernelImage {
+        reason: "no ELF kernel found, raw binary kernels such as those of ARM \
+                 `zImage` and arm64 `Image.gz` are not supported".to_string(),
+    })
+}
+
+/// Checks if a decompressed kernel is an ELF. Raw binary kernels carry no
+/// section headers to locate the export tables or symbols with
+fn is_elf(kernel: &[u8]) -> bool {
+    kernel.starts_with(goblin::elf::header::ELFMAG)
+}
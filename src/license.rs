@@ -0,0 +1,34 @@
+use std::collections::HashSet;
+
+/// Licenses the kernel treats as GPL-compatible, as `license_is_gpl_compatible`
+/// lists them
+const GPL_COMPATIBLE: &[&str] = &[
+    "GPL",
+    "GPL v2",
+    "GPL and additional rights",
+    "Dual BSD/GPL",
+    "Dual MIT/GPL",
+    "Dual MPL/GPL",
+];
+
+/// Returns whether the kernel lets a module with the license use GPL-only
+/// symbols
+pub fn is_gpl_compatible(license: &str) -> bool {
+    GPL_COMPATIBLE.contains(&license)
+}
+
+/// Finds the GPL-only symbols a module imports that the kernel refuses it
+/// because of its license. Modules without a license are treated as
+/// proprietary
+///
+/// # Arguments
+/// * `imports` - Symbols the module imports
+/// * `gpl_only` - Symbols exported with `EXPORT_SYMBOL_GPL`
+/// * `license` - Value of the module's `license` `.modinfo` entry
+pub fn refused_imports<'a>(imports: &'a [String], gpl_only: &HashSet<String>,
+                           license: Option<&str>) -> Vec<&'a str> {
+    if license.is_some_and(is_gpl_compatible) {
+        return Vec::new();
+    }
+    imports.iter().filter(|name| gpl_only.contains(*name)).map(String::as_str).collect()
+}
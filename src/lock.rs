@@ -0,0 +1,86 @@
+use crate::error::{Error, Result};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// Advisory lock on a file being written over, held until dropped. The lock
+/// is taken on a lock file next to the file, `.<name>.candycorn.lock`, as the
+/// file itself is replaced by writing it
+pub struct Lock {
+    path: PathBuf,
+    _file: std::fs::File,
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // Removed while still locked, so a process waiting on the removed
+        // file sees it was replaced
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Locks a file against other candycorn processes writing it, failing
+/// rather than waiting if another process holds the lock. The lock is taken
+/// next to the file symlinks resolve to, so every path naming the file
+/// shares it
+pub fn acquire(path: &Path) -> Result<Lock> {
+    let path = &std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".candycorn.lock");
+    let lock_path = path.with_file_name(name);
+
+    let write_err = |e| Error::Write { path: lock_path.clone(), source: e };
+    loop {
+        let file = std::fs::OpenOptions::new().write(true).create(true)
+                    .truncate(false).open(&lock_path).map_err(write_err)?;
+        // SAFETY: The descriptor belongs to `file`, which stays open for the
+        // call. `flock` takes no pointers, and the lock is released when the
+        // descriptor is closed
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let e = std::io::Error::last_os_error();
+            if e.raw_os_error() == Some(libc::EWOULDBLOCK) {
+                return Err(Error::Locked { lock: lock_path }.in_file(path));
+            }
+            return Err(write_err(e));
+        }
+
+        // The holder before may have removed the lock file after it was
+        // opened, in which case the lock is taken again on a new one
+        let locked = file.metadata().map_err(write_err)?;
+        let same = |current: std::fs::Metadata| {
+            current.dev() == locked.dev() && current.ino() == locked.ino()
+        };
+        if std::fs::metadata(&lock_path).is_ok_and(same) {
+            return Ok(Lock { path: lock_path, _file: file });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn second_acquire_fails_until_released() {
+        let dir = crate::tempdir::create("test").unwrap();
+        let path = dir.path().join("m.ko");
+        std::fs::write(&path, b"module").unwrap();
+        let link = dir.path().join("link.ko");
+        std::os::unix::fs::symlink(&path, &link).unwrap();
+
+        let lock = acquire(&path).unwrap();
+        let other_names = [path.clone(), dir.path().join(".").join("m.ko"), link];
+        for name in &other_names {
+            match acquire(name) {
+                Err(Error::InFile { source, .. }) => {
+                    assert!(matches!(*source, Error::Locked { .. }));
+                },
+                _ => panic!("{} acquired twice", name.display()),
+            }
+        }
+
+        drop(lock);
+        assert!(acquire(&path).is_ok());
+    }
+}
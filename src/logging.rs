@@ -0,0 +1,103 @@
+use crate::color::{self, Color, Stream};
+use log::{Level, LevelFilter, Metadata, Record};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Set when the patched module is written to stdout, in which case
+/// informational output is suppressed
+pub static STDOUT_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+/// Set by `--porcelain`, in which case informational messages go to stderr
+/// so stdout only carries the requested output
+pub static PORCELAIN: AtomicBool = AtomicBool::new(false);
+
+/// Set by `--quiet`, in which case only errors are printed even while
+/// warnings are captured
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Warnings logged since `capture` was called, for patch reports
+static CAPTURED: Mutex<Option<Vec<String>>> = Mutex::new(None);
+
+/// Prints informational messages to stdout, or stderr with `--porcelain`,
+/// and everything else to stderr, prefixed with its level. Progress bars are
+/// kept below the messages
+struct Logger;
+
+static LOGGER: Logger = Logger;
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if record.level() == Level::Warn {
+            if let Some(captured) = CAPTURED.lock().unwrap().as_mut() {
+                captured.push(record.args().to_string());
+            }
+            if QUIET.load(Ordering::Relaxed) {
+                return;
+            }
+        }
+        crate::progress::suspend(|| match record.level() {
+            Level::Error => eprintln!("{} {}", color::paint(Stream::Stderr, Color::Red,
+                                                            "ERROR:"),
+                                      record.args()),
+            Level::Warn => eprintln!("{} {}", color::paint(Stream::Stderr, Color::Yellow,
+                                                           "WARNING:"),
+                                     record.args()),
+            Level::Info if PORCELAIN.load(Ordering::Relaxed) => {
+                eprintln!("{}", record.args());
+            },
+            Level::Info if STDOUT_OUTPUT.load(Ordering::Relaxed) => {},
+            Level::Info => println!("{}", record.args()),
+            Level::Debug => eprintln!("DEBUG: {}", record.args()),
+            Level::Trace => eprintln!("TRACE: {}", record.args()),
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+/// Stream informational messages are written to
+pub fn info_stream() -> Stream {
+    if PORCELAIN.load(Ordering::Relaxed) { Stream::Stderr } else { Stream::Stdout }
+}
+
+/// Installs the logger, logging informational messages and above until
+/// `set_verbosity` is called
+pub fn init() {
+    // Only fails if a logger is already installed
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(LevelFilter::Info);
+}
+
+/// Sets the level of messages logged: errors only if `quiet`, otherwise
+/// informational messages and above, debug messages with one `-v` and trace
+/// messages with more
+pub fn set_verbosity(verbose: u64, quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+    log::set_max_level(match (quiet, verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    });
+}
+
+/// Starts keeping warnings, logging them even with `--quiet` so they can be
+/// reported
+pub fn capture() {
+    *CAPTURED.lock().unwrap() = Some(Vec::new());
+    if log::max_level() < LevelFilter::Warn {
+        log::set_max_level(LevelFilter::Warn);
+    }
+}
+
+/// Returns the warnings kept since `capture` was called
+pub fn captured() -> Vec<String> {
+    CAPTURED.lock().unwrap().clone().unwrap_or_default()
+}
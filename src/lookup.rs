@@ -0,0 +1,269 @@
+use crate::db;
+use crate::error::{Error, Result};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// Largest request head read, requests carry no body
+const MAX_REQUEST: usize = 8192;
+
+/// Symbol version as the service returns it
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Version {
+    crc: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    namespace: Option<String>,
+}
+
+fn lookup_error(url: &str, reason: impl Into<String>) -> Error {
+    Error::Lookup { url: url.to_string(), reason: reason.into() }
+}
+
+/// Percent-encodes a path segment
+fn encode(segment: &str) -> String {
+    segment.bytes().map(|b| match b {
+        b'A' ..= b'Z' | b'a' ..= b'z' | b'0' ..= b'9' | b'-' | b'.' | b'_' | b'~' => {
+            (b as char).to_string()
+        },
+        _ => format!("%{:02X}", b),
+    }).collect()
+}
+
+/// Decodes a percent-encoded path segment
+fn decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        if bytes[idx] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(idx + 1 .. idx + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            idx += 3;
+        } else {
+            decoded.push(bytes[idx]);
+            idx += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Fetches the symbol versions of a reference kernel from a lookup service
+/// with `curl`
+pub fn fetch(url: &str, kernel: &str) -> Result<db::KernelVersions> {
+    let url = format!("{}/crc/{}", url.trim_end_matches('/'), encode(kernel));
+    let body = crate::tools::output(std::process::Command::new("curl")
+                                        .args(["-fsSL", &url]))
+                .map_err(|e| match e {
+                    Error::Command { reason, .. } => lookup_error(&url, reason),
+                    e => e,
+                })?;
+    let versions: BTreeMap<String, Version> = serde_json::from_slice(&body)
+        .map_err(|e| lookup_error(&url, format!("invalid response -- {}", e)))?;
+
+    let mut kernel_versions = db::KernelVersions::default();
+    for (name, version) in versions {
+        if let Some(namespace) = version.namespace {
+            kernel_versions.namespaces.insert(name.clone(), namespace);
+        }
+        kernel_versions.crcs.insert(name, version.crc);
+    }
+    Ok(kernel_versions)
+}
+
+/// Serves the CRC database over HTTP, one thread per connection:
+///
+/// - `GET /kernels` lists the kernels and their number of symbols
+/// - `GET /crc/<kernel>` returns all versions of a kernel
+/// - `GET /crc/<kernel>/<symbol>` returns the version of one symbol
+pub fn serve(path: &Path, listen: &str) -> Result<()> {
+    // Fails early if the database does not exist
+    db::kernels(path)?;
+    let listener = std::net::TcpListener::bind(listen)
+                    .map_err(|e| lookup_error(listen, e.to_string()))?;
+    log::info!("Serving {} on http://{}", path.display(),
+               listener.local_addr().map(|a| a.to_string())
+                  .unwrap_or_else(|_| listen.to_string()));
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("Failed to accept connection: {}", e);
+                continue;
+            },
+        };
+        let path = path.to_path_buf();
+        std::thread::spawn(move || {
+            if let Err(e) = handle(stream, &path) {
+                log::warn!("Failed to answer request: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Reads a request head, returning its method and path
+fn read_request(stream: &mut std::net::TcpStream) -> std::io::Result<(String, String)> {
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < MAX_REQUEST {
+        let len = stream.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[.. len]);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let mut request_line = head.lines().next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default().to_string();
+    let target = request_line.next().unwrap_or_default();
+    // Query strings are ignored
+    let target = target.split('?').next().unwrap_or_default().to_string();
+    Ok((method, target))
+}
+
+/// Answers a single request with a JSON body
+fn handle(mut stream: std::net::TcpStream, path: &Path) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(std::time::Duration::from_secs(10)))?;
+    let peer = stream.peer_addr()?;
+    let (method, target) = read_request(&mut stream)?;
+
+    let (status, body) = if method != "GET" {
+        (405, serde_json::json!({ "error": "only GET is supported" }))
+    } else {
+        route(path, &target)
+    };
+    log::info!("{} {} {} {}", peer, method, target, status);
+
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    };
+    let body = body.to_string();
+    write!(stream, "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\n\
+                    Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+           status, reason, body.len(), body)?;
+    stream.flush()
+}
+
+/// Answers a `GET` request for a path with a status and JSON body
+fn route(path: &Path, target: &str) -> (u16, serde_json::Value) {
+    let error = |status, reason: String| (status, serde_json::json!({ "error": reason }));
+    let segments: Option<Vec<String>> = target.trim_start_matches('/').split('/')
+                                            .map(decode).collect();
+    let segments = match segments {
+        Some(segments) => segments,
+        None => return error(400, "invalid percent-encoding".to_string()),
+    };
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+    let result = match segments[..] {
+        ["kernels"] => db::kernels(path).map(|kernels| {
+            let kernels: Vec<_> = kernels.into_iter().map(|kernel| {
+                serde_json::json!({ "kernel": kernel.name, "symbols": kernel.symbols })
+            }).collect();
+            (200, serde_json::json!(kernels))
+        }),
+        ["crc", kernel] => db::query(path, kernel, None).map(|mut versions| {
+            if versions.crcs.is_empty() {
+                return error(404, format!("no kernel \"{}\"", kernel));
+            }
+            let versions: BTreeMap<String, Version> = versions.crcs.into_iter()
+                .map(|(name, crc)| {
+                    let namespace = versions.namespaces.remove(&name);
+                    (name, Version { crc, namespace })
+                })
+                .collect();
+            (200, serde_json::json!(versions))
+        }),
+        ["crc", kernel, symbol] => db::lookup(path, kernel, symbol).map(|version| {
+            match version {
+                Some((crc, namespace)) => (200, serde_json::json!({
+                    "symbol": symbol,
+                    "crc": crc,
+                    "namespace": namespace,
+                })),
+                None => error(404, format!("no version of \"{}\" for kernel \"{}\"",
+                                           symbol, kernel)),
+            }
+        }),
+        _ => Ok(error(404, "expected /kernels, /crc/<kernel> or \
+                            /crc/<kernel>/<symbol>".to_string())),
+    };
+    result.unwrap_or_else(|e| error(500, e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Database with a kernel whose name needs percent-encoding
+    fn database(dir: &Path) -> std::path::PathBuf {
+        let path = dir.join("crcs.sqlite");
+        db::import(&path, "6.1.0-1+deb", [("printk".to_string(), 0xaa, None),
+                                          ("kfree".to_string(), 0xbb,
+                                           Some("NS".to_string()))]).unwrap();
+        path
+    }
+
+    #[test]
+    fn segments_round_trip() {
+        assert_eq!(encode("6.1.0-1+deb/x y"), "6.1.0-1%2Bdeb%2Fx%20y");
+        let kernel = "6.1.0-1+deb/x y";
+        assert_eq!(decode(&encode(kernel)).as_deref(), Some(kernel));
+        assert_eq!(decode("%zz"), None);
+        assert_eq!(decode("%2"), None);
+    }
+
+    #[test]
+    fn requests_are_routed() {
+        let dir = crate::tempdir::create("test").unwrap();
+        let path = database(dir.path());
+        let route = |target| route(&path, target);
+
+        assert_eq!(route("/kernels"),
+                   (200, serde_json::json!([{ "kernel": "6.1.0-1+deb", "symbols": 2 }])));
+        assert_eq!(route("/crc/6.1.0-1%2Bdeb"), (200, serde_json::json!({
+            "kfree": { "crc": 0xbb, "namespace": "NS" },
+            "printk": { "crc": 0xaa },
+        })));
+        assert_eq!(route("/crc/6.1.0-1%2Bdeb/printk"), (200, serde_json::json!({
+            "symbol": "printk", "crc": 0xaa, "namespace": null,
+        })));
+        assert_eq!(route("/crc/5.15.0").0, 404);
+        assert_eq!(route("/crc/6.1.0-1%2Bdeb/kmalloc").0, 404);
+        assert_eq!(route("/crc/%zz").0, 400);
+        assert_eq!(route("/").0, 404);
+        assert_eq!(route("/crc/a/b/c").0, 404);
+    }
+
+    #[test]
+    fn requests_are_answered_over_http() {
+        let dir = crate::tempdir::create("test").unwrap();
+        let path = database(dir.path());
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let request = |request: &str| {
+            let mut client = std::net::TcpStream::connect(addr).unwrap();
+            client.write_all(request.as_bytes()).unwrap();
+            handle(listener.accept().unwrap().0, &path).unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            response
+        };
+        let response = request("GET /crc/6.1.0-1%2Bdeb/kfree?x=1 HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body, serde_json::json!({ "symbol": "kfree", "crc": 0xbb,
+                                             "namespace": "NS" }));
+
+        let response = request("POST /kernels HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"), "{}",
+                response);
+    }
+}
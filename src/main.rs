@@ -1,258 +1,4162 @@
-use clap::{Parser, Subcommand};
-use goblin;
-use std::collections::HashMap;
+use clap::{CommandFactory, Parser};
+use cli::{Args, Commands, DbArgs, DbCommands, DkmsArgs, ImageArgs, InstallArgs,
+          ModuleRename, PatchArgs, TestgenArgs, WatchArgs};
+use error::{Error, Result};
+use exit::ExitCode;
+use candycorn_core::{find_section, MODULE_SIG_STRING};
+use log::{debug, error, info, trace, warn};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::atomic::Ordering;
 
-#[derive(Parser)]
-#[clap(about, long_about = None)]
-struct Args {
+mod android;
+mod btf;
+mod cache;
+mod checksum;
+mod cli;
+mod color;
+mod compress;
+mod config;
+mod cpio;
+mod csv;
+mod db;
+mod dmesg;
+mod doctor;
+mod donors;
+mod elfedit;
+mod erofs;
+mod error;
+mod exit;
+mod exports;
+mod filter;
+mod hexdump;
+mod hook;
+mod kbuild;
+mod kimage;
+mod license;
+mod lock;
+mod logging;
+mod lookup;
+mod manifest;
+mod mirror;
+mod moddeps;
+mod modinfo;
+mod modversions;
+mod namespaces;
+mod note;
+mod output;
+mod overrides;
+mod package;
+mod patchfile;
+mod progress;
+mod report;
+mod review;
+mod running;
+mod squashfs;
+mod symtypes;
+mod symvers;
+mod sysmap;
+mod tempdir;
+mod testgen;
+mod thismodule;
+mod tools;
+mod vermagic;
+mod vmlinux;
+mod watch;
 
-    /// Target kernel module to patch
-    #[clap(required(true), parse(from_os_str))]
-    target: std::path::PathBuf,
+/// Set by `--reproducible`
+static REPRODUCIBLE: std::sync::atomic::AtomicBool =
+    std::sync::atomic::AtomicBool::new(false);
 
-    #[clap(subcommand)]
-    command: Commands,
+impl Args {
+    /// Parses a command line with the defaults of the configuration files
+    /// added, exiting on errors
+    fn parse_with_config(argv: Vec<std::ffi::OsString>) -> Args {
+        match config::apply(&Args::command(), argv) {
+            Ok(argv) => Args::parse_from(argv),
+            Err(e) => {
+                error!("{}", e);
+                exit::exit(e.exit_code());
+            },
+        }
+    }
+}
+
+/// Parses a CRC value given either in decimal or as `0x` prefixed hexadecimal
+///
+/// # Arguments
+/// * `s` - String to parse
+/// * `bare_hex` - Parse values without a `0x` prefix as hexadecimal
+fn parse_crc(s: &str, bare_hex: bool) -> Result<u64> {
+    let result = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None if bare_hex => u64::from_str_radix(s, 16),
+        None => s.parse(),
+    };
+    result.map_err(|e| Error::InvalidCrc { value: s.to_string(), source: e })
 }
 
-#[derive(Subcommand)]
-enum Commands {
-    /// Patch the target's symbol versions
-    Patch {
-        /// Reference kernel module to use for obtaining symbol versions
-        #[clap(short, long, parse(from_os_str))]
-        src: Option<std::path::PathBuf>,
+/// Parses the symbol table of an ELF along with its string table. Returns
+/// `None` if the ELF has no symbol table
+fn read_symtab<'a>(elf: &goblin::elf::Elf, data: &'a [u8])
+    -> Result<Option<(goblin::elf::Symtab<'a>, goblin::strtab::Strtab<'a>)>> {
 
-        /// Module layout version value to patch into target
-        #[clap(short, long, value_parser, required_unless("src"))]
-        module_layout_version: Option<u64>,
+    let ctx = goblin::container::Ctx::new(elf.header.container()?,
+                                          elf.header.endianness()?);
 
-        /// Keep the original target and write modified output to a new file
-        #[clap(short, long, value_parser)]
-        keep: Option<bool>,
-    },
-    /// List the target's symbol versions
-    List,
+    let symtab = match elf.section_headers.iter()
+                        .find(|sh| sh.sh_type == goblin::elf::section_header::SHT_SYMTAB) {
+        Some(symtab) => symtab,
+        None => return Ok(None),
+    };
+    let strtab = elf.section_headers.get(symtab.sh_link as usize)
+                    .ok_or_else(|| Error::MissingSection {
+                        section: ".strtab".to_string(),
+                    })?;
+    let sym_data = section_data(symtab, ".symtab", data)?;
+    let str_data = section_data(strtab, ".strtab", data)?;
+    let count = sym_data.len() / goblin::elf::sym::Sym::size(ctx.container);
+    let syms = goblin::elf::Symtab::parse(sym_data, 0, count, ctx)?;
+    let strtab = goblin::strtab::Strtab::parse(str_data, 0, str_data.len(), 0)?;
+    Ok(Some((syms, strtab)))
+}
+
+/// Parses only the ELF header, section headers and section name table of a
+/// kernel module. Symbol tables, relocations and the like are never used so
+/// are skipped, which matters for large modules
+fn parse_elf(buffer: &[u8]) -> Result<goblin::elf::Elf<'_>> {
+    use goblin::elf::section_header::{SectionHeader, SHN_XINDEX};
+
+    let header = goblin::elf::Elf::parse_header(buffer)?;
+    let ctx = goblin::container::Ctx::new(header.container()?,
+                                          header.endianness()?);
+    let mut elf = goblin::elf::Elf::lazy_parse(header)?;
+    elf.section_headers = SectionHeader::parse(buffer, header.e_shoff as usize,
+                                               header.e_shnum as usize, ctx)?;
+
+    // Modules with many sections store the name table index in the first
+    // section header
+    let mut strtab_idx = header.e_shstrndx as usize;
+    if strtab_idx == SHN_XINDEX as usize {
+        strtab_idx = elf.section_headers.first().map_or(0, |sh| sh.sh_link as usize);
+    }
+    if let Some(sh) = elf.section_headers.get(strtab_idx) {
+        let data = section_data(sh, ".shstrtab", buffer)?;
+        elf.shdr_strtab = goblin::strtab::Strtab::parse(data, 0, data.len(), 0)?;
+    }
+    Ok(elf)
 }
 
-/// Attempts to find a ELF section header matching provided name
+/// Returns the content of a section, failing if the section has no content in
+/// the file or extends past the end of the file
 ///
 /// # Arguments
-/// * `kmod` - Kernel module to search for section within
-/// * `name` - Name of the section header to locate
-fn find_section<'a>(kmod: &'a goblin::elf::Elf, name: &str) 
-    -> Option<&'a goblin::elf::section_header::SectionHeader> {
-   
-    for sh in &kmod.section_headers {
-        let sh_name = kmod.shdr_strtab.get_at(sh.sh_name).unwrap_or("");
-        if sh_name == name {
-            return Some(&sh)
+/// * `sh` - Header of the section
+/// * `name` - Name of the section, used for errors
+/// * `mod_data` - Backing byte content of the kernel module
+fn section_data<'a>(sh: &goblin::elf::section_header::SectionHeader, name: &str,
+                    mod_data: &'a [u8]) -> Result<&'a [u8]> {
+
+    Ok(candycorn_core::section_data(sh, name, mod_data)?)
+}
+
+/// Single `modversion_info` entry of a kernel module's `__versions` section,
+/// or entry of the extended `__version_ext_crcs` and `__version_ext_names`
+/// sections
+type SymVersion = candycorn_core::Entry;
+
+/// Indexes symbol versions by name. When a name appears more than once, the
+/// first entry is used
+fn versions_by_name(versions: &[SymVersion]) -> HashMap<&str, &SymVersion> {
+    let mut map = HashMap::new();
+    for version in versions {
+        map.entry(version.name.as_str()).or_insert(version);
+    }
+    map
+}
+
+/// Layout of a module's `modversion_info` entries. The CRC is stored as an
+/// `unsigned long` so its width and byte order follow the module's ELF class
+/// and endianness
+#[derive(Clone, Copy, Debug)]
+struct VersionLayout {
+    crc_size: usize,
+    little_endian: bool,
+}
+
+impl VersionLayout {
+    fn from_elf(info: &goblin::elf::Elf) -> VersionLayout {
+        VersionLayout {
+            crc_size: if info.is_64 { 8 } else { 4 },
+            little_endian: info.little_endian,
         }
     }
-    None
+
+    /// Layout of the CRC field of an entry
+    fn of(&self, version: &SymVersion) -> VersionLayout {
+        if version.extended {
+            VersionLayout { crc_size: 4, ..*self }
+        } else {
+            *self
+        }
+    }
+
+    /// Writes the CRC of a symbol to the CRC field at the provided offset,
+    /// failing if the CRC does not fit in the field
+    fn write_crc(&self, buffer: &mut [u8], symbol: &str, offset: usize, crc: u64)
+        -> Result<()> {
+
+        if !candycorn_core::crc_fits(crc, self.crc_size) {
+            return Err(Error::CrcWidth {
+                symbol: symbol.to_string(),
+                offset,
+                crc,
+                bits: self.crc_size * 8,
+            });
+        }
+
+        let field = &mut buffer[offset .. offset + self.crc_size];
+        let original = checksum::hex(field);
+        candycorn_core::write_crc(field, crc, self.little_endian);
+        trace!("Wrote \"{}\" at offset 0x{:x}: {} -> {}", symbol, offset, original,
+               checksum::hex(field));
+        Ok(())
+    }
+}
+
+/// Produces a list of symbol versioning info given a kernel module's ELF 
+/// metadata and backing byte content. Entries of `__versions` come first,
+/// followed by entries of the extended sections of kernels with
+/// `CONFIG_EXTENDED_MODVERSIONS`. Returns `None` if the module has neither
+fn get_versions(info: &goblin::elf::Elf, mod_data: &[u8])
+    -> Result<Option<Vec<SymVersion>>> {
+
+    let basic = basic_versions(info, mod_data)?;
+    let extended = modversions::extended_versions(info, mod_data)?;
+    if basic.is_none() && extended.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(basic.into_iter().chain(extended).flatten().collect()))
+}
+
+/// Produces the entries of a kernel module's `__versions` section in the
+/// order they appear in the ELF. Duplicate names are kept. Returns `None` if
+/// the module has no `__versions` section
+fn basic_versions(info: &goblin::elf::Elf, mod_data: &[u8])
+    -> Result<Option<Vec<SymVersion>>> {
+
+    let versions = candycorn_core::basic_entries(info, mod_data)?;
+    if let Some(versions) = &versions {
+        warn_duplicates(versions);
+    }
+    Ok(versions)
+}
+
+/// Warns of names appearing more than once among the entries of a section
+fn warn_duplicates(versions: &[SymVersion]) {
+    let mut seen = HashSet::new();
+    for version in versions {
+        if !seen.insert(version.name.as_str()) {
+            warn!("Duplicate version \"{}\" at offset 0x{:x}", version.name,
+                  version.offset);
+        }
+    }
+}
+
+/// Checks if a kernel module has a signature appended
+fn has_signature(mod_data: &[u8]) -> bool {
+    mod_data.ends_with(MODULE_SIG_STRING)
+}
+
+/// Machine, ELF class and byte order of a kernel module. Versions can only be
+/// taken from modules built for the same architecture as the target
+#[derive(Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+struct Arch {
+    machine: u16,
+    is_64: bool,
+    little_endian: bool,
+}
+
+impl Arch {
+    fn from_elf(info: &goblin::elf::Elf) -> Arch {
+        Arch {
+            machine: info.header.e_machine,
+            is_64: info.is_64,
+            little_endian: info.little_endian,
+        }
+    }
+}
+
+impl std::fmt::Display for Arch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {}-bit {}",
+               goblin::elf::header::machine_to_str(self.machine),
+               if self.is_64 { 64 } else { 32 },
+               if self.little_endian { "little-endian" } else { "big-endian" })
+    }
 }
 
-/// Finds first null byte in a byte slice and creates `String` from beginning of
-/// slice up to null byte. If no null byte is found in the slice, the `String`
-/// will be the entire byte slice
-fn str_from_u8(utf8: &[u8]) -> String {
-    // Find null byte
-    let mut null_idx = utf8.len();
-    for i in 0 .. utf8.len() {
-        if utf8[i] == 0 {
-            null_idx = i;
-            break;
+/// Kernel module read from disk along with its parsed metadata
+struct KernelModule<B = ModuleData> {
+    buffer: B,
+    arch: Arch,
+    layout: VersionLayout,
+    /// Symbol versions, if the module has a `__versions` section
+    versions: Option<Vec<SymVersion>>,
+    /// `.modinfo` entries, if the module has a `.modinfo` section
+    modinfo: Option<Vec<modinfo::ModInfoEntry>>,
+    /// Record of an earlier patch, if the module has a provenance note
+    provenance: Option<note::Provenance>,
+}
+
+/// Maps a file into memory read-only
+fn map_file(path: &std::path::Path) -> Result<memmap2::Mmap> {
+    let file = std::fs::File::open(path)
+                .map_err(|e| Error::Read { path: path.into(), source: e })?;
+
+    // SAFETY: The mapping is only valid as long as no other process truncates
+    // or modifies the file, which is the same assumption made when reading it
+    unsafe { memmap2::Mmap::map(&file) }
+        .map_err(|e| Error::Read { path: path.into(), source: e })
+}
+
+/// Content of a kernel module read from disk. Compressed modules are
+/// decompressed into memory, all others are mapped read-only
+enum ModuleData {
+    Mapped(memmap2::Mmap),
+    Decompressed(Vec<u8>),
+}
+
+impl std::ops::Deref for ModuleData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            ModuleData::Mapped(data) => data,
+            ModuleData::Decompressed(data) => data,
         }
     }
+}
+
+/// Reads a kernel module from disk, decompressing it if needed
+fn read_module(path: &std::path::Path) -> Result<ModuleData> {
+    decompress_module(path, map_file(path)?)
+}
+
+/// Decompresses a mapped kernel module if it is compressed
+fn decompress_module(path: &std::path::Path, mapped: memmap2::Mmap)
+    -> Result<ModuleData> {
+
+    match compress::decompress(&mapped).map_err(|e| e.in_file(path))? {
+        Some(data) => Ok(ModuleData::Decompressed(data)),
+        None => Ok(ModuleData::Mapped(mapped)),
+    }
+}
+
+/// Reads and parses a kernel module from disk
+fn load_module(path: &std::path::Path) -> Result<KernelModule> {
+    parse_module(read_module(path)?).map_err(|e| e.in_file(path))
+}
+
+/// Maps a file to patch. The file is mapped privately so patches are made in
+/// memory and only reach the file once written out. A path of `-` reads from
+/// stdin
+fn map_target(path: &std::path::Path) -> Result<memmap2::MmapMut> {
+    if is_stdio(path) {
+        return read_stdin();
+    }
+
+    let file = std::fs::File::open(path)
+                .map_err(|e| Error::Read { path: path.into(), source: e })?;
+
+    // SAFETY: See `map_file`
+    unsafe { memmap2::MmapOptions::new().map_copy(&file) }
+        .map_err(|e| Error::Read { path: path.into(), source: e })
+}
+
+/// Maps and parses a kernel module to patch
+fn load_target(path: &std::path::Path) -> Result<KernelModule<memmap2::MmapMut>> {
+    let name = if is_stdio(path) { "<stdin>".into() } else { path.to_path_buf() };
+    map_target(path).and_then(parse_module).map_err(|e| e.in_file(name))
+}
+
+/// Writes the targets of a `patch` command into `CANDYCORN_OUTPUT_DIR` if it
+/// is set and the command line says nowhere else to write them
+fn output_dir_from_env(mut args: PatchArgs) -> PatchArgs {
+    if args.output.is_none() && args.output_dir.is_none() && !args.keep
+        && args.emit_patch.is_none() && !args.in_place
+        && !args.targets.iter().any(|t| is_stdio(t)) {
+        args.output_dir = std::env::var_os("CANDYCORN_OUTPUT_DIR").map(Into::into);
+    }
+    args
+}
+
+/// Checks if an environment variable enabling a setting is set, to a value
+/// other than one clap takes for false
+fn env_flag(name: &str) -> bool {
+    std::env::var(name).is_ok_and(|value| {
+        let value = value.to_lowercase();
+        !["", "0", "n", "no", "f", "false", "off"].contains(&value.as_str())
+    })
+}
+
+/// Checks if a path refers to stdin or stdout
+fn is_stdio(path: &std::path::Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// Reads all of stdin into an anonymous mapping so it can be patched the same
+/// way as a mapped file
+fn read_stdin() -> Result<memmap2::MmapMut> {
+    use std::io::Read;
+
+    let read_err = |e| Error::Read { path: "<stdin>".into(), source: e };
+    let mut data = Vec::new();
+    std::io::stdin().read_to_end(&mut data).map_err(read_err)?;
+    map_copy(&data).map_err(read_err)
+}
+
+/// Copies data into an anonymous mapping
+fn map_copy(data: &[u8]) -> std::io::Result<memmap2::MmapMut> {
+    let mut buffer = memmap2::MmapMut::map_anon(data.len())?;
+    buffer.copy_from_slice(data);
+    Ok(buffer)
+}
+
+/// Parses a kernel module from its backing byte content
+fn parse_module<B: std::ops::Deref<Target = [u8]>>(buffer: B)
+    -> Result<KernelModule<B>> {
+
+    let ko = parse_elf(&buffer)?;
+
+    let versions = get_versions(&ko, &buffer)?;
+    let modinfo = modinfo::get_modinfo(&ko, &buffer)?;
+    let provenance = note::read(&ko, &buffer)?;
+    let arch = Arch::from_elf(&ko);
+    let layout = VersionLayout::from_elf(&ko);
+
+    // We no longer need the ELF data and holding it any longer will prevent
+    // updating the backing buffer
+    drop(ko);
 
-    std::string::String::from_utf8_lossy(&utf8[0 .. null_idx]).into_owned()
+    Ok(KernelModule { buffer, arch, layout, versions, modinfo, provenance })
 }
 
-#[derive(Debug)]
-struct SymVersion {
+/// Symbol version taken from a source
+#[derive(Clone, Copy)]
+struct SourceVersion {
     crc: u64,
-    offset: usize
-}
-
-/// Produces a hash map of symbol versioning info given a kernel module's ELF 
-/// metadata and backing byte content
-fn get_versions(info: &goblin::elf::Elf, mod_data: &Vec<u8>) 
-    -> Option<HashMap<String, SymVersion>> {
-   
-     // Find location of `__versions` section
-    const MOD_VER_INFO_NAME_OFFSET: usize = 8;
-    const MOD_VER_INFO_SIZE: usize = 64;
-
-    // Make sure `__versions` section is present
-    let vers_sh = find_section(info, "__versions")?;
-
-    let mut start_idx: usize = vers_sh.sh_offset as usize;
-
-    // Check if end_idx is sane value (multiple of ModVersionInfo size)
-    let entries: usize = vers_sh.sh_size as usize / MOD_VER_INFO_SIZE;
-    if (entries * MOD_VER_INFO_SIZE) != vers_sh.sh_size as usize {
-        eprintln!("ERROR: `__versions` section unexpected size");
-        return None;
-    }
-
-    let mut versions = HashMap::new();
-
-    // Parse all version entries and populate map with copies of data
-    // Borrow checker will prevent modifying backing data later if we use
-    // references
-    for _ in 0 .. entries {
-        let end_idx: usize = start_idx + MOD_VER_INFO_SIZE;
-        let ver_info_name = &mod_data[(start_idx + MOD_VER_INFO_NAME_OFFSET) 
-                                        .. end_idx];
-
-        let sym_ver = SymVersion {
-            crc: u64::from_le_bytes(
-                     (&mod_data[start_idx .. 
-                      (start_idx + MOD_VER_INFO_NAME_OFFSET)])
-                     .try_into().unwrap()),
-            offset: start_idx,
-        };
-        versions.insert(str_from_u8(&ver_info_name), sym_ver); 
-        
-        start_idx += MOD_VER_INFO_SIZE;
+    /// Index of the source in `Sources::paths`
+    source: usize,
+}
+
+/// Combined symbol versions of all source modules and `Module.symvers` files
+#[derive(Default)]
+struct Sources {
+    versions: HashMap<String, SourceVersion>,
+    paths: Vec<std::path::PathBuf>,
+    /// Architecture of each source, if the source is a module
+    archs: Vec<Option<Arch>>,
+    /// Set if a source lists every symbol its kernel and modules export,
+    /// rather than those some modules use
+    lists_exports: bool,
+}
+
+impl Sources {
+    /// Adds the versions of a source, replacing versions of earlier sources
+    fn add(&mut self, path: &std::path::Path, arch: Option<Arch>,
+           versions: impl IntoIterator<Item = (String, u64)>) {
+
+        let source = self.paths.len();
+        self.paths.push(path.to_path_buf());
+        self.archs.push(arch);
+        let mut count = 0;
+        for (name, crc) in versions {
+            self.versions.insert(name, SourceVersion { crc, source });
+            count += 1;
+        }
+        debug!("Read {} versions from {}", count, path.display());
+    }
+
+    /// Adds the versions of a source listing all exported symbols, such as
+    /// `Module.symvers`
+    fn add_exports(&mut self, path: &std::path::Path,
+                   versions: impl IntoIterator<Item = (String, u64)>) {
+        self.lists_exports = true;
+        self.add(path, None, versions);
+    }
+
+    /// Returns the CRC of a symbol along with the path and architecture of
+    /// its source
+    fn get(&self, name: &str) -> Option<(u64, &std::path::Path, Option<Arch>)> {
+        let ver = self.versions.get(name)?;
+        Some((ver.crc, &self.paths[ver.source], self.archs[ver.source]))
     }
-    Some(versions)
 }
 
-fn patch(src: Option<std::path::PathBuf>, module_layout_version: Option<u64>, 
-         keep: Option<bool>, mut t_buffer: Vec<u8>, t_versions: HashMap<String, SymVersion>) {
-    
-    // See if source kernel module was provided and handle
-    if src.is_some() {
-        let s_buffer = match std::fs::read(src.as_ref().unwrap()) {
-            Ok(buf) => buf,
-            Err(e) => {
-                eprintln!("{}", e);
-                std::process::exit(1);
+/// Patch values resolved once and applied to every target
+struct PatchPlan {
+    /// Symbol versions of the source modules and `Module.symvers`
+    sources: Option<Sources>,
+    /// Installed modules to look up versions missing from `sources` in
+    installed: Option<donors::Installed>,
+    /// Take versions from sources built for a different architecture, and
+    /// patch targets that do not look like kernel modules
+    force: bool,
+    /// Review the changes to each target before writing it
+    interactive: bool,
+    /// Confirm each CRC written on the terminal
+    confirm: bool,
+    /// Print the bytes of patched entries before and after patching
+    show_bytes: bool,
+    /// Manually provided symbol versions, applied after the source's
+    overrides: BTreeMap<String, u64>,
+    /// Overrides given on the command line, which every target must version.
+    /// Those from the overrides file are skipped by targets without them
+    required_overrides: BTreeSet<String>,
+    /// Program deciding the versions to write, applied after the overrides
+    hook: Option<std::path::PathBuf>,
+    /// Names of target symbols in the sources, for renamed symbols
+    renames: BTreeMap<String, String>,
+    /// Symbols whose versions may be patched from the sources
+    filter: filter::SymbolFilter,
+    /// Symbols to add to the target's `__versions` if missing. Their versions
+    /// are in `overrides`
+    add_versions: BTreeSet<String>,
+    vermagic: Option<String>,
+    srcversion: Option<String>,
+    /// Edits of arbitrary `.modinfo` entries, applied after `vermagic` and
+    /// `srcversion`
+    modinfo: Vec<modinfo::Edit>,
+    /// Changes to the target's `depends` list
+    depends: modinfo::DependsEdit,
+    /// New name of the module in its `struct module`
+    rename_module: Option<ModuleRename>,
+    strip_debug: bool,
+    strip_btf: bool,
+    /// Namespaces of symbols exported into one, from the sources
+    namespaces: HashMap<String, String>,
+    /// Symbols the sources export with `EXPORT_SYMBOL_GPL`
+    gpl_only: HashSet<String>,
+    /// Add `import_ns` entries for namespaces the target imports from
+    /// without declaring
+    import_ns: bool,
+    /// Warn about target imports no source exports, set if the sources list
+    /// the kernel's exports
+    check_exports: bool,
+    keep: bool,
+    /// Write only the changed bytes into the targets
+    in_place: bool,
+    /// Copies of overwritten targets to keep besides the original
+    backups: Option<usize>,
+    output: Option<std::path::PathBuf>,
+    /// Directory to write patched targets into, under their file names
+    output_dir: Option<std::path::PathBuf>,
+    emit_patch: Option<std::path::PathBuf>,
+    require_all: bool,
+    /// Symbols versioned by the source's `__versions` when cloning, which
+    /// must match the target's
+    clone: Option<BTreeSet<String>>,
+    create_versions: bool,
+    checksum: bool,
+    checksum_manifest: bool,
+    expect_sha256: Option<String>,
+    /// Source identities to record in a provenance note, if notes are enabled
+    note: Option<Vec<note::Source>>,
+    /// Report collecting the changes made to each module
+    report: Option<report::Report>,
+}
+
+impl PatchPlan {
+    /// Names to look a target symbol up by in the sources, the symbol's own
+    /// name first
+    fn source_names<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        std::iter::once(name).chain(self.renames.get(name).map(String::as_str))
+    }
+
+    /// Checks if the sources other than installed modules have a version for
+    /// a target symbol
+    fn in_sources(&self, name: &str) -> bool {
+        self.sources.as_ref().is_some_and(|sources| {
+            self.source_names(name).any(|n| sources.get(n).is_some())
+        })
+    }
+}
+
+/// Expands glob patterns in the provided target list. Paths without glob
+/// characters are passed through as-is so missing files are reported when
+/// they are read
+fn expand_targets(targets: &[std::path::PathBuf]) -> Result<Vec<std::path::PathBuf>> {
+    let mut expanded = Vec::new();
+    for target in targets {
+        let pattern = match target.to_str() {
+            Some(p) if p.contains(['*', '?', '[']) => p,
+            _ => {
+                expanded.push(target.clone());
+                continue;
             }
         };
 
-        let s_ko = match goblin::elf::Elf::parse(&s_buffer) {
-            Ok(bin) => bin,
-            Err(e) => {
-                eprintln!("Failed to parse source kernel module -- {}", e);
-                std::process::exit(1);
+        let mut matched = false;
+        for path in glob::glob(pattern)?.flatten() {
+            expanded.push(path);
+            matched = true;
+        }
+        if !matched {
+            warn!("No targets match \"{}\"", pattern);
+        }
+    }
+    Ok(expanded)
+}
+
+/// Name of a module file without its `.ko` extension and any compression
+/// extension
+fn module_stem(path: &std::path::Path) -> String {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    name.split(".ko").next().unwrap_or_default().to_string()
+}
+
+/// Finds the compression most installed modules use, given their paths
+fn common_compression(installed: &[&str]) -> Option<compress::Format> {
+    let mut counts: HashMap<Option<compress::Format>, usize> = HashMap::new();
+    for path in installed {
+        *counts.entry(compress::Format::from_path(std::path::Path::new(path)))
+            .or_default() += 1;
+    }
+    counts.into_iter().max_by_key(|(_, count)| *count).and_then(|(format, _)| format)
+}
+
+/// Directory to install a module into, relative to the kernel's module
+/// directory. Modules under `updates` take precedence over those under
+/// `kernel` with depmod's default configuration
+fn install_dir(installed: &[&str], name: &str) -> std::path::PathBuf {
+    let existing = installed.iter()
+        .map(std::path::Path::new)
+        .find(|path| module_stem(path).replace('-', "_") == name.replace('-', "_"));
+    match existing.and_then(|path| path.parent()?.strip_prefix("kernel").ok()) {
+        Some(dir) => std::path::Path::new("updates").join(dir),
+        None => "updates".into(),
+    }
+}
+
+fn install(args: InstallArgs) -> Result<()> {
+    let mut patch_args = args.patch;
+    if patch_args.output.is_some() || patch_args.output_dir.is_some()
+        || patch_args.emit_patch.is_some() || patch_args.keep || patch_args.in_place {
+        Args::command().error(clap::ErrorKind::ArgumentConflict,
+            "`install` can not be used with `--output`, `--output-dir`, \
+             `--emit-patch`, `--keep` or `--in-place`").exit();
+    }
+
+    let release = match args.kernel_release {
+        Some(release) => release,
+        None => running::release().ok_or(Error::RunningRelease)?,
+    };
+    let modules_dir = args.root.join("lib/modules").join(&release);
+    let deps_path = modules_dir.join("modules.dep");
+    let deps = std::fs::read_to_string(&deps_path)
+                .map_err(|e| Error::Read { path: deps_path, source: e })?;
+    let installed: Vec<&str> = deps.lines().filter_map(|l| l.split(':').next())
+                                .collect();
+    let format = match args.compress.as_deref() {
+        Some(name) => compress::Format::from_name(name),
+        None => common_compression(&installed),
+    };
+
+    // Targets are copied into place and patched there
+    let mut copies = Vec::new();
+    for target in expand_targets(&patch_args.targets)? {
+        if is_stdio(&target) {
+            Args::command().error(clap::ErrorKind::ArgumentConflict,
+                "`install` can not read targets from stdin").exit();
+        }
+        let name = module_stem(&target);
+        let dir = modules_dir.join(match &args.dir {
+            Some(dir) => dir.clone(),
+            None => install_dir(&installed, &name),
+        });
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| Error::Write { path: dir.clone(), source: e })?;
+        let copy = dir.join(format!("{}.ko", name));
+        std::fs::write(&copy, &*read_module(&target)?)
+            .map_err(|e| Error::Write { path: copy.clone(), source: e })?;
+        copies.push(copy);
+    }
+
+    patch_args.targets = copies.clone();
+    match patch(patch_args) {
+        Ok(()) | Err(Error::NothingToDo { .. }) => {},
+        Err(e) => {
+            for copy in &copies {
+                let _ = std::fs::remove_file(copy);
             }
-        };
-        let s_versions = match get_versions(&s_ko, &s_buffer) {
-            Some(s) => s,
-            None => { std::process::exit(1); },
+            return Err(e);
+        },
+    }
+
+    for copy in &copies {
+        let path = match format {
+            Some(format) => {
+                let data = std::fs::read(copy)
+                            .map_err(|e| Error::Read { path: copy.clone(), source: e })?;
+                let mut path = copy.clone().into_os_string();
+                path.push(".");
+                path.push(format.extension());
+                let path = std::path::PathBuf::from(path);
+                std::fs::write(&path, compress::compress(format, &data)?)
+                    .map_err(|e| Error::Write { path: path.clone(), source: e })?;
+                std::fs::remove_file(copy)
+                    .map_err(|e| Error::Write { path: copy.clone(), source: e })?;
+                path
+            },
+            None => copy.clone(),
         };
 
-        let mut misses = HashMap::new();
-        for name in t_versions.keys() {
-            match s_versions.get(name) {
-                Some(s_ver) => { 
-                    let off = t_versions[name].offset;
-                    println!(
-                        "Patching version \"{}\" in target with source CRC 0x{:x}",
-                        name, s_ver.crc);
-                    t_buffer.splice(off..off+8, s_ver.crc.to_le_bytes());
-                },
-                None => {
-                    misses.insert(name, t_versions[name].crc);
-                },
+        // depmod would find copies installed earlier in other formats too
+        for ext in ["", ".gz", ".xz", ".zst"] {
+            let mut stale = copy.clone().into_os_string();
+            stale.push(ext);
+            let stale = std::path::PathBuf::from(stale);
+            if stale != path && stale.exists() {
+                std::fs::remove_file(&stale)
+                    .map_err(|e| Error::Write { path: stale.clone(), source: e })?;
             }
         }
+        info!("Installed {}", path.display());
+    }
 
-        if misses.len() > 0 {
-            eprintln!("Target versions not found in source module:");
-            for name in misses.keys() {
-                eprintln!("    \"{}\" - 0x{:x}", name, misses[name]);
-            }
+    if !args.no_depmod {
+        let mut depmod = std::process::Command::new("depmod");
+        if args.root != std::path::Path::new("/") {
+            depmod.arg("-b").arg(&args.root);
+        }
+        let status = depmod.arg("-a").arg(&release).status()
+            .map_err(|e| Error::Command {
+                program: "depmod".to_string(),
+                reason: e.to_string(),
+            })?;
+        if !status.success() {
+            return Err(Error::Command {
+                program: "depmod".to_string(),
+                reason: status.to_string(),
+            });
         }
+        info!("Ran depmod for {}", release);
+    }
+    Ok(())
+}
+
+/// Adds the symbol versions of a kernel package's symvers files and modules
+/// to the sources. Modules are extracted to a temporary directory and read
+/// like `--src-dir`. Files are named by their path within the package
+fn add_package(path: &std::path::Path, data: &[u8], sources: &mut Sources,
+               namespaces: &mut HashMap<String, String>,
+               gpl_only: &mut HashSet<String>) -> Result<()> {
+
+    let is_symvers = |file: &str| {
+        let name = file.rsplit('/').next().unwrap_or(file);
+        name.starts_with("Module.symvers") || name.starts_with("symvers")
+    };
+    let files = package::files(data, |file| {
+        is_symvers(file) || donors::is_module_name(std::ffi::OsStr::new(file))
+    }).map_err(|e| e.in_file(path))?;
+
+    // Symvers files come first so modules take precedence
+    let (symvers, modules): (Vec<_>, Vec<_>) = files.into_iter()
+        .partition(|file| is_symvers(&file.path));
+    for file in &symvers {
+        let name = path.join(&file.path);
+        let content = compress::decompress(&file.data).map_err(|e| e.in_file(&name))?
+                        .unwrap_or_else(|| file.data.clone());
+        let exports = symvers::parse_exports(&String::from_utf8_lossy(&content))
+                        .map_err(|e| e.in_file(&name))?;
+        sources.add_exports(&name, exports.crcs);
+        namespaces.extend(exports.namespaces);
+        gpl_only.extend(exports.gpl_only);
     }
 
-    // If user provided "layout_module" crc manually, apply it now. This will
-    // overwrite the "layout_module" provided by the source kernel module if
-    // it existed
-    if module_layout_version.is_some() {
-        let t_module_layout = t_versions.get("module_layout")
-                    .expect("Unable to find \"module_layout\" symbol version");
-        let off = t_module_layout.offset;
-        println!("Patching \"{}\" in target with CRC 0x{:x}", "module_layout", 
-                    module_layout_version.unwrap());
-        t_buffer.splice(off..off+8, module_layout_version.unwrap()
-                        .to_le_bytes());
+    let temp = tempdir::create("package")?;
+    let dir = temp.path();
+    let donors = modules.iter().try_for_each(|file| {
+        let dest = dir.join(&file.path);
+        let write_err = |e| Error::Write { path: dest.clone(), source: e };
+        std::fs::create_dir_all(dest.parent().unwrap_or(dir)).map_err(write_err)?;
+        std::fs::write(&dest, &file.data).map_err(write_err)
+    }).and_then(|_| donors::from_dir(dir))?;
+
+    if symvers.is_empty() && donors.is_empty() {
+        warn!("No kernel modules with versions or symvers files \
+               found in {}", path.display());
+    }
+    for donor in donors {
+        let name = path.join(donor.path.strip_prefix(dir).unwrap_or(&donor.path));
+        sources.add(&name, Some(donor.arch), donor.versions);
     }
+    Ok(())
+}
 
-    // Write out result
-    // TODO: Handle keep option or provide new option to specify output path
-    //let mut new_filename = out_path.file_name().unwrap().to_os_string();
-    //new_filename.push(".patch");
-    //out_path.set_file_name(new_filename);
-    std::fs::write(std::path::Path::new("./test.ko"), t_buffer).unwrap();
+/// Read-only filesystem image whose modules are patched by extracting the
+/// image and repacking it with the filesystem's tools
+enum Image {
+    SquashFs(squashfs::Superblock),
+    Erofs(erofs::Superblock),
 }
 
-fn list(t_versions: HashMap<String, SymVersion>) {
-    let mut v: Vec<_> = t_versions.iter().collect();
-    
-    // List sorted by order in ELF (file offset)
-    v.sort_by(|a, b| a.1.offset.cmp(&b.1.offset));
-    for version in v {
-        let name = version.0;
-        let ver = version.1;
-        println!("0x{:x}: \"{}\", 0x{:x}", ver.offset,
-                    name, ver.crc);
+impl Image {
+    fn extract(&self, image: &std::path::Path, dir: &std::path::Path) -> Result<()> {
+        match self {
+            Image::SquashFs(_) => squashfs::extract(image, dir),
+            Image::Erofs(_) => erofs::extract(image, dir),
+        }
+    }
+
+    fn pack(&self, dir: &std::path::Path, image: &std::path::Path) -> Result<()> {
+        match self {
+            Image::SquashFs(settings) => squashfs::pack(dir, image, settings),
+            Image::Erofs(settings) => erofs::pack(dir, image, settings),
+        }
+    }
+
+    fn compression(&self) -> &'static str {
+        match self {
+            Image::SquashFs(settings) => settings.compression,
+            Image::Erofs(settings) => settings.compression.unwrap_or("no"),
+        }
     }
 }
 
-fn main() {
-    let args = Args::parse();
-   
-    // Try to open and read target file
-    //let mut out_path = args.target.clone();
-    let t_buffer = match std::fs::read(args.target) {
-        Ok(buf) => buf,
-        Err(e) => {
-            eprintln!("{}", e);
-            std::process::exit(1);
+/// Patches modules inside a filesystem image. The image is extracted to a
+/// temporary directory that is removed afterwards
+fn patch_image(args: ImageArgs, image: Image) -> Result<()> {
+    if args.patch.output.is_some() || args.patch.output_dir.is_some()
+        || args.patch.emit_patch.is_some() || args.patch.keep || args.patch.in_place {
+        Args::command().error(clap::ErrorKind::ArgumentConflict,
+            "image subcommands can not be used with `--output`, `--output-dir`, \
+             `--emit-patch`, `--keep` or `--in-place`, use `--image-output` or \
+             `--overlay`")
+            .exit();
+    }
+
+    let work = tempdir::create("image")?;
+    patch_extracted(args, &image, work.path())
+}
+
+fn patch_extracted(args: ImageArgs, image: &Image, work: &std::path::Path)
+    -> Result<()> {
+
+    let root = work.join("root");
+    info!("Extracting {}", args.image.display());
+    image.extract(&args.image, &root)?;
+
+    let mut patch_args = args.patch;
+    let patterns: Vec<std::path::PathBuf> = patch_args.targets.iter()
+        .map(|target| root.join(target.strip_prefix("/").unwrap_or(target)))
+        .collect();
+    let targets = expand_targets(&patterns)?;
+    patch_args.targets = targets.clone();
+    patch(patch_args)?;
+
+    if let Some(overlay) = &args.overlay {
+        for target in &targets {
+            let dest = overlay.join(target.strip_prefix(&root).unwrap_or(target));
+            let write_err = |e| Error::Write { path: dest.clone(), source: e };
+            if let Some(dir) = dest.parent() {
+                std::fs::create_dir_all(dir).map_err(write_err)?;
+            }
+            std::fs::copy(target, &dest).map_err(write_err)?;
+            info!("Wrote {}", dest.display());
         }
+        return Ok(());
+    }
+
+    // The image is packed next to its destination so it can be renamed into
+    // place
+    let out_path = args.image_output.as_ref().unwrap_or(&args.image);
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(out_path.file_name().unwrap_or_default());
+    tmp_name.push(format!(".{}.tmp", std::process::id()));
+    let tmp_path = out_path.with_file_name(tmp_name);
+    info!("Repacking {} with {} compression", out_path.display(), image.compression());
+    let packed = image.pack(&root, &tmp_path).and_then(|_| {
+        std::fs::rename(&tmp_path, out_path)
+            .map_err(|e| Error::Write { path: out_path.clone(), source: e })
+    });
+    if packed.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    packed
+}
+
+/// Patches the modules of a DKMS build. DKMS only tells success from failure,
+/// so modules that need no patching succeed and every failure exits with 1
+fn dkms(args: DkmsArgs) -> Result<()> {
+    let build = match args.kernel_source_dir {
+        Some(dir) => dir,
+        None => {
+            let release = match args.kernel_release {
+                Some(release) => release,
+                None => running::release().ok_or(Error::RunningRelease)?,
+            };
+            std::path::Path::new("/lib/modules").join(release).join("build")
+        },
+    };
+    let modules = donors::find_files(&args.dir, |name| {
+        name.to_string_lossy().ends_with(".ko")
+    })?;
+    if modules.is_empty() {
+        warn!("No modules found below {}", args.dir.display());
+        return Ok(());
+    }
+
+    // Options are parsed like those given to `patch`
+    let mut argv: Vec<std::ffi::OsString> = vec!["candycorn".into(), "patch".into(),
+                                                  "--symvers".into()];
+    argv.push(build.join("Module.symvers").into());
+    argv.extend(args.patch_options);
+    argv.extend(modules.into_iter().map(Into::into));
+    let patch_args = match Args::parse_with_config(argv).command {
+        Commands::Patch(patch_args) => *patch_args,
+        _ => unreachable!(),
     };
-    
-    // Try to parse target ELF
-    let t_ko = match goblin::elf::Elf::parse(&t_buffer) {
-        Ok(binary) => binary,
+
+    match patch(patch_args) {
+        Ok(()) | Err(Error::NothingToDo { .. }) => Ok(()),
         Err(e) => {
-            eprintln!("Failed to parse target kernel module -- {}", e);
-            std::process::exit(1);
+            error!("{}", e);
+            exit::exit(ExitCode::Failure);
+        },
+    }
+}
+
+fn watch(args: WatchArgs) -> Result<()> {
+    // Options are parsed like those given to `patch`, checked before
+    // watching with the directory standing in for the modules
+    let patch_args = |targets: Vec<std::ffi::OsString>| {
+        let mut argv: Vec<std::ffi::OsString> = vec!["candycorn".into(), "patch".into()];
+        argv.extend(args.patch_options.iter().cloned());
+        argv.push("--".into());
+        argv.extend(targets);
+        match Args::parse_with_config(argv).command {
+            Commands::Patch(patch_args) => output_dir_from_env(*patch_args),
+            _ => unreachable!(),
         }
     };
+    let output_dir = patch_args(vec![args.dir.clone().into()]).output_dir;
 
-    // Check if target has a "__versions" section. If not, exit.
-    // If target kernel was compiled with `CONFIG_MODULE_FORCE_LOAD`, this is
-    // OK as target doesn't need patched
-    let versions_sh = find_section(&t_ko, "__versions");
-    if versions_sh.is_none() {
-        println!("WARNING: `__versions` section not found in target.\n\
-                  This may or may not be a problem depending on if target \
-                  kernel was compiled with `CONFIG_MODULE_FORCE_LOAD`. If \
-                  this configuration is enabled, the target module to patch \
-                  must have a `__versions` section. If disabled, no patching \
-                  is required to force load target.");
-        return;
+    let mut watcher = watch::Watcher::new(&args.dir)?;
+    info!("Watching {} for modules", args.dir.display());
+
+    // Modules patched in place are written again, which must not patch them
+    // another time
+    let mut written: HashMap<std::path::PathBuf, String> = HashMap::new();
+    let settle = std::time::Duration::from_millis(args.settle);
+    loop {
+        let modules: Vec<std::path::PathBuf> = watcher.wait(settle)?.into_iter()
+            .filter(|path| output_dir.as_ref().is_none_or(|dir| !path.starts_with(dir)))
+            .filter(|path| {
+                let sha256 = std::fs::read(path).map(|data| checksum::sha256(&data));
+                sha256.ok() != written.get(path).cloned()
+            })
+            .collect();
+        if modules.is_empty() {
+            continue;
+        }
+
+        match patch(patch_args(modules.iter().map(Into::into).collect())) {
+            Ok(()) | Err(Error::NothingToDo { .. }) => {},
+            // Failed modules are reported and patched again once rebuilt
+            Err(e) => error!("{}", e),
+        }
+        for path in modules {
+            if let Ok(data) = std::fs::read(&path) {
+                written.insert(path, checksum::sha256(&data));
+            }
+        }
+    }
+}
+
+/// Adds the symbol versions of a `db import` source to the sources. Files
+/// that are neither packages nor ELF files but text are read as
+/// `Module.symvers`, other files as kernel images
+fn add_db_source(path: &std::path::Path, sources: &mut Sources,
+                 namespaces: &mut HashMap<String, String>) -> Result<()> {
+
+    if path.is_dir() {
+        let donors = donors::from_dir(path)?;
+        if donors.is_empty() {
+            warn!("No kernel modules with versions found in {}",
+                    path.display());
+        }
+        for donor in donors {
+            sources.add(&donor.path, Some(donor.arch), donor.versions);
+        }
+        return Ok(());
     }
 
-    // TODO: Improve unwrap by returning useful errors
-    let t_versions = get_versions(&t_ko, &t_buffer).unwrap();
+    let data = read_module(path)?;
+    if package::is_package(&data) {
+        return add_package(path, &data, sources, namespaces, &mut HashSet::new());
+    }
+    if !data.starts_with(goblin::elf::header::ELFMAG) {
+        if let Ok(content) = std::str::from_utf8(&data) {
+            let exports = symvers::parse_exports(content).map_err(|e| e.in_file(path))?;
+            sources.add_exports(path, exports.crcs);
+            namespaces.extend(exports.namespaces);
+            return Ok(());
+        }
+    } else {
+        let info = parse_elf(&data).map_err(|e| e.in_file(path))?;
+        if info.header.e_type == goblin::elf::header::ET_REL {
+            namespaces.extend(exports::namespaces(&info, &data)
+                                .map_err(|e| e.in_file(path))?);
+            let ko = parse_module(data).map_err(|e| e.in_file(path))?;
+            let versions = donors::module_versions(&ko).map_err(|e| e.in_file(path))?;
+            sources.add(path, Some(ko.arch), versions);
+            return Ok(());
+        }
+    }
 
-    // Get endianness 
-    // We no longer need the target ELF data and holding it any longer will
-    // prevent updating the backing target buffer
-    drop(t_ko);
+    let bar = progress::start(format!("Reading {}", path.display()), 0);
+    let image = vmlinux::read(&data).map_err(|e| e.in_file(path))?;
+    drop(bar);
+    sources.add(path, Some(image.arch), image.crcs);
+    namespaces.extend(image.namespaces);
+    Ok(())
+}
 
+fn db(args: DbArgs) -> Result<()> {
+    let path = db::path(args.db)?;
     match args.command {
-        Commands::Patch { src, module_layout_version, keep } => {
-            patch(src, module_layout_version, keep, t_buffer, t_versions); 
+        DbCommands::Import { sources: paths, kernel } => {
+            let mut sources = Sources::default();
+            let mut namespaces = HashMap::new();
+            for source in &paths {
+                add_db_source(source, &mut sources, &mut namespaces)?;
+            }
+            if sources.versions.is_empty() {
+                return Err(Error::NothingToDo {
+                    reason: "no symbol versions found in the sources".to_string(),
+                });
+            }
+
+            let imported = sources.versions.len();
+            let versions = sources.versions.into_iter().map(|(name, ver)| {
+                let namespace = namespaces.remove(&name);
+                (name, ver.crc, namespace)
+            });
+            let total = db::import(&path, &kernel, versions)?;
+            info!("Imported {} symbol versions of kernel \"{}\" into {}, {} in total",
+                  imported, kernel, path.display(), total);
+        },
+        DbCommands::List => {
+            let kernels = db::kernels(&path)?;
+            let width = kernels.iter().map(|kernel| kernel.name.len()).max()
+                            .unwrap_or(0);
+            for kernel in kernels {
+                println!("{:width$}  {} symbols", kernel.name, kernel.symbols,
+                         width = width);
+            }
         },
-        Commands::List => {
-            list(t_versions);
+        DbCommands::Remove { kernel } => {
+            if !db::remove(&path, &kernel)? {
+                return Err(Error::Database {
+                    path,
+                    reason: format!("no kernel \"{}\"", kernel),
+                });
+            }
+            info!("Removed kernel \"{}\" from {}", kernel, path.display());
         },
     }
+    Ok(())
+}
+
+/// Runs the entries of a manifest in order as `patch` commands. Entries
+/// failing are reported and the remaining entries still run
+fn run(path: &std::path::Path) -> Result<()> {
+    let entries = manifest::load(path)?;
+    let base = path.parent().unwrap_or(std::path::Path::new(""));
+
+    let mut failures = Vec::new();
+    let mut nothing_to_do = 0;
+    for (idx, entry) in entries.iter().enumerate() {
+        info!("Manifest entry {} of {}", idx + 1, entries.len());
+        let argv = config::apply(&Args::command(), entry.patch_args(base))?;
+        let result = match Args::try_parse_from(argv) {
+            Ok(Args { command: Commands::Patch(patch_args), .. }) => {
+                patch(output_dir_from_env(*patch_args))
+            },
+            Ok(_) => unreachable!(),
+            Err(e) => {
+                // Only the message, not clap's usage
+                let e = e.to_string();
+                let message = e.split("\nUSAGE:").next().unwrap_or_default();
+                let reason = message.trim_start_matches("error: ")
+                                .split_whitespace().collect::<Vec<_>>().join(" ");
+                Err(Error::Manifest { reason: format!("entry {}: {}", idx + 1, reason) }
+                        .in_file(path))
+            },
+        };
+        match result {
+            Ok(()) => {},
+            Err(Error::NothingToDo { .. }) => nothing_to_do += 1,
+            Err(e) => {
+                error!("{}", e);
+                failures.push(e.exit_code());
+            },
+        }
+    }
+
+    if let Some(&code) = failures.first() {
+        let code = if failures.iter().all(|&c| c == code) {
+            code
+        } else {
+            ExitCode::Failure
+        };
+        return Err(Error::EntriesFailed {
+            failed: failures.len(),
+            total: entries.len(),
+            code,
+        });
+    }
+    if nothing_to_do == entries.len() {
+        return Err(Error::NothingToDo {
+            reason: "no manifest entry needed patching".to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Identifies a source file for a provenance note by its SHA-256
+fn file_source(path: &std::path::Path) -> Result<note::Source> {
+    let content = std::fs::read(path)
+                    .map_err(|e| Error::Read { path: path.into(), source: e })?;
+    Ok(note::Source {
+        path: path.display().to_string(),
+        sha256: checksum::sha256(&content),
+    })
+}
+
+fn patch(args: PatchArgs) -> Result<()> {
+    cache::DISABLED.store(args.no_cache, Ordering::Relaxed);
+    if args.report_file.is_some() {
+        logging::capture();
+    }
+
+    // Vermagic explicitly provided by the user takes precedence over source
+    let mut vermagic = args.vermagic;
+    let mut srcversion = args.srcversion;
+    if args.vermagic_from_running {
+        vermagic = Some(running::vermagic().ok_or(Error::RunningVermagic)?);
+    }
 
-    println!("Done!");
+    // Sources are only parsed once regardless of the number of targets.
+    // `System.map`, the kernel image and `Module.symvers` come first so source
+    // modules take precedence
+    let mut sources = Sources::default();
+    let mut namespaces = HashMap::new();
+    let mut gpl_only = HashSet::new();
+    let mut source_ids = Vec::new();
+    if let Some(path) = &args.system_map {
+        let crcs = sysmap::load(path)?;
+        if crcs.is_empty() {
+            warn!("No absolute `__crc_` symbols found in {}",
+                    path.display());
+        }
+        if args.note {
+            source_ids.push(file_source(path)?);
+        }
+        sources.add(path, None, crcs);
+    }
+    if let Some(path) = &args.symtypes {
+        let crcs = symtypes::load(path)?;
+        if crcs.is_empty() {
+            warn!("No exported symbols found in {}", path.display());
+        }
+        if args.note {
+            let mut lines: Vec<String> = crcs.iter()
+                .map(|(name, crc)| format!("0x{:08x}\t{}\n", crc, name))
+                .collect();
+            lines.sort();
+            source_ids.push(note::Source {
+                path: path.display().to_string(),
+                sha256: checksum::sha256(lines.concat().as_bytes()),
+            });
+        }
+        sources.add(path, None, crcs);
+    }
+    if let Some(path) = &args.vmlinux {
+        let data = read_module(path)?;
+        let bar = progress::start(format!("Reading {}", path.display()), 0);
+        let image = vmlinux::read(&data).map_err(|e| e.in_file(path))?;
+        drop(bar);
+        if image.crcs.is_empty() {
+            warn!("No `__crc_` symbols found in {}", path.display());
+        }
+        if args.note {
+            source_ids.push(note::Source {
+                path: path.display().to_string(),
+                sha256: checksum::sha256(&data),
+            });
+        }
+        sources.add(path, Some(image.arch), image.crcs);
+        namespaces.extend(image.namespaces);
+        gpl_only.extend(image.gpl_only);
+    }
+    if let Some(path) = &args.symvers {
+        let exports = symvers::load_exports(path)?;
+        sources.add_exports(path, exports.crcs);
+        namespaces.extend(exports.namespaces);
+        gpl_only.extend(exports.gpl_only);
+        if args.note {
+            source_ids.push(file_source(path)?);
+        }
+    }
+    if !args.src_db.is_empty() {
+        let path = match &args.src_url {
+            Some(url) => std::path::PathBuf::from(url),
+            None => db::path(args.db.clone())?,
+        };
+        for kernel in &args.src_db {
+            let versions = match &args.src_url {
+                Some(url) => lookup::fetch(url, kernel)?,
+                None => db::load(&path, kernel)?,
+            };
+            let name = format!("{}:{}", path.display(), kernel);
+            if args.note {
+                let mut lines: Vec<String> = versions.crcs.iter()
+                    .map(|(name, crc)| format!("0x{:08x}\t{}\n", crc, name))
+                    .collect();
+                lines.sort();
+                source_ids.push(note::Source {
+                    path: name.clone(),
+                    sha256: checksum::sha256(lines.concat().as_bytes()),
+                });
+            }
+            sources.add_exports(std::path::Path::new(&name), versions.crcs);
+            namespaces.extend(versions.namespaces);
+        }
+    }
+    for dir in &args.src_dir {
+        let donors = donors::from_dir(dir)?;
+        if donors.is_empty() {
+            warn!("No kernel modules with versions found in {}",
+                    dir.display());
+        }
+        if args.note {
+            source_ids.push(note::Source {
+                path: dir.display().to_string(),
+                sha256: donors::digest(&donors),
+            });
+        }
+        for donor in donors {
+            sources.add(&donor.path, Some(donor.arch), donor.versions);
+        }
+    }
+    for kernel in &args.src_kernel {
+        info!("Fetching reference kernel {}", kernel);
+        let path = mirror::fetch(kernel)?;
+        let data = read_module(&path)?;
+        if args.note {
+            source_ids.push(note::Source {
+                path: kernel.to_string(),
+                sha256: checksum::sha256(&data),
+            });
+        }
+        add_package(&path, &data, &mut sources, &mut namespaces, &mut gpl_only)?;
+    }
+    if args.clone_versions && args.src.len() != 1 {
+        Args::command().error(clap::ErrorKind::ArgumentConflict,
+            "`--clone-versions` requires exactly one `--src`").exit();
+    }
+    let mut clone = None;
+    for src in &args.src {
+        let data = read_module(src)?;
+        if args.note {
+            source_ids.push(note::Source {
+                path: src.display().to_string(),
+                sha256: checksum::sha256(&data),
+            });
+        }
+        if package::is_package(&data) {
+            if args.clone_versions || args.vermagic_from_src || args.srcversion_from_src {
+                Args::command().error(clap::ErrorKind::ArgumentConflict,
+                    "`--clone-versions`, `--vermagic-from-src` and \
+                     `--srcversion-from-src` require `--src` modules, not \
+                     packages").exit();
+            }
+            add_package(src, &data, &mut sources, &mut namespaces, &mut gpl_only)?;
+            continue;
+        }
+        let s_ko = parse_module(data).map_err(|e| e.in_file(src))?;
+        let s_versions = donors::module_versions(&s_ko).map_err(|e| e.in_file(src))?;
+        if s_versions.is_empty() && s_ko.versions.is_none() {
+            return Err(Error::MissingSection { section: "__versions".to_string() }
+                        .in_file(src));
+        }
+        sources.add(src, Some(s_ko.arch), s_versions);
+        let s_info = parse_elf(&s_ko.buffer).map_err(|e| e.in_file(src))?;
+        namespaces.extend(exports::namespaces(&s_info, &s_ko.buffer)
+                            .map_err(|e| e.in_file(src))?);
+        gpl_only.extend(exports::gpl_only(&s_info, &s_ko.buffer)
+                            .map_err(|e| e.in_file(src))?);
+        if args.clone_versions {
+            let versions = s_ko.versions.as_ref().ok_or_else(|| {
+                Error::MissingSection { section: "__versions".to_string() }
+                    .in_file(src)
+            })?;
+            clone = Some(versions.iter().map(|ver| ver.name.clone()).collect());
+        }
+
+        // `.modinfo` entries are taken from the last source module
+        let s_modinfo = s_ko.modinfo.unwrap_or_default();
+        if args.vermagic_from_src {
+            vermagic = Some(source_modinfo(&s_modinfo, "vermagic")
+                                .map_err(|e| e.in_file(src))?);
+        }
+        if args.srcversion_from_src {
+            srcversion = Some(source_modinfo(&s_modinfo, "srcversion")
+                                .map_err(|e| e.in_file(src))?);
+        }
+    }
+
+    // Installed modules are only searched for versions missing from all
+    // other sources
+    let mut report_sources = Vec::new();
+    let installed = match args.auto_src {
+        Some(dir) => {
+            let dir = dir.or_else(running::modules_dir)
+                        .ok_or(Error::RunningRelease)?;
+            let installed = donors::Installed::open(&dir)?;
+            report_sources.push(format!("{} (installed modules)", dir.display()));
+            namespaces.extend(running::namespaces(&dir));
+            if args.note {
+                source_ids.push(note::Source {
+                    path: dir.display().to_string(),
+                    sha256: installed.sha256.clone(),
+                });
+            }
+            Some(installed)
+        },
+        None => None,
+    };
+
+    // Overrides on the command line take precedence over the overrides file
+    // and "module_layout" provided through its dedicated option takes
+    // precedence over all others
+    let mut overrides = BTreeMap::new();
+    if let Some(path) = &args.overrides {
+        overrides = overrides::load(path)?;
+    }
+    let mut only = args.only;
+    if args.module_layout_from_src {
+        only.push(filter::Pattern::Exact("module_layout".to_string()));
+    }
+    let renames = match &args.rename_map {
+        Some(path) => overrides::load_renames(path)?,
+        None => BTreeMap::new(),
+    };
+    let mut add_versions: BTreeSet<String> = args.add_version.iter()
+                                                .map(|(name, _)| name.clone())
+                                                .collect();
+    if let Some(path) = &args.from_dmesg {
+        let errors = dmesg::load(path.as_deref())?;
+        if errors.is_empty() {
+            return Err(Error::NothingToDo {
+                reason: "no symbol version errors found in the kernel log"
+                            .to_string(),
+            });
+        }
+        info!("Symbols with version errors in the kernel log:");
+        for name in errors.mismatched.iter().chain(&errors.missing) {
+            info!("    \"{}\"", name);
+            let pattern = glob::Pattern::new(&glob::Pattern::escape(name))?;
+            only.push(filter::Pattern::Glob(pattern));
+        }
+        add_versions.extend(errors.missing);
+    }
+    let mut manual = args.set;
+    manual.extend(args.add_version);
+    if let Some(module_layout_version) = args.module_layout_version {
+        manual.push(("module_layout".to_string(), module_layout_version));
+    }
+    let required_overrides = manual.iter().map(|(name, _)| name.clone()).collect();
+    for (name, crc) in manual {
+        let crc = parse_crc(&crc, args.hex)
+                    .map_err(|e| Error::Override {
+                        symbol: name.clone(),
+                        source: Box::new(e),
+                    })?;
+        overrides.insert(name, crc);
+    }
+
+    let mut modinfo_edits: Vec<modinfo::Edit> = args.set_modinfo.into_iter()
+        .map(|(key, value)| modinfo::Edit::Set { key, value })
+        .collect();
+    modinfo_edits.extend(args.delete_modinfo.into_iter()
+                            .map(|key| modinfo::Edit::Delete { key, value: None }));
+    for (key, flag) in [("intree", args.intree), ("retpoline", args.retpoline)] {
+        let key = key.to_string();
+        match flag {
+            Some(true) => modinfo_edits.push(modinfo::Edit::Set {
+                key, value: "Y".to_string(),
+            }),
+            Some(false) => modinfo_edits.push(modinfo::Edit::Delete {
+                key, value: None,
+            }),
+            None => {},
+        }
+    }
+    match args.intree {
+        Some(true) => warn!("Marking targets in-tree hides the \
+                             out-of-tree (O) taint the kernel would \
+                             report for them"),
+        Some(false) => warn!("Loading targets will taint the \
+                              kernel as out-of-tree (O)"),
+        None => {},
+    }
+    if let Some(license) = args.set_license {
+        warn!("Setting the license of targets to \"{}\" does not relicense their \
+               code. Their authors' license still applies, and redistributing \
+               them under another may infringe it", license);
+        if license::is_gpl_compatible(&license) {
+            warn!("A GPL-compatible license gives targets GPL-only symbols and \
+                   hides the proprietary (P) taint from bug reports, only set it \
+                   if the code is actually under that license");
+        } else {
+            warn!("Loading targets will taint the kernel as proprietary (P), \
+                   and they can not use GPL-only symbols");
+        }
+        modinfo_edits.push(modinfo::Edit::Set { key: "license".to_string(),
+                                                value: license });
+    }
+    match args.retpoline {
+        Some(true) => warn!("Marking targets as built with \
+                             retpoline hides the kernel's Spectre v2 \
+                             warning if they were not"),
+        Some(false) => warn!("Retpoline kernels will warn that \
+                              targets may be vulnerable to Spectre v2"),
+        None => {},
+    }
+
+    // Settings from the environment only apply where the command line allows
+    // them
+    let has_sources = !sources.paths.is_empty() || installed.is_some();
+    let require_all = args.require_all
+                        || (has_sources && env_flag("CANDYCORN_STRICT"));
+    let output_dir = args.output_dir;
+    if let Some(dir) = &output_dir {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| Error::Write { path: dir.clone(), source: e })?;
+    }
+
+    report_sources.extend(sources.paths.iter().map(|path| path.display().to_string()));
+
+    // Kernel images and `System.map` lack the exports of modules, which the
+    // installed modules' `modules.symbols` lists
+    let kernel_exports = args.vmlinux.is_some() || args.system_map.is_some();
+    let check_exports = sources.lists_exports || (kernel_exports && installed.is_some());
+    let mut exclude = args.exclude;
+    let mut kbuild_modules = Vec::new();
+    if let Some(dir) = &args.kbuild {
+        let tree = kbuild::read(dir)?;
+        if tree.modules.is_empty() {
+            warn!("No modules listed in {}", dir.join("modules.order").display());
+        }
+        info!("Found {} modules in {}, exporting {} symbols", tree.modules.len(),
+              dir.display(), tree.exports.len());
+        exclude.extend(tree.exports.into_iter().map(filter::Pattern::Exact));
+        kbuild_modules = tree.modules;
+    }
+
+    let plan = PatchPlan {
+        sources: Some(sources).filter(|s| !s.paths.is_empty()),
+        installed,
+        force: args.force,
+        interactive: args.interactive,
+        confirm: args.confirm,
+        show_bytes: args.show_bytes,
+        overrides,
+        required_overrides,
+        hook: args.hook,
+        renames,
+        filter: filter::SymbolFilter { only, exclude },
+        add_versions,
+        vermagic,
+        srcversion,
+        modinfo: modinfo_edits,
+        depends: modinfo::DependsEdit {
+            rename: args.rename_depend,
+            add: args.add_depend,
+            remove: args.remove_depend,
+        },
+        rename_module: args.rename_module,
+        strip_debug: args.strip_debug,
+        strip_btf: args.strip_btf,
+        namespaces,
+        gpl_only,
+        import_ns: args.import_ns,
+        check_exports,
+        keep: args.keep,
+        in_place: args.in_place,
+        backups: args.backup.map(|limit| limit.unwrap_or(5)),
+        output: args.output,
+        output_dir,
+        emit_patch: args.emit_patch,
+        require_all,
+        clone,
+        create_versions: args.create_versions,
+        checksum: args.checksum,
+        checksum_manifest: args.checksum_manifest,
+        expect_sha256: args.expect_sha256,
+        note: args.note.then_some(source_ids),
+        report: args.report_file.map(report::Report::new),
+    };
+
+    // Reviews are held one at a time
+    let jobs = if plan.interactive || plan.confirm { 1 } else { args.jobs };
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(jobs).build()?;
+
+    // Workers share the parsed source through the plan. Results are collected
+    // in target order regardless of completion order
+    let mut targets = expand_targets(&args.targets)?;
+    targets.extend(kbuild_modules);
+    if args.with_deps {
+        let deps = moddeps::collect(&targets)?;
+        info!("Found {} modules the targets depend on", deps.len());
+        targets.extend(deps);
+    }
+
+    // A single output path or stdin can only serve one target
+    let stdin = targets.iter().any(|t| is_stdio(t));
+    let single = plan.output.is_some() || plan.emit_patch.is_some();
+    if (stdin || single) && targets.len() != 1 {
+        Args::command().error(clap::ErrorKind::ArgumentConflict,
+            "`--output`, `--emit-patch` and `-` require exactly one target")
+            .exit();
+    }
+    if plan.rename_module.as_ref().is_some_and(|r| r.old.is_none()) && targets.len() > 1 {
+        Args::command().error(clap::ErrorKind::ArgumentConflict,
+            "`--rename-module` requires OLD=NEW with more than one target").exit();
+    }
+    if stdin && (plan.keep || plan.output_dir.is_some() || plan.in_place) {
+        Args::command().error(clap::ErrorKind::ArgumentConflict,
+            "`--keep`, `--output-dir` and `--in-place` can not be used with `-`").exit();
+    }
+    if plan.output_dir.is_some() {
+        let mut names = HashSet::new();
+        if !targets.iter().all(|target| names.insert(target.file_name())) {
+            Args::command().error(clap::ErrorKind::ArgumentConflict,
+                "`--output-dir` requires targets with distinct file names").exit();
+        }
+    }
+    let stdout = match &plan.output {
+        Some(output) => is_stdio(output),
+        None => stdin && plan.emit_patch.is_none(),
+    };
+    logging::STDOUT_OUTPUT.store(stdout, Ordering::Relaxed);
+
+    // A single target's progress is that of its sources. Reviews share the
+    // terminal with the bar
+    let bar = match targets.len() {
+        _ if plan.interactive || plan.confirm => progress::Bar::default(),
+        1 => progress::Bar::default(),
+        count => progress::start("Patching", count),
+    };
+    let results: Vec<_> = pool.install(|| {
+        targets.par_iter().map(|target| {
+            info!("Patching {}", target.display());
+            let result = patch_target(target, &plan);
+            let status = match &result {
+                Ok(_) => "patched",
+                Err(Error::NothingToDo { .. }) => "nothing to do",
+                Err(e) => {
+                    error!("{}", e);
+                    "failed"
+                },
+            };
+            bar.inc(format!("{} {}", target.display(), status));
+            (target, result)
+        }).collect()
+    });
+    drop(bar);
+
+    // Per-target summary, aligned on the targets' outcomes
+    info!("Summary:");
+    let width = results.iter().map(|(target, _)| target.display().to_string().len())
+                    .max().unwrap_or(0);
+    let paint = |color, text: String| color::paint(logging::info_stream(), color, text);
+    let mut failures = Vec::new();
+    let mut nothing_to_do = 0;
+    let mut totals = PatchStats::default();
+    let mut outcomes = Vec::new();
+    for (target, result) in &results {
+        outcomes.push(report::Outcome {
+            target: target.display().to_string(),
+            result: match result {
+                Ok((out_path, _)) => format!("patched into {}", out_path.display()),
+                Err(e @ Error::NothingToDo { .. }) => e.to_string(),
+                Err(e) => format!("FAILED: {}", e),
+            },
+        });
+        let target = format!("{:<width$}", target.display().to_string(), width = width);
+        match result {
+            Ok((out_path, stats)) => {
+                info!("    {} - {} ({})", target,
+                      paint(color::Color::Green,
+                            format!("patched into {}", out_path.display())),
+                      stats);
+                totals.add(stats);
+            },
+            Err(e @ Error::NothingToDo { .. }) => {
+                info!("    {} - {}", target, paint(color::Color::Yellow, e.to_string()));
+                nothing_to_do += 1;
+            },
+            Err(e) => {
+                info!("    {} - {}", target,
+                      paint(color::Color::Red, format!("FAILED: {}", e)));
+                failures.push(e.exit_code());
+            }
+        }
+    }
+    info!("Total: {}", totals);
+    if let Some(report) = &plan.report {
+        // Targets are patched in parallel, so warnings are captured in no
+        // particular order
+        let mut warnings = logging::captured();
+        if REPRODUCIBLE.load(Ordering::Relaxed) {
+            warnings.sort();
+        }
+        report.write(&report_sources, &outcomes, &warnings)?;
+        info!("Wrote report to {}", report.path().display());
+    }
+
+    // Targets failing for the same reason report that reason, otherwise the
+    // failure is generic
+    if let Some(&code) = failures.first() {
+        let code = if failures.iter().all(|&c| c == code) {
+            code
+        } else {
+            ExitCode::Failure
+        };
+        return Err(Error::TargetsFailed {
+            failed: failures.len(),
+            total: results.len(),
+            code,
+        });
+    }
+    info!("0 of {} targets failed", results.len());
+    if results.is_empty() {
+        return Err(Error::TargetsFailed {
+            failed: 0,
+            total: 0,
+            code: ExitCode::Failure,
+        });
+    }
+    if nothing_to_do == results.len() {
+        return Err(Error::NothingToDo {
+            reason: "no target needed to be patched".to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Counts of how each `__versions` entry of a target was handled
+#[derive(Default)]
+struct PatchStats {
+    total: usize,
+    from_source: usize,
+    overridden: usize,
+    no_source: usize,
+    already_correct: usize,
+    /// Entries left untouched by `--only` and `--exclude`, or declined
+    /// during a review
+    filtered: usize,
+}
+
+impl PatchStats {
+    fn add(&mut self, other: &PatchStats) {
+        self.total += other.total;
+        self.from_source += other.from_source;
+        self.overridden += other.overridden;
+        self.no_source += other.no_source;
+        self.already_correct += other.already_correct;
+        self.filtered += other.filtered;
+    }
+}
+
+impl std::fmt::Display for PatchStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} entries, {} patched from source, {} overridden manually, \
+                   {} without source, {} already correct", self.total,
+                   self.from_source, self.overridden, self.no_source,
+                   self.already_correct)?;
+        if self.filtered > 0 {
+            write!(f, ", {} filtered out", self.filtered)?;
+        }
+        Ok(())
+    }
+}
+
+/// Ensures the target versions the same set of symbols as the source it
+/// clones its versions from, listing the symbols only one of them versions
+fn check_same_symbols(t_versions: &[SymVersion], s_names: &BTreeSet<String>)
+    -> Result<()> {
+
+    let t_names: BTreeSet<String> = t_versions.iter()
+                                        .map(|v| v.name.clone()).collect();
+    let only_target: Vec<_> = t_names.difference(s_names).collect();
+    let only_source: Vec<_> = s_names.difference(&t_names).collect();
+    if only_target.is_empty() && only_source.is_empty() {
+        return Ok(());
+    }
+
+    for (names, place) in [(&only_target, "target"), (&only_source, "source")] {
+        if !names.is_empty() {
+            let names: String = names.iter().map(|name| format!("\n    \"{}\"", name))
+                                    .collect();
+            error!("Versions only in {}:{}", place, names);
+        }
+    }
+    Err(Error::SymbolSetMismatch {
+        only_target: only_target.len(),
+        only_source: only_source.len(),
+    })
+}
+
+/// Checks if writing to `out_path` writes over the target. Paths are compared
+/// by the file they name, so other spellings of the target's path, symlinks
+/// and hard links to it count as the target
+fn writes_over(target: &std::path::Path, out_path: &std::path::Path) -> bool {
+    !is_stdio(target) && !is_stdio(out_path) && output::same_file(target, out_path)
+}
+
+/// Locks a target written over until it is written, against other processes
+/// writing it at the same time. Targets written elsewhere and stdin are not
+/// locked
+fn lock_target(target: &std::path::Path, overwrites: bool) -> Result<Option<lock::Lock>> {
+    if !overwrites {
+        return Ok(None);
+    }
+    lock::acquire(target).map(Some)
+}
+
+/// Writes the result of `revert`, `strip-versions` or `apply` to its output,
+/// which is the target unless given. Output to `-` goes to stdout
+fn write_output(target: &std::path::Path, out_path: &std::path::Path, data: &[u8])
+    -> Result<()> {
+
+    let written = if is_stdio(out_path) {
+        use std::io::Write;
+        std::io::stdout().lock().write_all(data)
+    } else {
+        let original = Some(target).filter(|t| !is_stdio(t));
+        output::write_atomic(out_path, data, original)
+    };
+    written.map_err(|e| Error::Write { path: out_path.to_path_buf(), source: e })
+}
+
+/// Applies the patch plan to a single target, writing the result and
+/// returning the path of the patched output along with patch statistics
+fn patch_target(target: &std::path::Path, plan: &PatchPlan)
+    -> Result<(std::path::PathBuf, PatchStats)> {
+
+    let out_path = patch_out_path(target, plan);
+    let overwrites = plan.emit_patch.is_none() && writes_over(target, &out_path);
+    let _lock = lock_target(target, overwrites)?;
+
+    let name = if is_stdio(target) { "<stdin>".into() } else { target.to_path_buf() };
+    let t_buffer = map_target(target).map_err(|e| e.in_file(&name))?;
+
+    // Only hash the target when needed as modules can be large
+    let t_sha256 = if plan.checksum || plan.expect_sha256.is_some() {
+        Some(checksum::sha256(&t_buffer))
+    } else {
+        None
+    };
+    if let (Some(expected), Some(actual)) = (&plan.expect_sha256, &t_sha256) {
+        if expected != actual {
+            return Err(Error::ChecksumMismatch {
+                expected: expected.clone(),
+                actual: actual.clone(),
+            }.in_file(target));
+        }
+    }
+
+    // Archives such as initramfs images are patched module by module and
+    // written back whole
+    let vendor_boot = android::is_vendor_boot(&t_buffer);
+    if vendor_boot || cpio::is_archive(&t_buffer)
+        || compress::Format::detect(&t_buffer).is_some() {
+        if plan.emit_patch.is_some() {
+            return Err(Error::Unsupported {
+                reason: "patch files can not be emitted for archives".to_string(),
+            }.in_file(name));
+        }
+        if plan.in_place {
+            return Err(Error::Unsupported {
+                reason: "archives can not be patched in place".to_string(),
+            }.in_file(name));
+        }
+        let mut stats = PatchStats::default();
+        let data = if vendor_boot {
+            patch_vendor_boot(target, &t_buffer, plan, &mut stats)?
+        } else {
+            patch_archive(target, &t_buffer, plan, &mut stats)?
+        };
+        if stats.total == 0 {
+            return Err(Error::NothingToDo {
+                reason: "no modules with symbol versions in archive".to_string(),
+            });
+        }
+        write_target(target, &out_path, overwrites, &data, &[], plan, t_sha256)?;
+        return Ok((out_path, stats));
+    }
+
+    let t_ko = parse_module(t_buffer).map_err(|e| e.in_file(name))?;
+    let original = plan.emit_patch.as_ref().map(|_| t_ko.buffer.to_vec());
+    let resize = original.is_none() && !plan.in_place;
+    let (data, stats, changed) = patch_module(target, t_ko, plan, resize)?;
+
+    // Only the changes are written when emitting a patch file
+    if let (Some(path), Some(original)) = (&plan.emit_patch, &original) {
+        let file = target.file_name().unwrap_or(target.as_os_str())
+                    .to_string_lossy();
+        let patch_file = patchfile::PatchFile::from_diff(&file, original, &data);
+        patch_file.save(path)?;
+        info!("Wrote {} changes to {}", patch_file.writes.len(), path.display());
+        return Ok((path.clone(), stats));
+    }
+
+    write_target(target, &out_path, overwrites, &data, &changed, plan, t_sha256)?;
+    Ok((out_path, stats))
+}
+
+/// Patches each module in a cpio archive, or in a compressed cpio archive,
+/// and rebuilds the archive around them. Compressed modules are recompressed
+/// in their format. Initramfs images may concatenate several archives, such
+/// as an uncompressed one with CPU microcode followed by a compressed one, so
+/// data following an archive is patched as well
+fn patch_archive(target: &std::path::Path, data: &[u8], plan: &PatchPlan,
+                 stats: &mut PatchStats) -> Result<Vec<u8>> {
+
+    if let Some(format) = compress::Format::detect(data) {
+        let inner = compress::decompress_stream(format, data)
+                        .map_err(|e| e.in_file(target))?;
+        let patched = patch_archive(target, &inner, plan, stats)?;
+        return compress::compress(format, &patched);
+    }
+    if !cpio::is_archive(data) {
+        return Err(Error::Unsupported {
+            reason: "data is neither a module nor a cpio archive".to_string(),
+        }.in_file(target));
+    }
+
+    let archive = cpio::Archive::parse(data).map_err(|e| e.in_file(target))?;
+    let mut replaced = HashMap::new();
+    for (idx, entry) in archive.entries.iter().enumerate() {
+        let path = std::path::Path::new(&entry.name);
+        let format = compress::Format::from_path(path);
+        if !entry.name.ends_with(".ko") && !format.is_some_and(|format| {
+            entry.name.ends_with(&format!(".ko.{}", format.extension()))
+        }) {
+            continue;
+        }
+
+        let member = target.join(entry.name.trim_start_matches('/'));
+        info!("Patching {}", member.display());
+        let content = archive.content(entry);
+        let module = match format {
+            Some(format) => compress::decompress_stream(format, content)
+                                .map_err(|e| e.in_file(&member))?,
+            None => content.to_vec(),
+        };
+        let buffer = map_copy(&module)
+                        .map_err(|e| Error::Read { path: member.clone(), source: e })?;
+        let t_ko = parse_module(buffer).map_err(|e| e.in_file(&member))?;
+        let (patched, member_stats, _) = match patch_module(&member, t_ko, plan, true) {
+            Ok(result) => result,
+            Err(Error::NothingToDo { .. }) => continue,
+            Err(e) => return Err(e),
+        };
+        stats.add(&member_stats);
+        replaced.insert(idx, match format {
+            Some(format) => compress::compress(format, &patched)?,
+            None => patched.to_vec(),
+        });
+    }
+
+    let mut out = archive.rebuild(&replaced);
+    let rest = &data[archive.end ..];
+    let padding = rest.iter().take_while(|&&b| b == 0).count();
+    out.extend_from_slice(&rest[.. padding]);
+    if padding < rest.len() {
+        out.extend(patch_archive(target, &rest[padding ..], plan, stats)?);
+    }
+    Ok(out)
+}
+
+/// Patches the modules in the vendor ramdisks of an Android vendor boot image
+fn patch_vendor_boot(target: &std::path::Path, data: &[u8], plan: &PatchPlan,
+                     stats: &mut PatchStats) -> Result<Vec<u8>> {
+
+    let image = android::VendorBoot::parse(data).map_err(|e| e.in_file(target))?;
+    let ramdisks = image.ramdisks()
+        .map(|ramdisk| match ramdisk.is_empty() {
+            true => Ok(Vec::new()),
+            false => patch_archive(target, ramdisk, plan, stats),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(image.rebuild(&ramdisks))
+}
+
+/// Applies the plan to a parsed module, returning the patched module along
+/// with the ranges patching may have changed. Modules are only resized, for
+/// `.modinfo` edits and notes, if `resize` is set
+fn patch_module(target: &std::path::Path, mut t_ko: KernelModule<memmap2::MmapMut>,
+                plan: &PatchPlan, resize: bool)
+    -> Result<(memmap2::MmapMut, PatchStats, Vec<std::ops::Range<usize>>)> {
+
+    // Executables and shared libraries are easily picked by mistake
+    let info = parse_elf(&t_ko.buffer).map_err(|e| e.in_file(target))?;
+    if let Some(reason) = check_module_type(&info) {
+        if !plan.force {
+            return Err(Error::NotAModule { reason }.in_file(target));
+        }
+        warn!("Target is not a kernel module: {}", reason);
+    }
+
+    // Stripping moves sections, so it comes before any offsets are taken
+    if plan.strip_debug {
+        t_ko = strip_sections(&t_ko.buffer, "debug",
+                              |name| name.starts_with(".debug_"))
+                .map_err(|e| e.in_file(target))?;
+    }
+    if plan.strip_btf {
+        t_ko = strip_sections(&t_ko.buffer, "BTF",
+                              |name| name == ".BTF" || name.starts_with(".BTF."))
+                .map_err(|e| e.in_file(target))?;
+    }
+
+    if t_ko.versions.is_none() && plan.create_versions {
+        t_ko = create_versions(&t_ko.buffer, plan).map_err(|e| e.in_file(target))?;
+    }
+
+    // Added entries start with a zero CRC and are patched with the overrides
+    let t_names: HashSet<&str> = t_ko.versions.iter().flatten()
+                                    .map(|v| v.name.as_str()).collect();
+    let added: Vec<(String, u64)> = plan.add_versions.iter()
+        .filter(|name| !t_names.contains(name.as_str()))
+        .map(|name| (name.clone(), 0))
+        .collect();
+    if !added.is_empty() {
+        for (name, _) in &added {
+            info!("Adding version \"{}\" to target", name);
+        }
+        t_ko = add_versions(&t_ko.buffer, &added).map_err(|e| e.in_file(target))?;
+    }
+    let t_arch = t_ko.arch;
+    let layout = t_ko.layout;
+    let mut t_buffer = t_ko.buffer;
+    // Targets written in place are compared against their file rather than
+    // a copy
+    let input: Box<dyn std::ops::Deref<Target = [u8]>> = if plan.in_place {
+        Box::new(map_file(target)?)
+    } else {
+        Box::new(t_buffer.to_vec())
+    };
+
+    // If target kernel was compiled with `CONFIG_MODULE_FORCE_LOAD`, a
+    // missing `__versions` section is OK as target doesn't need patched
+    let t_versions = match t_ko.versions {
+        Some(versions) => versions,
+        None => {
+            warn!("`__versions` section not found in target.\n\
+                  This may or may not be a problem depending on if target \
+                  kernel was compiled with `CONFIG_MODULE_FORCE_LOAD`. If \
+                  this configuration is enabled, the target module to patch \
+                  must have a `__versions` section. If disabled, no patching \
+                  is required to force load target.");
+
+            // Only `.modinfo` edits can still apply
+            if plan.vermagic.is_none() && plan.srcversion.is_none()
+                && plan.modinfo.is_empty() && plan.depends.is_empty()
+                && plan.rename_module.is_none() {
+                return Err(Error::NothingToDo {
+                    reason: "`__versions` section not found".to_string(),
+                });
+            }
+            Vec::new()
+        }
+    };
+
+    if let Some(clone) = &plan.clone {
+        check_same_symbols(&t_versions, clone).map_err(|e| e.in_file(target))?;
+    }
+
+    let mut stats = PatchStats {
+        total: t_versions.len(),
+        ..Default::default()
+    };
+    let mut patched = Vec::new();
+    // Where each patched entry's CRC came from, by offset
+    let mut origins = HashMap::new();
+    // CRCs the sources have for target entries, by offset
+    let mut source_crcs = HashMap::new();
+    if plan.sources.is_some() || plan.installed.is_some() {
+        // Manual overrides are applied later and take precedence
+        let wanted: Vec<&SymVersion> = t_versions.iter()
+            .filter(|v| !plan.overrides.contains_key(&v.name))
+            .collect();
+        let (wanted, filtered): (Vec<&SymVersion>, Vec<&SymVersion>) =
+            wanted.into_iter().partition(|v| plan.filter.matches(&v.name));
+        for t_ver in &filtered {
+            info!("Leaving version \"{}\" in target untouched", t_ver.name);
+        }
+        stats.filtered = filtered.len();
+
+        // Installed modules are only searched for versions the other sources
+        // lack
+        let installed = plan.installed.as_ref().map(|installed| {
+            let missing: Vec<&str> = wanted.iter()
+                .filter(|v| !plan.in_sources(&v.name))
+                .flat_map(|v| plan.source_names(&v.name))
+                .collect();
+            installed.resolve(&missing)
+        }).unwrap_or_default();
+
+        // Duplicate entries in the target are all patched
+        let mut misses = Vec::new();
+        let mut renamed = BTreeMap::new();
+        let mut mismatched = std::collections::HashSet::new();
+        for t_ver in wanted {
+            let name = &t_ver.name;
+            trace!("\"{}\" at offset 0x{:x} has CRC 0x{:x}", name, t_ver.offset,
+                   t_ver.crc);
+            let lookup = |n: &str| plan.sources.as_ref().and_then(|s| s.get(n))
+                .or_else(|| installed.get(n)
+                                .map(|r| (r.crc, r.path.as_path(), Some(r.arch))));
+            let found = plan.source_names(name)
+                            .find_map(|n| Some((n, lookup(n)?)));
+            if let Some((s_name, _)) = found.filter(|(s_name, _)| s_name != name) {
+                renamed.insert(name.as_str(), s_name);
+            }
+            let source = found.map(|(_, source)| source);
+            if let Some((s_crc, _, _)) = source {
+                source_crcs.insert(t_ver.offset, s_crc);
+            }
+
+            // CRCs of other architectures are meaningless for the target
+            if let Some((_, s_path, Some(s_arch))) = source {
+                if s_arch != t_arch && mismatched.insert(s_path) {
+                    let e = Error::ArchMismatch {
+                        donor: s_path.to_path_buf(),
+                        donor_arch: s_arch.to_string(),
+                        arch: t_arch.to_string(),
+                    }.in_file(target);
+                    if !plan.force {
+                        return Err(e);
+                    }
+                    warn!("{}", e);
+                }
+            }
+
+            match source {
+                Some((s_crc, s_path, _)) if s_crc == t_ver.crc => {
+                    debug!("Version \"{}\" already matches {}", name, s_path.display());
+                    stats.already_correct += 1;
+                },
+                Some((s_crc, s_path, _)) => {
+                    info!(
+                        "Patching version \"{}\" in target with CRC 0x{:x} from {}",
+                        name, s_crc, s_path.display());
+                    layout.of(t_ver)
+                        .write_crc(&mut t_buffer, name, t_ver.offset, s_crc)
+                        .map_err(|e| e.in_file(target))?;
+                    check_crc(t_ver, s_crc, &s_path.display().to_string());
+                    stats.from_source += 1;
+                    origins.insert(t_ver.offset, s_path.display().to_string());
+                    patched.push(note::PatchedSymbol {
+                        name: name.clone(),
+                        offset: t_ver.offset,
+                        original: t_ver.crc,
+                        crc: s_crc,
+                    });
+                },
+                None => {
+                    debug!("No source has a version of \"{}\"", name);
+                    misses.push(t_ver);
+                },
+            }
+        }
+
+        if !renamed.is_empty() {
+            info!("Versions found under renamed symbols:");
+            for (name, s_name) in &renamed {
+                info!("    \"{}\" as \"{}\"", name, s_name);
+            }
+        }
+        if !misses.is_empty() {
+            let list: String = misses.iter()
+                .map(|t_ver| format!("\n    \"{}\" - 0x{:x}", t_ver.name, t_ver.crc))
+                .collect();
+            warn!("Target versions not found in sources:{}", list);
+            if plan.require_all {
+                return Err(Error::MissingVersions { count: misses.len() }
+                            .in_file(target));
+            }
+        }
+    }
+
+    // If user provided crcs manually, apply them now. This will overwrite the
+    // versions provided by the source kernel module if they existed
+    let mut overridden = HashSet::new();
+    for (name, crc) in &plan.overrides {
+        let matches: Vec<_> = t_versions.iter().filter(|v| &v.name == name)
+                                .collect();
+        if matches.is_empty() {
+            if plan.required_overrides.contains(name) {
+                return Err(Error::MissingSymbol { symbol: name.clone() }
+                            .in_file(target));
+            }
+            debug!("Target has no version of \"{}\" to override", name);
+            continue;
+        }
+        for t_ver in matches {
+            info!("Patching \"{}\" in target with CRC 0x{:x}", name, crc);
+            layout.of(t_ver).write_crc(&mut t_buffer, name, t_ver.offset, *crc)
+                .map_err(|e| e.in_file(target))?;
+            check_crc(t_ver, *crc, "the overrides");
+            stats.overridden += 1;
+            overridden.insert(t_ver.offset);
+            origins.insert(t_ver.offset, "manual".to_string());
+            patched.push(note::PatchedSymbol {
+                name: name.clone(),
+                offset: t_ver.offset,
+                original: t_ver.crc,
+                crc: *crc,
+            });
+        }
+    }
+    if let Some(hook) = &plan.hook {
+        let hooked = HookedTarget {
+            target,
+            t_versions: &t_versions,
+            modinfo: t_ko.modinfo.as_deref(),
+            source_crcs: &source_crcs,
+            layout,
+        };
+        apply_hook(hook, &hooked, &mut t_buffer, &mut patched, &mut overridden,
+                   &mut origins, &mut stats, plan)?;
+    }
+    stats.no_source = stats.total - stats.from_source - stats.overridden
+                        - stats.already_correct - stats.filtered;
+
+    // Vermagic and srcversion are only replaced, never added
+    let mut modinfo_edits = Vec::new();
+    let replaced = [("vermagic", &plan.vermagic), ("srcversion", &plan.srcversion)];
+    for (key, value) in replaced {
+        if let Some(value) = value {
+            t_ko.modinfo.as_deref().and_then(|m| modinfo::find_entry(m, key))
+                .ok_or(Error::MissingModInfo { key: key.to_string() }.in_file(target))?;
+            modinfo_edits.push(modinfo::Edit::Set {
+                key: key.to_string(),
+                value: value.clone(),
+            });
+        }
+    }
+    if !plan.depends.is_empty() {
+        let depends = t_ko.modinfo.as_deref()
+                        .and_then(|m| modinfo::find_entry(m, "depends"))
+                        .map_or("", |entry| entry.value.as_str());
+        modinfo_edits.push(modinfo::Edit::Set {
+            key: "depends".to_string(),
+            value: plan.depends.apply(depends),
+        });
+    }
+    modinfo_edits.extend(plan.modinfo.iter().cloned());
+
+    // Newer kernels refuse namespaced symbols unless their namespace is
+    // imported
+    let mut imports = parse_elf(&t_buffer)
+        .and_then(|info| modversions::undefined_symbols(&info, &t_buffer))
+        .map_err(|e| e.in_file(target))?;
+    if imports.is_empty() {
+        imports = t_versions.iter().map(|v| v.name.clone()).collect();
+    }
+    if plan.check_exports {
+        check_exports(target, &t_buffer, plan)?;
+    }
+    let missing_ns = namespaces::missing_imports(&imports, &plan.namespaces,
+                                                 t_ko.modinfo.as_deref());
+    if !missing_ns.is_empty() && plan.import_ns {
+        for namespace in missing_ns.keys() {
+            modinfo_edits.push(modinfo::Edit::Add {
+                key: "import_ns".to_string(),
+                value: namespace.clone(),
+            });
+        }
+    } else if !missing_ns.is_empty() {
+        warn!("Target uses namespaced symbols without importing \
+               their namespace, use `--import-ns` to add the imports:{}",
+              namespaces::describe_missing(&missing_ns));
+    }
+
+    // The kernel refuses GPL-only symbols to modules of other licenses
+    let license = modinfo::edited_value(t_ko.modinfo.as_deref().unwrap_or_default(),
+                                        &modinfo_edits, "license");
+    let refused = license::refused_imports(&imports, &plan.gpl_only, license.as_deref());
+    if !refused.is_empty() {
+        let list: String = refused.iter().map(|name| format!("\n    \"{}\"", name))
+                            .collect();
+        warn!("Target imports GPL-only symbols, loading it will fail with \"Unknown \
+               symbol\" as its license is {}:{}",
+              license.map_or("missing".to_string(), |l| format!("\"{}\"", l)), list);
+    }
+
+    let info = parse_elf(&t_buffer).map_err(|e| e.in_file(target))?;
+    check_special_module(&info, t_ko.modinfo.as_deref());
+
+    let mut renamed_field = None;
+    if let Some(rename) = &plan.rename_module {
+        renamed_field = rename_module(target, &mut t_buffer, rename,
+                                      t_ko.modinfo.as_deref(), &mut modinfo_edits)?;
+    }
+
+    let entries = t_ko.modinfo.as_deref().unwrap_or_default();
+    let name = modinfo::edited_value(entries, &modinfo_edits, "name")
+                .or_else(|| Some(module_stem(target)).filter(|_| !is_stdio(target)));
+    if let Some(name) = name {
+        let name = modinfo::module_name(&name);
+        if let Some(loaded) = running::loaded_module(&name) {
+            let srcversion = modinfo::edited_value(entries, &modinfo_edits, "srcversion");
+            let version = modinfo::edited_value(entries, &modinfo_edits, "version");
+            warn!("The running kernel already has {}. Loading the patched module fails \
+                   with \"File exists\"{}",
+                  describe_loaded(&name, &loaded, srcversion.as_deref(),
+                                  version.as_deref()),
+                  if loaded.builtin { "" } else { " until it is unloaded" });
+        }
+    }
+
+    if plan.interactive || plan.confirm {
+        review_target(target, &t_versions, &mut t_buffer, &mut patched, &overridden,
+                      &mut stats, t_ko.modinfo.as_deref(), &modinfo_edits,
+                      Some(&origins).filter(|_| plan.confirm), layout)?;
+    }
+
+    if plan.show_bytes {
+        show_bytes(&t_versions, &patched, &input, &t_buffer);
+    }
+
+    // Patch files can only describe edits in place
+    let (t_buffer, patched_modinfo) = edit_modinfo(
+        t_buffer, t_ko.modinfo.as_deref(), &modinfo_edits, resize)
+        .map_err(|e| e.in_file(target))?;
+
+    let patched_offsets: HashMap<usize, u64> = patched.iter()
+                                                .map(|sym| (sym.offset, sym.crc))
+                                                .collect();
+    if let Some(report) = &plan.report {
+        report.add(report::Module {
+            path: target.to_path_buf(),
+            arch: t_arch.to_string(),
+            stats: stats.to_string(),
+            symbols: patched.iter().map(|sym| report::Change {
+                name: sym.name.clone(),
+                offset: sym.offset,
+                original: sym.original,
+                crc: sym.crc,
+                source: origins.remove(&sym.offset).unwrap_or_default(),
+            }).collect(),
+            modinfo: patched_modinfo.clone(),
+            vermagic: t_ko.modinfo.as_deref()
+                        .and_then(|m| modinfo::find_entry(m, "vermagic"))
+                        .map(|entry| entry.value.clone()),
+        });
+    }
+
+    // The note grows the module so it is added to a copy
+    let data = match plan.note.as_ref().filter(|_| resize) {
+        Some(source) => {
+            let mut provenance = note::Provenance {
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                sources: source.clone(),
+                timestamp: unix_time(),
+                symbols: patched,
+                modinfo: patched_modinfo,
+            };
+            if let Some(earlier) = t_ko.provenance {
+                provenance.merge_earlier(earlier);
+            }
+            let data = note::append(&t_buffer, &provenance)
+                        .map_err(|e| e.in_file(target))?;
+            map_copy(&data).map_err(|e| Error::Read { path: target.into(), source: e })?
+        },
+        None => t_buffer,
+    };
+    let changeable = changeable_ranges(&input, &t_versions, &patched_offsets, layout,
+                                       !modinfo_edits.is_empty(),
+                                       renamed_field.as_slice())
+                        .map_err(|e| e.in_file(target))?;
+    // Only the changeable ranges are written in place, so bytes outside them
+    // can not reach the target and are not compared
+    verify_patched(&input, &data, &t_versions, &patched_offsets,
+                   Some(changeable.as_slice()).filter(|_| !plan.in_place))
+        .map_err(|e| e.in_file(target))?;
+    Ok((data, stats, changeable))
+}
+
+/// Finds the ranges of a module patching may change: the patched CRC fields,
+/// other fields such as the module name, and `.modinfo` if it was edited
+fn changeable_ranges(input: &[u8], t_versions: &[SymVersion],
+                     patched: &HashMap<usize, u64>, layout: VersionLayout,
+                     modinfo: bool, fields: &[std::ops::Range<usize>])
+    -> Result<Vec<std::ops::Range<usize>>> {
+
+    let mut changeable: Vec<std::ops::Range<usize>> = t_versions.iter()
+        .filter(|t_ver| patched.contains_key(&t_ver.offset))
+        .map(|t_ver| t_ver.offset .. t_ver.offset + layout.of(t_ver).crc_size)
+        .chain(fields.iter().cloned())
+        .collect();
+    if modinfo {
+        let input_info = parse_elf(input)?;
+        if let Some(sh) = find_section(&input_info, ".modinfo") {
+            let start = sh.sh_offset as usize;
+            changeable.push(start .. start + sh.sh_size as usize);
+        }
+    }
+    Ok(changeable)
+}
+
+/// Checks a patched module before it is written, to catch writes at wrong
+/// offsets. The module must still parse with every version entry holding its
+/// intended CRC and, unless it was resized, differ from the input only in the
+/// changeable ranges, if given
+fn verify_patched(input: &[u8], data: &[u8], t_versions: &[SymVersion],
+                  patched: &HashMap<usize, u64>,
+                  changeable: Option<&[std::ops::Range<usize>]>) -> Result<()> {
+
+    let failed = |reason: String| Err(Error::Verify { reason });
+    let info = parse_elf(data)?;
+    let versions = get_versions(&info, data)?.unwrap_or_default();
+    if versions.len() != t_versions.len() {
+        return failed(format!("{} version entries, expected {}", versions.len(),
+                              t_versions.len()));
+    }
+    for (ver, t_ver) in versions.iter().zip(t_versions) {
+        let crc = patched.get(&t_ver.offset).copied().unwrap_or(t_ver.crc);
+        if ver.name != t_ver.name || ver.crc != crc {
+            return failed(format!("entry \"{}\" has CRC 0x{:x}, expected \"{}\" with \
+                                   0x{:x}", ver.name, ver.crc, t_ver.name, crc));
+        }
+    }
+
+    // Resized modules have their sections moved
+    let changeable = match changeable {
+        Some(changeable) if data.len() == input.len() => changeable,
+        Some(_) => {
+            debug!("Not comparing bytes of resized module");
+            return Ok(());
+        },
+        None => return Ok(()),
+    };
+    let unexpected = input.iter().zip(data).enumerate().find(|(offset, (a, b))| {
+        a != b && !changeable.iter().any(|range| range.contains(offset))
+    });
+    if let Some((offset, (before, after))) = unexpected {
+        return failed(format!("byte at offset 0x{:x} changed from 0x{:02x} to 0x{:02x} \
+                               outside the patched fields", offset, before, after));
+    }
+    Ok(())
+}
+
+/// Renames a target in its `struct module`, returning the range of the name
+/// field. The kernel reports and blacklists modules by their `.modinfo` name
+/// and modprobe resolves them by it, so `name` is set and the old name kept
+/// as an alias ahead of other `.modinfo` edits. Targets other than the one
+/// renamed by OLD=NEW have their dependency on it renamed instead
+fn rename_module(target: &std::path::Path, t_buffer: &mut [u8], rename: &ModuleRename,
+                 modinfo: Option<&[modinfo::ModInfoEntry]>,
+                 edits: &mut Vec<modinfo::Edit>)
+    -> Result<Option<std::ops::Range<usize>>> {
+
+    let info = parse_elf(t_buffer).map_err(|e| e.in_file(target))?;
+    let field = thismodule::find_name(&info, t_buffer).map_err(|e| e.in_file(target))?;
+    match (&rename.old, &field) {
+        (Some(old), Some(field))
+            if modinfo::module_name(old) == modinfo::module_name(&field.name) => {},
+        (Some(old), _) => {
+            rename_dependency(old, &rename.new, modinfo, edits);
+            return Ok(None);
+        },
+        (None, _) => {},
+    }
+    let field = field.ok_or(Error::MissingSection {
+                                section: thismodule::SECTION.to_string(),
+                            }.in_file(target))?;
+    let exports = ["__ksymtab", "__ksymtab_gpl"].iter()
+                    .any(|s| find_section(&info, s).is_some_and(|sh| sh.sh_size > 0));
+    let name = &rename.new;
+
+    if &field.name == name {
+        info!("Module is already named \"{}\"", name);
+    } else {
+        thismodule::rename(t_buffer, &field, name).map_err(|e| e.in_file(target))?;
+        info!("Renaming module \"{}\" to \"{}\"", field.name, name);
+    }
+
+    let old = &field.name;
+    edits.insert(0, modinfo::Edit::Set { key: "name".to_string(), value: name.clone() });
+    let aliased = modinfo.into_iter().flatten().any(|entry| {
+        entry.key == "alias"
+            && modinfo::module_name(&entry.value) == modinfo::module_name(old)
+    });
+    if modinfo::module_name(old) != modinfo::module_name(name) && !aliased {
+        edits.insert(1, modinfo::Edit::Add { key: "alias".to_string(),
+                                             value: old.clone() });
+    }
+    if exports {
+        warn!("Module exports symbols, so the kernel refuses to load it alongside \
+               the original exporting the same symbols");
+    }
+    Ok(Some(field.offset .. field.offset + field.len))
+}
+
+/// Renames a dependency of a target on a renamed module, after any `depends`
+/// edits already made
+fn rename_dependency(old: &str, new: &str, modinfo: Option<&[modinfo::ModInfoEntry]>,
+                     edits: &mut Vec<modinfo::Edit>) {
+    let edited = edits.iter().rev().find_map(|edit| match edit {
+        modinfo::Edit::Set { key, value } if key == "depends" => Some(value.as_str()),
+        _ => None,
+    });
+    let depends = match edited.or_else(|| {
+        modinfo.and_then(|m| modinfo::find_entry(m, "depends"))
+            .map(|entry| entry.value.as_str())
+    }) {
+        Some(depends) => depends,
+        None => return,
+    };
+    if !depends.split(',').any(|d| modinfo::module_name(d) == modinfo::module_name(old)) {
+        return;
+    }
+    let edit = modinfo::DependsEdit {
+        rename: vec![(old.to_string(), new.to_string())],
+        ..Default::default()
+    };
+    info!("Renaming dependency on \"{}\" to \"{}\"", old, new);
+    let value = edit.apply(depends);
+    edits.push(modinfo::Edit::Set { key: "depends".to_string(), value });
+}
+
+/// Machines Linux runs on, whose kernels load modules
+const LINUX_MACHINES: &[u16] = {
+    use goblin::elf::header::*;
+    &[EM_386, EM_X86_64, EM_ARM, EM_AARCH64, EM_PPC, EM_PPC64, EM_S390, EM_MIPS,
+      EM_RISCV, EM_SPARC, EM_SPARCV9, EM_PARISC, EM_SH, EM_IA_64, EM_68K, EM_XTENSA,
+      EM_ARC_COMPACT, EM_ARC_COMPACT2, EM_OPENRISC, EM_MICROBLAZE, EM_ALTERA_NIOS2,
+      EM_CSKY, EM_NDS32, EM_H8_300,
+      // Hexagon, LoongArch and Alpha, which goblin has no constants for
+      164, 258, 0x9026]
+};
+
+/// Checks that a target looks like a kernel module, returning why not if it
+/// does not. Modules are relocatable objects for a machine Linux runs on,
+/// with `.modinfo` or `struct module`
+fn check_module_type(info: &goblin::elf::Elf) -> Option<String> {
+    use goblin::elf::header;
+
+    if info.header.e_type != header::ET_REL {
+        return Some(format!("ELF type is {} rather than REL, the type of kernel \
+                             modules", header::et_to_str(info.header.e_type)));
+    }
+    if !LINUX_MACHINES.contains(&info.header.e_machine) {
+        return Some(format!("machine {} is not one Linux runs on",
+                            header::machine_to_str(info.header.e_machine)));
+    }
+    if find_section(info, ".modinfo").is_none()
+        && find_section(info, thismodule::SECTION).is_none() {
+        return Some(format!("neither `.modinfo` nor `{}` section found",
+                            thismodule::SECTION));
+    }
+    None
+}
+
+/// Warns about targets with load-time requirements beyond their symbol
+/// versions, which patching can not meet
+fn check_special_module(info: &goblin::elf::Elf,
+                        modinfo: Option<&[modinfo::ModInfoEntry]>) {
+    let flag = |key: &str| {
+        modinfo.and_then(|m| modinfo::find_entry(m, key)).is_some_and(|e| e.value == "Y")
+    };
+    let has_section = |prefix: &str| {
+        info.section_headers.iter().any(|sh| {
+            info.shdr_strtab.get_at(sh.sh_name).is_some_and(|n| n.starts_with(prefix))
+        })
+    };
+
+    // Livepatch relocations name kernel symbols by their position among
+    // symbols of the same name, which only holds for the exact kernel build
+    if flag("livepatch") || has_section(".klp.rela.") {
+        warn!("Target is a livepatch module. Its `.klp.rela` relocations and the \
+               functions it replaces must match the exact kernel build it was made \
+               for, which matching CRCs do not ensure. Loading it elsewhere can \
+               crash the kernel, and taints it (K)");
+    }
+    if flag("test") || has_section(".kunit_test_suites") {
+        warn!("Target is a KUnit test module. It needs the kernel's `kunit` module \
+               and runs its tests when loaded, against `struct kunit` layouts that \
+               change between kernels, and taints the kernel (N)");
+    }
+}
+
+/// Describes a module of the running kernel sharing a target's name, and
+/// whether it is the target's build. `srcversion` is a checksum of the
+/// module's sources, which patching leaves alone unless told otherwise, so
+/// it tells builds apart where `version` may not
+fn describe_loaded(name: &str, loaded: &running::LoadedModule, srcversion: Option<&str>,
+                   version: Option<&str>) -> String {
+    if loaded.builtin {
+        return format!("module \"{}\" built in", name);
+    }
+    let build = match (loaded.srcversion.as_deref(), srcversion) {
+        (Some(loaded), Some(target)) if loaded == target => {
+            format!("the same build, srcversion {}", target)
+        },
+        (Some(loaded), Some(target)) => {
+            format!("a different build, srcversion {} rather than {}", loaded, target)
+        },
+        _ => match (loaded.version.as_deref(), version) {
+            (Some(loaded), Some(target)) if loaded == target => {
+                format!("the same version {}, possibly of a different build", target)
+            },
+            (Some(loaded), Some(target)) => {
+                format!("a different build, version {} rather than {}", loaded, target)
+            },
+            _ => "a build that can not be compared".to_string(),
+        },
+    };
+    format!("module \"{}\" loaded from {}", name, build)
+}
+
+/// Warns about CRCs written to a target that are unlikely to be right, as
+/// when a source was misread. `genksyms` CRCs are 32-bit even where the field
+/// holding them is wider, and are hardly ever zero. CRCs too wide for their
+/// field already fail to be written
+fn check_crc(t_ver: &SymVersion, crc: u64, origin: &str) {
+    let name = &t_ver.name;
+    if crc == 0 {
+        warn!("CRC of \"{}\" from {} is zero", name, origin);
+    } else if crc > u64::from(u32::MAX) {
+        warn!("CRC 0x{:x} of \"{}\" from {} is wider than the 32 bits of kernel \
+               CRCs", crc, name, origin);
+    }
+    if crc == t_ver.crc {
+        warn!("CRC 0x{:x} of \"{}\" from {} is already the target's, writing it \
+               changes nothing", crc, name, origin);
+    }
+}
+
+/// Warns about symbols the target needs that neither the sources nor the
+/// installed modules export. Such targets fail to load with "Unknown symbol"
+/// whatever their versions, unless another module provides the symbols
+fn check_exports(target: &std::path::Path, t_buffer: &[u8], plan: &PatchPlan)
+    -> Result<()> {
+
+    let required = parse_elf(t_buffer)
+        .and_then(|info| modversions::required_symbols(&info, t_buffer))
+        .map_err(|e| e.in_file(target))?;
+    // `modpost` leaves symbols the toolchain or architecture provides alone
+    const PROVIDED: &[&str] = &["_GLOBAL_OFFSET_TABLE_", ".TOC."];
+    let unresolvable: Vec<&String> = required.iter().filter(|name| {
+        !PROVIDED.contains(&name.as_str()) && !name.starts_with("__gnu_lto")
+            && plan.sources.as_ref().is_none_or(|sources| sources.get(name).is_none())
+            && !plan.installed.as_ref().is_some_and(|installed| installed.exports(name))
+    }).collect();
+    if !unresolvable.is_empty() {
+        let list: String = unresolvable.iter().map(|name| format!("\n    \"{}\"", name))
+                            .collect();
+        warn!("Target imports symbols the sources do not export, loading it will \
+               fail with \"Unknown symbol\" unless another module exports them:{}",
+              list);
+    }
+    Ok(())
+}
+
+/// Target a hook decides the versions of
+struct HookedTarget<'a> {
+    target: &'a std::path::Path,
+    t_versions: &'a [SymVersion],
+    modinfo: Option<&'a [modinfo::ModInfoEntry]>,
+    /// CRCs the sources have for the target's entries, by offset
+    source_crcs: &'a HashMap<usize, u64>,
+    layout: VersionLayout,
+}
+
+/// Runs a hook on a target and applies its decisions. CRCs it sets count as
+/// overridden manually, and entries it skips as filtered out
+#[allow(clippy::too_many_arguments)]
+fn apply_hook(hook: &std::path::Path, hooked: &HookedTarget, t_buffer: &mut [u8],
+              patched: &mut Vec<note::PatchedSymbol>, overridden: &mut HashSet<usize>,
+              origins: &mut HashMap<usize, String>, stats: &mut PatchStats,
+              plan: &PatchPlan) -> Result<()> {
+
+    let target = hooked.target;
+    let mut modinfo: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for entry in hooked.modinfo.unwrap_or_default() {
+        modinfo.entry(entry.key.as_str()).or_default().push(entry.value.as_str());
+    }
+    let current = |patched: &[note::PatchedSymbol], t_ver: &SymVersion| {
+        patched.iter().find(|p| p.offset == t_ver.offset).map_or(t_ver.crc, |p| p.crc)
+    };
+    let input = hook::Input {
+        target: target.display().to_string(),
+        modinfo,
+        symbols: hooked.t_versions.iter().map(|t_ver| hook::Symbol {
+            name: &t_ver.name,
+            target_crc: t_ver.crc,
+            source_crc: hooked.source_crcs.get(&t_ver.offset).copied(),
+            crc: current(patched, t_ver),
+        }).collect(),
+    };
+    let decisions = hook::run(hook, &input).map_err(|e| e.in_file(hook))?;
+
+    let has_sources = plan.sources.is_some() || plan.installed.is_some();
+    for t_ver in hooked.t_versions {
+        let name = &t_ver.name;
+        let was_patched = patched.iter().any(|p| p.offset == t_ver.offset);
+        let crc = match decisions.get(name) {
+            Some(hook::Decision::Crc(crc)) => *crc,
+            Some(hook::Decision::Skip) if was_patched => t_ver.crc,
+            _ => continue,
+        };
+        if crc == current(patched, t_ver) {
+            continue;
+        }
+        hooked.layout.of(t_ver).write_crc(t_buffer, name, t_ver.offset, crc)
+            .map_err(|e| e.in_file(target))?;
+
+        // Take the entry out of the count it was in
+        if !was_patched {
+            if hooked.source_crcs.get(&t_ver.offset) == Some(&t_ver.crc) {
+                stats.already_correct -= 1;
+            } else if has_sources && !plan.filter.matches(name)
+                && !plan.overrides.contains_key(name) {
+                stats.filtered -= 1;
+            }
+        } else if overridden.remove(&t_ver.offset) {
+            stats.overridden -= 1;
+        } else {
+            stats.from_source -= 1;
+        }
+        patched.retain(|p| p.offset != t_ver.offset);
+        origins.remove(&t_ver.offset);
+
+        if crc == t_ver.crc {
+            info!("Leaving version \"{}\" in target untouched, as the hook \
+                   decided", name);
+            stats.filtered += 1;
+            continue;
+        }
+        info!("Patching \"{}\" in target with CRC 0x{:x} from the hook", name, crc);
+        check_crc(t_ver, crc, "the hook");
+        stats.overridden += 1;
+        overridden.insert(t_ver.offset);
+        origins.insert(t_ver.offset, hook.display().to_string());
+        patched.push(note::PatchedSymbol {
+            name: name.clone(),
+            offset: t_ver.offset,
+            original: t_ver.crc,
+            crc,
+        });
+    }
+    Ok(())
+}
+
+/// Lets the user review the versions patched into a target, restoring the
+/// original CRCs of the entries they decline. Skipping the target leaves it
+/// unwritten. Given the origins of the patched CRCs by offset, each write is
+/// confirmed in turn instead
+#[allow(clippy::too_many_arguments)]
+fn review_target(target: &std::path::Path, t_versions: &[SymVersion],
+                 t_buffer: &mut [u8], patched: &mut Vec<note::PatchedSymbol>,
+                 overridden: &HashSet<usize>, stats: &mut PatchStats,
+                 modinfo: Option<&[modinfo::ModInfoEntry]>, edits: &[modinfo::Edit],
+                 origins: Option<&HashMap<usize, String>>,
+                 layout: VersionLayout) -> Result<()> {
+
+    let mut entries: Vec<review::Entry> = t_versions.iter().map(|t_ver| {
+        let proposed = patched.iter().find(|p| p.offset == t_ver.offset).map(|p| p.crc);
+        review::Entry {
+            name: t_ver.name.clone(),
+            offset: t_ver.offset,
+            current: t_ver.crc,
+            proposed,
+            selected: proposed.is_some(),
+        }
+    }).collect();
+    if let Some(origins) = origins {
+        let origins: Vec<String> = entries.iter().map(|entry| {
+            origins.get(&entry.offset).cloned().unwrap_or_default()
+        }).collect();
+        review::confirm(target, &mut entries, &origins)?;
+    } else if let review::Decision::Skip = review::review(target, &mut entries, modinfo,
+                                                          edits)? {
+        return Err(Error::NothingToDo { reason: "skipped during review".to_string() });
+    }
+
+    for (t_ver, entry) in t_versions.iter().zip(&entries) {
+        if entry.proposed.is_none() || entry.selected {
+            continue;
+        }
+        debug!("Leaving version \"{}\" at 0x{:x} unpatched", entry.name, entry.offset);
+        layout.of(t_ver).write_crc(t_buffer, &entry.name, entry.offset, entry.current)
+            .map_err(|e| e.in_file(target))?;
+        patched.retain(|p| p.offset != entry.offset);
+        if overridden.contains(&entry.offset) {
+            stats.overridden -= 1;
+        } else {
+            stats.from_source -= 1;
+        }
+        stats.filtered += 1;
+    }
+    Ok(())
+}
+
+/// Prints a hexdump of each patched entry before and after patching. Entries
+/// of the extended version sections are only their 32-bit CRC
+fn show_bytes(t_versions: &[SymVersion], patched: &[note::PatchedSymbol],
+              before: &[u8], after: &[u8]) {
+    for t_ver in t_versions {
+        if !patched.iter().any(|p| p.offset == t_ver.offset) {
+            continue;
+        }
+        let len = if t_ver.extended { 4 } else { modversions::ENTRY_SIZE };
+        let range = t_ver.offset .. t_ver.offset + len;
+        let (old, new) = match (before.get(range.clone()), after.get(range)) {
+            (Some(old), Some(new)) if old != new => (old, new),
+            _ => continue,
+        };
+        info!("\"{}\" at 0x{:x}:\n{}", t_ver.name, t_ver.offset,
+              hexdump::diff(logging::info_stream(), t_ver.offset, old, new));
+    }
+}
+
+/// Path a patched target is written to: the output, the target's name in the
+/// output directory, the target with a `.patch` suffix when kept, or else the
+/// target itself. Targets read from stdin are written to stdout by default
+fn patch_out_path(target: &std::path::Path, plan: &PatchPlan) -> std::path::PathBuf {
+    if let Some(output) = &plan.output {
+        return output.clone();
+    }
+    if let Some(dir) = &plan.output_dir {
+        return dir.join(target.file_name().unwrap_or_default());
+    }
+    let mut out_path = target.to_path_buf();
+    if plan.keep {
+        let mut new_filename = out_path.file_name().unwrap_or_default()
+                                .to_os_string();
+        new_filename.push(".patch");
+        out_path.set_file_name(new_filename);
+    }
+    out_path
+}
+
+/// Writes a patched target to `out_path`. Targets written in place only have
+/// the `changed` ranges written, and targets written over are backed up first
+#[allow(clippy::too_many_arguments)]
+fn write_target(target: &std::path::Path, out_path: &std::path::Path, overwrites: bool,
+                data: &[u8], changed: &[std::ops::Range<usize>], plan: &PatchPlan,
+                t_sha256: Option<String>) -> Result<()> {
+
+    // Backups are only kept of targets written over
+    if let Some(limit) = plan.backups.filter(|_| overwrites) {
+        let kept = output::backup(target, limit)
+                    .map_err(|e| Error::Write { path: target.to_path_buf(), source: e })?;
+        if let Some(kept) = kept {
+            info!("Kept the target as {}", kept.display());
+        }
+    }
+
+    let written = if plan.in_place {
+        let len = std::fs::metadata(target)
+                    .map_err(|e| Error::Read { path: target.into(), source: e })?.len();
+        if len != data.len() as u64 {
+            return Err(Error::Unsupported {
+                reason: "the changes resize the module, which can not be written in \
+                         place".to_string(),
+            }.in_file(target));
+        }
+        output::write_changes(target, data, changed).map(|written| {
+            debug!("Wrote {} bytes in place", written);
+        })
+    } else if is_stdio(out_path) {
+        use std::io::Write;
+        std::io::stdout().lock().write_all(data)
+    } else {
+        let original = Some(target).filter(|t| !is_stdio(t));
+        output::write_atomic(out_path, data, original)
+    };
+    written.map_err(|e| Error::Write { path: out_path.to_path_buf(), source: e })?;
+
+    // Reading the output back catches writes cut short or altered on the way.
+    // Of targets written in place, only the written ranges are read
+    let intact = if plan.in_place {
+        output::changes_match(out_path, data, changed)
+            .map_err(|e| Error::Read { path: out_path.to_path_buf(), source: e })?
+    } else {
+        is_stdio(out_path) || *map_file(out_path)? == *data
+    };
+    if !intact {
+        return Err(Error::Verify {
+            reason: "written file differs from the patched module".to_string(),
+        }.in_file(out_path));
+    }
+
+    if let Some(t_sha256) = t_sha256.filter(|_| plan.checksum) {
+        let out_sha256 = checksum::sha256(data);
+        info!("SHA-256 {} -> {}", t_sha256, out_sha256);
+        if plan.checksum_manifest && is_stdio(out_path) {
+            warn!("Not writing checksum manifest for stdout");
+        } else if plan.checksum_manifest {
+            let manifest = checksum::write_manifest((target, &t_sha256),
+                                                    (out_path, &out_sha256))?;
+            info!("Wrote checksums to {}", manifest.display());
+        }
+    }
+    Ok(())
+}
+
+/// Gives a target a `__versions` section listing its undefined symbols that
+/// have a version in the sources, overrides or installed modules. CRCs are
+/// left zero to be patched like those of any other target
+fn create_versions(t_buffer: &[u8], plan: &PatchPlan)
+    -> Result<KernelModule<memmap2::MmapMut>> {
+
+    let info = parse_elf(t_buffer)?;
+    let crc_size = modversions::crc_size(info.is_64);
+    let mut undefined = modversions::undefined_symbols(&info, t_buffer)?;
+
+    // `modpost` versions `module_layout` although no module imports it.
+    // Kernels that no longer export it do not have a version for it
+    if !undefined.iter().any(|name| name == "module_layout") {
+        undefined.insert(0, "module_layout".to_string());
+    }
+
+    let in_sources = |name: &str| plan.overrides.contains_key(name)
+        || plan.filter.matches(name) && plan.in_sources(name);
+    let installed = plan.installed.as_ref().map(|installed| {
+        let missing: Vec<&str> = undefined.iter().map(String::as_str)
+            .filter(|name| !in_sources(name))
+            .flat_map(|name| plan.source_names(name))
+            .collect();
+        installed.resolve(&missing)
+    }).unwrap_or_default();
+
+    let mut names = Vec::new();
+    let mut misses = Vec::new();
+    for name in undefined {
+        let in_installed = plan.filter.matches(&name)
+            && plan.source_names(&name).any(|n| installed.contains_key(n));
+        if !in_sources(&name) && !in_installed {
+            if name != "module_layout" {
+                misses.push(name);
+            }
+        } else if name.len() >= modversions::ENTRY_SIZE - crc_size {
+            warn!("Not versioning \"{}\", name is too long", name);
+        } else {
+            names.push(name);
+        }
+    }
+    if !misses.is_empty() {
+        let list: String = misses.iter().map(|name| format!("\n    \"{}\"", name))
+                                .collect();
+        warn!("Undefined symbols not found in sources:{}", list);
+    }
+    if names.is_empty() {
+        return Err(Error::NothingToDo {
+            reason: "no undefined symbols found in sources to create \
+                     `__versions` from".to_string(),
+        });
+    }
+
+    info!("Creating `__versions` section with {} versions", names.len());
+    let entries: Vec<(String, u64)> = names.into_iter().map(|name| (name, 0))
+                                        .collect();
+    add_versions(t_buffer, &entries)
+}
+
+/// Adds entries to the end of a target's `__versions` section, creating the
+/// section if the target has none
+fn add_versions(t_buffer: &[u8], entries: &[(String, u64)])
+    -> Result<KernelModule<memmap2::MmapMut>> {
+
+    let data = modversions::add_entries(t_buffer, entries)?;
+    let buffer = map_copy(&data)
+                    .map_err(|e| Error::Write { path: "<memory>".into(), source: e })?;
+    parse_module(buffer)
+}
+
+/// Removes the sections whose names match along with their relocations and
+/// lays the module out again without gaps
+fn strip_sections(t_buffer: &[u8], kind: &str, matches: impl Fn(&str) -> bool)
+    -> Result<KernelModule<memmap2::MmapMut>> {
+
+    let info = parse_elf(t_buffer)?;
+    let mut editor = elfedit::Editor::new(t_buffer)?;
+    let mut count = 0;
+    for (idx, sh) in info.section_headers.iter().enumerate() {
+        if info.shdr_strtab.get_at(sh.sh_name).is_some_and(&matches) {
+            editor.remove(idx);
+            count += 1;
+        }
+    }
+    if count == 0 {
+        info!("No {} sections in target to strip", kind);
+        return parse_module(map_copy(t_buffer)
+            .map_err(|e| Error::Write { path: "<memory>".into(), source: e })?);
+    }
+
+    editor.compact();
+    let data = editor.finish()?;
+    info!("Stripped {} {} sections from target, saving {} bytes", count, kind,
+          t_buffer.len().saturating_sub(data.len()));
+    let buffer = map_copy(&data)
+                    .map_err(|e| Error::Write { path: "<memory>".into(), source: e })?;
+    parse_module(buffer)
+}
+
+/// Looks up the value of a source module's `.modinfo` entry
+fn source_modinfo(s_modinfo: &[modinfo::ModInfoEntry], key: &str) -> Result<String> {
+    modinfo::find_entry(s_modinfo, key)
+        .map(|entry| entry.value.clone())
+        .ok_or(Error::MissingModInfo { key: key.to_string() })
+}
+
+/// Returns the time to record in notes and reports in seconds since the Unix
+/// epoch: `SOURCE_DATE_EPOCH` if set, as reproducible builds set it, zero for
+/// `--reproducible` output and the current time otherwise
+fn unix_time() -> u64 {
+    let epoch = std::env::var("SOURCE_DATE_EPOCH").ok().and_then(|s| s.parse().ok());
+    if let Some(epoch) = epoch {
+        return epoch;
+    }
+    if REPRODUCIBLE.load(Ordering::Relaxed) {
+        return 0;
+    }
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Applies edits to a target's `.modinfo` entries, returning the target's
+/// content afterwards along with records of the changes. Values are written
+/// in place when every edit fits. Otherwise the section is rebuilt and moved
+/// to the end of the module, or when `resize` is not set the edits fail
+/// before any is applied, as adding and deleting entries always resizes
+///
+/// # Arguments
+/// * `t_buffer` - Backing byte content of the target
+/// * `t_modinfo` - Target `.modinfo` entries, if the target has the section
+/// * `edits` - Edits to apply, in order
+/// * `resize` - Allow rebuilding the section
+fn edit_modinfo(mut t_buffer: memmap2::MmapMut,
+                t_modinfo: Option<&[modinfo::ModInfoEntry]>,
+                edits: &[modinfo::Edit], resize: bool)
+    -> Result<(memmap2::MmapMut, Vec<note::PatchedModInfo>)> {
+
+    let entries = t_modinfo.unwrap_or_default();
+    let mut records = Vec::new();
+    // First edit needing the section rebuilt, if any
+    let mut resizing = None;
+    for edit in edits {
+        match edit {
+            modinfo::Edit::Set { key, value } => {
+                let entry = modinfo::find_entry(entries, key);
+                match entry {
+                    Some(entry) => info!("Patching {} \"{}\" in target with \"{}\"",
+                                         key, entry.value, value),
+                    None => info!("Adding {} \"{}\" to target", key, value),
+                }
+                // Need room for the separator and null terminator as well
+                match entry {
+                    Some(e) if key.len() + value.len() + 2 <= e.capacity => {},
+                    Some(_) => {
+                        resizing.get_or_insert(format!("setting `{}` to a value longer \
+                                                        than its entry", key));
+                    },
+                    None => {
+                        resizing.get_or_insert(format!("adding `{}`", key));
+                    },
+                }
+                records.push(note::PatchedModInfo {
+                    key: key.clone(),
+                    original: entry.map(|e| e.value.clone()).unwrap_or_default(),
+                    value: value.clone(),
+                    added: entry.is_none(),
+                    deleted: false,
+                });
+            },
+            modinfo::Edit::Add { key, value } => {
+                info!("Adding {} \"{}\" to target", key, value);
+                resizing.get_or_insert(format!("adding `{}`", key));
+                records.push(note::PatchedModInfo {
+                    key: key.clone(),
+                    original: String::new(),
+                    value: value.clone(),
+                    added: true,
+                    deleted: false,
+                });
+            },
+            modinfo::Edit::Delete { key, value } => {
+                let matches: Vec<_> = entries.iter()
+                    .filter(|e| e.key == *key
+                                && value.as_ref().is_none_or(|v| e.value == *v))
+                    .collect();
+                if matches.is_empty() {
+                    warn!("No {} entry in target to delete", key);
+                }
+                for entry in matches {
+                    info!("Deleting {} \"{}\" from target", key, entry.value);
+                    resizing.get_or_insert(format!("deleting `{}`", key));
+                    records.push(note::PatchedModInfo {
+                        key: key.clone(),
+                        original: entry.value.clone(),
+                        value: String::new(),
+                        added: false,
+                        deleted: true,
+                    });
+                }
+            },
+        }
+    }
+    if records.is_empty() {
+        return Ok((t_buffer, records));
+    }
+
+    if let Some(reason) = resizing.as_ref().filter(|_| !resize) {
+        return Err(Error::Unsupported {
+            reason: format!("{} resizes `.modinfo`, which in-place writes and patch \
+                             files can not do", reason),
+        });
+    }
+    if resizing.is_none() {
+        for edit in edits {
+            if let modinfo::Edit::Set { key, value } = edit {
+                let entry = modinfo::find_entry(entries, key)
+                                .ok_or(Error::MissingModInfo { key: key.clone() })?;
+                modinfo::patch_entry(&mut t_buffer, entry, value)?;
+            }
+        }
+        return Ok((t_buffer, records));
+    }
+
+    let content = modinfo::rebuild(entries, edits);
+    let mut editor = elfedit::Editor::new(&t_buffer)?;
+    match editor.find(".modinfo") {
+        Some(idx) => editor.set_content(idx, content),
+        None => {
+            let header = goblin::elf::section_header::SectionHeader {
+                sh_type: goblin::elf::section_header::SHT_PROGBITS,
+                sh_flags: goblin::elf::section_header::SHF_ALLOC as u64,
+                sh_addralign: 1,
+                ..Default::default()
+            };
+            editor.add(".modinfo", header, content)?;
+        },
+    }
+    let data = editor.finish()?;
+    let buffer = map_copy(&data)
+                    .map_err(|e| Error::Write { path: "<memory>".into(), source: e })?;
+    Ok((buffer, records))
+}
+
+/// Loads a kernel module's symbol versions, failing if the module has no
+/// `__versions` section
+fn load_versions(path: &std::path::Path) -> Result<Vec<SymVersion>> {
+    load_module(path)?.versions
+        .ok_or(Error::MissingSection { section: "__versions".to_string() }
+                .in_file(path))
+}
+
+fn list(target: &std::path::Path) -> Result<()> {
+    // Versions are listed in order in ELF (file offset)
+    for ver in load_versions(target)? {
+        println!("0x{:x}: \"{}\", 0x{:x}", ver.offset,
+                    ver.name, ver.crc);
+    }
+    Ok(())
+}
+
+fn verify(target: &std::path::Path) -> Result<()> {
+    let t_ko = load_module(target)?;
+
+    let k_crcs = running::crcs();
+    if k_crcs.is_empty() {
+        warn!("No symbol versions found for the running kernel");
+    }
+
+    let mut failures = 0;
+    let t_versions = t_ko.versions.as_deref();
+    for ver in t_versions.unwrap_or_default() {
+        let name = &ver.name;
+        match k_crcs.get(name) {
+            Some(&crc) if crc == ver.crc => {
+                println!("{}\"{}\" 0x{:x}", status("OK"), name, ver.crc);
+            },
+            Some(&crc) => {
+                println!("{}\"{}\" target 0x{:x}, kernel 0x{:x}", status("MISMATCH"),
+                            name, ver.crc, crc);
+                failures += 1;
+            },
+            None => {
+                println!("{}\"{}\" 0x{:x} not found in running kernel", status("UNKNOWN"),
+                            name, ver.crc);
+            },
+        }
+    }
+
+    let t_vermagic = t_ko.modinfo.as_deref()
+                        .and_then(|m| modinfo::find_entry(m, "vermagic"));
+    match (t_vermagic, running::vermagic()) {
+        (Some(t_vermagic), Some(k_vermagic)) => {
+            let reasons = vermagic::mismatches(&t_vermagic.value, &k_vermagic,
+                                               t_versions.is_some());
+            if reasons.is_empty() {
+                println!("{}vermagic \"{}\"", status("OK"), t_vermagic.value);
+            } else {
+                println!("{}vermagic target \"{}\", kernel \"{}\"", status("MISMATCH"),
+                            t_vermagic.value, k_vermagic);
+                for reason in reasons {
+                    println!("              {}", reason);
+                }
+                failures += 1;
+            }
+        },
+        (None, _) => {
+            println!("{}vermagic missing from target", status("MISMATCH"));
+            failures += 1;
+        },
+        (Some(t_vermagic), None) => {
+            println!("{}vermagic \"{}\", unable to determine running \
+                      kernel vermagic", status("UNKNOWN"), t_vermagic.value);
+        },
+    }
+
+    // Namespaces of the running kernel's symbols are only known from its
+    // build tree
+    let k_namespaces = running::modules_dir()
+                        .map(|dir| running::namespaces(&dir))
+                        .unwrap_or_default();
+    let imports = parse_elf(&t_ko.buffer)
+        .and_then(|info| modversions::undefined_symbols(&info, &t_ko.buffer))
+        .map_err(|e| e.in_file(target))?;
+    let missing_ns = namespaces::missing_imports(&imports, &k_namespaces,
+                                                 t_ko.modinfo.as_deref());
+    for (namespace, symbols) in &missing_ns {
+        println!("{}namespace \"{}\" not imported, used by {}", status("MISMATCH"),
+                    namespace, symbols.join(", "));
+        failures += 1;
+    }
+
+    if failures > 0 {
+        return Err(Error::CheckFailed { count: failures });
+    }
+    println!("0 entries would cause insmod to fail");
+    Ok(())
+}
+
+/// Label of a check's outcome, padded to align the checks' messages and
+/// colored by the outcome
+fn status(label: &str) -> String {
+    let color = match label {
+        "OK" => color::Color::Green,
+        "UNKNOWN" | "WARNING" => color::Color::Yellow,
+        _ => color::Color::Red,
+    };
+    color::paint(color::Stream::Stdout, color, format!("{:<10}", label))
+}
+
+/// Error the kernel would refuse to load a module with
+struct LoadFailure {
+    /// Error `insmod` reports
+    errno: &'static str,
+    message: String,
+}
+
+/// Prints the outcome of one of the kernel's checks of a module to load,
+/// recording the failure
+fn load_step(failures: &mut Vec<LoadFailure>,
+             result: std::result::Result<String, LoadFailure>) {
+    match result {
+        Ok(message) => println!("{}{}", status("OK"), message),
+        Err(failure) => {
+            println!("{}{}", status("FAIL"), failure.message);
+            failures.push(failure);
+        },
+    }
+}
+
+fn simulate_load(target: &std::path::Path) -> Result<()> {
+    const ENOEXEC: &str = "Invalid module format";
+    const EINVAL: &str = "Invalid argument";
+    const EKEYREJECTED: &str = "Key was rejected by service";
+    let fail = |errno, message: String| Err(LoadFailure { errno, message });
+
+    let t_ko = load_module(target)?;
+    let info = parse_elf(&t_ko.buffer).map_err(|e| e.in_file(target))?;
+    let mut failures = Vec::new();
+
+    load_step(&mut failures, if doctor::modules_blocked() {
+        fail("Operation not permitted", "module loading is disabled".to_string())
+    } else {
+        Ok("module loading is enabled".to_string())
+    });
+
+    // Signatures can not be verified here, but a module patched after signing
+    // no longer matches its signature
+    let signed = has_signature(&t_ko.buffer);
+    match (doctor::signature_required(), signed) {
+        (true, false) => load_step(&mut failures, fail(EKEYREJECTED, "module is \
+            unsigned but signatures are required".to_string())),
+        (true, true) if t_ko.provenance.is_some() => {
+            load_step(&mut failures, fail(EKEYREJECTED, "module was patched \
+                after signing, its signature is invalid".to_string()));
+        },
+        (true, true) => println!("{}module is signed, its signature can \
+                                  not be checked", status("UNKNOWN")),
+        (false, false) => load_step(&mut failures, Ok("signatures are not \
+            required, loading the unsigned module taints the kernel".to_string())),
+        (false, true) => load_step(&mut failures, Ok("signatures are not required"
+                                                        .to_string())),
+    }
+
+    match running::arch() {
+        _ if info.header.e_type != goblin::elf::header::ET_REL => {
+            load_step(&mut failures, fail(ENOEXEC, "module is not a relocatable \
+                                                    object".to_string()));
+        },
+        Some(arch) if arch != t_ko.arch => {
+            load_step(&mut failures, fail(ENOEXEC, format!("module is {} but the \
+                                                            kernel is {}",
+                                                           t_ko.arch, arch)));
+        },
+        Some(arch) => load_step(&mut failures, Ok(format!("module is {}", arch))),
+        None => println!("{}module is {}, unable to determine the \
+                          kernel's architecture", status("UNKNOWN"), t_ko.arch),
+    }
+
+    let t_vermagic = t_ko.modinfo.as_deref()
+                        .and_then(|m| modinfo::find_entry(m, "vermagic"));
+    match (t_vermagic, running::vermagic()) {
+        (Some(t_vermagic), Some(k_vermagic)) => {
+            let reasons = vermagic::mismatches(&t_vermagic.value, &k_vermagic,
+                                               t_ko.versions.is_some());
+            load_step(&mut failures, if reasons.is_empty() {
+                Ok(format!("vermagic \"{}\"", t_vermagic.value))
+            } else {
+                fail(ENOEXEC, format!("version magic '{}' should be '{}'",
+                                      t_vermagic.value, k_vermagic))
+            });
+        },
+        (None, _) => load_step(&mut failures, fail(ENOEXEC, "no version magic"
+                                                             .to_string())),
+        (Some(_), None) => println!("{}unable to determine running \
+                                     kernel vermagic", status("UNKNOWN")),
+    }
+
+    let name = t_ko.modinfo.as_deref()
+                .and_then(|m| modinfo::find_entry(m, "name"))
+                .map(|entry| entry.value.clone())
+                .or_else(|| {
+                    let stem = target.file_name()?.to_string_lossy().into_owned();
+                    Some(stem.split('.').next()?.to_string())
+                })
+                .unwrap_or_default()
+                .replace('-', "_");
+    load_step(&mut failures, if let Some(loaded) = running::loaded_module(&name) {
+        let entries = t_ko.modinfo.as_deref().unwrap_or_default();
+        let value = |key| modinfo::find_entry(entries, key).map(|e| e.value.as_str());
+        fail("File exists", format!("kernel has {}",
+                                    describe_loaded(&name, &loaded, value("srcversion"),
+                                                    value("version"))))
+    } else if running::loaded_modules().contains(&name) {
+        fail("File exists", format!("module \"{}\" is already loaded", name))
+    } else {
+        Ok(format!("module \"{}\" is not loaded", name))
+    });
+
+    // Symbols are resolved last, each checked for a matching version
+    let imports = modversions::undefined_symbols(&info, &t_ko.buffer)
+                    .map_err(|e| e.in_file(target))?;
+    let k_exports = running::exports();
+    if k_exports.is_empty() {
+        println!("{}unable to read the kernel's exported symbols", status("UNKNOWN"));
+    }
+    let k_crcs = running::crcs();
+    let t_versions = t_ko.versions.as_deref().map(versions_by_name);
+    let force_load = running::config().is_some_and(|config| {
+        running::config_option(&config, "CONFIG_MODULE_FORCE_LOAD") == Some("y")
+    });
+    let mut resolved = 0;
+    for import in &imports {
+        if !k_exports.is_empty() && !k_exports.contains(import) {
+            load_step(&mut failures, fail("Unknown symbol in module",
+                                          format!("Unknown symbol {}", import)));
+            continue;
+        }
+        let k_crc = k_crcs.get(import);
+        match (k_crc, &t_versions) {
+            // Without `__versions` the kernel can only force load the module
+            (Some(_), None) if !force_load => {
+                load_step(&mut failures, fail(ENOEXEC, "module has no `__versions` \
+                                                        section".to_string()));
+                break;
+            },
+            (Some(&crc), Some(versions)) => match versions.get(import.as_str()) {
+                Some(ver) if ver.crc != crc => {
+                    load_step(&mut failures, fail(EINVAL, format!(
+                        "disagrees about version of symbol {}", import)));
+                    continue;
+                },
+                None => {
+                    load_step(&mut failures, fail(EINVAL, format!(
+                        "no symbol version for {}", import)));
+                    continue;
+                },
+                Some(_) => {},
+            },
+            _ => {},
+        }
+        resolved += 1;
+    }
+    if resolved > 0 {
+        load_step(&mut failures, Ok(format!("{} of {} symbols resolved", resolved,
+                                            imports.len())));
+    }
+
+    let k_namespaces = running::modules_dir()
+                        .map(|dir| running::namespaces(&dir))
+                        .unwrap_or_default();
+    let missing_ns = namespaces::missing_imports(&imports, &k_namespaces,
+                                                 t_ko.modinfo.as_deref());
+    for (namespace, symbols) in &missing_ns {
+        load_step(&mut failures, fail(EINVAL, format!(
+            "module uses symbol {} from namespace {}, but does not import it",
+            symbols[0], namespace)));
+    }
+
+    match failures.first() {
+        Some(failure) => {
+            println!("insmod would fail with \"{}\" after logging: {}",
+                        failure.errno, failure.message);
+            Err(Error::CheckFailed { count: failures.len() })
+        },
+        None => {
+            println!("The kernel would accept the module");
+            Ok(())
+        },
+    }
+}
+
+fn doctor() -> Result<()> {
+    let mut blocked = 0;
+    for check in doctor::checks() {
+        let label = match check.status {
+            doctor::Status::Ok => "OK",
+            doctor::Status::Warning => "WARNING",
+            doctor::Status::Blocked => {
+                blocked += 1;
+                "BLOCKED"
+            },
+            doctor::Status::Unknown => "UNKNOWN",
+        };
+        println!("{}{}", status(label), check.message);
+    }
+
+    if blocked > 0 {
+        return Err(Error::CheckFailed { count: blocked });
+    }
+    println!("Nothing prevents loading patched modules");
+    Ok(())
+}
+
+fn inspect(target: &std::path::Path, format: csv::Format) -> Result<()> {
+    let buffer = read_module(target)?;
+    let ko = parse_elf(&buffer).map_err(|e| e.in_file(target))?;
+
+    if format == csv::Format::Csv {
+        // Stdout carries the table
+        logging::STDOUT_OUTPUT.store(true, Ordering::Relaxed);
+        let versions = get_versions(&ko, &buffer).map_err(|e| e.in_file(target))?
+                        .ok_or_else(|| Error::MissingSection {
+                            section: "__versions".to_string(),
+                        }.in_file(target))?;
+        println!("{}", csv::row(&["symbol", "crc"]));
+        for ver in versions {
+            println!("{}", csv::row(&[ver.name, csv::crc(ver.crc)]));
+        }
+        return Ok(());
+    }
+
+    println!("File:       {}", target.display());
+    println!("ELF:        {}, {} endian, {}, {}",
+                if ko.is_64 { "ELF64" } else { "ELF32" },
+                if ko.little_endian { "little" } else { "big" },
+                goblin::elf::header::machine_to_str(ko.header.e_machine),
+                goblin::elf::header::et_to_str(ko.header.e_type));
+    println!("Signature:  {}",
+                if has_signature(&buffer) { "present" } else { "not present" });
+
+    match get_versions(&ko, &buffer) {
+        Ok(Some(versions)) => println!("Versions:   {} entries", versions.len()),
+        Ok(None) => println!("Versions:   no `__versions` section"),
+        Err(e) => println!("Versions:   {}", e),
+    }
+
+    match note::read(&ko, &buffer) {
+        Ok(Some(provenance)) => {
+            println!("Patched:    by candycorn {} at {}, {} symbols",
+                        provenance.version, provenance.timestamp,
+                        provenance.symbols.len());
+            for source in provenance.sources {
+                println!("    source  {} (SHA-256 {})", source.path, source.sha256);
+            }
+        },
+        Ok(None) => println!("Patched:    no `{}` section", note::SECTION),
+        Err(e) => println!("Patched:    {}", e),
+    }
+
+    let modinfo = modinfo::get_modinfo(&ko, &buffer);
+    if let Ok(entries) = &modinfo {
+        let entries = entries.as_deref().unwrap_or_default();
+        match modinfo::find_entry(entries, "license") {
+            Some(entry) if license::is_gpl_compatible(&entry.value) => {
+                println!("License:    {} (GPL-compatible)", entry.value);
+            },
+            Some(entry) => {
+                println!("License:    {} (not GPL-compatible, taints the kernel as \
+                          proprietary and can not use GPL-only symbols)", entry.value);
+            },
+            None => println!("License:    none (taints the kernel as proprietary \
+                              and can not use GPL-only symbols)"),
+        }
+    }
+    match modinfo {
+        Ok(Some(entries)) => {
+            println!("Modinfo:");
+            let width = entries.iter().map(|e| e.key.len()).max().unwrap_or(0);
+            for entry in entries {
+                println!("    {:<width$}  {}", format!("{}:", entry.key),
+                            entry.value, width = width + 1);
+            }
+        },
+        Ok(None) => println!("Modinfo:    no `.modinfo` section"),
+        Err(e) => println!("Modinfo:    {}", e),
+    }
+
+    println!("Sections:");
+    println!("    {:>4}  {:<32}  {:<16}  {:>10}  {:>10}", "Idx", "Name", "Type",
+                "Offset", "Size");
+    for (idx, sh) in ko.section_headers.iter().enumerate() {
+        println!("    {:>4}  {:<32}  {:<16}  {:>#10x}  {:>#10x}", idx,
+                    ko.shdr_strtab.get_at(sh.sh_name).unwrap_or(""),
+                    goblin::elf::section_header::sht_to_str(sh.sh_type),
+                    sh.sh_offset, sh.sh_size);
+    }
+    Ok(())
+}
+
+fn revert(target: &std::path::Path, output: Option<std::path::PathBuf>)
+    -> Result<()> {
+
+    let out_path = output.unwrap_or_else(|| target.to_path_buf());
+    let _lock = lock_target(target, writes_over(target, &out_path))?;
+    logging::STDOUT_OUTPUT.store(is_stdio(&out_path), Ordering::Relaxed);
+    let t_ko = load_target(target)?;
+    let layout = t_ko.layout;
+    let mut t_buffer = t_ko.buffer;
+    let provenance = t_ko.provenance.ok_or_else(|| {
+        Error::MissingSection { section: note::SECTION.to_string() }.in_file(target)
+    })?;
+    if provenance.symbols.is_empty() && provenance.modinfo.is_empty() {
+        return Err(Error::NothingToDo {
+            reason: "no patches recorded in target".to_string(),
+        });
+    }
+
+    // Entries must still be where they were when patched
+    let t_versions = t_ko.versions.unwrap_or_default();
+    for sym in &provenance.symbols {
+        let t_ver = t_versions.iter()
+                        .find(|v| v.offset == sym.offset && v.name == sym.name)
+                        .ok_or_else(|| Error::MissingSymbol {
+                            symbol: sym.name.clone(),
+                        }.in_file(target))?;
+        info!("Reverting \"{}\" in target from CRC 0x{:x} to 0x{:x}",
+                 sym.name, t_ver.crc, sym.original);
+        layout.of(t_ver).write_crc(&mut t_buffer, &sym.name, sym.offset,
+                                   sym.original)
+            .map_err(|e| e.in_file(target))?;
+    }
+    let edits: Vec<modinfo::Edit> = provenance.modinfo.iter().map(|entry| {
+        if entry.added {
+            modinfo::Edit::Delete {
+                key: entry.key.clone(),
+                value: Some(entry.value.clone()),
+            }
+        } else if entry.deleted {
+            modinfo::Edit::Add { key: entry.key.clone(), value: entry.original.clone() }
+        } else {
+            modinfo::Edit::Set { key: entry.key.clone(), value: entry.original.clone() }
+        }
+    }).collect();
+    let (t_buffer, _) = edit_modinfo(t_buffer, t_ko.modinfo.as_deref(), &edits, true)
+                            .map_err(|e| e.in_file(target))?;
+
+    // Clear the records so the target is not reverted twice
+    let reverted = note::Provenance {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        sources: Vec::new(),
+        timestamp: unix_time(),
+        symbols: Vec::new(),
+        modinfo: Vec::new(),
+    };
+    let data = note::append(&t_buffer, &reverted).map_err(|e| e.in_file(target))?;
+
+    write_output(target, &out_path, &data)?;
+    info!("Reverted {} into {}", target.display(), out_path.display());
+    Ok(())
+}
+
+fn strip_versions(target: &std::path::Path, output: Option<std::path::PathBuf>,
+                  keep_vermagic: bool) -> Result<()> {
+
+    let out_path = output.unwrap_or_else(|| target.to_path_buf());
+    let _lock = lock_target(target, writes_over(target, &out_path))?;
+    logging::STDOUT_OUTPUT.store(is_stdio(&out_path), Ordering::Relaxed);
+    let mut t_buffer = map_target(target)?;
+    let t_modinfo = parse_elf(&t_buffer)
+                    .and_then(|info| modinfo::get_modinfo(&info, &t_buffer))
+                    .map_err(|e| e.in_file(target))?;
+
+    // Kernels without `CONFIG_MODVERSIONS` also lack the flag in their
+    // vermagic. The shorter value always fits in place
+    let vermagic = t_modinfo.as_deref()
+                    .and_then(|m| modinfo::find_entry(m, "vermagic"))
+                    .filter(|_| !keep_vermagic);
+    if let Some(entry) = vermagic {
+        let value = entry.value.split(' ').filter(|flag| *flag != "modversions")
+                        .collect::<Vec<_>>().join(" ");
+        if value != entry.value {
+            info!("Removing `modversions` from target vermagic \"{}\"",
+                     entry.value);
+            modinfo::patch_entry(&mut t_buffer, entry, &value)
+                .map_err(|e| e.in_file(target))?;
+        }
+    }
+
+    let mut editor = elfedit::Editor::new(&t_buffer).map_err(|e| e.in_file(target))?;
+    let sections = ["__versions", modversions::EXT_CRCS, modversions::EXT_NAMES];
+    let mut removed = Vec::new();
+    for name in sections {
+        if let Some(idx) = editor.find(name) {
+            editor.remove(idx);
+            removed.push(format!("`{}`", name));
+        }
+    }
+    if removed.is_empty() {
+        return Err(Error::NothingToDo {
+            reason: "`__versions` section not found".to_string(),
+        });
+    }
+    let data = editor.finish().map_err(|e| e.in_file(target))?;
+
+    write_output(target, &out_path, &data)?;
+    info!("Removed {} from {} into {}", removed.join(", "), target.display(),
+             out_path.display());
+    Ok(())
+}
+
+fn apply(patch: &std::path::Path, target: &std::path::Path,
+         output: Option<std::path::PathBuf>, ignore_hash: bool) -> Result<()> {
+
+    let out_path = output.unwrap_or_else(|| target.to_path_buf());
+    let _lock = lock_target(target, writes_over(target, &out_path))?;
+    logging::STDOUT_OUTPUT.store(is_stdio(&out_path), Ordering::Relaxed);
+    let patch_file = patchfile::PatchFile::load(patch)?;
+    let mut t_buffer = map_target(target)?;
+    let t_sha256 = checksum::sha256(&t_buffer);
+    if t_sha256 == patch_file.patched_sha256 {
+        return Err(Error::NothingToDo {
+            reason: "patch is already applied".to_string(),
+        });
+    }
+    if t_sha256 != patch_file.sha256 {
+        let mismatch = Error::ChecksumMismatch {
+            expected: patch_file.sha256.clone(),
+            actual: t_sha256,
+        };
+        if !ignore_hash {
+            return Err(mismatch.in_file(target));
+        }
+        warn!("{}: {}", target.display(), mismatch);
+    }
+
+    patch_file.apply(&mut t_buffer).map_err(|e| e.in_file(target))?;
+
+    write_output(target, &out_path, &t_buffer)?;
+    info!("Applied {} changes from {} into {}", patch_file.writes.len(),
+             patch.display(), out_path.display());
+    Ok(())
+}
+
+fn testgen(args: TestgenArgs) -> Result<()> {
+    let name = args.name.unwrap_or_else(|| {
+        let stem = args.output.file_stem().filter(|_| !is_stdio(&args.output));
+        stem.map_or_else(|| "testgen".to_string(),
+                         |stem| stem.to_string_lossy().into_owned())
+    });
+    let mut spec = testgen::Spec {
+        machine: args.machine,
+        is_64: !args.elf32,
+        little_endian: !args.big_endian,
+        name,
+        versions: Vec::new(),
+        extended: args.extended,
+        modinfo: args.modinfo,
+        signed: args.signed,
+    };
+    for (symbol, crc) in args.symbol {
+        let crc = parse_crc(&crc, false)
+                    .map_err(|e| Error::Override { symbol: symbol.clone(),
+                                                   source: Box::new(e) })?;
+        let bits = if spec.extended || !spec.is_64 { 32 } else { 64 };
+        if bits < 64 && crc > u32::MAX as u64 {
+            Args::command().error(clap::ErrorKind::InvalidValue,
+                format!("CRC {:#x} of \"{}\" does not fit in a {}-bit CRC field",
+                        crc, symbol, bits)).exit();
+        }
+        if !spec.extended && symbol.len() > spec.max_name_len() {
+            Args::command().error(clap::ErrorKind::InvalidValue,
+                format!("\"{}\" is longer than the {} bytes a `__versions` entry \
+                         holds, use --extended", symbol, spec.max_name_len())).exit();
+        }
+        spec.versions.push((symbol, crc));
+    }
+
+    let data = testgen::generate(&spec);
+    logging::STDOUT_OUTPUT.store(is_stdio(&args.output), Ordering::Relaxed);
+    let written = if is_stdio(&args.output) {
+        use std::io::Write;
+        std::io::stdout().lock().write_all(&data)
+    } else {
+        output::write_atomic(&args.output, &data, None)
+    };
+    written.map_err(|e| Error::Write { path: args.output.clone(), source: e })?;
+    info!("Wrote {} with {} symbol versions and {} `.modinfo` entries",
+          args.output.display(), spec.versions.len(), spec.modinfo.len());
+    Ok(())
+}
+
+fn diff(a: &std::path::Path, b: &std::path::Path, hide_matching: bool,
+        format: csv::Format) -> Result<()> {
+
+    let a_versions = load_versions(a)?;
+    let b_versions = load_versions(b)?;
+
+    // Symbols of the first module in ELF order followed by symbols only found
+    // in the second module in ELF order
+    let a_names = versions_by_name(&a_versions);
+    let b_names = versions_by_name(&b_versions);
+    let b_only: Vec<_> = b_versions.iter()
+                            .filter(|v| !a_names.contains_key(v.name.as_str()))
+                            .collect();
+
+    if format == csv::Format::Csv {
+        logging::STDOUT_OUTPUT.store(true, Ordering::Relaxed);
+        println!("{}", csv::row(&["symbol", "crc_a", "crc_b"]));
+        for a_ver in &a_versions {
+            let b_crc = b_names.get(a_ver.name.as_str()).map(|b_ver| b_ver.crc);
+            if hide_matching && b_crc == Some(a_ver.crc) {
+                continue;
+            }
+            println!("{}", csv::row(&[a_ver.name.clone(), csv::crc(a_ver.crc),
+                                      b_crc.map(csv::crc).unwrap_or_default()]));
+        }
+        for b_ver in b_only {
+            println!("{}", csv::row(&[b_ver.name.clone(), String::new(),
+                                      csv::crc(b_ver.crc)]));
+        }
+        return Ok(());
+    }
+
+    let width = a_versions.iter().chain(&b_versions)
+                    .map(|v| v.name.len() + 2).max().unwrap_or(0).max(6);
+    println!("  {:<width$}  {:<18}  {}", "Symbol", a.display(), b.display(),
+                width = width);
+
+    let row = |color, row: String| {
+        println!("{}", color::paint(color::Stream::Stdout, color, row));
+    };
+    let (mut differing, mut a_count, mut b_count, mut matching) = (0, 0, 0, 0);
+    for a_ver in &a_versions {
+        let quoted = format!("\"{}\"", a_ver.name);
+        let a_crc = format!("0x{:x}", a_ver.crc);
+        match b_names.get(a_ver.name.as_str()) {
+            Some(b_ver) if b_ver.crc == a_ver.crc => {
+                matching += 1;
+                if !hide_matching {
+                    row(color::Color::Green,
+                        format!("= {:<width$}  {:<18}  0x{:x}", quoted, a_crc,
+                                b_ver.crc, width = width));
+                }
+            },
+            Some(b_ver) => {
+                differing += 1;
+                row(color::Color::Red,
+                    format!("~ {:<width$}  {:<18}  0x{:x}", quoted, a_crc,
+                            b_ver.crc, width = width));
+            },
+            None => {
+                a_count += 1;
+                row(color::Color::Yellow,
+                    format!("- {:<width$}  {:<18}  -", quoted, a_crc,
+                            width = width));
+            },
+        }
+    }
+    for b_ver in b_only {
+        b_count += 1;
+        row(color::Color::Yellow,
+            format!("+ {:<width$}  {:<18}  0x{:x}", format!("\"{}\"", b_ver.name),
+                    "-", b_ver.crc, width = width));
+    }
+
+    println!("{} differing, {} only in {}, {} only in {}, {} matching",
+                differing, a_count, a.display(), b_count, b.display(), matching);
+    Ok(())
+}
+
+/// Compares the exports of two `Module.symvers` files, sorted by name, and
+/// reports which of them each module imports. Fails if any module imports an
+/// export that changed or was removed
+fn symvers_diff(old: &std::path::Path, new: &std::path::Path,
+                modules: &[std::path::PathBuf], show_unchanged: bool,
+                format: csv::Format) -> Result<()> {
+
+    let old_crcs = symvers::load(old)?;
+    let new_crcs = symvers::load(new)?;
+    let names: BTreeSet<&String> = old_crcs.keys().chain(new_crcs.keys()).collect();
+    let rows: Vec<(&String, Option<u64>, Option<u64>)> = names.into_iter()
+        .map(|name| (name, old_crcs.get(name).copied(), new_crcs.get(name).copied()))
+        .collect();
+
+    if format == csv::Format::Csv {
+        logging::STDOUT_OUTPUT.store(true, Ordering::Relaxed);
+        println!("{}", csv::row(&["symbol", "crc_old", "crc_new"]));
+        for (name, old_crc, new_crc) in &rows {
+            if old_crc == new_crc && !show_unchanged {
+                continue;
+            }
+            println!("{}", csv::row(&[name.to_string(),
+                                      old_crc.map(csv::crc).unwrap_or_default(),
+                                      new_crc.map(csv::crc).unwrap_or_default()]));
+        }
+    } else {
+        let width = rows.iter().map(|(name, ..)| name.len() + 2).max().unwrap_or(0)
+                        .max(6);
+        println!("  {:<width$}  {:<18}  {}", "Symbol", old.display(), new.display(),
+                    width = width);
+        let crc = |crc: &Option<u64>| {
+            crc.map_or("-".to_string(), |crc| format!("0x{:x}", crc))
+        };
+        let (mut changed, mut removed, mut added, mut unchanged) = (0, 0, 0, 0);
+        for (name, old_crc, new_crc) in &rows {
+            let (mark, color) = match (old_crc, new_crc) {
+                (Some(a), Some(b)) if a == b => {
+                    unchanged += 1;
+                    if !show_unchanged {
+                        continue;
+                    }
+                    ('=', color::Color::Green)
+                },
+                (Some(_), Some(_)) => {
+                    changed += 1;
+                    ('~', color::Color::Red)
+                },
+                (Some(_), None) => {
+                    removed += 1;
+                    ('-', color::Color::Red)
+                },
+                _ => {
+                    added += 1;
+                    ('+', color::Color::Yellow)
+                },
+            };
+            println!("{}", color::paint(color::Stream::Stdout, color,
+                format!("{} {:<width$}  {:<18}  {}", mark, format!("\"{}\"", name),
+                        crc(old_crc), crc(new_crc), width = width)));
+        }
+        println!("{} changed, {} removed, {} added, {} unchanged", changed, removed,
+                    added, unchanged);
+    }
+
+    // Exports only in the new kernel can not break modules built for the old
+    let broken: HashMap<&str, bool> = rows.iter()
+        .filter(|(_, old_crc, new_crc)| old_crc.is_some() && old_crc != new_crc)
+        .map(|(name, _, new_crc)| (name.as_str(), new_crc.is_some()))
+        .collect();
+    let mut affected = 0;
+    for module in expand_targets(modules)? {
+        let data = read_module(&module)?;
+        let imports = parse_elf(&data)
+            .and_then(|info| modversions::undefined_symbols(&info, &data))
+            .map_err(|e| e.in_file(&module))?;
+        let (changed, removed): (Vec<&String>, Vec<&String>) = imports.iter()
+            .filter(|name| broken.contains_key(name.as_str()))
+            .partition(|name| broken[name.as_str()]);
+        let list = |names: &[&String]| {
+            names.iter().map(|name| format!("\"{}\"", name))
+                .collect::<Vec<_>>().join(", ")
+        };
+        if changed.is_empty() && removed.is_empty() {
+            info!("{}: unaffected", module.display());
+            continue;
+        }
+        affected += changed.len() + removed.len();
+        if !changed.is_empty() {
+            warn!("{}: needs patching, imports {} changed exports: {}",
+                  module.display(), changed.len(), list(&changed));
+        }
+        if !removed.is_empty() {
+            warn!("{}: imports {} removed exports, patching can not make it load: {}",
+                  module.display(), removed.len(), list(&removed));
+        }
+    }
+    if affected > 0 {
+        return Err(Error::CheckFailed { count: affected });
+    }
+    Ok(())
+}
+
+/// What loading a module on a kernel takes, as `plan` reports it
+struct ModulePlan {
+    /// Imports whose version in the module differs from the kernel's or is
+    /// missing
+    crcs: usize,
+    /// The module's vermagic differs from the kernel's
+    vermagic: bool,
+    /// Imports neither the kernel nor the other modules export
+    missing: Vec<String>,
+    /// GPL-only imports the kernel refuses the module because of its license
+    gpl_only: Vec<String>,
+}
+
+impl ModulePlan {
+    fn status(&self) -> &'static str {
+        if !self.missing.is_empty() || !self.gpl_only.is_empty() {
+            "unfixable"
+        } else if self.crcs > 0 || self.vermagic {
+            "needs patch"
+        } else {
+            "loads as-is"
+        }
+    }
+}
+
+/// Checks a module's imports and vermagic against a kernel
+///
+/// # Arguments
+/// * `exports` - Exports of the kernel
+/// * `tree_exports` - Symbols exported by the modules being planned, which
+///   resolve imports without being versioned against the kernel
+/// * `vermagic` - Vermagic of the kernel, if known
+fn plan_module(path: &std::path::Path, exports: &symvers::Exports,
+               tree_exports: &HashSet<String>, vermagic: Option<&str>)
+    -> Result<ModulePlan> {
+
+    let ko = load_module(path)?;
+    let info = parse_elf(&ko.buffer).map_err(|e| e.in_file(path))?;
+    let imports = modversions::undefined_symbols(&info, &ko.buffer)
+                    .map_err(|e| e.in_file(path))?;
+    let versions = ko.versions.as_deref().map(versions_by_name).unwrap_or_default();
+    let modinfo = ko.modinfo.as_deref().unwrap_or_default();
+    let gpl = modinfo::find_entry(modinfo, "license")
+                .is_some_and(|entry| license::is_gpl_compatible(&entry.value));
+
+    let mut plan = ModulePlan { crcs: 0, vermagic: false, missing: Vec::new(),
+                                gpl_only: Vec::new() };
+    for import in &imports {
+        match exports.crcs.get(import) {
+            Some(_) if !gpl && exports.gpl_only.contains(import) => {
+                plan.gpl_only.push(import.clone());
+            },
+            Some(&crc) if versions.get(import.as_str()).is_none_or(|v| v.crc != crc) => {
+                plan.crcs += 1;
+            },
+            Some(_) => {},
+            None if tree_exports.contains(import) => {},
+            None => plan.missing.push(import.clone()),
+        }
+    }
+    if let Some(k_vermagic) = vermagic {
+        let m_vermagic = modinfo::find_entry(modinfo, "vermagic")
+                            .map_or("", |entry| entry.value.as_str());
+        plan.vermagic = !vermagic::same_magic(m_vermagic, k_vermagic,
+                                              ko.versions.is_some());
+    }
+    Ok(plan)
+}
+
+/// Reports which modules below a directory load on a kernel as they are,
+/// need their versions or vermagic patched, or import symbols the kernel
+/// lacks. Fails if any module can not be fixed by patching
+fn plan(dir: &std::path::Path, symvers_path: &std::path::Path, vermagic: Option<&str>,
+        format: csv::Format) -> Result<()> {
+
+    let exports = symvers::load_exports(symvers_path)?;
+    let modules = donors::find_modules(dir)?;
+    if modules.is_empty() {
+        return Err(Error::NothingToDo {
+            reason: format!("no kernel modules found in {}", dir.display()),
+        });
+    }
+    if vermagic.is_none() {
+        info!("Vermagic is not checked without `--vermagic`");
+    }
+
+    // Modules may import symbols exported by other modules of the tree
+    let mut tree_exports = HashSet::new();
+    for path in &modules {
+        let ko = load_module(path)?;
+        let info = parse_elf(&ko.buffer).map_err(|e| e.in_file(path))?;
+        tree_exports.extend(exports::crcs(&info, &ko.buffer)
+                                .map_err(|e| e.in_file(path))?.into_keys());
+    }
+
+    let plans = modules.iter()
+        .map(|path| plan_module(path, &exports, &tree_exports, vermagic))
+        .collect::<Result<Vec<_>>>()?;
+    let names: Vec<String> = modules.iter()
+        .map(|path| path.strip_prefix(dir).unwrap_or(path).display().to_string())
+        .collect();
+
+    if format == csv::Format::Csv {
+        logging::STDOUT_OUTPUT.store(true, Ordering::Relaxed);
+        println!("{}", csv::row(&["module", "status", "crcs", "vermagic", "missing",
+                                  "gpl_only"]));
+        for (name, plan) in names.iter().zip(&plans) {
+            println!("{}", csv::row(&[name.clone(), plan.status().to_string(),
+                                      plan.crcs.to_string(),
+                                      if plan.vermagic { "differs" } else { "" }
+                                        .to_string(),
+                                      plan.missing.join(" "), plan.gpl_only.join(" ")]));
+        }
+    } else {
+        let width = names.iter().map(String::len).max().unwrap_or(0).max(6);
+        println!("  {:<width$}  {:<11}  Details", "Module", "Status", width = width);
+        for (name, plan) in names.iter().zip(&plans) {
+            let list = |names: &[String]| {
+                names.iter().map(|name| format!("\"{}\"", name))
+                    .collect::<Vec<_>>().join(", ")
+            };
+            let mut details = Vec::new();
+            if !plan.missing.is_empty() {
+                details.push(format!("missing {}", list(&plan.missing)));
+            }
+            if !plan.gpl_only.is_empty() {
+                details.push(format!("GPL-only {}", list(&plan.gpl_only)));
+            }
+            if plan.crcs > 0 {
+                details.push(format!("{} CRCs", plan.crcs));
+            }
+            if plan.vermagic {
+                details.push("vermagic".to_string());
+            }
+            let color = match plan.status() {
+                "unfixable" => color::Color::Red,
+                "needs patch" => color::Color::Yellow,
+                _ => color::Color::Green,
+            };
+            println!("{}", color::paint(color::Stream::Stdout, color,
+                format!("  {:<width$}  {:<11}  {}", name, plan.status(),
+                        details.join(", "), width = width).trim_end()));
+        }
+    }
+
+    let count = |status| plans.iter().filter(|plan| plan.status() == status).count();
+    let unfixable = count("unfixable");
+    info!("{} modules: {} load as-is, {} need patching, {} unfixable", plans.len(),
+          count("loads as-is"), count("needs patch"), unfixable);
+    if unfixable > 0 {
+        return Err(Error::CheckFailed { count: unfixable });
+    }
+    Ok(())
+}
+
+/// Reads the BTF of a kernel module, kernel image or raw BTF file. Module BTF
+/// is split from the BTF of the kernel and needs the kernel's BTF as a base
+fn load_btf(path: &std::path::Path, base: Option<&std::path::Path>)
+    -> Result<btf::Btf> {
+
+    let base = base.map(|base| load_btf(base, None)).transpose()?;
+    let data = read_module(path)?;
+    if !data.starts_with(goblin::elf::header::ELFMAG) {
+        return btf::Btf::parse(&data, base.as_ref()).map_err(|e| e.in_file(path));
+    }
+
+    let load = || {
+        let info = parse_elf(&data)?;
+        let sh = find_section(&info, ".BTF")
+                    .ok_or(Error::MissingSection { section: ".BTF".to_string() })?;
+        let is_module = info.header.e_type == goblin::elf::header::ET_REL;
+        if is_module && base.is_none() {
+            return Err(Error::Unsupported {
+                reason: "module BTF is split from the kernel's BTF, which must \
+                         be given as a base".to_string(),
+            });
+        }
+        btf::Btf::parse(section_data(sh, ".BTF", &data)?, base.as_ref())
+    };
+    load().map_err(|e| e.in_file(path))
+}
+
+fn btf_diff(target: &std::path::Path, source: &std::path::Path,
+            target_base: Option<&std::path::Path>,
+            source_base: Option<&std::path::Path>, hide_matching: bool)
+    -> Result<()> {
+
+    let t_versions = load_versions(target)?;
+
+    // Modules import functions from the kernel, so without BTF of its own the
+    // target's kernel BTF is all that is needed
+    let t_btf = match target_base {
+        Some(base) => match load_btf(target, Some(base)) {
+            Err(Error::InFile { source, .. })
+                if matches!(*source, Error::MissingSection { .. }) => {
+                load_btf(base, None)?
+            },
+            result => result?,
+        },
+        None => load_btf(target, None)?,
+    };
+    let s_btf = load_btf(source, source_base)?;
+
+    let mark = |color, mark: &str| color::paint(color::Stream::Stdout, color, mark);
+    let mut comparison = btf::Comparison::new(&t_btf, &s_btf);
+    let (mut differing, mut unknown, mut matching) = (0, 0, 0);
+    for ver in &t_versions {
+        match comparison.function(&ver.name) {
+            btf::FunctionMatch::Unknown => {
+                unknown += 1;
+                println!("{} \"{}\" not found in both BTF",
+                         mark(color::Color::Yellow, "?"), ver.name);
+            },
+            btf::FunctionMatch::Matching => {
+                matching += 1;
+                if !hide_matching {
+                    println!("{} \"{}\"", mark(color::Color::Green, "="), ver.name);
+                }
+            },
+            btf::FunctionMatch::Differing { prototypes, layouts } => {
+                differing += 1;
+                println!("{} \"{}\"", mark(color::Color::Red, "~"), ver.name);
+                if let Some((t_proto, s_proto)) = prototypes {
+                    println!("      target prototype {}", t_proto);
+                    println!("      source prototype {}", s_proto);
+                }
+                if !layouts.is_empty() {
+                    println!("      layouts differ: {}", layouts.join(", "));
+                }
+            },
+        }
+    }
+
+    let layouts = comparison.layout_differences();
+    if !layouts.is_empty() {
+        println!("Layout differences:");
+        for (name, difference) in &layouts {
+            println!("    {}: {}", name, difference);
+        }
+    }
+    println!("{} differing, {} not found, {} matching", differing, unknown,
+                matching);
+    if differing > 0 {
+        return Err(Error::AbiMismatch { count: differing });
+    }
+    Ok(())
+}
+
+fn completions(shell: clap_complete::Shell) -> Result<()> {
+    // Stdout carries the script
+    logging::STDOUT_OUTPUT.store(true, Ordering::Relaxed);
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut Args::command(), "candycorn", &mut script);
+    use std::io::Write;
+    std::io::stdout().lock().write_all(&script)
+        .map_err(|e| Error::Write { path: "-".into(), source: e })
+}
+
+fn main() {
+    logging::init();
+    let args = Args::parse_with_config(std::env::args_os().collect());
+    logging::set_verbosity(args.verbose, args.quiet);
+    logging::PORCELAIN.store(args.porcelain, Ordering::Relaxed);
+    REPRODUCIBLE.store(args.reproducible, Ordering::Relaxed);
+    color::init(args.color);
+    progress::init();
+
+    let result = match args.command {
+        Commands::Patch(patch_args) => patch(output_dir_from_env(*patch_args)),
+        Commands::Install(install_args) => install(*install_args),
+        Commands::Squashfs(image_args) => {
+            squashfs::superblock(&image_args.image)
+                .and_then(|settings| patch_image(*image_args, Image::SquashFs(settings)))
+        },
+        Commands::Erofs(image_args) => {
+            erofs::superblock(&image_args.image)
+                .and_then(|settings| patch_image(*image_args, Image::Erofs(settings)))
+        },
+        Commands::Run { manifest } => run(&manifest),
+        Commands::Dkms(dkms_args) => dkms(*dkms_args),
+        Commands::Watch(watch_args) => watch(watch_args),
+        Commands::Db(db_args) => db(db_args),
+        Commands::Serve { db, listen } => {
+            db::path(db).and_then(|path| lookup::serve(&path, &listen))
+        },
+        Commands::List { target } => list(&target),
+        Commands::Verify { target } => verify(&target),
+        Commands::SimulateLoad { target } => simulate_load(&target),
+        Commands::Doctor => doctor(),
+        Commands::Inspect { target, format } => inspect(&target, format),
+        Commands::Revert { target, output } => revert(&target, output),
+        Commands::StripVersions { target, output, keep_vermagic } => {
+            strip_versions(&target, output, keep_vermagic)
+        },
+        Commands::Apply { patch, target, output, ignore_hash } => {
+            apply(&patch, &target, output, ignore_hash)
+        },
+        Commands::Diff { a, b, hide_matching, format } => {
+            diff(&a, &b, hide_matching, format)
+        },
+        Commands::SymversDiff { old, new, module, show_unchanged, format } => {
+            symvers_diff(&old, &new, &module, show_unchanged, format)
+        },
+        Commands::Plan { dir, symvers, vermagic, format } => {
+            plan(&dir, &symvers, vermagic.as_deref(), format)
+        },
+        Commands::BtfDiff { target, source, target_base, source_base,
+                            hide_matching } => {
+            btf_diff(&target, &source, target_base.as_deref(),
+                     source_base.as_deref(), hide_matching)
+        },
+        Commands::Testgen(testgen_args) => testgen(testgen_args),
+        Commands::Completions { shell } => completions(shell),
+    };
+
+    if let Err(e) = result {
+        error!("{}", e);
+        exit::exit(e.exit_code());
+    }
+    info!("Done!");
 }
@@ -0,0 +1,188 @@
+use crate::error::{Error, Result};
+use crate::overrides::CrcValue;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Patch recipe for a set of targets. Paths are relative to the manifest's
+/// directory
+#[derive(Clone, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Entry {
+    /// Target modules or archives, glob patterns are expanded
+    pub targets: Vec<PathBuf>,
+    pub src: Vec<PathBuf>,
+    pub src_dir: Vec<PathBuf>,
+    pub src_kernel: Vec<String>,
+    pub src_db: Vec<String>,
+    pub symvers: Option<PathBuf>,
+    pub vmlinux: Option<PathBuf>,
+    pub system_map: Option<PathBuf>,
+    /// Saved `/proc/kallsyms`, read like a `System.map`
+    pub kallsyms: Option<PathBuf>,
+    pub symtypes: Option<PathBuf>,
+    /// Symbol versions to patch, like `--set`
+    pub set: BTreeMap<String, CrcValue>,
+    pub overrides: Option<PathBuf>,
+    pub only: Vec<String>,
+    pub vermagic: Option<String>,
+    /// Output path, for entries with a single target
+    pub output: Option<PathBuf>,
+    pub keep: Option<bool>,
+    /// Fail targets with versions missing from the sources
+    pub require_all: Option<bool>,
+    /// Further `patch` options, given as on the command line
+    pub options: Vec<String>,
+}
+
+/// Manifest listing patch recipes. Settings in `defaults` apply to every
+/// entry that does not set them itself
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default)]
+    defaults: Entry,
+    #[serde(default)]
+    target: Vec<Entry>,
+}
+
+/// Loads a manifest, returning its entries with the defaults applied. Files
+/// with a `.json` extension are parsed as JSON, all others as TOML
+///
+/// Example TOML file:
+/// ```toml
+/// [defaults]
+/// symvers = "kernel/Module.symvers"
+///
+/// [[target]]
+/// targets = ["vendor/*.ko"]
+/// require-all = true
+///
+/// [[target]]
+/// targets = ["extra/wifi.ko"]
+/// src = ["reference/cfg80211.ko"]
+/// set = { printk = "0xdeadbeef" }
+/// output = "out/wifi.ko"
+/// ```
+pub fn load(path: &Path) -> Result<Vec<Entry>> {
+    let content = std::fs::read_to_string(path)
+                    .map_err(|e| Error::Read { path: path.into(), source: e })?;
+    let manifest: Manifest = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => serde_json::from_str(&content).map_err(Error::from),
+        Some("yaml" | "yml") => Err(Error::Manifest {
+            reason: "YAML manifests are not supported, use TOML or JSON"
+                        .to_string(),
+        }),
+        _ => toml::from_str(&content).map_err(Error::from),
+    }.map_err(|e| e.in_file(path))?;
+
+    if manifest.target.is_empty() {
+        return Err(Error::Manifest { reason: "no `target` entries".to_string() }
+                    .in_file(path));
+    }
+    Ok(manifest.target.into_iter()
+        .map(|entry| entry.with_defaults(&manifest.defaults))
+        .collect())
+}
+
+/// Takes a setting from the defaults if an entry leaves it empty
+fn or_default<T: Clone>(value: Option<T>, default: &Option<T>) -> Option<T> {
+    value.or_else(|| default.clone())
+}
+
+fn or_default_list<T: Clone>(value: Vec<T>, default: &[T]) -> Vec<T> {
+    if value.is_empty() { default.to_vec() } else { value }
+}
+
+impl Entry {
+    fn with_defaults(self, defaults: &Entry) -> Entry {
+        let mut set = defaults.set.clone();
+        set.extend(self.set);
+        let mut options = defaults.options.clone();
+        options.extend(self.options);
+        Entry {
+            targets: or_default_list(self.targets, &defaults.targets),
+            src: or_default_list(self.src, &defaults.src),
+            src_dir: or_default_list(self.src_dir, &defaults.src_dir),
+            src_kernel: or_default_list(self.src_kernel, &defaults.src_kernel),
+            src_db: or_default_list(self.src_db, &defaults.src_db),
+            symvers: or_default(self.symvers, &defaults.symvers),
+            vmlinux: or_default(self.vmlinux, &defaults.vmlinux),
+            system_map: or_default(self.system_map, &defaults.system_map),
+            kallsyms: or_default(self.kallsyms, &defaults.kallsyms),
+            symtypes: or_default(self.symtypes, &defaults.symtypes),
+            set,
+            overrides: or_default(self.overrides, &defaults.overrides),
+            only: or_default_list(self.only, &defaults.only),
+            vermagic: or_default(self.vermagic, &defaults.vermagic),
+            output: self.output,
+            keep: or_default(self.keep, &defaults.keep),
+            require_all: or_default(self.require_all, &defaults.require_all),
+            options,
+        }
+    }
+
+    /// Builds the `patch` command line of the entry, resolving paths against
+    /// the manifest's directory
+    pub fn patch_args(&self, base: &Path) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec!["candycorn".into(), "patch".into()];
+        let mut path = |option: &str, path: &Path| {
+            args.push(option.into());
+            args.push(base.join(path).into());
+        };
+        for src in &self.src {
+            path("--src", src);
+        }
+        for dir in &self.src_dir {
+            path("--src-dir", dir);
+        }
+        let files = [
+            ("--symvers", &self.symvers),
+            ("--vmlinux", &self.vmlinux),
+            ("--system-map", &self.system_map),
+            ("--system-map", &self.kallsyms),
+            ("--symtypes", &self.symtypes),
+            ("--overrides", &self.overrides),
+            ("--output", &self.output),
+        ];
+        for (option, file) in files {
+            if let Some(file) = file {
+                path(option, file);
+            }
+        }
+
+        let mut value = |option: &str, value: String| {
+            args.push(option.into());
+            args.push(value.into());
+        };
+        for kernel in &self.src_kernel {
+            value("--src-kernel", kernel.clone());
+        }
+        for kernel in &self.src_db {
+            value("--src-db", kernel.clone());
+        }
+        for (name, crc) in &self.set {
+            let crc = match crc {
+                CrcValue::Int(crc) => format!("{:#x}", crc),
+                CrcValue::Str(crc) => crc.clone(),
+            };
+            value("--set", format!("{}={}", name, crc));
+        }
+        for pattern in &self.only {
+            value("--only", pattern.clone());
+        }
+        if let Some(vermagic) = &self.vermagic {
+            value("--vermagic", vermagic.clone());
+        }
+
+        if self.keep == Some(true) {
+            args.push("--keep".into());
+        }
+        if self.require_all == Some(true) {
+            args.push("--require-all".into());
+        }
+        args.extend(self.options.iter().map(Into::into));
+        args.extend(self.targets.iter().map(|target| base.join(target).into()));
+        args
+    }
+}
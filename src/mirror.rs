@@ -0,0 +1,189 @@
+use crate::error::{Error, Result};
+use crate::tools;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Distribution whose official mirror reference kernels are fetched from
+#[derive(Clone, Copy, Debug)]
+pub enum Distro {
+    Ubuntu,
+    Debian,
+    Fedora,
+}
+
+/// Kernel release of a distribution, as given on the command line
+#[derive(Clone, Debug)]
+pub struct Kernel {
+    pub distro: Distro,
+    /// Release as `uname -r` prints it
+    pub release: String,
+    /// Package architecture. Debian and Fedora releases name it already
+    pub arch: Option<String>,
+}
+
+impl std::fmt::Display for Kernel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let distro = match self.distro {
+            Distro::Ubuntu => "ubuntu",
+            Distro::Debian => "debian",
+            Distro::Fedora => "fedora",
+        };
+        write!(f, "{}:{}", distro, self.release)?;
+        if let Some(arch) = &self.arch {
+            write!(f, ":{}", arch)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a reference kernel given as `DISTRO:RELEASE[:ARCH]`
+pub fn parse_kernel(value: &str) -> std::result::Result<Kernel, String> {
+    let mut parts = value.splitn(3, ':');
+    let distro = match parts.next() {
+        Some("ubuntu") => Distro::Ubuntu,
+        Some("debian") => Distro::Debian,
+        Some("fedora") => Distro::Fedora,
+        _ => return Err("expected DISTRO:RELEASE with DISTRO one of ubuntu, \
+                         debian or fedora".to_string()),
+    };
+    let release = parts.next().filter(|release| !release.is_empty())
+                    .ok_or("expected DISTRO:RELEASE")?;
+    Ok(Kernel {
+        distro,
+        release: release.to_string(),
+        arch: parts.next().map(str::to_string),
+    })
+}
+
+/// Debian package architecture of the machine candycorn was built for
+fn host_deb_arch() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "x86" => "i386",
+        "aarch64" => "arm64",
+        "arm" => "armhf",
+        "powerpc64" => "ppc64el",
+        arch => arch,
+    }
+}
+
+/// Debian package architecture of a Debian kernel flavour, such as
+/// `cloud-amd64` or `686-pae`
+fn flavour_deb_arch(flavour: &str) -> Option<&'static str> {
+    let arch = flavour.rsplit('-').next()?;
+    match arch {
+        "amd64" => Some("amd64"),
+        "arm64" => Some("arm64"),
+        "686" | "pae" => Some("i386"),
+        "armmp" | "lpae" => Some("armhf"),
+        "powerpc64le" => Some("ppc64el"),
+        "s390x" => Some("s390x"),
+        "riscv64" => Some("riscv64"),
+        _ => None,
+    }
+}
+
+fn not_found(kernel: &Kernel, reason: impl Into<String>) -> Error {
+    Error::Mirror { kernel: kernel.to_string(), reason: reason.into() }
+}
+
+/// Downloads a URL with `curl`
+fn download(url: &str) -> Result<Vec<u8>> {
+    tools::output(Command::new("curl").args(["-fsSL", url]))
+}
+
+/// Finds a package in a mirror's pool directory listing by the start and end
+/// of its file name
+fn find_in_pool(pool: &str, prefix: &str, suffix: &str) -> Result<Option<String>> {
+    let listing = download(pool)?;
+    let listing = String::from_utf8_lossy(&listing);
+    let href = regex::Regex::new(r#"href="([^"/?]+)""#).unwrap();
+    Ok(href.captures_iter(&listing)
+        .map(|captures| captures[1].replace("%2B", "+").replace("%7E", "~"))
+        .filter(|name| name.starts_with(prefix) && name.ends_with(suffix))
+        .max()
+        .map(|name| format!("{}{}", pool, name)))
+}
+
+/// URL of the package holding a kernel's modules. Ubuntu ships them in
+/// `linux-modules`, Debian in the signed `linux-image` and Fedora's
+/// `kernel-core` holds `symvers.xz`, which covers every export
+fn package_url(kernel: &Kernel) -> Result<String> {
+    let release = &kernel.release;
+    match kernel.distro {
+        Distro::Ubuntu => {
+            let arch = kernel.arch.as_deref().unwrap_or(host_deb_arch());
+            let mirror = match arch {
+                "amd64" | "i386" => "http://archive.ubuntu.com/ubuntu",
+                _ => "http://ports.ubuntu.com/ubuntu-ports",
+            };
+
+            // Hardware enablement kernels are built from their own source
+            // packages, named by the kernel version
+            let version: Vec<&str> = release.split('.').take(2).collect();
+            let sources = ["linux".to_string(),
+                           format!("linux-hwe-{}", version.join("."))];
+            for (idx, source) in sources.iter().enumerate() {
+                let pool = format!("{}/pool/main/l/{}/", mirror, source);
+                let prefix = format!("linux-modules-{}_", release);
+                let suffix = format!("_{}.deb", arch);
+                match find_in_pool(&pool, &prefix, &suffix) {
+                    Ok(Some(url)) => return Ok(url),
+                    // Most versions have no hardware enablement kernels
+                    Err(e) if idx == 0 => return Err(e),
+                    _ => {},
+                }
+            }
+            Err(not_found(kernel, format!("no linux-modules package for {} in \
+                                           {}", arch, mirror)))
+        },
+        Distro::Debian => {
+            let arch = kernel.arch.as_deref()
+                        .or_else(|| flavour_deb_arch(release))
+                        .ok_or_else(|| not_found(kernel, "unknown flavour, \
+                                                          give the architecture \
+                                                          as debian:RELEASE:ARCH"))?;
+            let pool = format!("http://deb.debian.org/debian/pool/main/l/\
+                                linux-signed-{}/", arch);
+            find_in_pool(&pool, &format!("linux-image-{}_", release),
+                         &format!("_{}.deb", arch))?
+                .ok_or_else(|| not_found(kernel, format!("no linux-image package \
+                                                          in {}", pool)))
+        },
+        Distro::Fedora => {
+            // Releases have the form `<version>-<release>.<arch>`
+            let (version, rest) = release.split_once('-')
+                .ok_or_else(|| not_found(kernel, "expected VERSION-RELEASE.ARCH"))?;
+            let (build, arch) = rest.rsplit_once('.')
+                .ok_or_else(|| not_found(kernel, "expected VERSION-RELEASE.ARCH"))?;
+            let arch = kernel.arch.as_deref().unwrap_or(arch);
+            Ok(format!("https://kojipkgs.fedoraproject.org/packages/kernel/{}/{}/\
+                        {}/kernel-core-{}-{}.{}.rpm", version, build, arch,
+                       version, build, arch))
+        },
+    }
+}
+
+/// Downloads the package holding a kernel's modules unless it is cached,
+/// returning its path. Packages are cached by the kernel they were fetched
+/// for, so cached kernels need no access to the mirror
+pub fn fetch(kernel: &Kernel) -> Result<PathBuf> {
+    let dir = crate::cache::dir("packages")
+                .ok_or_else(|| not_found(kernel, "no cache directory, set \
+                                                  `XDG_CACHE_HOME`"))?;
+    let ext = match kernel.distro {
+        Distro::Ubuntu | Distro::Debian => "deb",
+        Distro::Fedora => "rpm",
+    };
+    let path = dir.join(format!("{}.{}", kernel.to_string().replace(':', "_"), ext));
+    if path.exists() {
+        return Ok(path);
+    }
+
+    let url = package_url(kernel)?;
+    let data = download(&url)?;
+    std::fs::create_dir_all(&dir)
+        .and_then(|_| crate::output::write_atomic(&path, &data, None))
+        .map_err(|e| Error::Write { path: path.clone(), source: e })?;
+    Ok(path)
+}
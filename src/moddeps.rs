@@ -0,0 +1,106 @@
+use crate::error::{Error, Result};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Dependencies of a directory's modules as its `modules.dep` lists them, by
+/// canonical module path
+type DepIndex = HashMap<PathBuf, Vec<PathBuf>>;
+
+/// Finds the closest directory at or above a module's that holds a
+/// `modules.dep`
+fn dep_dir(module: &Path) -> Option<PathBuf> {
+    let dir = std::fs::canonicalize(module).ok()?.parent()?.to_path_buf();
+    dir.ancestors().find(|dir| dir.join("modules.dep").is_file())
+        .map(Path::to_path_buf)
+}
+
+/// Reads a `modules.dep`, whose lines are "<path>: <dependency paths>" with
+/// paths relative to its directory
+fn read_index(dir: &Path) -> Result<DepIndex> {
+    let dep_path = dir.join("modules.dep");
+    let deps = std::fs::read_to_string(&dep_path)
+                .map_err(|e| Error::Read { path: dep_path, source: e })?;
+    let canonical = |path: &str| {
+        let path = dir.join(path);
+        std::fs::canonicalize(&path).unwrap_or(path)
+    };
+    Ok(deps.lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(path, deps)| {
+            (canonical(path), deps.split_whitespace().map(canonical).collect())
+        })
+        .collect())
+}
+
+/// Resolves the names in a module's `depends` entry against the modules below
+/// its directory
+fn modinfo_deps(module: &Path) -> Result<Vec<PathBuf>> {
+    let entries = crate::load_module(module)?.modinfo.unwrap_or_default();
+    let names: Vec<String> = match crate::modinfo::find_entry(&entries, "depends") {
+        Some(entry) => entry.value.split(',').filter(|name| !name.is_empty())
+                        .map(crate::modinfo::module_name).collect(),
+        None => return Ok(Vec::new()),
+    };
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let dir = module.parent().filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or(Path::new("."));
+    let found = crate::donors::find_modules(dir)?;
+    let mut deps = Vec::new();
+    for name in names {
+        let path = found.iter().find(|path| {
+            crate::modinfo::module_name(&crate::module_stem(path)) == name
+        });
+        match path {
+            Some(path) => deps.push(path.clone()),
+            None => log::warn!("Dependency {} of {} not found below {}", name,
+                               module.display(), dir.display()),
+        }
+    }
+    Ok(deps)
+}
+
+/// Collects the modules the targets depend on, directly or through other
+/// dependencies, that are not targets themselves, in the order found.
+/// Dependencies are taken from the closest `modules.dep` at or above a
+/// module's directory, or else from its `.modinfo` `depends` entry
+pub fn collect(targets: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let canonical = |path: &Path| {
+        std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+    };
+    let mut seen: HashSet<PathBuf> = targets.iter().map(|t| canonical(t)).collect();
+    let mut indices: HashMap<PathBuf, DepIndex> = HashMap::new();
+    let mut queue: Vec<PathBuf> = targets.iter()
+        .filter(|t| t.file_name().is_some_and(crate::donors::is_module_name))
+        .cloned().collect();
+    let mut found = Vec::new();
+
+    let mut idx = 0;
+    while idx < queue.len() {
+        let module = queue[idx].clone();
+        idx += 1;
+        let listed = match dep_dir(&module) {
+            Some(dir) => {
+                if !indices.contains_key(&dir) {
+                    indices.insert(dir.clone(), read_index(&dir)?);
+                }
+                indices[&dir].get(&canonical(&module)).cloned()
+            },
+            None => None,
+        };
+        let deps = match listed {
+            Some(deps) => deps,
+            None => modinfo_deps(&module)?,
+        };
+        for dep in deps {
+            if seen.insert(canonical(&dep)) {
+                log::debug!("{} depends on {}", module.display(), dep.display());
+                found.push(dep.clone());
+                queue.push(dep);
+            }
+        }
+    }
+    Ok(found)
+}
@@ -0,0 +1,269 @@
+use crate::error::{Error, Result};
+
+pub use candycorn_core::ModInfoEntry;
+
+/// Produces a list of `.modinfo` entries given a kernel module's ELF metadata
+/// and backing byte content. Entries are in the order they appear in the ELF.
+/// Returns `None` if the module has no `.modinfo` section
+pub fn get_modinfo(info: &goblin::elf::Elf, mod_data: &[u8])
+    -> Result<Option<Vec<ModInfoEntry>>> {
+
+    Ok(candycorn_core::modinfo(info, mod_data)?)
+}
+
+/// Finds the first `.modinfo` entry with the provided key
+pub fn find_entry<'a>(entries: &'a [ModInfoEntry], key: &str)
+    -> Option<&'a ModInfoEntry> {
+
+    entries.iter().find(|e| e.key == key)
+}
+
+/// Rewrites the value of an existing `.modinfo` entry in place. Shorter values
+/// are padded with null bytes. Values that do not fit within the space of the
+/// existing entry are refused, the section must be rebuilt for them
+///
+/// # Arguments
+/// * `mod_data` - Backing byte content of the kernel module to patch
+/// * `entry` - Entry to rewrite
+/// * `value` - New value for the entry
+pub fn patch_entry(mod_data: &mut [u8], entry: &ModInfoEntry, value: &str)
+    -> Result<()> {
+
+    let new_entry = format!("{}={}", entry.key, value);
+
+    // Need room for the null terminator as well
+    if new_entry.len() + 1 > entry.capacity {
+        return Err(Error::ModInfoTooLong {
+            key: entry.key.clone(),
+            value: value.to_string(),
+            offset: entry.offset,
+            available: entry.capacity.saturating_sub(entry.key.len() + 2),
+        });
+    }
+
+    let region = &mut mod_data[entry.offset .. entry.offset + entry.capacity];
+    region.fill(0);
+    region[.. new_entry.len()].copy_from_slice(new_entry.as_bytes());
+    Ok(())
+}
+
+/// Change to the `.modinfo` entries of a module
+#[derive(Clone, Debug)]
+pub enum Edit {
+    /// Sets the value of the first entry with the key, adding an entry if
+    /// there is none
+    Set { key: String, value: String },
+    /// Adds an entry, even if there are entries with the key already
+    Add { key: String, value: String },
+    /// Removes all entries with the key, or only those with the value if one
+    /// is given
+    Delete { key: String, value: Option<String> },
+}
+
+/// Returns the value of the first entry with the key once edits are applied,
+/// which is the value the kernel reads
+pub fn edited_value(entries: &[ModInfoEntry], edits: &[Edit], key: &str)
+    -> Option<String> {
+
+    let mut values: Vec<&str> = entries.iter().filter(|e| e.key == key)
+                                    .map(|e| e.value.as_str()).collect();
+    for edit in edits {
+        match edit {
+            Edit::Set { key: k, value } if k == key => match values.first_mut() {
+                Some(first) => *first = value,
+                None => values.push(value),
+            },
+            Edit::Add { key: k, value } if k == key => values.push(value),
+            Edit::Delete { key: k, value } if k == key => values.retain(|v| {
+                value.as_ref().is_some_and(|value| v != value)
+            }),
+            _ => {},
+        }
+    }
+    values.first().map(|v| v.to_string())
+}
+
+/// Produces the content of a `.modinfo` section holding the provided entries
+/// with edits applied. Entries keep their order and are packed without
+/// padding. Added entries go at the end
+pub fn rebuild(entries: &[ModInfoEntry], edits: &[Edit]) -> Vec<u8> {
+    let mut pairs: Vec<(&str, &str)> = entries.iter()
+        .map(|e| (e.key.as_str(), e.value.as_str()))
+        .collect();
+    for edit in edits {
+        match edit {
+            Edit::Set { key, value } => {
+                match pairs.iter_mut().find(|(k, _)| k == key) {
+                    Some(pair) => pair.1 = value,
+                    None => pairs.push((key, value)),
+                }
+            },
+            Edit::Add { key, value } => pairs.push((key, value)),
+            Edit::Delete { key, value } => pairs.retain(|(k, v)| {
+                k != key || value.as_ref().is_some_and(|value| v != value)
+            }),
+        }
+    }
+
+    let mut content = Vec::new();
+    for (key, value) in pairs {
+        content.extend_from_slice(key.as_bytes());
+        content.push(b'=');
+        content.extend_from_slice(value.as_bytes());
+        content.push(0);
+    }
+    content
+}
+
+/// Changes to the comma separated list of modules in a `depends` entry
+#[derive(Default)]
+pub struct DependsEdit {
+    /// Dependencies to replace, as old and new module names
+    pub rename: Vec<(String, String)>,
+    pub add: Vec<String>,
+    pub remove: Vec<String>,
+}
+
+/// Normalizes a module name. Dashes and underscores are interchangeable
+pub fn module_name(name: &str) -> String {
+    name.replace('-', "_")
+}
+
+impl DependsEdit {
+    pub fn is_empty(&self) -> bool {
+        self.rename.is_empty() && self.add.is_empty() && self.remove.is_empty()
+    }
+
+    /// Applies the changes to the value of a `depends` entry, warning about
+    /// dependencies to rename or remove that are not in the list
+    pub fn apply(&self, value: &str) -> String {
+        let mut depends: Vec<String> = value.split(',').filter(|d| !d.is_empty())
+                                        .map(str::to_string).collect();
+        let position = |depends: &[String], name: &str| {
+            depends.iter().position(|d| module_name(d) == module_name(name))
+        };
+
+        for (old, new) in &self.rename {
+            match position(&depends, old) {
+                Some(idx) => depends[idx] = new.clone(),
+                None => log::warn!("Target does not depend on {}", old),
+            }
+        }
+        for name in &self.remove {
+            match position(&depends, name) {
+                Some(idx) => {
+                    depends.remove(idx);
+                },
+                None => log::warn!("Target does not depend on {}", name),
+            }
+        }
+        for name in &self.add {
+            if position(&depends, name).is_none() {
+                depends.push(name.clone());
+            }
+        }
+        depends.join(",")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testgen;
+
+    /// Module whose `version` entry is followed by extra null padding
+    fn padded_module() -> Vec<u8> {
+        testgen::generate(&testgen::Spec {
+            machine: crate::cli::Machine::X86_64,
+            is_64: true,
+            little_endian: true,
+            name: "padded".to_string(),
+            versions: Vec::new(),
+            extended: false,
+            modinfo: vec![("version".to_string(), "1.0\0\0\0".to_string()),
+                          ("license".to_string(), "GPL".to_string())],
+            signed: false,
+        })
+    }
+
+    fn modinfo(data: &[u8]) -> Vec<ModInfoEntry> {
+        let elf = crate::parse_elf(data).unwrap();
+        get_modinfo(&elf, data).unwrap().unwrap()
+    }
+
+    fn values(data: &[u8]) -> Vec<(String, String)> {
+        modinfo(data).into_iter().map(|e| (e.key, e.value)).collect()
+    }
+
+    fn pairs(entries: &[(&str, &str)]) -> Vec<(String, String)> {
+        entries.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn padding_counts_towards_capacity() {
+        let module = padded_module();
+        let entries = modinfo(&module);
+        assert_eq!(values(&module), pairs(&[("version", "1.0"), ("license", "GPL")]));
+        assert_eq!(entries[0].capacity, "version=1.0".len() + 4);
+        assert_eq!(entries[1].capacity, "license=GPL".len() + 1);
+    }
+
+    #[test]
+    fn shorter_values_are_padded() {
+        let mut module = padded_module();
+        let entry = &modinfo(&module)[0];
+        patch_entry(&mut module, entry, "1").unwrap();
+
+        let region = &module[entry.offset .. entry.offset + entry.capacity];
+        assert_eq!(region, b"version=1\0\0\0\0\0\0");
+        assert_eq!(values(&module), pairs(&[("version", "1"), ("license", "GPL")]));
+        assert_eq!(modinfo(&module)[0].capacity, entry.capacity);
+    }
+
+    #[test]
+    fn longer_values_fill_padding_or_are_refused() {
+        let mut module = padded_module();
+        let entry = &modinfo(&module)[0];
+        patch_entry(&mut module, entry, "1.0.12").unwrap();
+        assert_eq!(values(&module), pairs(&[("version", "1.0.12"), ("license", "GPL")]));
+
+        let before = module.clone();
+        let refused = patch_entry(&mut module, entry, "1.0.123");
+        assert!(matches!(refused, Err(Error::ModInfoTooLong { available: 6, .. })));
+        assert_eq!(module, before);
+    }
+
+    #[test]
+    fn resizing_edits_need_resize() {
+        let module = padded_module();
+        let entries = modinfo(&module);
+        let set = |key: &str, value: &str| Edit::Set {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+        let apply = |edits: &[Edit], resize: bool| {
+            let buffer = crate::map_copy(&module).unwrap();
+            crate::edit_modinfo(buffer, Some(&entries), edits, resize)
+                .map(|(buffer, _)| buffer.to_vec())
+        };
+
+        let resizing = [
+            set("license", "Dual MIT/GPL"),
+            set("author", "candycorn"),
+            Edit::Add { key: "alias".to_string(), value: "fixture".to_string() },
+            Edit::Delete { key: "license".to_string(), value: None },
+        ];
+        for edit in &resizing {
+            let refused = apply(std::slice::from_ref(edit), false);
+            assert!(matches!(refused, Err(Error::Unsupported { .. })), "{:?}", edit);
+        }
+
+        let in_place = apply(&[set("version", "2.0.1")], false).unwrap();
+        assert_eq!(in_place.len(), module.len());
+        assert_eq!(values(&in_place), pairs(&[("version", "2.0.1"), ("license", "GPL")]));
+
+        let rebuilt = apply(&[set("license", "Dual MIT/GPL")], true).unwrap();
+        assert_eq!(values(&rebuilt), pairs(&[("version", "1.0"),
+                                             ("license", "Dual MIT/GPL")]));
+    }
+}
@@ -0,0 +1,200 @@
+use crate::elfedit::Editor;
+use crate::error::{Error, Result};
+use crate::{SymVersion, VersionLayout};
+use goblin::elf::section_header::{SectionHeader, SHF_ALLOC, SHT_PROGBITS};
+use goblin::elf::sym::{STB_GLOBAL, STB_WEAK};
+
+pub use candycorn_core::{crc_size, ENTRY_SIZE, EXT_CRCS, EXT_NAMES};
+use candycorn_core::{names_len, EXT_CRC_SIZE};
+
+/// Collects the names of the symbols a module imports, in symbol table order
+/// without duplicates. These are the symbols `modpost` would version
+pub fn undefined_symbols(info: &goblin::elf::Elf, mod_data: &[u8])
+    -> Result<Vec<String>> {
+
+    undefined(info, mod_data, true)
+}
+
+/// Collects the names of the symbols a module imports that must be resolved
+/// for it to load. The kernel leaves weak symbols it does not export zero
+pub fn required_symbols(info: &goblin::elf::Elf, mod_data: &[u8])
+    -> Result<Vec<String>> {
+
+    undefined(info, mod_data, false)
+}
+
+fn undefined(info: &goblin::elf::Elf, mod_data: &[u8], weak: bool)
+    -> Result<Vec<String>> {
+
+    let (syms, strtab) = match crate::read_symtab(info, mod_data)? {
+        Some(symtab) => symtab,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut names = Vec::new();
+    let mut seen = std::collections::HashSet::new();
+    for sym in syms.iter() {
+        let bind = sym.st_bind();
+        if sym.st_shndx != 0 || (bind != STB_GLOBAL && (!weak || bind != STB_WEAK)) {
+            continue;
+        }
+        match strtab.get_at(sym.st_name) {
+            Some(name) if !name.is_empty() && seen.insert(name) => {
+                names.push(name.to_string());
+            },
+            _ => {},
+        }
+    }
+    Ok(names)
+}
+
+/// Produces the entries of a module's extended version sections in the order
+/// they appear. Returns `None` if the module does not have them
+pub fn extended_versions(info: &goblin::elf::Elf, mod_data: &[u8])
+    -> Result<Option<Vec<SymVersion>>> {
+
+    let versions = candycorn_core::extended_entries(info, mod_data)?;
+    if let Some(versions) = &versions {
+        crate::warn_duplicates(versions);
+    }
+    Ok(versions)
+}
+
+/// Produces a copy of a module with entries added to the end of its
+/// `__versions` section and, if the module has them, its extended version
+/// sections. `__versions` is created if the module has neither. Sections are
+/// moved to the end of the file to make room. Names too long for `__versions`
+/// are only added to the extended sections
+pub fn add_entries(mod_data: &[u8], entries: &[(String, u64)]) -> Result<Vec<u8>> {
+    let mut editor = Editor::new(mod_data)?;
+    let layout = VersionLayout {
+        crc_size: crc_size(editor.is_64()),
+        little_endian: editor.endian() == scroll::LE,
+    };
+    let ext_layout = VersionLayout { crc_size: EXT_CRC_SIZE, ..layout };
+
+    let basic = editor.find("__versions");
+    let extended = editor.find(EXT_CRCS).zip(editor.find(EXT_NAMES));
+    let with_basic = basic.is_some() || extended.is_none();
+
+    let mut content = match basic {
+        Some(idx) => editor.content(idx)?.to_vec(),
+        None => Vec::new(),
+    };
+    let (mut ext_crcs, mut ext_names) = match extended {
+        Some((crcs, names)) => {
+            let crcs = editor.content(crcs)?.to_vec();
+            let names = editor.content(names)?;
+            // Padding after the last name would shift the added names
+            let len = names_len(names, crcs.len() / EXT_CRC_SIZE)
+                        .unwrap_or(names.len());
+            (crcs, names[.. len].to_vec())
+        },
+        None => (Vec::new(), Vec::new()),
+    };
+
+    for (name, crc) in entries {
+        let max_len = ENTRY_SIZE - layout.crc_size - 1;
+        if with_basic && name.len() <= max_len {
+            let offset = content.len();
+            content.resize(offset + ENTRY_SIZE, 0);
+            layout.write_crc(&mut content, name, offset, *crc)?;
+            let name_start = offset + layout.crc_size;
+            content[name_start .. name_start + name.len()]
+                .copy_from_slice(name.as_bytes());
+        } else if extended.is_none() {
+            return Err(Error::Unsupported {
+                reason: format!("\"{}\" is longer than the {} bytes a \
+                                 `__versions` entry can hold", name, max_len),
+            });
+        }
+
+        if extended.is_some() {
+            let offset = ext_crcs.len();
+            ext_crcs.resize(offset + EXT_CRC_SIZE, 0);
+            ext_layout.write_crc(&mut ext_crcs, name, offset, *crc)?;
+            ext_names.extend_from_slice(name.as_bytes());
+            ext_names.push(0);
+        }
+    }
+
+    match basic {
+        Some(idx) => editor.set_content(idx, content),
+        None if with_basic => {
+            let header = SectionHeader {
+                sh_type: SHT_PROGBITS,
+                sh_flags: SHF_ALLOC as u64,
+                sh_addralign: layout.crc_size as u64,
+                ..Default::default()
+            };
+            editor.add("__versions", header, content)?;
+        },
+        None => {},
+    }
+    if let Some((crcs, names)) = extended {
+        editor.set_content(crcs, ext_crcs);
+        editor.set_content(names, ext_names);
+    }
+    editor.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testgen;
+
+    fn versions(data: &[u8]) -> Vec<(String, u64, bool)> {
+        crate::parse_module(data).unwrap().versions.unwrap_or_default().into_iter()
+            .map(|v| (v.name, v.crc, v.extended))
+            .collect()
+    }
+
+    fn entries(names: &[(&str, u64)]) -> Vec<(String, u64)> {
+        names.iter().map(|&(name, crc)| (name.to_string(), crc)).collect()
+    }
+
+    #[test]
+    fn entries_are_added_to_versions() {
+        for module in testgen::fixtures(&[("printk", 0x1234)], false) {
+            let added = add_entries(&module, &entries(&[("kmalloc", 0xabcd),
+                                                         ("kfree", 0x5678)]))
+                            .unwrap();
+            assert_eq!(versions(&added), [("printk".to_string(), 0x1234, false),
+                                          ("kmalloc".to_string(), 0xabcd, false),
+                                          ("kfree".to_string(), 0x5678, false)]);
+        }
+    }
+
+    #[test]
+    fn versions_section_is_created() {
+        for module in testgen::fixtures(&[], false) {
+            let mut editor = Editor::new(&module).unwrap();
+            let idx = editor.find("__versions").unwrap();
+            editor.remove(idx);
+            let bare = editor.finish().unwrap();
+            assert!(crate::parse_module(&bare[..]).unwrap().versions.is_none());
+
+            let added = add_entries(&bare, &entries(&[("printk", 0x1234)])).unwrap();
+            assert_eq!(versions(&added), [("printk".to_string(), 0x1234, false)]);
+        }
+    }
+
+    #[test]
+    fn entries_are_added_to_extended_versions() {
+        let long = "a".repeat(ENTRY_SIZE);
+        for module in testgen::fixtures(&[("printk", 0x1234)], true) {
+            let added = add_entries(&module, &entries(&[(&long, 0xabcd)])).unwrap();
+            assert_eq!(versions(&added), [("printk".to_string(), 0x1234, true),
+                                          (long.clone(), 0xabcd, true)]);
+        }
+    }
+
+    #[test]
+    fn long_names_need_extended_versions() {
+        let long = "a".repeat(ENTRY_SIZE);
+        for module in testgen::fixtures(&[("printk", 0x1234)], false) {
+            let added = add_entries(&module, &entries(&[(&long, 0xabcd)]));
+            assert!(matches!(added, Err(Error::Unsupported { .. })));
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use crate::modinfo::ModInfoEntry;
+use std::collections::{BTreeMap, HashMap};
+
+/// Finds the namespaces a module imports symbols from without declaring them
+/// with an `import_ns` `.modinfo` entry. Returns the symbols imported from
+/// each such namespace
+///
+/// # Arguments
+/// * `imports` - Symbols the module imports
+/// * `namespaces` - Namespaces of the symbols exported into one
+/// * `modinfo` - The module's `.modinfo` entries, if it has the section
+pub fn missing_imports(imports: &[String], namespaces: &HashMap<String, String>,
+                       modinfo: Option<&[ModInfoEntry]>)
+    -> BTreeMap<String, Vec<String>> {
+
+    let imported: Vec<&str> = modinfo.unwrap_or_default().iter()
+        .filter(|e| e.key == "import_ns")
+        .map(|e| e.value.as_str())
+        .collect();
+
+    let mut missing: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for name in imports {
+        if let Some(namespace) = namespaces.get(name) {
+            if !imported.contains(&namespace.as_str()) {
+                missing.entry(namespace.clone()).or_default().push(name.clone());
+            }
+        }
+    }
+    missing
+}
+
+/// Lists the namespaces missing imports along with their symbols, one per
+/// line
+pub fn describe_missing(missing: &BTreeMap<String, Vec<String>>) -> String {
+    missing.iter()
+        .map(|(namespace, symbols)| {
+            format!("\n    {}: {}", namespace, symbols.join(", "))
+        })
+        .collect()
+}
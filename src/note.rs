@@ -0,0 +1,157 @@
+use crate::error::{Error, Result};
+use crate::elfedit::{align_up, Editor};
+use crate::{find_section, section_data};
+use goblin::elf::section_header::{SectionHeader, SHT_NOTE};
+use scroll::Cwrite;
+
+/// Name of the section recording how a module was patched
+pub const SECTION: &str = ".note.candycorn";
+
+/// Owner name of the note
+const NOTE_NAME: &[u8] = b"candycorn\0";
+
+/// Note type of the provenance record
+const NT_PROVENANCE: u32 = 1;
+
+/// Record of how a module was patched, stored as JSON in the note descriptor
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Provenance {
+    /// Version of candycorn that patched the module
+    pub version: String,
+    /// Sources of the symbol versions, in order of increasing precedence
+    #[serde(default)]
+    pub sources: Vec<Source>,
+    /// Time of patching in seconds since the Unix epoch
+    pub timestamp: u64,
+    /// Symbol versions that were patched
+    pub symbols: Vec<PatchedSymbol>,
+    /// `.modinfo` entries that were patched
+    #[serde(default)]
+    pub modinfo: Vec<PatchedModInfo>,
+}
+
+impl Provenance {
+    /// Carries over the records of an earlier patch of the same module. Values
+    /// from before the earlier patch remain the original values
+    pub fn merge_earlier(&mut self, earlier: Provenance) {
+        for old in earlier.symbols {
+            match self.symbols.iter_mut().find(|s| s.offset == old.offset) {
+                Some(new) => new.original = old.original,
+                None => self.symbols.push(old),
+            }
+        }
+        self.symbols.sort_by_key(|s| s.offset);
+
+        for old in earlier.modinfo {
+            // Entries added under the same key by separate patches are
+            // separate changes
+            let same = |m: &PatchedModInfo| m.key == old.key
+                && !(m.added && old.added && m.value != old.value);
+            match self.modinfo.iter_mut().find(|m| same(m)) {
+                Some(new) => {
+                    new.original = old.original;
+                    new.added = old.added;
+                },
+                None => self.modinfo.push(old),
+            }
+        }
+    }
+}
+
+/// Symbol version changed by patching
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct PatchedSymbol {
+    pub name: String,
+    /// File offset of the symbol's `modversion_info` entry
+    pub offset: usize,
+    /// CRC before the module was first patched
+    pub original: u64,
+    pub crc: u64,
+}
+
+/// `.modinfo` entry changed by patching
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct PatchedModInfo {
+    pub key: String,
+    /// Value before the module was first patched
+    pub original: String,
+    pub value: String,
+    /// The entry did not exist before the module was first patched
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub added: bool,
+    /// The entry was removed by patching
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deleted: bool,
+}
+
+/// Identity of the source module or `Module.symvers` versions were taken from
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Source {
+    pub path: String,
+    /// SHA-256 of the file. For directories searched with `--src-dir` this is
+    /// of the combined versions and for `--auto-src` of `modules.dep`
+    pub sha256: String,
+}
+
+/// Produces a copy of a module with a provenance note. The note, a copy of the
+/// section name table and the section header table are appended to the end of
+/// the module, leaving existing section content where it is. A note from an
+/// earlier run is replaced
+pub fn append(mod_data: &[u8], provenance: &Provenance) -> Result<Vec<u8>> {
+    let mut editor = Editor::new(mod_data)?;
+    let endian = editor.endian();
+
+    // Note header, padded name and padded descriptor
+    let desc = serde_json::to_vec(provenance)?;
+    let mut note = vec![0; 12 + align_up(NOTE_NAME.len(), 4) + align_up(desc.len(), 4)];
+    note.cwrite_with(NOTE_NAME.len() as u32, 0, endian);
+    note.cwrite_with(desc.len() as u32, 4, endian);
+    note.cwrite_with(NT_PROVENANCE, 8, endian);
+    note[12 .. 12 + NOTE_NAME.len()].copy_from_slice(NOTE_NAME);
+    let desc_start = 12 + align_up(NOTE_NAME.len(), 4);
+    note[desc_start .. desc_start + desc.len()].copy_from_slice(&desc);
+
+    match editor.find(SECTION) {
+        Some(idx) => editor.set_content(idx, note),
+        None => {
+            let header = SectionHeader {
+                sh_type: SHT_NOTE,
+                sh_addralign: 4,
+                ..Default::default()
+            };
+            editor.add(SECTION, header, note)?;
+        },
+    }
+    editor.finish()
+}
+
+/// Reads the provenance note of a module. Returns `None` if the module has not
+/// been patched with a note
+pub fn read(info: &goblin::elf::Elf, mod_data: &[u8]) -> Result<Option<Provenance>> {
+    let sh = match find_section(info, SECTION) {
+        Some(sh) => sh,
+        None => return Ok(None),
+    };
+    let data = section_data(sh, SECTION, mod_data)?;
+    let malformed = |reason: &str| Error::MalformedSection {
+        section: SECTION.to_string(),
+        offset: sh.sh_offset as usize,
+        reason: reason.to_string(),
+    };
+
+    let word = |offset: usize| -> Result<usize> {
+        let bytes: [u8; 4] = data.get(offset .. offset + 4)
+                                .and_then(|b| b.try_into().ok())
+                                .ok_or_else(|| malformed("truncated note header"))?;
+        Ok(if info.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        } as usize)
+    };
+    let (name_size, desc_size) = (word(0)?, word(4)?);
+    let desc_start = 12 + align_up(name_size, 4);
+    let desc = data.get(desc_start .. desc_start + desc_size)
+                .ok_or_else(|| malformed("truncated note descriptor"))?;
+    Ok(Some(serde_json::from_slice(desc)?))
+}
@@ -0,0 +1,89 @@
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+pub use candycorn_core::fs::write_atomic;
+
+/// Writes the ranges of a patched module that patching changed into the file
+/// it was read from with positioned writes, leaving all other bytes
+/// untouched, and syncs it. Only the ranges are read, so large modules are
+/// not read whole. Returns the number of bytes written
+///
+/// # Arguments
+/// * `path` - File the module was read from, of the same size as `data`
+/// * `data` - Content of the patched module
+/// * `ranges` - Ranges of `data` patching may have changed
+pub fn write_changes(path: &Path, data: &[u8], ranges: &[Range<usize>])
+    -> std::io::Result<usize> {
+
+    use std::os::unix::fs::FileExt;
+
+    let file = std::fs::OpenOptions::new().write(true).open(path)?;
+    for range in ranges {
+        file.write_all_at(&data[range.clone()], range.start as u64)?;
+    }
+    file.sync_data()?;
+    Ok(ranges.iter().map(|range| range.len()).sum())
+}
+
+/// Reads the ranges written by `write_changes` back, returning whether they
+/// hold the patched module's bytes
+pub fn changes_match(path: &Path, data: &[u8], ranges: &[Range<usize>])
+    -> std::io::Result<bool> {
+
+    use std::os::unix::fs::FileExt;
+
+    let file = std::fs::File::open(path)?;
+    for range in ranges {
+        let mut written = vec![0u8; range.len()];
+        file.read_exact_at(&mut written, range.start as u64)?;
+        if written != data[range.clone()] {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Keeps a copy of a file about to be written over, returning its path. The
+/// first copy is `<path>.orig`, which is never replaced so the file's
+/// original content survives any number of patches. Later copies are
+/// `<path>.orig.1`, the most recent, through `<path>.orig.<limit>`, the
+/// oldest, which is dropped to make room. Returns `None` if there is no room
+/// for a later copy
+///
+/// # Arguments
+/// * `path` - File about to be written over
+/// * `limit` - Number of copies kept besides `<path>.orig`
+pub fn backup(path: &Path, limit: usize) -> std::io::Result<Option<PathBuf>> {
+    let with_suffix = |suffix: String| {
+        let mut name = path.as_os_str().to_os_string();
+        name.push(suffix);
+        PathBuf::from(name)
+    };
+    let mut dest = with_suffix(".orig".to_string());
+    if dest.exists() {
+        if limit == 0 {
+            return Ok(None);
+        }
+        for idx in (1 .. limit).rev() {
+            match std::fs::rename(with_suffix(format!(".orig.{}", idx)),
+                                  with_suffix(format!(".orig.{}", idx + 1))) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {},
+            }
+        }
+        dest = with_suffix(".orig.1".to_string());
+    }
+    write_atomic(&dest, &std::fs::read(path)?, Some(path))?;
+    Ok(Some(dest))
+}
+
+/// Checks if two paths name the same file, following symlinks. Paths naming
+/// no file never match
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (std::fs::metadata(a), std::fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
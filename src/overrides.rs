@@ -0,0 +1,94 @@
+use crate::error::{Error, Result};
+use std::collections::BTreeMap;
+
+/// CRC value as written in an overrides file. TOML supports hexadecimal
+/// integers natively but JSON does not, so hexadecimal strings are accepted
+/// in both formats
+#[derive(Clone, serde::Deserialize)]
+#[serde(untagged)]
+pub enum CrcValue {
+    Int(u64),
+    Str(String),
+}
+
+/// Loads a file mapping symbol names to CRC values. Files with a `.json`
+/// extension are parsed as JSON, all others as TOML
+///
+/// Example TOML file:
+/// ```toml
+/// module_layout = 0x54f9a716
+/// printk = "0xdeadbeef"
+/// ```
+pub fn load(path: &std::path::Path) -> Result<BTreeMap<String, u64>> {
+    let content = std::fs::read_to_string(path)
+                    .map_err(|e| Error::Read { path: path.into(), source: e })?;
+    parse(&content, path.extension().is_some_and(|ext| ext == "json"))
+        .map_err(|e| e.in_file(path))
+}
+
+/// Parses the content of an overrides file
+fn parse(content: &str, json: bool) -> Result<BTreeMap<String, u64>> {
+    let values: BTreeMap<String, CrcValue> = if json {
+        serde_json::from_str(content)?
+    } else {
+        toml::from_str(content)?
+    };
+
+    let mut overrides = BTreeMap::new();
+    for (name, value) in values {
+        let crc = match value {
+            CrcValue::Int(crc) => crc,
+            CrcValue::Str(s) => crate::parse_crc(&s, false)
+                                    .map_err(|e| Error::Override {
+                                        symbol: name.clone(),
+                                        source: Box::new(e),
+                                    })?,
+        };
+        overrides.insert(name, crc);
+    }
+    Ok(overrides)
+}
+
+/// Loads a file mapping symbol names of targets to the names the same symbols
+/// have in the sources, for symbols renamed between kernel versions. Files
+/// with a `.json` extension are parsed as JSON, all others as TOML
+///
+/// Example TOML file:
+/// ```toml
+/// __alloc_pages_nodemask = "__alloc_pages"
+/// ```
+pub fn load_renames(path: &std::path::Path) -> Result<BTreeMap<String, String>> {
+    let content = std::fs::read_to_string(path)
+                    .map_err(|e| Error::Read { path: path.into(), source: e })?;
+    let renames = if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::from_str(&content).map_err(Error::from)
+    } else {
+        toml::from_str(&content).map_err(Error::from)
+    };
+    renames.map_err(|e| e.in_file(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crcs_are_read_from_toml_and_json() {
+        let expected = BTreeMap::from([("module_layout".to_string(), 0x54f9a716),
+                                       ("printk".to_string(), 0xdeadbeef),
+                                       ("kfree".to_string(), 42)]);
+        let toml = "module_layout = 0x54f9a716\nprintk = \"0xdeadbeef\"\nkfree = 42\n";
+        assert_eq!(parse(toml, false).unwrap(), expected);
+        let json = r#"{ "module_layout": "0x54f9a716", "printk": 3735928559,
+                        "kfree": "42" }"#;
+        assert_eq!(parse(json, true).unwrap(), expected);
+    }
+
+    #[test]
+    fn invalid_crcs_are_refused() {
+        assert!(matches!(parse("printk = \"0xnope\"\n", false),
+                         Err(Error::Override { symbol, .. }) if symbol == "printk"));
+        assert!(matches!(parse("printk = [1]\n", false), Err(Error::Toml(_))));
+        assert!(matches!(parse("{ \"printk\": true }", true), Err(Error::Json(_))));
+    }
+}
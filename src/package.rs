@@ -0,0 +1,272 @@
+use crate::error::{Error, Result};
+
+/// Magic of `ar` archives, which Debian packages are
+const AR_MAGIC: &[u8] = b"!<arch>\n";
+
+/// Magic of the lead at the start of RPM packages
+const RPM_MAGIC: &[u8] = &[0xed, 0xab, 0xee, 0xdb];
+
+/// Size of the RPM lead, which precedes the signature header
+const RPM_LEAD: usize = 96;
+
+/// Magic of RPM headers
+const RPM_HEADER_MAGIC: &[u8] = &[0x8e, 0xad, 0xe8, 0x01];
+
+/// Size of tar header and data blocks
+const TAR_BLOCK: usize = 512;
+
+/// Checks if data starts with a Debian or RPM package
+pub fn is_package(data: &[u8]) -> bool {
+    data.starts_with(AR_MAGIC) || data.starts_with(RPM_MAGIC)
+}
+
+/// Regular file of a package
+pub struct File {
+    /// Path of the file on an installed system, without leading `./` or `/`
+    pub path: String,
+    pub data: Vec<u8>,
+}
+
+fn malformed(reason: &str) -> Error {
+    Error::Package { reason: reason.to_string() }
+}
+
+/// Normalizes a path stored in a package
+fn installed_path(path: &str) -> String {
+    path.trim_start_matches("./").trim_start_matches('/').to_string()
+}
+
+/// Reads the regular files of a Debian or RPM package whose installed paths
+/// match
+pub fn files(data: &[u8], wanted: impl Fn(&str) -> bool) -> Result<Vec<File>> {
+    if data.starts_with(AR_MAGIC) {
+        deb_files(data, wanted)
+    } else {
+        rpm_files(data, wanted)
+    }
+}
+
+/// Decompresses a package payload if it is compressed
+fn decompress(data: &[u8]) -> Result<std::borrow::Cow<'_, [u8]>> {
+    Ok(match crate::compress::decompress(data)? {
+        Some(data) => data.into(),
+        None => data.into(),
+    })
+}
+
+/// Reads files from the `data.tar` member of a Debian package. Each `ar`
+/// member has a 60 byte header holding its name and decimal size, and is
+/// padded to an even size
+fn deb_files(data: &[u8], wanted: impl Fn(&str) -> bool) -> Result<Vec<File>> {
+    let mut idx = AR_MAGIC.len();
+    while let Some(header) = data.get(idx .. idx + 60) {
+        let name = String::from_utf8_lossy(&header[.. 16]);
+        let size = std::str::from_utf8(&header[48 .. 58]).ok()
+                    .and_then(|size| size.trim().parse::<usize>().ok())
+                    .ok_or_else(|| malformed("invalid ar member size"))?;
+        let member = data.get(idx + 60 .. idx + 60 + size)
+                        .ok_or_else(|| malformed("truncated ar member"))?;
+        if name.starts_with("data.tar") {
+            return tar_files(&decompress(member)?, wanted);
+        }
+        idx += 60 + size.next_multiple_of(2);
+    }
+    Err(malformed("no `data.tar` member"))
+}
+
+/// Parses an octal tar header field
+fn octal(field: &[u8]) -> Option<usize> {
+    let field = std::str::from_utf8(field).ok()?;
+    let field = field.trim_matches(|c: char| c == '\0' || c == ' ');
+    usize::from_str_radix(field, 8).ok()
+}
+
+/// Reads files from a tar archive. Long paths are taken from GNU long name
+/// entries and pax extended headers
+fn tar_files(data: &[u8], wanted: impl Fn(&str) -> bool) -> Result<Vec<File>> {
+    let mut files = Vec::new();
+    let mut long_path = None;
+    let mut idx = 0;
+    while let Some(header) = data.get(idx .. idx + TAR_BLOCK) {
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let size = octal(&header[124 .. 136])
+                    .ok_or_else(|| malformed("invalid tar entry size"))?;
+        let content = data.get(idx + TAR_BLOCK .. idx + TAR_BLOCK + size)
+                        .ok_or_else(|| malformed("truncated tar entry"))?;
+        idx += TAR_BLOCK + size.next_multiple_of(TAR_BLOCK);
+
+        let field = |range: std::ops::Range<usize>| {
+            let field = &header[range];
+            let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+            String::from_utf8_lossy(&field[.. len]).into_owned()
+        };
+        match header[156] {
+            b'L' => {
+                let len = content.iter().position(|&b| b == 0).unwrap_or(size);
+                long_path = Some(String::from_utf8_lossy(&content[.. len]).into_owned());
+                continue;
+            },
+            // Records have the form `<length> <key>=<value>\n`
+            b'x' => {
+                long_path = String::from_utf8_lossy(content).lines()
+                    .filter_map(|record| record.split_once(' '))
+                    .find_map(|(_, record)| record.strip_prefix("path="))
+                    .map(str::to_string);
+                continue;
+            },
+            b'0' | 0 => {},
+            _ => {
+                long_path = None;
+                continue;
+            },
+        }
+
+        let path = long_path.take().unwrap_or_else(|| {
+            let (prefix, name) = (field(345 .. 500), field(0 .. 100));
+            if header[257 .. 262] == *b"ustar" && !prefix.is_empty() {
+                format!("{}/{}", prefix, name)
+            } else {
+                name
+            }
+        });
+        let path = installed_path(&path);
+        if wanted(&path) {
+            files.push(File { path, data: content.to_vec() });
+        }
+    }
+    Ok(files)
+}
+
+/// Finds the end of an RPM header, given its offset. Headers have a 16 byte
+/// preamble holding the number of index entries and the size of the data,
+/// followed by 16 bytes per index entry and the data
+fn rpm_header_end(data: &[u8], offset: usize) -> Result<usize> {
+    let preamble = data.get(offset .. offset + 16)
+                    .filter(|preamble| preamble.starts_with(RPM_HEADER_MAGIC))
+                    .ok_or_else(|| malformed("bad RPM header"))?;
+    let entries = u32::from_be_bytes(preamble[8 .. 12].try_into().unwrap()) as usize;
+    let size = u32::from_be_bytes(preamble[12 .. 16].try_into().unwrap()) as usize;
+    Ok(offset + 16 + entries * 16 + size)
+}
+
+/// Reads files from the payload of an RPM package, a compressed cpio archive
+/// following the lead, the signature header padded to 8 bytes and the header
+fn rpm_files(data: &[u8], wanted: impl Fn(&str) -> bool) -> Result<Vec<File>> {
+    let signature_end = rpm_header_end(data, RPM_LEAD)?.next_multiple_of(8);
+    let payload = data.get(rpm_header_end(data, signature_end)? ..)
+                    .ok_or_else(|| malformed("truncated RPM package"))?;
+    let payload = decompress(payload)?;
+    let archive = crate::cpio::Archive::parse(&payload)?;
+
+    let mut files = Vec::new();
+    for entry in &archive.entries {
+        let path = installed_path(&entry.name);
+        if wanted(&path) {
+            files.push(File { path, data: archive.content(entry).to_vec() });
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Appends a tar entry, with the name split into the ustar prefix at the
+    /// last `/` if given
+    fn tar_entry(tar: &mut Vec<u8>, name: &str, kind: u8, content: &[u8], prefix: bool) {
+        let mut header = [0u8; TAR_BLOCK];
+        let (dir, name) = match name.rsplit_once('/') {
+            Some((dir, name)) if prefix => (dir, name),
+            _ => ("", name),
+        };
+        header[.. name.len()].copy_from_slice(name.as_bytes());
+        let size = format!("{:011o}\0", content.len());
+        header[124 .. 136].copy_from_slice(size.as_bytes());
+        header[156] = kind;
+        header[257 .. 263].copy_from_slice(b"ustar\0");
+        header[345 .. 345 + dir.len()].copy_from_slice(dir.as_bytes());
+        tar.extend_from_slice(&header);
+        tar.extend_from_slice(content);
+        tar.resize(tar.len().next_multiple_of(TAR_BLOCK), 0);
+    }
+
+    fn ar_member(ar: &mut Vec<u8>, name: &str, content: &[u8]) {
+        let header = format!("{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n", name, 0, 0, 0,
+                             "100644", content.len());
+        ar.extend_from_slice(header.as_bytes());
+        ar.extend_from_slice(content);
+        ar.resize(ar.len().next_multiple_of(2), b'\n');
+    }
+
+    fn paths(files: &[File]) -> Vec<(&str, &[u8])> {
+        files.iter().map(|file| (file.path.as_str(), file.data.as_slice())).collect()
+    }
+
+    #[test]
+    fn deb_files_are_read_from_data_tar() {
+        let long = format!("./lib/modules/6.1.0/kernel/{}/long.ko", "d".repeat(100));
+        let mut tar = Vec::new();
+        tar_entry(&mut tar, "./lib/modules/6.1.0/", b'5', b"", false);
+        tar_entry(&mut tar, "./lib/modules/6.1.0/kernel/a.ko", b'0', b"module a", true);
+        let long_name = format!("{}\0", long);
+        tar_entry(&mut tar, "././@LongLink", b'L', long_name.as_bytes(), false);
+        tar_entry(&mut tar, "long.ko", b'0', b"long module", false);
+        let record = "path=/boot/symvers-6.1.0\n";
+        // The length counts itself, two digits and a space here
+        let record = format!("{} {}", record.len() + 3, record);
+        tar_entry(&mut tar, "PaxHeader", b'x', record.as_bytes(), false);
+        tar_entry(&mut tar, "symvers", b'0', b"symvers", false);
+        tar_entry(&mut tar, "./usr/share/doc/README", b'0', b"readme", false);
+        tar.extend_from_slice(&[0; 2 * TAR_BLOCK]);
+
+        let mut deb = AR_MAGIC.to_vec();
+        ar_member(&mut deb, "debian-binary", b"2.0\n");
+        ar_member(&mut deb, "control.tar", b"x");
+        ar_member(&mut deb, "data.tar", &tar);
+        assert!(is_package(&deb));
+
+        let read = files(&deb, |path| !path.starts_with("usr/")).unwrap();
+        assert_eq!(paths(&read), [("lib/modules/6.1.0/kernel/a.ko", &b"module a"[..]),
+                                   (&long[2 ..], b"long module"),
+                                   ("boot/symvers-6.1.0", b"symvers")]);
+
+        let mut deb = AR_MAGIC.to_vec();
+        ar_member(&mut deb, "debian-binary", b"2.0\n");
+        assert!(matches!(files(&deb, |_| true), Err(Error::Package { .. })));
+    }
+
+    #[test]
+    fn rpm_files_are_read_from_the_payload() {
+        let cpio = crate::cpio::tests::archive(b"070701", &[
+            ("./lib/modules/6.1.0/kernel/a.ko", b"module a"),
+            ("./boot/symvers-6.1.0.gz", b"symvers"),
+        ]);
+        let mut payload = flate2::write::GzEncoder::new(Vec::new(),
+                                                        flate2::Compression::default());
+        payload.write_all(&cpio).unwrap();
+
+        // Lead, a signature header with one entry padded to 8 bytes and an
+        // empty header
+        let mut rpm = RPM_MAGIC.to_vec();
+        rpm.resize(RPM_LEAD, 0);
+        for (entries, size) in [(1u32, 5u32), (0, 0)] {
+            rpm.extend_from_slice(RPM_HEADER_MAGIC);
+            rpm.extend_from_slice(&[0; 4]);
+            rpm.extend_from_slice(&entries.to_be_bytes());
+            rpm.extend_from_slice(&size.to_be_bytes());
+            rpm.resize(rpm.len() + entries as usize * 16 + size as usize, 0xff);
+            rpm.resize(rpm.len().next_multiple_of(8), 0);
+        }
+        rpm.extend_from_slice(&payload.finish().unwrap());
+        assert!(is_package(&rpm));
+
+        let read = files(&rpm, |path| path.ends_with(".ko")).unwrap();
+        assert_eq!(paths(&read), [("lib/modules/6.1.0/kernel/a.ko", &b"module a"[..])]);
+        assert!(matches!(files(&rpm[.. RPM_LEAD + 8], |_| true),
+                         Err(Error::Package { .. })));
+    }
+}
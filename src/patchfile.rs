@@ -0,0 +1,184 @@
+use crate::checksum;
+use crate::error::{Error, Result};
+
+/// Version of the patch file format
+const FORMAT: u32 = 1;
+
+/// Description of the changes patching made to a module. Patch files can be
+/// redistributed where the module itself can not
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct PatchFile {
+    pub format: u32,
+    /// File name of the module the patch was made from, for reference only
+    pub file: String,
+    /// Size of the module in bytes
+    pub size: usize,
+    /// SHA-256 of the module before patching
+    pub sha256: String,
+    /// SHA-256 of the module after patching
+    pub patched_sha256: String,
+    pub writes: Vec<Write>,
+}
+
+/// Replacement of a run of bytes
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct Write {
+    /// File offset of the first byte
+    pub offset: usize,
+    /// Hexadecimal bytes expected at the offset before patching
+    pub original: String,
+    /// Hexadecimal bytes to write at the offset
+    pub replacement: String,
+}
+
+impl PatchFile {
+    /// Describes the changes between a module and its patched copy. Both must
+    /// be the same size
+    ///
+    /// # Arguments
+    /// * `file` - File name of the module
+    /// * `original` - Content of the module before patching
+    /// * `patched` - Content of the module after patching
+    pub fn from_diff(file: &str, original: &[u8], patched: &[u8]) -> PatchFile {
+        let mut writes = Vec::new();
+        let mut idx = 0;
+        while idx < original.len() {
+            if original[idx] == patched[idx] {
+                idx += 1;
+                continue;
+            }
+
+            let start = idx;
+            while idx < original.len() && original[idx] != patched[idx] {
+                idx += 1;
+            }
+            writes.push(Write {
+                offset: start,
+                original: checksum::hex(&original[start .. idx]),
+                replacement: checksum::hex(&patched[start .. idx]),
+            });
+        }
+
+        PatchFile {
+            format: FORMAT,
+            file: file.to_string(),
+            size: original.len(),
+            sha256: checksum::sha256(original),
+            patched_sha256: checksum::sha256(patched),
+            writes,
+        }
+    }
+
+    /// Reads a patch file
+    pub fn load(path: &std::path::Path) -> Result<PatchFile> {
+        let content = std::fs::read_to_string(path)
+                        .map_err(|e| Error::Read { path: path.into(), source: e })?;
+        let patch_file: PatchFile = serde_json::from_str(&content)
+                                        .map_err(|e| Error::from(e).in_file(path))?;
+        if patch_file.format != FORMAT {
+            return Err(Error::Unsupported {
+                reason: format!("patch file format {}", patch_file.format),
+            }.in_file(path));
+        }
+        Ok(patch_file)
+    }
+
+    /// Applies the writes to a module. Nothing is written unless the module
+    /// has the expected size and all bytes to replace match
+    pub fn apply(&self, data: &mut [u8]) -> Result<()> {
+        if data.len() != self.size {
+            return Err(Error::SizeMismatch { expected: self.size, actual: data.len() });
+        }
+
+        let mut writes = Vec::new();
+        for write in &self.writes {
+            let original = decode_hex(&write.original)?;
+            let replacement = decode_hex(&write.replacement)?;
+            let region = write.offset.checked_add(original.len())
+                            .and_then(|end| data.get(write.offset .. end));
+            if original.len() != replacement.len() || region.is_none() {
+                return Err(Error::InvalidPatch {
+                    reason: format!("write at offset {:#x} does not fit the module",
+                                    write.offset),
+                });
+            }
+            if region != Some(&original[..]) {
+                return Err(Error::PatchMismatch {
+                    offset: write.offset,
+                    expected: write.original.clone(),
+                    actual: checksum::hex(region.unwrap_or_default()),
+                });
+            }
+            writes.push((write.offset, replacement));
+        }
+
+        for (offset, replacement) in writes {
+            data[offset .. offset + replacement.len()].copy_from_slice(&replacement);
+        }
+        Ok(())
+    }
+
+    /// Writes the patch file as JSON
+    pub fn save(&self, path: &std::path::Path) -> Result<()> {
+        let mut content = serde_json::to_string_pretty(self)?;
+        content.push('\n');
+        std::fs::write(path, content)
+            .map_err(|e| Error::Write { path: path.into(), source: e })
+    }
+}
+
+/// Decodes a hexadecimal string of bytes
+fn decode_hex(s: &str) -> Result<Vec<u8>> {
+    let invalid = || Error::InvalidPatch {
+        reason: format!("invalid hexadecimal bytes \"{}\"", s),
+    };
+    (0 .. s.len()).step_by(2)
+        .map(|i| s.get(i .. i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patch_files_apply_their_diff() {
+        let original = b"\x01\x02\x03\x04\x05\x06\x07\x08";
+        let patched = b"\x01\xaa\xbb\x04\x05\x06\x07\xcc";
+        let patch_file = PatchFile::from_diff("target.ko", original, patched);
+        let writes: Vec<_> = patch_file.writes.iter().map(|write| {
+            (write.offset, write.original.as_str(), write.replacement.as_str())
+        }).collect();
+        assert_eq!(writes, [(1, "0203", "aabb"), (7, "08", "cc")]);
+        assert_eq!(patch_file.sha256, checksum::sha256(original));
+        assert_eq!(patch_file.patched_sha256, checksum::sha256(patched));
+
+        let dir = crate::tempdir::create("test").unwrap();
+        let path = dir.path().join("target.ko.patch");
+        patch_file.save(&path).unwrap();
+        let loaded = PatchFile::load(&path).unwrap();
+        let mut data = original.to_vec();
+        loaded.apply(&mut data).unwrap();
+        assert_eq!(data, patched);
+    }
+
+    #[test]
+    fn mismatching_modules_are_left_untouched() {
+        let patch_file = PatchFile::from_diff("target.ko", b"\x01\x02\x03",
+                                              b"\x01\xaa\xbb");
+        let mut data = b"\x01\x02\xff".to_vec();
+        assert!(matches!(patch_file.apply(&mut data),
+                         Err(Error::PatchMismatch { offset: 1, .. })));
+        assert_eq!(data, b"\x01\x02\xff");
+        assert!(matches!(patch_file.apply(&mut [0; 4]),
+                         Err(Error::SizeMismatch { expected: 3, actual: 4 })));
+
+        let mut patch_file = patch_file;
+        patch_file.writes[0].replacement = "aa".to_string();
+        assert!(matches!(patch_file.apply(&mut [1, 2, 3]),
+                         Err(Error::InvalidPatch { .. })));
+        assert!(decode_hex("0g").is_err());
+        assert!(decode_hex("012").is_err());
+    }
+}
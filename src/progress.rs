@@ -0,0 +1,85 @@
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Templates of bars over a known and an unknown number of items
+const TEMPLATE: &str = "{prefix} [{bar:30}] {pos}/{len} {wide_msg}";
+const SPINNER_TEMPLATE: &str = "{prefix}... {spinner}";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Bar of the operation in progress, drawn on the last line of stderr
+static CURRENT: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// Enables progress bars if both stdout and stderr are terminals and
+/// informational messages are logged
+pub fn init() {
+    let enabled = std::io::stdout().is_terminal() && std::io::stderr().is_terminal()
+                    && log::max_level() >= log::LevelFilter::Info;
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Progress bar of an operation, removed when dropped. The default bar is
+/// not shown
+#[derive(Default)]
+pub struct Bar {
+    bar: Option<ProgressBar>,
+}
+
+/// Shows the progress of an operation over `total` items, or an operation of
+/// unknown length if `total` is zero. Operations started while another is in
+/// progress show no bar of their own
+pub fn start(label: impl Into<String>, total: usize) -> Bar {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return Bar::default();
+    }
+    let mut current = CURRENT.lock().unwrap();
+    if current.is_some() {
+        return Bar::default();
+    }
+
+    let bar = match total {
+        0 => ProgressBar::new_spinner()
+                .with_style(ProgressStyle::with_template(SPINNER_TEMPLATE).unwrap()),
+        total => ProgressBar::new(total as u64)
+                    .with_style(ProgressStyle::with_template(TEMPLATE).unwrap()
+                                    .progress_chars("= ")),
+    };
+    bar.set_draw_target(ProgressDrawTarget::stderr());
+    bar.set_prefix(label.into());
+    if total == 0 {
+        bar.enable_steady_tick(std::time::Duration::from_millis(100));
+    }
+    *current = Some(bar.clone());
+    Bar { bar: Some(bar) }
+}
+
+impl Bar {
+    /// Counts an item as done, showing its name and status
+    pub fn inc(&self, item: impl std::fmt::Display) {
+        if let Some(bar) = &self.bar {
+            bar.inc(1);
+            bar.set_message(item.to_string());
+        }
+    }
+}
+
+impl Drop for Bar {
+    fn drop(&mut self) {
+        if let Some(bar) = self.bar.take() {
+            *CURRENT.lock().unwrap() = None;
+            bar.finish_and_clear();
+        }
+    }
+}
+
+/// Runs `print` with the progress bar removed, drawing it again afterwards so
+/// it stays below the printed lines
+pub fn suspend(print: impl FnOnce()) {
+    let current = CURRENT.lock().unwrap().clone();
+    match current {
+        Some(bar) => bar.suspend(print),
+        None => print(),
+    }
+}
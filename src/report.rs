@@ -0,0 +1,301 @@
+use crate::error::{Error, Result};
+use crate::note;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// `__versions` entry changed in a module
+pub struct Change {
+    pub name: String,
+    pub offset: usize,
+    pub original: u64,
+    pub crc: u64,
+    /// Source the CRC was taken from, or how it was given
+    pub source: String,
+}
+
+/// Changes made to a module, or to a module inside an archive
+pub struct Module {
+    pub path: PathBuf,
+    pub arch: String,
+    /// Counts of how the module's entries were handled
+    pub stats: String,
+    pub symbols: Vec<Change>,
+    pub modinfo: Vec<note::PatchedModInfo>,
+    /// Vermagic of the module before patching
+    pub vermagic: Option<String>,
+}
+
+/// Outcome of patching a target given on the command line
+pub struct Outcome {
+    pub target: String,
+    pub result: String,
+}
+
+/// Report of a patch run, collected while targets are patched and written
+/// once they are all done
+pub struct Report {
+    path: PathBuf,
+    modules: Mutex<Vec<Module>>,
+}
+
+/// Inline text of a report, either prose or a literal such as a symbol name
+enum Span {
+    Text(String),
+    Code(String),
+}
+
+fn text(text: impl Into<String>) -> Span {
+    Span::Text(text.into())
+}
+
+fn code(text: impl Into<String>) -> Span {
+    Span::Code(text.into())
+}
+
+enum Block {
+    Heading(usize, Vec<Span>),
+    Paragraph(Vec<Span>),
+    List(Vec<Vec<Span>>),
+    Table(Vec<&'static str>, Vec<Vec<Span>>),
+}
+
+impl Report {
+    pub fn new(path: PathBuf) -> Report {
+        Report { path, modules: Mutex::new(Vec::new()) }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Records the changes made to a module
+    pub fn add(&self, module: Module) {
+        self.modules.lock().unwrap().push(module);
+    }
+
+    /// Writes the report, as HTML if its file name ends in `.html` and as
+    /// Markdown otherwise
+    pub fn write(&self, sources: &[String], outcomes: &[Outcome], warnings: &[String])
+        -> Result<()> {
+
+        let mut modules = self.modules.lock().unwrap();
+        modules.sort_by(|a, b| a.path.cmp(&b.path));
+        let blocks = blocks(sources, outcomes, &modules, warnings);
+        let html = self.path.extension()
+                    .is_some_and(|ext| ext == "html" || ext == "htm");
+        let document = if html { to_html(&blocks) } else { to_markdown(&blocks) };
+        std::fs::write(&self.path, document)
+            .map_err(|e| Error::Write { path: self.path.clone(), source: e })
+    }
+}
+
+/// Formats seconds since the Unix epoch as a UTC date and time
+fn utc(timestamp: u64) -> String {
+    // Days to civil dates, from Howard Hinnant's `civil_from_days`
+    let days = timestamp / 86400;
+    let seconds = timestamp % 86400;
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day, seconds / 3600,
+            seconds / 60 % 60, seconds % 60)
+}
+
+fn crc(crc: u64) -> Span {
+    code(format!("0x{:08x}", crc))
+}
+
+fn blocks(sources: &[String], outcomes: &[Outcome], modules: &[Module],
+          warnings: &[String]) -> Vec<Block> {
+
+    let mut blocks = vec![
+        Block::Heading(1, vec![text("Patch report")]),
+        Block::Paragraph(vec![text(format!("Written by candycorn {} at {}.",
+                                           env!("CARGO_PKG_VERSION"),
+                                           utc(crate::unix_time())))]),
+        Block::Heading(2, vec![text("Sources")]),
+    ];
+    if sources.is_empty() {
+        blocks.push(Block::Paragraph(vec![text("No sources, only manual versions.")]));
+    } else {
+        blocks.push(Block::Paragraph(vec![text("In order of increasing precedence:")]));
+        blocks.push(Block::List(sources.iter().map(|s| vec![code(s)]).collect()));
+    }
+
+    blocks.push(Block::Heading(2, vec![text("Targets")]));
+    blocks.push(Block::Table(vec!["Target", "Result"], outcomes.iter().map(|outcome| {
+        vec![code(&outcome.target), text(&outcome.result)]
+    }).collect()));
+
+    for module in modules {
+        blocks.push(Block::Heading(2, vec![code(module.path.display().to_string())]));
+        blocks.push(Block::List(vec![
+            vec![text(format!("Architecture: {}", module.arch))],
+            vec![text(format!("Versions: {}", module.stats))],
+        ]));
+
+        blocks.push(Block::Heading(3, vec![text("Changed Versions")]));
+        if module.symbols.is_empty() {
+            blocks.push(Block::Paragraph(vec![text("No versions were changed.")]));
+        } else {
+            let rows = module.symbols.iter().map(|change| vec![
+                code(&change.name),
+                code(format!("0x{:x}", change.offset)),
+                crc(change.original),
+                crc(change.crc),
+                text(&change.source),
+            ]).collect();
+            blocks.push(Block::Table(vec!["Symbol", "Offset", "Before", "After", "Source"],
+                                     rows));
+        }
+
+        blocks.push(Block::Heading(3, vec![text("Vermagic")]));
+        let patched = module.modinfo.iter().find(|entry| entry.key == "vermagic");
+        let vermagic = match (&module.vermagic, patched) {
+            (None, _) => vec![text("The module has no vermagic.")],
+            (Some(before), Some(entry)) if entry.value != *before => vec![
+                text("Changed from "), code(before), text(" to "),
+                code(&entry.value), text("."),
+            ],
+            (Some(before), _) => vec![text("Unchanged, "), code(before), text(".")],
+        };
+        blocks.push(Block::Paragraph(vermagic));
+
+        let modinfo: Vec<_> = module.modinfo.iter()
+                                .filter(|entry| entry.key != "vermagic").collect();
+        if !modinfo.is_empty() {
+            blocks.push(Block::Heading(3, vec![text("Modinfo Changes")]));
+            let rows = modinfo.iter().map(|entry| {
+                let before = if entry.added { text("") } else { code(&entry.original) };
+                let after = match entry.deleted {
+                    true => text("removed"),
+                    false => code(&entry.value),
+                };
+                vec![code(&entry.key), before, after]
+            }).collect();
+            blocks.push(Block::Table(vec!["Key", "Before", "After"], rows));
+        }
+    }
+
+    blocks.push(Block::Heading(2, vec![text("Warnings")]));
+    if warnings.is_empty() {
+        blocks.push(Block::Paragraph(vec![text("None.")]));
+    } else {
+        blocks.push(Block::List(warnings.iter().map(|w| vec![text(w)]).collect()));
+    }
+    blocks
+}
+
+fn markdown_spans(spans: &[Span], in_table: bool) -> String {
+    spans.iter().map(|span| match span {
+        Span::Text(text) => {
+            let mut escaped = String::new();
+            for c in text.chars() {
+                if "\\`*_[]<>|#".contains(c) {
+                    escaped.push('\\');
+                }
+                // Lines of multi-line warnings stay in their list item
+                match c {
+                    '\n' if in_table => escaped.push(' '),
+                    '\n' => escaped.push_str("  \n  "),
+                    c => escaped.push(c),
+                }
+            }
+            escaped
+        },
+        Span::Code(text) if text.is_empty() => String::new(),
+        Span::Code(text) => {
+            let text = if in_table { text.replace('|', "\\|") } else { text.clone() };
+            if text.contains('`') {
+                format!("`` {} ``", text)
+            } else {
+                format!("`{}`", text)
+            }
+        },
+    }).collect()
+}
+
+fn to_markdown(blocks: &[Block]) -> String {
+    let mut document = Vec::new();
+    for block in blocks {
+        document.push(match block {
+            Block::Heading(level, spans) => {
+                format!("{} {}", "#".repeat(*level), markdown_spans(spans, false))
+            },
+            Block::Paragraph(spans) => markdown_spans(spans, false),
+            Block::List(items) => items.iter()
+                .map(|item| format!("- {}", markdown_spans(item, false)))
+                .collect::<Vec<_>>().join("\n"),
+            Block::Table(headers, rows) => {
+                let mut lines = vec![format!("| {} |", headers.join(" | ")),
+                                     format!("|{}", "---|".repeat(headers.len()))];
+                for row in rows {
+                    let cells: Vec<String> = row.iter()
+                        .map(|cell| markdown_spans(std::slice::from_ref(cell), true))
+                        .collect();
+                    lines.push(format!("| {} |", cells.join(" | ")));
+                }
+                lines.join("\n")
+            },
+        });
+    }
+    document.join("\n\n") + "\n"
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn html_spans(spans: &[Span]) -> String {
+    spans.iter().map(|span| match span {
+        Span::Text(text) => html_escape(text).replace('\n', "<br>\n"),
+        Span::Code(text) => format!("<code>{}</code>", html_escape(text)),
+    }).collect()
+}
+
+fn to_html(blocks: &[Block]) -> String {
+    let mut document = String::from("<!DOCTYPE html>\n<html>\n<head>\n\
+                                     <meta charset=\"utf-8\">\n\
+                                     <title>Patch report</title>\n</head>\n<body>\n");
+    for block in blocks {
+        match block {
+            Block::Heading(level, spans) => {
+                document += &format!("<h{0}>{1}</h{0}>\n", level, html_spans(spans));
+            },
+            Block::Paragraph(spans) => {
+                document += &format!("<p>{}</p>\n", html_spans(spans));
+            },
+            Block::List(items) => {
+                document += "<ul>\n";
+                for item in items {
+                    document += &format!("<li>{}</li>\n", html_spans(item));
+                }
+                document += "</ul>\n";
+            },
+            Block::Table(headers, rows) => {
+                document += "<table>\n<tr>";
+                for header in headers {
+                    document += &format!("<th>{}</th>", html_escape(header));
+                }
+                document += "</tr>\n";
+                for row in rows {
+                    document += "<tr>";
+                    for cell in row {
+                        document += &format!("<td>{}</td>",
+                                             html_spans(std::slice::from_ref(cell)));
+                    }
+                    document += "</tr>\n";
+                }
+                document += "</table>\n";
+            },
+        }
+    }
+    document + "</body>\n</html>\n"
+}
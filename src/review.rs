@@ -0,0 +1,372 @@
+use crate::error::{Error, Result};
+use crate::modinfo::{Edit, ModInfoEntry};
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::crossterm::event::KeyModifiers;
+use ratatui::crossterm::{execute, terminal};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState, Wrap};
+use ratatui::Frame;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+/// `__versions` entry of a target under review
+pub struct Entry {
+    pub name: String,
+    pub offset: usize,
+    pub current: u64,
+    /// CRC the entry is to be patched with, if any
+    pub proposed: Option<u64>,
+    /// Whether the proposed CRC is written
+    pub selected: bool,
+}
+
+/// Outcome of reviewing a target
+pub enum Decision {
+    Write,
+    Skip,
+}
+
+/// Terminal confirmations are asked on, so they work while stdin or stdout
+/// carry modules
+struct Terminal {
+    input: std::io::BufReader<std::fs::File>,
+    output: std::fs::File,
+}
+
+impl Terminal {
+    /// Opens the terminal for the option that asked for it
+    fn open(option: &str) -> Result<Terminal> {
+        let unsupported = |e: std::io::Error| Error::Unsupported {
+            reason: format!("`{}` needs a terminal -- {}", option, e),
+        };
+        let output = std::fs::OpenOptions::new().read(true).write(true)
+                        .open("/dev/tty").map_err(unsupported)?;
+        let input = std::io::BufReader::new(output.try_clone().map_err(unsupported)?);
+        Ok(Terminal { input, output })
+    }
+
+    /// Prompts for a line, returning `None` at the end of input
+    fn prompt(&mut self, prompt: &str) -> Option<String> {
+        let _ = write!(self.output, "{}", prompt);
+        let _ = self.output.flush();
+        let mut line = String::new();
+        match self.input.read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim().to_string()),
+        }
+    }
+}
+
+/// Full screen view of a review on the terminal, restored when dropped
+struct Screen {
+    terminal: ratatui::Terminal<CrosstermBackend<std::fs::File>>,
+}
+
+impl Screen {
+    /// Opens the terminal for the option that asked for it and switches it
+    /// to a blank screen read key by key
+    fn open(option: &str) -> Result<Screen> {
+        let unsupported = |e: std::io::Error| Error::Unsupported {
+            reason: format!("`{}` needs a terminal -- {}", option, e),
+        };
+        let tty = std::fs::OpenOptions::new().read(true).write(true).open("/dev/tty")
+                    .map_err(unsupported)?;
+        let terminal = ratatui::Terminal::new(CrosstermBackend::new(tty))
+                        .map_err(unsupported)?;
+
+        // From here on dropping the screen restores the terminal
+        let mut screen = Screen { terminal };
+        terminal::enable_raw_mode().map_err(unsupported)?;
+        execute!(screen.terminal.backend_mut(), terminal::EnterAlternateScreen)
+            .map_err(unsupported)?;
+        screen.terminal.clear().map_err(unsupported)?;
+        Ok(screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(self.terminal.backend_mut(), terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+        let _ = self.terminal.show_cursor();
+    }
+}
+
+/// Entries the cursor moves by a page
+const PAGE: usize = 10;
+
+/// Keys of the review, shown below the entries
+const HELP: &str = "up/down move  space toggle  a all  n none  m modinfo  y write  \
+                    q skip";
+
+/// State of the review of a target
+struct View<'a> {
+    target: &'a Path,
+    entries: &'a mut [Entry],
+    modinfo: Option<&'a [ModInfoEntry]>,
+    edits: &'a [Edit],
+    table: TableState,
+    show_modinfo: bool,
+}
+
+impl View<'_> {
+    /// Moves the cursor to an entry, stopping at the last one
+    fn select(&mut self, idx: usize) {
+        self.table.select(Some(idx.min(self.entries.len().saturating_sub(1))));
+    }
+
+    /// Handles a key, returning the decision once one is made
+    fn key(&mut self, key: KeyEvent) -> Option<Decision> {
+        let last = self.entries.len().saturating_sub(1);
+        let cursor = self.table.selected().unwrap_or(0);
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Some(Decision::Skip);
+            },
+            KeyCode::Char('y') | KeyCode::Enter => return Some(Decision::Write),
+            KeyCode::Char('q') | KeyCode::Esc => return Some(Decision::Skip),
+            KeyCode::Up | KeyCode::Char('k') => self.select(cursor.saturating_sub(1)),
+            KeyCode::Down | KeyCode::Char('j') => self.select(cursor + 1),
+            KeyCode::PageUp => self.select(cursor.saturating_sub(PAGE)),
+            KeyCode::PageDown => self.select(cursor + PAGE),
+            KeyCode::Home | KeyCode::Char('g') => self.select(0),
+            KeyCode::End | KeyCode::Char('G') => self.select(last),
+            KeyCode::Char(' ') | KeyCode::Char('x') => {
+                if let Some(entry) = self.entries.get_mut(cursor) {
+                    entry.selected = !entry.selected;
+                }
+            },
+            KeyCode::Char(key @ ('a' | 'n')) => {
+                for entry in self.entries.iter_mut() {
+                    entry.selected = key == 'a';
+                }
+            },
+            KeyCode::Char('m') | KeyCode::Tab => self.show_modinfo = !self.show_modinfo,
+            _ => {},
+        }
+        None
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [body, help] = Layout::vertical([Constraint::Min(1), Constraint::Length(1)])
+                            .areas(frame.area());
+        let (list, modinfo) = if self.show_modinfo {
+            let [list, modinfo] = Layout::horizontal([Constraint::Percentage(60),
+                                                      Constraint::Percentage(40)])
+                                    .areas(body);
+            (list, Some(modinfo))
+        } else {
+            (body, None)
+        };
+
+        let patching = self.entries.iter().filter(|e| e.proposed.is_some() && e.selected)
+                        .count();
+        let title = format!(" {} - patching {} of {} versions ", self.target.display(),
+                            patching, self.entries.len());
+        let rows = self.entries.iter().enumerate().map(|(idx, entry)| {
+            let (mark, proposed, style) = match entry.proposed {
+                Some(crc) if entry.selected => {
+                    ("[x]", format!("0x{:x}", crc), Style::new().bold())
+                },
+                Some(crc) => ("[ ]", format!("0x{:x}", crc), Style::new().dim()),
+                None => ("", "-".to_string(), Style::new()),
+            };
+            Row::new([mark.to_string(), (idx + 1).to_string(), entry.name.clone(),
+                      format!("0x{:x}", entry.offset), format!("0x{:x}", entry.current),
+                      proposed]).style(style)
+        });
+        let widths = [Constraint::Length(3), Constraint::Length(4), Constraint::Fill(1),
+                      Constraint::Length(8), Constraint::Length(18),
+                      Constraint::Length(18)];
+        let header = Row::new(["", "#", "Symbol", "Offset", "Current", "Proposed"])
+                        .style(Style::new().underlined());
+        let table = Table::new(rows, widths).header(header)
+                        .block(Block::bordered().title(title))
+                        .row_highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(table, list, &mut self.table);
+
+        if let Some(area) = modinfo {
+            let paragraph = Paragraph::new(modinfo_lines(self.modinfo, self.edits))
+                                .block(Block::bordered().title(" Modinfo "))
+                                .wrap(Wrap { trim: false });
+            frame.render_widget(paragraph, area);
+        }
+        frame.render_widget(Paragraph::new(HELP).dim(), help);
+    }
+}
+
+/// Lines of the modinfo pane, the `.modinfo` entries followed by the edits
+/// to be made to them
+fn modinfo_lines<'a>(modinfo: Option<&[ModInfoEntry]>, edits: &[Edit]) -> Vec<Line<'a>> {
+    let mut lines: Vec<Line> = match modinfo {
+        Some(entries) => entries.iter().map(|entry| {
+            Line::from(format!("{}={}", entry.key, entry.value))
+        }).collect(),
+        None => vec![Line::from("no `.modinfo` section").italic()],
+    };
+    if !edits.is_empty() {
+        lines.push(Line::default());
+        lines.push(Line::from("Edits:").bold());
+    }
+    for edit in edits {
+        lines.push(Line::from(match edit {
+            Edit::Set { key, value } => format!("set {}={}", key, value),
+            Edit::Add { key, value } => format!("add {}={}", key, value),
+            Edit::Delete { key, value: Some(value) } => {
+                format!("delete {}={}", key, value)
+            },
+            Edit::Delete { key, value: None } => format!("delete {}", key),
+        }));
+    }
+    lines
+}
+
+/// Lists a target's versions with their current and proposed CRCs on the
+/// terminal and lets the user toggle which to write, inspect the `.modinfo`
+/// section and its edits and confirm writing the target
+pub fn review(target: &Path, entries: &mut [Entry], modinfo: Option<&[ModInfoEntry]>,
+              edits: &[Edit]) -> Result<Decision> {
+
+    let tty = || std::path::PathBuf::from("/dev/tty");
+    let mut screen = Screen::open("--interactive")?;
+    let mut view = View {
+        target,
+        entries,
+        modinfo,
+        edits,
+        table: TableState::default().with_selected(0),
+        show_modinfo: !edits.is_empty(),
+    };
+    loop {
+        screen.terminal.draw(|frame| view.draw(frame))
+            .map_err(|e| Error::Write { path: tty(), source: e })?;
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                if let Some(decision) = view.key(key) {
+                    return Ok(decision);
+                }
+            },
+            Ok(_) => {},
+            Err(e) => return Err(Error::Read { path: tty(), source: e }),
+        }
+    }
+}
+
+/// Asks before each proposed CRC is written, showing the entry's current and
+/// proposed CRC and where the proposed one comes from. `a` accepts the entry
+/// and the remaining ones, `q` declines them. Declined entries are deselected
+pub fn confirm(target: &Path, entries: &mut [Entry], origins: &[String]) -> Result<()> {
+    let mut tty = Terminal::open("--confirm")?;
+    let _ = writeln!(tty.output, "{}:", target.display());
+    for idx in 0 .. entries.len() {
+        let entry = &mut entries[idx];
+        let crc = match entry.proposed {
+            Some(crc) if entry.selected => crc,
+            _ => continue,
+        };
+        let question = format!("\"{}\" at 0x{:x}: 0x{:x} -> 0x{:x} from {}, write? \
+                                [y,n,a,q,?] ", entry.name, entry.offset, entry.current,
+                               crc, origins[idx]);
+        loop {
+            // The end of input declines the remaining entries, as `q` does
+            let input = tty.prompt(&question).unwrap_or_else(|| "q".to_string());
+            match input.as_str() {
+                "y" | "yes" => break,
+                "n" | "no" => {
+                    entry.selected = false;
+                    break;
+                },
+                "a" | "all" => return Ok(()),
+                "q" | "quit" => {
+                    for entry in &mut entries[idx ..] {
+                        entry.selected = false;
+                    }
+                    return Ok(());
+                },
+                _ => {
+                    let _ = writeln!(tty.output, "y - write this CRC\n\
+                        n - leave this entry\n\
+                        a - write this and all remaining CRCs\n\
+                        q - leave this and all remaining entries");
+                },
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<Entry> {
+        let entry = |name: &str, proposed: Option<u64>| Entry {
+            name: name.to_string(),
+            offset: 0x40,
+            current: 0x1111,
+            proposed,
+            selected: proposed.is_some(),
+        };
+        vec![entry("module_layout", Some(0x2222)), entry("printk", None),
+             entry("kfree", Some(0x3333))]
+    }
+
+    fn press(view: &mut View, code: KeyCode) -> Option<Decision> {
+        view.key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    #[test]
+    fn keys_move_and_toggle_entries() {
+        let mut entries = entries();
+        let mut view = View { target: Path::new("target.ko"), entries: &mut entries,
+                              modinfo: None, edits: &[],
+                              table: TableState::default().with_selected(0),
+                              show_modinfo: false };
+        assert!(press(&mut view, KeyCode::Char(' ')).is_none());
+        for _ in 0 .. 3 {
+            press(&mut view, KeyCode::Down);
+        }
+        assert_eq!(view.table.selected(), Some(2));
+        press(&mut view, KeyCode::Char(' '));
+        assert!(view.entries.iter().all(|e| !e.selected));
+        press(&mut view, KeyCode::Char('a'));
+        assert!(view.entries.iter().all(|e| e.selected));
+        press(&mut view, KeyCode::Up);
+        press(&mut view, KeyCode::Char('x'));
+        assert!(!view.entries[1].selected);
+        press(&mut view, KeyCode::Char('m'));
+        assert!(view.show_modinfo);
+
+        assert!(matches!(press(&mut view, KeyCode::Char('y')), Some(Decision::Write)));
+        assert!(matches!(press(&mut view, KeyCode::Esc), Some(Decision::Skip)));
+        let ctrl_c = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
+        assert!(matches!(view.key(ctrl_c), Some(Decision::Skip)));
+    }
+
+    #[test]
+    fn entries_and_modinfo_are_drawn() {
+        let mut entries = entries();
+        entries[2].selected = false;
+        let license = ModInfoEntry { key: "license".to_string(), value: "GPL".to_string(),
+                                     offset: 0, capacity: 12 };
+        let modinfo = [license];
+        let edits = [Edit::Set { key: "intree".to_string(), value: "Y".to_string() }];
+        let mut view = View { target: Path::new("target.ko"), entries: &mut entries,
+                              modinfo: Some(&modinfo), edits: &edits,
+                              table: TableState::default().with_selected(0),
+                              show_modinfo: true };
+
+        let backend = ratatui::backend::TestBackend::new(120, 10);
+        let mut terminal = ratatui::Terminal::new(backend).unwrap();
+        terminal.draw(|frame| view.draw(frame)).unwrap();
+        let screen: String = terminal.backend().buffer().content().iter()
+                                .map(|cell| cell.symbol()).collect();
+        for text in ["target.ko - patching 1 of 3 versions", "[x]", "0x2222", "[ ]",
+                     "license=GPL", "set intree=Y", HELP] {
+            assert!(screen.contains(text), "{:?} not drawn", text);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use crate::modinfo;
+use std::collections::{HashMap, HashSet};
+
+/// Returns the release of the running kernel (equivalent to `uname -r`)
+pub fn release() -> Option<String> {
+    let release = std::fs::read_to_string("/proc/sys/kernel/osrelease").ok()?;
+    Some(release.trim().to_string())
+}
+
+/// Returns the directory holding the running kernel's installed modules
+pub fn modules_dir() -> Option<std::path::PathBuf> {
+    Some(std::path::Path::new("/lib/modules").join(release()?))
+}
+
+/// Reads the configuration the running kernel was built with, from
+/// `/proc/config.gz` if the kernel provides it, otherwise from `/boot`
+pub fn config() -> Option<String> {
+    if let Ok(data) = std::fs::read("/proc/config.gz") {
+        if let Ok(Some(config)) = crate::compress::decompress(&data) {
+            return Some(String::from_utf8_lossy(&config).into_owned());
+        }
+    }
+    std::fs::read_to_string(format!("/boot/config-{}", release()?)).ok()
+}
+
+/// Looks up an option of a kernel configuration, returning its value such as
+/// `y` or `m`. Options that are not set are `None`
+pub fn config_option<'a>(config: &'a str, option: &str) -> Option<&'a str> {
+    config.lines()
+        .filter_map(|line| line.split_once('='))
+        .find(|(name, _)| *name == option)
+        .map(|(_, value)| value)
+}
+
+/// Attempts to determine the vermagic string modules must carry to be loaded
+/// by the running kernel. The vermagic of an installed module that is
+/// currently loaded is preferred. If no such module can be read, the vermagic
+/// is composed from the kernel release and `/proc/version`
+pub fn vermagic() -> Option<String> {
+    if let Some(vermagic) = vermagic_from_loaded() {
+        return Some(vermagic);
+    }
+
+    log::warn!("Unable to read vermagic from a loaded module, \
+                guessing vermagic from `/proc/version`");
+    vermagic_from_proc_version()
+}
+
+/// Returns the paths of the installed objects of currently loaded modules.
+/// Modules that can not be found in `modules.dep` are skipped
+fn loaded_module_paths() -> Vec<std::path::PathBuf> {
+    let mut paths = Vec::new();
+    let modules_dir = match modules_dir() {
+        Some(dir) => dir,
+        None => return paths,
+    };
+    let deps = std::fs::read_to_string(modules_dir.join("modules.dep"))
+                .unwrap_or_default();
+
+    for name in loaded_modules() {
+        // `modules.dep` lines are "<path>: <dependencies>" where the path's
+        // file name uses either dashes or underscores
+        let path = deps.lines()
+            .filter_map(|l| l.split(':').next())
+            .find(|p| {
+                let file = p.rsplit('/').next().unwrap_or(p);
+                file.replace('-', "_") == format!("{}.ko", name)
+            });
+        if let Some(path) = path {
+            paths.push(modules_dir.join(path));
+        }
+    }
+    paths
+}
+
+/// Returns the names of the currently loaded modules
+pub fn loaded_modules() -> Vec<String> {
+    let loaded = std::fs::read_to_string("/proc/modules").unwrap_or_default();
+    loaded.lines().filter_map(|l| l.split(' ').next()).map(str::to_string).collect()
+}
+
+/// Module of the running kernel, as `/sys/module/<name>` describes it
+pub struct LoadedModule {
+    /// Built into the kernel rather than loaded, so it lacks `initstate`
+    pub builtin: bool,
+    pub srcversion: Option<String>,
+    pub version: Option<String>,
+}
+
+/// Looks up a module of the running kernel by name. Built-in modules are only
+/// found if they have parameters or a version, as sysfs lists no others
+pub fn loaded_module(name: &str) -> Option<LoadedModule> {
+    let dir = std::path::Path::new("/sys/module").join(name);
+    if !dir.is_dir() {
+        return None;
+    }
+    let read = |file: &str| {
+        std::fs::read_to_string(dir.join(file)).ok().map(|value| value.trim().to_string())
+    };
+    Some(LoadedModule {
+        builtin: !dir.join("initstate").exists(),
+        srcversion: read("srcversion"),
+        version: read("version"),
+    })
+}
+
+/// Returns the architecture of the running kernel, as the architecture of the
+/// first loaded module whose object can be read
+pub fn arch() -> Option<crate::Arch> {
+    loaded_module_paths().iter().find_map(|path| {
+        crate::load_module(path).ok().map(|ko| ko.arch)
+    })
+}
+
+/// Collects the names of the symbols exported by the running kernel and its
+/// loaded modules from their `__ksymtab_` symbols in `/proc/kallsyms`
+pub fn exports() -> HashSet<String> {
+    let kallsyms = std::fs::read_to_string("/proc/kallsyms").unwrap_or_default();
+    kallsyms.lines()
+        .filter_map(|line| line.split_whitespace().nth(2)?.strip_prefix("__ksymtab_"))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Collects the namespaces of the symbols exported by a kernel from the
+/// `Module.symvers` of the build tree linked from its module directory.
+/// Returns an empty map if there is no build tree
+pub fn namespaces(modules_dir: &std::path::Path) -> HashMap<String, String> {
+    crate::symvers::load_exports(&modules_dir.join("build/Module.symvers"))
+        .map(|exports| exports.namespaces)
+        .unwrap_or_default()
+}
+
+/// Collects the symbol CRCs of the running kernel. CRCs are gathered, in order
+/// of preference, from the kernel build tree's `Module.symvers`, absolute
+/// `__crc_` symbols in `/proc/kallsyms` and the `__versions` of loaded modules
+pub fn crcs() -> HashMap<String, u64> {
+    let mut crcs = HashMap::new();
+
+    if let Some(dir) = modules_dir() {
+        if let Ok(symvers) = crate::symvers::load(&dir.join("build/Module.symvers")) {
+            crcs.extend(symvers);
+        }
+    }
+
+    // Only kernels using absolute CRCs store the CRC as the symbol value. On
+    // other kernels the value is an address, and addresses are zeroed for
+    // unprivileged users
+    let kallsyms = std::fs::read_to_string("/proc/kallsyms").unwrap_or_default();
+    for line in kallsyms.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 3 {
+            continue;
+        }
+        let name = match fields[2].strip_prefix("__crc_") {
+            Some(name) => name,
+            None => continue,
+        };
+        match u64::from_str_radix(fields[0], 16) {
+            Ok(crc) if crc != 0 && crc <= u32::MAX as u64 => {
+                crcs.entry(name.to_string()).or_insert(crc);
+            },
+            _ => {},
+        }
+    }
+
+    // Installed modules were built against the running kernel so the versions
+    // of the symbols they import are correct
+    for path in loaded_module_paths() {
+        let versions = match crate::load_module(&path) {
+            Ok(ko) => ko.versions.unwrap_or_default(),
+            Err(_) => continue,
+        };
+        for version in versions {
+            crcs.entry(version.name).or_insert(version.crc);
+        }
+    }
+    crcs
+}
+
+/// Reads the vermagic of the first loaded module whose object can be read
+/// from the running kernel's module directory
+fn vermagic_from_loaded() -> Option<String> {
+    for path in loaded_module_paths() {
+        let entries = match crate::load_module(&path) {
+            Ok(ko) => ko.modinfo.unwrap_or_default(),
+            Err(_) => continue,
+        };
+        if let Some(entry) = modinfo::find_entry(&entries, "vermagic") {
+            return Some(entry.value.clone());
+        }
+    }
+    None
+}
+
+/// Composes a vermagic string from the kernel release and the build flags
+/// reported in `/proc/version`. This assumes `CONFIG_MODULE_UNLOAD` and
+/// `CONFIG_MODVERSIONS` are enabled
+fn vermagic_from_proc_version() -> Option<String> {
+    let version = std::fs::read_to_string("/proc/version").ok()?;
+    let flags: Vec<&str> = version.split_whitespace().collect();
+
+    let mut vermagic = format!("{} ", release()?);
+    if flags.contains(&"SMP") {
+        vermagic.push_str("SMP ");
+    }
+    if flags.contains(&"PREEMPT_RT") {
+        vermagic.push_str("preempt_rt ");
+    } else if flags.iter().any(|f| *f == "PREEMPT" || *f == "PREEMPT_DYNAMIC") {
+        vermagic.push_str("preempt ");
+    }
+    vermagic.push_str("mod_unload modversions ");
+    if std::env::consts::ARCH == "aarch64" {
+        vermagic.push_str("aarch64");
+    }
+    Some(vermagic)
+}
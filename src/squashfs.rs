@@ -0,0 +1,73 @@
+use crate::error::{Error, Result};
+use crate::tools::run;
+use std::path::Path;
+use std::process::Command;
+
+/// Magic at the start of a little-endian SquashFS 4.0 superblock
+const MAGIC: &[u8] = b"hsqs";
+
+/// Settings of an image that are kept when it is repacked
+pub struct Superblock {
+    /// Creation time, in seconds since the Unix epoch
+    pub mkfs_time: u32,
+    pub block_size: u32,
+    /// Compressor name as `mksquashfs -comp` takes it
+    pub compression: &'static str,
+}
+
+/// Reads the superblock of a SquashFS image
+pub fn superblock(path: &Path) -> Result<Superblock> {
+    use std::io::Read;
+
+    let mut header = [0; 96];
+    std::fs::File::open(path).and_then(|mut file| file.read_exact(&mut header))
+        .map_err(|e| Error::Read { path: path.into(), source: e })?;
+    let unsupported = |reason: &str| Error::Image {
+        kind: "SquashFS",
+        reason: reason.to_string(),
+    }.in_file(path);
+    if !header.starts_with(MAGIC) {
+        return Err(unsupported("not a little-endian image"));
+    }
+
+    let u32_at = |offset: usize| {
+        u32::from_le_bytes(header[offset .. offset + 4].try_into().unwrap())
+    };
+    let u16_at = |offset: usize| {
+        u16::from_le_bytes(header[offset .. offset + 2].try_into().unwrap())
+    };
+    if u16_at(28) != 4 {
+        return Err(unsupported("only version 4.0 is supported"));
+    }
+    let compression = match u16_at(20) {
+        1 => "gzip",
+        2 => "lzma",
+        3 => "lzo",
+        4 => "xz",
+        5 => "lz4",
+        6 => "zstd",
+        _ => return Err(unsupported("unknown compression")),
+    };
+    Ok(Superblock { mkfs_time: u32_at(8), block_size: u32_at(12), compression })
+}
+
+/// Extracts a SquashFS image into a directory that does not exist yet
+pub fn extract(image: &Path, dir: &Path) -> Result<()> {
+    run(Command::new("unsquashfs").arg("-no-progress").arg("-d").arg(dir)
+            .arg(image))
+}
+
+/// Packs a directory into a new SquashFS image with the settings of an
+/// existing image
+pub fn pack(dir: &Path, image: &Path, settings: &Superblock) -> Result<()> {
+    let mut command = Command::new("mksquashfs");
+    command.arg(dir).arg(image)
+        .args(["-noappend", "-no-progress", "-comp", settings.compression])
+        .arg("-b").arg(settings.block_size.to_string())
+        .arg("-mkfs-time").arg(settings.mkfs_time.to_string());
+    // Versions before 4.4 order fragments by which thread finishes first
+    if crate::REPRODUCIBLE.load(std::sync::atomic::Ordering::Relaxed) {
+        command.args(["-processors", "1"]);
+    }
+    run(&mut command)
+}
@@ -0,0 +1,131 @@
+use crate::error::{Error, Result};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// CRC-32 lookup table for the reflected polynomial genksyms uses
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut idx = 0;
+    while idx < 256 {
+        let mut crc = idx as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[idx] = crc;
+        idx += 1;
+    }
+    table
+};
+
+/// Continues a CRC over a token followed by a space, as genksyms does for
+/// every token it hashes
+fn crc_token(crc: u32, token: &str) -> u32 {
+    token.bytes().chain(std::iter::once(b' ')).fold(crc, |crc, b| {
+        CRC_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Name genksyms hashes for references to a kind of type, by the prefix of
+/// the reference
+fn kind_name(prefix: &str) -> Option<&'static str> {
+    match prefix {
+        "t" => Some("typedef"),
+        "e" => Some("enum"),
+        "s" => Some("struct"),
+        "u" => Some("union"),
+        "E" => Some("enum constant"),
+        _ => None,
+    }
+}
+
+/// Expands the definitions of one exported symbol. Each type is expanded the
+/// first time it is referenced and only named afterwards
+struct Expander<'a> {
+    defs: &'a HashMap<&'a str, Vec<&'a str>>,
+    expanded: HashSet<&'a str>,
+}
+
+impl<'a> Expander<'a> {
+    fn expand(&mut self, name: &'a str, mut crc: u32) -> u32 {
+        let defs = self.defs;
+        for &token in defs.get(name).into_iter().flatten() {
+            let (kind, type_name) = match token.split_once('#') {
+                Some((prefix, type_name)) => match kind_name(prefix) {
+                    Some(kind) => (kind, type_name),
+                    None => {
+                        crc = crc_token(crc, token);
+                        continue;
+                    },
+                },
+                None => {
+                    crc = crc_token(crc, token);
+                    continue;
+                },
+            };
+
+            let aggregate = matches!(kind, "struct" | "union" | "enum");
+            if !self.expanded.insert(token) {
+                if aggregate {
+                    crc = crc_token(crc, kind);
+                }
+                crc = crc_token(crc, type_name);
+            } else if defs.contains_key(token) {
+                crc = self.expand(token, crc);
+            } else if aggregate {
+                // genksyms defines types it can not find as unknown
+                for token in [kind, type_name, "{", "UNKNOWN", "}"] {
+                    crc = crc_token(crc, token);
+                }
+            } else {
+                crc = crc_token(crc, type_name);
+            }
+        }
+        crc
+    }
+}
+
+/// Computes the CRCs genksyms gives the exported symbols of a `.symtypes`
+/// file, as written by kbuild with `KBUILD_SYMTYPES=1`. Each line holds a
+/// symbol followed by the tokens of its definition. Types are named with a
+/// prefix such as `s#` for structs, exported symbols have none
+pub fn parse(content: &str) -> HashMap<String, u64> {
+    let mut defs: HashMap<&str, Vec<&str>> = HashMap::new();
+    for line in content.lines() {
+        // Definitions preserved from a reference file are marked
+        let line = line.strip_prefix("override ").unwrap_or(line);
+        let mut tokens = line.split_whitespace();
+        if let Some(name) = tokens.next() {
+            defs.insert(name, tokens.collect());
+        }
+    }
+
+    let mut crcs = HashMap::new();
+    for &name in defs.keys().filter(|name| !name.contains('#')) {
+        let mut expander = Expander { defs: &defs, expanded: HashSet::from([name]) };
+        let crc = expander.expand(name, 0xffffffff) ^ 0xffffffff;
+        crcs.insert(name.to_string(), crc as u64);
+    }
+    crcs
+}
+
+/// Reads a `.symtypes` file, or all `.symtypes` files below a directory, and
+/// computes the CRCs of their exported symbols
+pub fn load(path: &Path) -> Result<HashMap<String, u64>> {
+    let files = if path.is_dir() {
+        crate::donors::find_files(path, |name| {
+            name.to_string_lossy().ends_with(".symtypes")
+        })?
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    let mut crcs = HashMap::new();
+    for file in files {
+        let content = std::fs::read_to_string(&file)
+                        .map_err(|e| Error::Read { path: file.clone(), source: e })?;
+        crcs.extend(parse(&content));
+    }
+    Ok(crcs)
+}
@@ -0,0 +1,90 @@
+use crate::error::{Error, Result};
+use std::collections::{HashMap, HashSet};
+
+/// Symbols listed in a `Module.symvers` file
+#[derive(Default)]
+pub struct Exports {
+    pub crcs: HashMap<String, u64>,
+    /// Namespaces of the symbols exported into one
+    pub namespaces: HashMap<String, String>,
+    /// Symbols exported with `EXPORT_SYMBOL_GPL`
+    pub gpl_only: HashSet<String>,
+    /// Module exporting each symbol, `vmlinux` for the kernel itself
+    pub modules: HashMap<String, String>,
+}
+
+/// Parses the content of a `Module.symvers` file. Each line has the form
+/// `<crc>\t<symbol>\t<module>\t<export type>[\t<namespace>]`
+pub fn parse_exports(content: &str) -> Result<Exports> {
+    let mut exports = Exports::default();
+    for (idx, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 4 {
+            return Err(Error::Symvers {
+                line: idx + 1,
+                reason: "expected at least 4 fields".to_string(),
+            });
+        }
+        let crc = fields[0].strip_prefix("0x").unwrap_or(fields[0]);
+        let crc = u64::from_str_radix(crc, 16)
+                    .map_err(|e| Error::Symvers {
+                        line: idx + 1,
+                        reason: format!("invalid CRC -- {}", e),
+                    })?;
+        exports.crcs.insert(fields[1].to_string(), crc);
+        exports.modules.insert(fields[1].to_string(), fields[2].to_string());
+        if let Some(namespace) = fields.get(4).filter(|ns| !ns.is_empty()) {
+            exports.namespaces.insert(fields[1].to_string(), namespace.to_string());
+        }
+        if fields[3] == "EXPORT_SYMBOL_GPL" {
+            exports.gpl_only.insert(fields[1].to_string());
+        }
+    }
+    Ok(exports)
+}
+
+/// Reads and parses a `Module.symvers` file
+pub fn load(path: &std::path::Path) -> Result<HashMap<String, u64>> {
+    Ok(load_exports(path)?.crcs)
+}
+
+/// Reads and parses a `Module.symvers` file along with the namespaces and
+/// export types of its symbols
+pub fn load_exports(path: &std::path::Path) -> Result<Exports> {
+    let content = std::fs::read_to_string(path)
+                    .map_err(|e| Error::Read { path: path.into(), source: e })?;
+    parse_exports(&content).map_err(|e| e.in_file(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_are_parsed_with_their_types() {
+        let exports = parse_exports("0x000000aa\tprintk\tvmlinux\tEXPORT_SYMBOL\t\n\
+                                     \n\
+                                     0xbb\tgpio_set\tgpio\tEXPORT_SYMBOL_GPL\tGPIO_NS\n")
+                        .unwrap();
+        assert_eq!(exports.crcs, HashMap::from([("printk".to_string(), 0xaa),
+                                                ("gpio_set".to_string(), 0xbb)]));
+        assert_eq!(exports.modules["gpio_set"], "gpio");
+        assert_eq!(exports.namespaces,
+                   HashMap::from([("gpio_set".to_string(), "GPIO_NS".to_string())]));
+        assert_eq!(exports.gpl_only, HashSet::from(["gpio_set".to_string()]));
+    }
+
+    #[test]
+    fn malformed_lines_are_reported() {
+        let line = |content| match parse_exports(content) {
+            Err(Error::Symvers { line, .. }) => line,
+            _ => panic!("{:?} parsed", content),
+        };
+        assert_eq!(line("0xaa\tprintk\tvmlinux\tEXPORT_SYMBOL\n0xbb\tkfree\n"), 2);
+        assert_eq!(line("0xzz\tprintk\tvmlinux\tEXPORT_SYMBOL\n"), 1);
+    }
+}
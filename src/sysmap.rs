@@ -0,0 +1,51 @@
+use crate::error::{Error, Result};
+use std::collections::HashMap;
+
+/// Parses the content of a `System.map` file into a map of symbol names to
+/// CRCs. Each line has the form `<address> <type> <symbol>`. Kernels using
+/// absolute CRCs define a `__crc_<symbol>` absolute symbol whose address is
+/// the CRC. Other `__crc_` symbols are addresses of the CRC and are skipped
+pub fn parse(content: &str) -> Result<HashMap<String, u64>> {
+    let mut crcs = HashMap::new();
+    let mut skipped = 0;
+    for (idx, line) in content.lines().enumerate() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() {
+            continue;
+        }
+        if fields.len() < 3 {
+            return Err(Error::SystemMap {
+                line: idx + 1,
+                reason: "expected 3 fields".to_string(),
+            });
+        }
+
+        let name = match fields[2].strip_prefix("__crc_") {
+            Some(name) => name,
+            None => continue,
+        };
+        if !fields[1].eq_ignore_ascii_case("a") {
+            skipped += 1;
+            continue;
+        }
+        let crc = u64::from_str_radix(fields[0], 16)
+                    .map_err(|e| Error::SystemMap {
+                        line: idx + 1,
+                        reason: format!("invalid address -- {}", e),
+                    })?;
+        crcs.insert(name.to_string(), crc);
+    }
+    if skipped > 0 {
+        log::warn!("{} `__crc_` symbols are not absolute and were \
+                    skipped, use `--vmlinux` for kernels storing CRCs as data",
+                   skipped);
+    }
+    Ok(crcs)
+}
+
+/// Reads and parses a `System.map` file
+pub fn load(path: &std::path::Path) -> Result<HashMap<String, u64>> {
+    let content = std::fs::read_to_string(path)
+                    .map_err(|e| Error::Read { path: path.into(), source: e })?;
+    parse(&content).map_err(|e| e.in_file(path))
+}
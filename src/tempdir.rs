@@ -0,0 +1,55 @@
+use crate::error::{Error, Result};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+
+/// Attempts at finding an unused name before giving up
+const ATTEMPTS: usize = 16;
+
+/// Temporary directory private to the process, removed with its contents
+/// when dropped
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Random part of a directory name, from the kernel's random number generator
+fn random_suffix() -> std::io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    // SAFETY: The pointer and length describe `bytes`, which is writable and
+    // outlives the call. The kernel writes at most that many bytes
+    let read = unsafe { libc::getrandom(bytes.as_mut_ptr().cast(), bytes.len(), 0) };
+    if read != bytes.len() as isize {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(u64::from_ne_bytes(bytes))
+}
+
+/// Creates a new directory in the system's temporary directory, accessible
+/// only to the user. Names are random, so other users can not create the
+/// directory in advance, and a name already taken is never reused
+pub fn create(purpose: &str) -> Result<TempDir> {
+    let base = std::env::temp_dir();
+    let write_err = |path, e| Error::Write { path, source: e };
+    for _ in 0 .. ATTEMPTS {
+        let suffix = random_suffix().map_err(|e| write_err(base.clone(), e))?;
+        let path = base.join(format!("candycorn-{}-{:016x}", purpose, suffix));
+        match std::fs::DirBuilder::new().mode(0o700).create(&path) {
+            Ok(()) => return Ok(TempDir { path }),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(write_err(path, e)),
+        }
+    }
+    Err(write_err(base, std::io::Error::new(std::io::ErrorKind::AlreadyExists,
+                                            "no unused temporary directory name found")))
+}
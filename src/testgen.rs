@@ -0,0 +1,242 @@
+use crate::cli::Machine;
+use crate::modversions::{ENTRY_SIZE, EXT_CRCS, EXT_NAMES};
+use goblin::container::{Container, Ctx};
+use goblin::elf::header::{self, Header};
+use goblin::elf::section_header::{SectionHeader, SHF_ALLOC, SHF_WRITE, SHT_NULL,
+                                  SHT_PROGBITS, SHT_STRTAB, SHT_SYMTAB};
+use goblin::elf::sym::{Sym, STB_GLOBAL, STT_NOTYPE, STT_OBJECT};
+use scroll::ctx::SizeWith;
+use scroll::Pwrite;
+
+/// Size of the `name` field of `struct module`
+const MODULE_NAME_LEN: usize = 56;
+
+impl Machine {
+    fn e_machine(self) -> u16 {
+        match self {
+            Machine::X86_64 => header::EM_X86_64,
+            Machine::I386 => header::EM_386,
+            Machine::Aarch64 => header::EM_AARCH64,
+            Machine::Arm => header::EM_ARM,
+            Machine::Ppc64 => header::EM_PPC64,
+            Machine::S390 => header::EM_S390,
+            Machine::Riscv => header::EM_RISCV,
+            Machine::Mips => header::EM_MIPS,
+        }
+    }
+}
+
+/// Contents of a synthetic kernel module
+pub struct Spec {
+    pub machine: Machine,
+    pub is_64: bool,
+    pub little_endian: bool,
+    /// Name in `.gnu.linkonce.this_module`
+    pub name: String,
+    /// Symbol versions, also added to the symbol table as undefined symbols
+    pub versions: Vec<(String, u64)>,
+    /// Write the versions to the extended version sections instead of
+    /// `__versions`
+    pub extended: bool,
+    /// `.modinfo` entries in order
+    pub modinfo: Vec<(String, String)>,
+    /// Append a placeholder PKCS#7 signature
+    pub signed: bool,
+}
+
+impl Spec {
+    /// Longest symbol name a `__versions` entry of the spec's class holds
+    pub fn max_name_len(&self) -> usize {
+        let crc_size = if self.is_64 { 8 } else { 4 };
+        ENTRY_SIZE - crc_size - 1
+    }
+}
+
+/// Section being laid out, with its name and content
+struct Section {
+    name: &'static str,
+    header: SectionHeader,
+    content: Vec<u8>,
+}
+
+fn section(name: &'static str, sh_type: u32, sh_flags: u64, sh_addralign: u64,
+           content: Vec<u8>) -> Section {
+    Section {
+        name,
+        header: SectionHeader { sh_type, sh_flags, sh_addralign, ..Default::default() },
+        content,
+    }
+}
+
+/// Writes an integer field of a CRC's or pointer's width
+fn write_word(buf: &mut [u8], offset: usize, value: u64, is_64: bool,
+              endian: scroll::Endian) {
+    if is_64 {
+        buf.pwrite_with(value, offset, endian).unwrap();
+    } else {
+        buf.pwrite_with(value as u32, offset, endian).unwrap();
+    }
+}
+
+/// Builds a minimal relocatable kernel module. It has no code, but its
+/// `__versions`, `.modinfo`, `.gnu.linkonce.this_module` and symbol table
+/// are laid out as the kernel's build system lays them out
+pub fn generate(spec: &Spec) -> Vec<u8> {
+    let container = if spec.is_64 { Container::Big } else { Container::Little };
+    let endian = if spec.little_endian { scroll::LE } else { scroll::BE };
+    let ctx = Ctx::new(container, endian);
+    let word = if spec.is_64 { 8 } else { 4 };
+
+    let mut sections = vec![section("", SHT_NULL, 0, 0, Vec::new()),
+                            section(".text", SHT_PROGBITS, 0x6, 16, Vec::new())];
+
+    let mut modinfo = Vec::new();
+    for (key, value) in &spec.modinfo {
+        modinfo.extend_from_slice(format!("{}={}\0", key, value).as_bytes());
+    }
+    sections.push(section(".modinfo", SHT_PROGBITS, SHF_ALLOC as u64, 1, modinfo));
+
+    if spec.extended {
+        let mut crcs = vec![0u8; spec.versions.len() * 4];
+        let mut names = Vec::new();
+        for (idx, (name, crc)) in spec.versions.iter().enumerate() {
+            crcs.pwrite_with(*crc as u32, idx * 4, endian).unwrap();
+            names.extend_from_slice(name.as_bytes());
+            names.push(0);
+        }
+        sections.push(section(EXT_CRCS, SHT_PROGBITS, SHF_ALLOC as u64, 4, crcs));
+        sections.push(section(EXT_NAMES, SHT_PROGBITS, SHF_ALLOC as u64, 1, names));
+    } else {
+        let mut versions = vec![0u8; spec.versions.len() * ENTRY_SIZE];
+        for (idx, (name, crc)) in spec.versions.iter().enumerate() {
+            let entry = idx * ENTRY_SIZE;
+            write_word(&mut versions, entry, *crc, spec.is_64, endian);
+            versions[entry + word .. entry + word + name.len()]
+                .copy_from_slice(name.as_bytes());
+        }
+        sections.push(section("__versions", SHT_PROGBITS, SHF_ALLOC as u64, 32,
+                              versions));
+    }
+
+    // `struct module` starts with its state and list head, followed by its
+    // name. The rest is left zeroed
+    let name_offset = if spec.is_64 { 24 } else { 12 };
+    let mut this_module = vec![0u8; if spec.is_64 { 0x380 } else { 0x200 }];
+    let name = &spec.name.as_bytes()[.. spec.name.len().min(MODULE_NAME_LEN - 1)];
+    this_module[name_offset .. name_offset + name.len()].copy_from_slice(name);
+    let this_module_idx = sections.len();
+    sections.push(section(".gnu.linkonce.this_module", SHT_PROGBITS,
+                          (SHF_ALLOC | SHF_WRITE) as u64, 64, this_module));
+
+    // Symbols: the null symbol, `__this_module` and the versioned imports
+    let mut strtab = vec![0u8];
+    let mut symbols = vec![Sym::default()];
+    let mut add_name = |name: &str| {
+        let offset = strtab.len();
+        strtab.extend_from_slice(name.as_bytes());
+        strtab.push(0);
+        offset
+    };
+    symbols.push(Sym {
+        st_name: add_name("__this_module"),
+        st_info: (STB_GLOBAL << 4) | STT_OBJECT,
+        st_shndx: this_module_idx,
+        st_size: sections[this_module_idx].content.len() as u64,
+        ..Default::default()
+    });
+    for (name, _) in &spec.versions {
+        symbols.push(Sym {
+            st_name: add_name(name),
+            st_info: (STB_GLOBAL << 4) | STT_NOTYPE,
+            ..Default::default()
+        });
+    }
+    let sym_size = goblin::elf::sym::Sym::size_with(&ctx);
+    let mut symtab = vec![0u8; symbols.len() * sym_size];
+    for (idx, sym) in symbols.into_iter().enumerate() {
+        symtab.pwrite_with(sym, idx * sym_size, ctx).unwrap();
+    }
+    let symtab_idx = sections.len();
+    let mut symtab = section(".symtab", SHT_SYMTAB, 0, word as u64, symtab);
+    symtab.header.sh_link = symtab_idx as u32 + 1;
+    symtab.header.sh_info = 1;
+    symtab.header.sh_entsize = sym_size as u64;
+    sections.push(symtab);
+    sections.push(section(".strtab", SHT_STRTAB, 0, 1, strtab));
+
+    let mut shstrtab = vec![0u8];
+    for section in &mut sections[1 ..] {
+        section.header.sh_name = shstrtab.len();
+        shstrtab.extend_from_slice(section.name.as_bytes());
+        shstrtab.push(0);
+    }
+    let shstrndx = sections.len();
+    let mut names = section(".shstrtab", SHT_STRTAB, 0, 1, Vec::new());
+    names.header.sh_name = shstrtab.len();
+    shstrtab.extend_from_slice(b".shstrtab\0");
+    names.content = shstrtab;
+    sections.push(names);
+
+    // Content follows the ELF header in section order, then the section
+    // header table
+    let mut elf_header = Header::new(ctx);
+    let mut data = vec![0u8; elf_header.e_ehsize as usize];
+    for section in &mut sections[1 ..] {
+        let align = section.header.sh_addralign.max(1) as usize;
+        data.resize(crate::elfedit::align_up(data.len(), align), 0);
+        section.header.sh_offset = data.len() as u64;
+        section.header.sh_size = section.content.len() as u64;
+        data.extend_from_slice(&section.content);
+    }
+    data.resize(crate::elfedit::align_up(data.len(), word), 0);
+
+    elf_header.e_type = header::ET_REL;
+    elf_header.e_machine = spec.machine.e_machine();
+    elf_header.e_shoff = data.len() as u64;
+    elf_header.e_shnum = sections.len() as u16;
+    elf_header.e_shstrndx = shstrndx as u16;
+    let sh_size = elf_header.e_shentsize as usize;
+    let table = data.len();
+    data.resize(table + sections.len() * sh_size, 0);
+    for (idx, section) in sections.into_iter().enumerate() {
+        data.pwrite_with(section.header, table + idx * sh_size, ctx).unwrap();
+    }
+    data.pwrite_with(elf_header, 0, endian).unwrap();
+
+    if spec.signed {
+        append_signature(&mut data);
+    }
+    data
+}
+
+/// Appends a zeroed PKCS#7 signature, its `struct module_signature` and the
+/// marker the kernel looks for
+fn append_signature(data: &mut Vec<u8>) {
+    const PKEY_ID_PKCS7: u8 = 2;
+    const SIG_LEN: u32 = 64;
+
+    data.extend_from_slice(&[0u8; SIG_LEN as usize]);
+    data.extend_from_slice(&[0, 0, PKEY_ID_PKCS7, 0, 0, 0, 0, 0]);
+    data.extend_from_slice(&SIG_LEN.to_be_bytes());
+    data.extend_from_slice(crate::MODULE_SIG_STRING);
+}
+
+/// Generates a module in each ELF class and byte order, for tests
+#[cfg(test)]
+pub fn fixtures(versions: &[(&str, u64)], extended: bool) -> Vec<Vec<u8>> {
+    let layouts = [(Machine::X86_64, true, true), (Machine::I386, false, true),
+                   (Machine::Ppc64, true, false), (Machine::Mips, false, false)];
+    layouts.into_iter().map(|(machine, is_64, little_endian)| {
+        generate(&Spec {
+            machine,
+            is_64,
+            little_endian,
+            name: "fixture".to_string(),
+            versions: versions.iter().map(|&(name, crc)| (name.to_string(), crc))
+                        .collect(),
+            extended,
+            modinfo: vec![("license".to_string(), "GPL".to_string())],
+            signed: false,
+        })
+    }).collect()
+}
@@ -0,0 +1,70 @@
+use crate::error::{Error, Result};
+use crate::{read_symtab, section_data};
+
+/// Section holding the module's `struct module`, `__this_module`
+pub const SECTION: &str = ".gnu.linkonce.this_module";
+
+/// `name` field of a module's `struct module`
+pub struct NameField {
+    /// File offset of the field
+    pub offset: usize,
+    /// Size of the field, `MODULE_NAME_LEN`
+    pub len: usize,
+    pub name: String,
+}
+
+/// Finds the `name` field of a module's `struct module`. The field follows
+/// `enum module_state state` and `struct list_head list`, which has held
+/// since Linux 2.6, and is `64 - sizeof(unsigned long)` bytes. Returns
+/// `None` if the module has no `struct module`
+pub fn find_name(info: &goblin::elf::Elf, data: &[u8]) -> Result<Option<NameField>> {
+    let idx = match info.section_headers.iter()
+                    .position(|sh| info.shdr_strtab.get_at(sh.sh_name) == Some(SECTION)) {
+        Some(idx) => idx,
+        None => return Ok(None),
+    };
+    let sh = &info.section_headers[idx];
+    let content = section_data(sh, SECTION, data)?;
+
+    // `__this_module` starts the section in modules built by Kbuild, but its
+    // symbol is used where present
+    let start = read_symtab(info, data)?.and_then(|(syms, strtab)| {
+        syms.iter().find(|sym| sym.st_shndx == idx
+                            && strtab.get_at(sym.st_name) == Some("__this_module"))
+            .map(|sym| sym.st_value as usize)
+    }).unwrap_or(0);
+    let (offset, len) = if info.is_64 { (start + 24, 56) } else { (start + 12, 60) };
+
+    let unrecognized = |reason: &str| Error::MalformedSection {
+        section: SECTION.to_string(),
+        offset: sh.sh_offset as usize,
+        reason: reason.to_string(),
+    };
+    let field = content.get(offset .. offset + len)
+                    .ok_or_else(|| unrecognized("too small for `struct module`"))?;
+    let name = match field.iter().position(|&b| b == 0) {
+        Some(end) if end > 0 => &field[.. end],
+        _ => return Err(unrecognized("no module name where `struct module` has it")),
+    };
+    if !name.iter().all(|&b| b.is_ascii_graphic()) {
+        return Err(unrecognized("no module name where `struct module` has it"));
+    }
+    Ok(Some(NameField {
+        offset: sh.sh_offset as usize + offset,
+        len,
+        name: String::from_utf8_lossy(name).into_owned(),
+    }))
+}
+
+/// Writes a new module name into the `name` field, which must leave room for
+/// its null terminator
+pub fn rename(data: &mut [u8], field: &NameField, name: &str) -> Result<()> {
+    if name.len() >= field.len {
+        return Err(Error::ModuleNameTooLong { name: name.to_string(),
+                                              max: field.len - 1 });
+    }
+    let bytes = &mut data[field.offset .. field.offset + field.len];
+    bytes.fill(0);
+    bytes[.. name.len()].copy_from_slice(name.as_bytes());
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use crate::error::{Error, Result};
+use std::process::Command;
+
+/// Runs an external program with its output captured, failing with its
+/// error output if it does not succeed
+pub fn run(command: &mut Command) -> Result<()> {
+    output(command).map(|_| ())
+}
+
+/// Runs an external program like `run`, returning its standard output
+pub fn output(command: &mut Command) -> Result<Vec<u8>> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command.output().map_err(|e| Error::Command {
+        program: program.clone(),
+        reason: e.to_string(),
+    })?;
+    check(program, output)
+}
+
+fn check(program: String, output: std::process::Output) -> Result<Vec<u8>> {
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(Error::Command {
+            program,
+            reason: format!("{}: {}", output.status, stderr.trim()),
+        });
+    }
+    Ok(output.stdout)
+}
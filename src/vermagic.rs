@@ -0,0 +1,75 @@
+/// Vermagic flags and the kernel configuration options that produce them
+const FLAGS: &[(&str, &str)] = &[
+    ("SMP", "CONFIG_SMP"),
+    ("preempt", "CONFIG_PREEMPT"),
+    ("preempt_rt", "CONFIG_PREEMPT_RT"),
+    ("mod_unload", "CONFIG_MODULE_UNLOAD"),
+    ("modversions", "CONFIG_MODVERSIONS"),
+];
+
+/// Components of a vermagic string, e.g.
+/// `6.1.0-13-amd64 SMP preempt mod_unload modversions`
+pub struct Vermagic<'a> {
+    pub release: &'a str,
+    /// Flags following the release, in order
+    pub flags: Vec<&'a str>,
+}
+
+impl<'a> Vermagic<'a> {
+    pub fn parse(vermagic: &'a str) -> Vermagic<'a> {
+        let mut parts = vermagic.split_whitespace();
+        Vermagic {
+            release: parts.next().unwrap_or(""),
+            flags: parts.collect(),
+        }
+    }
+}
+
+/// Describes a flag for mismatch explanations
+fn describe(flag: &str) -> String {
+    match FLAGS.iter().find(|(f, _)| *f == flag) {
+        Some((_, config)) => format!("{} ({})", flag, config),
+        None => format!("\"{}\"", flag),
+    }
+}
+
+/// Compares vermagic strings the way the kernel does when loading a module.
+/// When the module has symbol versions, the kernel release is not compared as
+/// `module_layout` already covers it
+pub fn same_magic(a: &str, b: &str, has_crcs: bool) -> bool {
+    if has_crcs {
+        let a = a.find(' ').map_or("", |i| &a[i ..]);
+        let b = b.find(' ').map_or("", |i| &b[i ..]);
+        return a == b;
+    }
+    a == b
+}
+
+/// Explains each difference between the vermagic of a module and that of a
+/// kernel that prevents the kernel from loading the module
+pub fn mismatches(module: &str, kernel: &str, has_crcs: bool) -> Vec<String> {
+    let m = Vermagic::parse(module);
+    let k = Vermagic::parse(kernel);
+    let mut reasons = Vec::new();
+
+    if !has_crcs && m.release != k.release {
+        reasons.push(format!("module is for release {} but kernel is {}",
+                             m.release, k.release));
+    }
+    for flag in m.flags.iter().filter(|f| !k.flags.contains(f)) {
+        reasons.push(format!("module has {} but kernel does not",
+                             describe(flag)));
+    }
+    for flag in k.flags.iter().filter(|f| !m.flags.contains(f)) {
+        reasons.push(format!("kernel has {} but module does not",
+                             describe(flag)));
+    }
+
+    // The comparison is textual so the same flags in a different order or
+    // with different spacing still fail
+    if reasons.is_empty() && !same_magic(module, kernel, has_crcs) {
+        reasons.push("flags match but are ordered or spaced differently"
+                        .to_string());
+    }
+    reasons
+}
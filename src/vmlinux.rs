@@ -0,0 +1,95 @@
+use crate::error::Result;
+use goblin::elf::section_header::{SHN_ABS, SHT_NOBITS};
+use std::collections::{HashMap, HashSet};
+
+/// Symbol versioning data of a kernel image
+pub struct Image {
+    pub arch: crate::Arch,
+    pub crcs: HashMap<String, u64>,
+    /// Namespaces of the symbols exported into one
+    pub namespaces: HashMap<String, String>,
+    /// Symbols exported to GPL-compatible modules only
+    pub gpl_only: HashSet<String>,
+}
+
+/// Collects the symbol CRCs, namespaces and GPL-only exports of a kernel
+/// image. Compressed images are unwrapped first. CRCs are read from `__crc_`
+/// symbols if the image has a symbol table, otherwise from its export tables.
+/// Namespaces and GPL-only exports are only found in the export tables
+pub fn read(data: &[u8]) -> Result<Image> {
+    if !data.starts_with(goblin::elf::header::ELFMAG) {
+        return read(&crate::kimage::unwrap(data)?);
+    }
+
+    let elf = crate::parse_elf(data)?;
+    let crcs = match symbol_crcs(&elf, data)? {
+        Some(crcs) if !crcs.is_empty() => crcs,
+        _ => crate::exports::crcs(&elf, data)?,
+    };
+    Ok(Image {
+        arch: crate::Arch::from_elf(&elf),
+        crcs,
+        namespaces: crate::exports::namespaces(&elf, data)?,
+        gpl_only: crate::exports::gpl_only(&elf, data)?,
+    })
+}
+
+/// Collects symbol CRCs from `__crc_` symbols. Older kernels define these as
+/// absolute symbols whose value is the CRC. Kernels generating CRCs in C
+/// instead store each CRC as a 32-bit value at the symbol's address. Returns
+/// `None` if the image has no symbol table
+fn symbol_crcs(elf: &goblin::elf::Elf, data: &[u8])
+    -> Result<Option<HashMap<String, u64>>> {
+
+    let (syms, strtab) = match crate::read_symtab(elf, data)? {
+        Some(symtab) => symtab,
+        None => return Ok(None),
+    };
+
+    let mut crcs = HashMap::new();
+    let mut unreadable = 0;
+    for sym in syms.iter() {
+        let name = match strtab.get_at(sym.st_name)
+                        .and_then(|n| n.strip_prefix("__crc_")) {
+            Some(name) => name,
+            None => continue,
+        };
+
+        if sym.st_shndx == SHN_ABS as usize {
+            crcs.insert(name.to_string(), sym.st_value);
+            continue;
+        }
+        match crc_at(elf, data, sym.st_shndx, sym.st_value) {
+            Some(crc) => {
+                crcs.insert(name.to_string(), crc);
+            },
+            None => unreadable += 1,
+        }
+    }
+    if unreadable > 0 {
+        log::warn!("{} `__crc_` symbols point outside of the image's \
+                    file content and were skipped", unreadable);
+    }
+    Ok(Some(crcs))
+}
+
+/// Reads a 32-bit CRC stored at an address within a section
+fn crc_at(elf: &goblin::elf::Elf, data: &[u8], shndx: usize, addr: u64)
+    -> Option<u64> {
+
+    let sh = elf.section_headers.get(shndx)?;
+    if sh.sh_type == SHT_NOBITS {
+        return None;
+    }
+    let offset = addr.checked_sub(sh.sh_addr)?;
+    if offset.checked_add(4)? > sh.sh_size {
+        return None;
+    }
+    let start = usize::try_from(sh.sh_offset.checked_add(offset)?).ok()?;
+    let bytes: [u8; 4] = data.get(start .. start.checked_add(4)?)?.try_into().ok()?;
+    Some(if elf.little_endian {
+        u32::from_le_bytes(bytes)
+    } else {
+        u32::from_be_bytes(bytes)
+    } as u64)
+}
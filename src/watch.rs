@@ -0,0 +1,164 @@
+use crate::error::{Error, Result};
+use std::collections::{BTreeSet, HashMap};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Size of `struct inotify_event` without its name
+const EVENT_SIZE: usize = 16;
+
+/// Watches a directory tree with inotify for kernel modules being written
+pub struct Watcher {
+    inotify: std::fs::File,
+    /// Watched directories by watch descriptor
+    dirs: HashMap<i32, PathBuf>,
+    /// Modules written since they were last returned
+    pending: BTreeSet<PathBuf>,
+}
+
+fn is_module(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| {
+        let name = name.to_string_lossy();
+        name.ends_with(".ko") && !name.starts_with('.')
+    })
+}
+
+impl Watcher {
+    /// Starts watching a directory and all directories below it
+    pub fn new(root: &Path) -> Result<Watcher> {
+        // SAFETY: `inotify_init1` takes no pointers and returns a new
+        // descriptor, or -1 which is checked below
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(Error::Read { path: root.into(),
+                                     source: std::io::Error::last_os_error() });
+        }
+        let mut watcher = Watcher {
+            // SAFETY: `fd` is a valid descriptor nothing else owns, so the file
+            // takes ownership of it and closes it when dropped
+            inotify: unsafe { std::fs::File::from_raw_fd(fd) },
+            dirs: HashMap::new(),
+            pending: BTreeSet::new(),
+        };
+        watcher.add_tree(root, false)?;
+        Ok(watcher)
+    }
+
+    /// Watches a directory and the directories below it. Modules already in
+    /// directories created after watching started are queued, as they may
+    /// have been written before the directories were watched
+    fn add_tree(&mut self, dir: &Path, queue: bool) -> Result<()> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let read_err = |e| Error::Read { path: dir.into(), source: e };
+        let mut c_path = dir.as_os_str().as_bytes().to_vec();
+        c_path.push(0);
+        let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_CREATE
+                    | libc::IN_ONLYDIR;
+        // SAFETY: The descriptor belongs to `self.inotify`, which outlives the
+        // call. `c_path` is null terminated and, as paths can not contain null
+        // bytes, holds the whole path. The kernel only reads it during the call
+        let wd = unsafe {
+            libc::inotify_add_watch(self.inotify.as_raw_fd(), c_path.as_ptr().cast(),
+                                    mask)
+        };
+        if wd < 0 {
+            return Err(read_err(std::io::Error::last_os_error()));
+        }
+        self.dirs.insert(wd, dir.to_path_buf());
+
+        for entry in std::fs::read_dir(dir).map_err(read_err)? {
+            let entry = entry.map_err(read_err)?;
+            let path = entry.path();
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                self.add_tree(&path, queue)?;
+            } else if queue && is_module(&path) {
+                self.pending.insert(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits until the inotify descriptor is readable or the timeout passes,
+    /// returning whether it is readable
+    fn poll(&self, timeout: Option<Duration>) -> std::io::Result<bool> {
+        let mut pollfd = libc::pollfd {
+            fd: self.inotify.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.map_or(-1, |t| {
+            t.as_millis().min(i32::MAX as u128) as i32
+        });
+        loop {
+            // SAFETY: `pollfd` is a single valid entry, matching the count of
+            // 1, and its descriptor belongs to `self.inotify`, which outlives
+            // the call
+            match unsafe { libc::poll(&mut pollfd, 1, timeout) } {
+                -1 => {
+                    let e = std::io::Error::last_os_error();
+                    if e.kind() != std::io::ErrorKind::Interrupted {
+                        return Err(e);
+                    }
+                },
+                ready => return Ok(ready > 0),
+            }
+        }
+    }
+
+    /// Reads pending events, queueing written modules and watching created
+    /// directories
+    fn read_events(&mut self) -> Result<()> {
+        use std::io::Read;
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut buffer = vec![0u8; 64 * 1024];
+        let len = self.inotify.read(&mut buffer)
+                    .map_err(|e| Error::Read { path: "inotify".into(), source: e })?;
+        let mut offset = 0;
+        while offset + EVENT_SIZE <= len {
+            let field = |at: usize| {
+                u32::from_ne_bytes(buffer[offset + at .. offset + at + 4]
+                                    .try_into().unwrap())
+            };
+            let wd = field(0) as i32;
+            let mask = field(4);
+            let name_len = field(12) as usize;
+            let name = &buffer[offset + EVENT_SIZE .. offset + EVENT_SIZE + name_len];
+            let name = name.split(|&b| b == 0).next().unwrap_or_default();
+            offset += EVENT_SIZE + name_len;
+
+            let dir = match self.dirs.get(&wd) {
+                Some(dir) => dir,
+                None => continue,
+            };
+            let path = dir.join(std::ffi::OsStr::from_bytes(name));
+            if mask & libc::IN_ISDIR != 0 {
+                if mask & libc::IN_CREATE != 0 {
+                    self.add_tree(&path, true)?;
+                }
+            } else if mask & (libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO) != 0
+                && is_module(&path) {
+                self.pending.insert(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits for modules to be written, returning them once no module was
+    /// written for `settle`. Build steps such as BTF generation rewrite
+    /// modules after linking them, so a module is returned once complete
+    pub fn wait(&mut self, settle: Duration) -> Result<Vec<PathBuf>> {
+        let poll_err = |e| Error::Read { path: "inotify".into(), source: e };
+        while self.pending.is_empty() {
+            self.poll(None).map_err(poll_err)?;
+            self.read_events()?;
+        }
+        while self.poll(Some(settle)).map_err(poll_err)? {
+            self.read_events()?;
+        }
+        // Modules removed again, such as by `make clean`, are dropped
+        Ok(std::mem::take(&mut self.pending).into_iter()
+            .filter(|path| path.is_file()).collect())
+    }
+}
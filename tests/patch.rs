@@ -0,0 +1,353 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// `testgen` options for a module of each ELF class and byte order
+const LAYOUTS: &[&[&str]] = &[
+    &["--machine", "x86-64"],
+    &["--machine", "i386", "--elf32"],
+    &["--machine", "ppc64", "--big-endian"],
+    &["--machine", "mips", "--elf32", "--big-endian"],
+];
+
+/// Exit codes for a missing section and for nothing to do
+const MISSING_SECTION: i32 = 4;
+const NOTHING_TO_DO: i32 = 7;
+
+fn candycorn(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_candycorn"))
+        .env("CANDYCORN_NO_CONFIG", "1")
+        .arg("--porcelain")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Runs candycorn, failing the test if it fails
+fn run(args: &[&str]) -> Output {
+    let output = candycorn(args);
+    assert!(output.status.success(), "candycorn failed: {}",
+            String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+/// Empty directory for a test's files
+fn work_dir(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Path of a file in a test's directory
+fn file(dir: &Path, name: &str) -> String {
+    dir.join(name).to_str().unwrap().to_string()
+}
+
+/// Generates a module with the provided symbol versions, as SYMBOL=CRC
+fn testgen(path: &str, layout: &[&str], symbols: &[&str], extra: &[&str]) {
+    let mut args = vec!["testgen"];
+    args.extend(layout.iter().chain(extra));
+    for symbol in symbols {
+        args.extend(["--symbol", symbol]);
+    }
+    args.push(path);
+    run(&args);
+}
+
+/// Symbol versions of a module as `list` prints them, `0x40: "name", 0x1`
+fn list(path: &str) -> Vec<(String, u64)> {
+    let output = run(&["list", path]);
+    String::from_utf8(output.stdout).unwrap().lines()
+        .map(|line| {
+            let (_, entry) = line.split_once(": \"").unwrap();
+            let (name, crc) = entry.rsplit_once("\", 0x").unwrap();
+            (name.to_string(), u64::from_str_radix(crc, 16).unwrap())
+        })
+        .collect()
+}
+
+fn versions(entries: &[(&str, u64)]) -> Vec<(String, u64)> {
+    entries.iter().map(|&(name, crc)| (name.to_string(), crc)).collect()
+}
+
+#[test]
+fn patch_from_source_module() {
+    let dir = work_dir("patch_from_source_module");
+    for (idx, layout) in LAYOUTS.iter().enumerate() {
+        let target = file(&dir, &format!("target{}.ko", idx));
+        let source = file(&dir, &format!("source{}.ko", idx));
+        let patched = file(&dir, &format!("patched{}.ko", idx));
+        testgen(&target, layout, &["printk=0x1", "kmalloc=0x2"], &[]);
+        testgen(&source, layout, &["printk=0xaa", "kmalloc=0xbb", "kfree=0xcc"], &[]);
+
+        run(&["patch", "-s", &source, "-o", &patched, &target]);
+        assert_eq!(list(&patched), versions(&[("printk", 0xaa), ("kmalloc", 0xbb)]));
+        assert_eq!(list(&target), versions(&[("printk", 0x1), ("kmalloc", 0x2)]));
+    }
+}
+
+#[test]
+fn patch_extended_from_symvers() {
+    let dir = work_dir("patch_extended_from_symvers");
+    let symvers = file(&dir, "Module.symvers");
+    std::fs::write(&symvers, "0x000000aa\tprintk\tvmlinux\tEXPORT_SYMBOL\t\n").unwrap();
+    for (idx, layout) in LAYOUTS.iter().enumerate() {
+        let target = file(&dir, &format!("target{}.ko", idx));
+        testgen(&target, layout, &["printk=0x1", "kmalloc=0x2"], &["--extended"]);
+
+        run(&["patch", "--symvers", &symvers, &target]);
+        assert_eq!(list(&target), versions(&[("printk", 0xaa), ("kmalloc", 0x2)]));
+    }
+}
+
+#[test]
+fn patch_with_overrides() {
+    let dir = work_dir("patch_with_overrides");
+    let overrides = file(&dir, "overrides.toml");
+    std::fs::write(&overrides, "printk = 0x11\nkfree = 0x33\n").unwrap();
+    for (idx, layout) in LAYOUTS.iter().enumerate() {
+        let target = file(&dir, &format!("target{}.ko", idx));
+        testgen(&target, layout, &["printk=0x1", "kmalloc=0x2"], &[]);
+
+        run(&["patch", "--overrides", &overrides, "--set", "kmalloc=0x22", &target]);
+        assert_eq!(list(&target), versions(&[("printk", 0x11), ("kmalloc", 0x22)]));
+    }
+}
+
+#[test]
+fn matching_versions_are_kept() {
+    let dir = work_dir("matching_versions_are_kept");
+    for (idx, layout) in LAYOUTS.iter().enumerate() {
+        let target = file(&dir, &format!("target{}.ko", idx));
+        let source = file(&dir, &format!("source{}.ko", idx));
+        let patched = file(&dir, &format!("patched{}.ko", idx));
+        testgen(&target, layout, &["printk=0x1"], &[]);
+        testgen(&source, layout, &["printk=0x1"], &[]);
+
+        run(&["patch", "-s", &source, "-o", &patched, &target]);
+        assert_eq!(std::fs::read(&patched).unwrap(), std::fs::read(&target).unwrap());
+    }
+}
+
+#[test]
+fn revert_restores_versions() {
+    let dir = work_dir("revert_restores_versions");
+    for (idx, layout) in LAYOUTS.iter().enumerate() {
+        let target = file(&dir, &format!("target{}.ko", idx));
+        let reverted = file(&dir, &format!("reverted{}.ko", idx));
+        testgen(&target, layout, &["printk=0x1", "kmalloc=0x2"], &[]);
+
+        run(&["patch", "--note", "--set", "printk=0xaa", &target]);
+        assert_eq!(list(&target), versions(&[("printk", 0xaa), ("kmalloc", 0x2)]));
+        run(&["revert", "-o", &reverted, &target]);
+        assert_eq!(list(&reverted), versions(&[("printk", 0x1), ("kmalloc", 0x2)]));
+    }
+}
+
+#[test]
+fn stripped_module_is_nothing_to_do() {
+    let dir = work_dir("stripped_module_is_nothing_to_do");
+    for (idx, layout) in LAYOUTS.iter().enumerate() {
+        for extra in [&[][..], &["--extended"]] {
+            let target = file(&dir, &format!("target{}.ko", idx));
+            testgen(&target, layout, &["printk=0x1"], extra);
+
+            run(&["strip-versions", &target]);
+            let output = candycorn(&["list", &target]);
+            assert_eq!(output.status.code(), Some(MISSING_SECTION));
+            let output = candycorn(&["patch", "--set", "printk=0xaa", &target]);
+            assert_eq!(output.status.code(), Some(NOTHING_TO_DO));
+        }
+    }
+}
+
+#[test]
+fn backups_rotate_behind_the_original() {
+    let dir = work_dir("backups_rotate_behind_the_original");
+    let target = file(&dir, "target.ko");
+    let read = |suffix: &str| std::fs::read(format!("{}{}", target, suffix)).ok();
+    testgen(&target, LAYOUTS[0], &["printk=0x1"], &[]);
+
+    let mut generations = Vec::new();
+    for crc in ["0x2", "0x3", "0x4"] {
+        generations.push(read("").unwrap());
+        run(&["patch", "--backup=2", "--set", &format!("printk={}", crc), &target]);
+    }
+    assert_eq!(read(".orig"), Some(generations[0].clone()));
+    assert_eq!(read(".orig.1"), Some(generations[2].clone()));
+    assert_eq!(read(".orig.2"), Some(generations[1].clone()));
+    assert_eq!(read(".orig.3"), None);
+    assert_eq!(list(&target), versions(&[("printk", 0x4)]));
+}
+
+#[test]
+fn backups_without_limit_keep_only_the_original() {
+    let dir = work_dir("backups_without_limit_keep_only_the_original");
+    let target = file(&dir, "target.ko");
+    testgen(&target, LAYOUTS[0], &["printk=0x1"], &[]);
+    let pristine = std::fs::read(&target).unwrap();
+
+    for crc in ["0x2", "0x3"] {
+        run(&["patch", "--backup=0", "--set", &format!("printk={}", crc), &target]);
+    }
+    assert_eq!(std::fs::read(format!("{}.orig", target)).unwrap(), pristine);
+    assert!(!Path::new(&format!("{}.orig.1", target)).exists());
+}
+
+/// Writes a "newc" cpio archive of regular files
+fn cpio(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let trailer = ("TRAILER!!!", &b""[..]);
+    for (idx, &(name, content)) in files.iter().chain([&trailer]).enumerate() {
+        out.extend_from_slice(b"070701");
+        let (size, name_len) = (content.len(), name.len() + 1);
+        for field in [idx + 1, 0o100644, 0, 0, 1, 0, size, 0, 0, 0, 0, name_len, 0] {
+            out.extend_from_slice(format!("{:08X}", field).as_bytes());
+        }
+        out.extend_from_slice(name.as_bytes());
+        out.push(0);
+        out.resize(out.len().next_multiple_of(4), 0);
+        out.extend_from_slice(content);
+        out.resize(out.len().next_multiple_of(4), 0);
+    }
+    out
+}
+
+/// Reads the files of a "newc" cpio archive written by `cpio`
+fn cpio_files(data: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let mut files = Vec::new();
+    let mut idx = 0;
+    loop {
+        let field = |n: usize| {
+            let digits = std::str::from_utf8(&data[idx + 6 + n * 8 .. idx + 14 + n * 8]);
+            usize::from_str_radix(digits.unwrap(), 16).unwrap()
+        };
+        let (size, name_len) = (field(6), field(11));
+        let name = &data[idx + 110 .. idx + 110 + name_len - 1];
+        let start = (idx + 110 + name_len).next_multiple_of(4);
+        if name == b"TRAILER!!!" {
+            return files;
+        }
+        files.push((String::from_utf8(name.to_vec()).unwrap(),
+                    data[start .. start + size].to_vec()));
+        idx = (start + size).next_multiple_of(4);
+    }
+}
+
+#[test]
+fn patch_modules_in_initramfs() {
+    let dir = work_dir("patch_modules_in_initramfs");
+    let module = file(&dir, "module.ko");
+    testgen(&module, LAYOUTS[0], &["printk=0x1", "kmalloc=0x2"], &[]);
+    let initramfs = file(&dir, "initramfs.cpio");
+    std::fs::write(&initramfs, cpio(&[
+        ("init", b"#!/bin/sh\n"),
+        ("lib/modules/6.1.0/kernel/module.ko", &std::fs::read(&module).unwrap()),
+    ])).unwrap();
+
+    run(&["patch", "--set", "printk=0xaa", &initramfs]);
+    let files = cpio_files(&std::fs::read(&initramfs).unwrap());
+    assert_eq!(files.len(), 2);
+    assert_eq!(files[0], ("init".to_string(), b"#!/bin/sh\n".to_vec()));
+    assert_eq!(files[1].0, "lib/modules/6.1.0/kernel/module.ko");
+    let patched = file(&dir, "patched.ko");
+    std::fs::write(&patched, &files[1].1).unwrap();
+    assert_eq!(list(&patched), versions(&[("printk", 0xaa), ("kmalloc", 0x2)]));
+}
+
+#[test]
+fn patch_from_rpm_package() {
+    let dir = work_dir("patch_from_rpm_package");
+    let target = file(&dir, "target.ko");
+    let donor = file(&dir, "donor.ko");
+    testgen(&target, LAYOUTS[0], &["printk=0x1", "kmalloc=0x2", "kfree=0x3"], &[]);
+    testgen(&donor, LAYOUTS[0], &["printk=0xaa"], &[]);
+
+    // Lead, empty signature header and header, then an uncompressed payload
+    let mut rpm = vec![0xed, 0xab, 0xee, 0xdb];
+    rpm.resize(96, 0);
+    for _ in 0 .. 2 {
+        rpm.extend_from_slice(&[0x8e, 0xad, 0xe8, 0x01]);
+        rpm.resize(rpm.len() + 12, 0);
+    }
+    rpm.extend_from_slice(&cpio(&[
+        ("./lib/modules/6.1.0/kernel/donor.ko", &std::fs::read(&donor).unwrap()),
+        ("./boot/symvers-6.1.0", b"0x000000bb\tkmalloc\tvmlinux\tEXPORT_SYMBOL\t\n"),
+    ]));
+    let package = file(&dir, "kernel-6.1.0.rpm");
+    std::fs::write(&package, rpm).unwrap();
+
+    run(&["patch", "-s", &package, &target]);
+    assert_eq!(list(&target), versions(&[("printk", 0xaa), ("kmalloc", 0xbb),
+                                         ("kfree", 0x3)]));
+}
+
+#[test]
+fn patch_symbols_from_dmesg() {
+    let dir = work_dir("patch_symbols_from_dmesg");
+    let target = file(&dir, "target.ko");
+    let source = file(&dir, "source.ko");
+    testgen(&target, LAYOUTS[0], &["printk=0x1", "kmalloc=0x2"], &[]);
+    testgen(&source, LAYOUTS[0], &["printk=0xaa", "kmalloc=0xbb", "kfree=0xcc"], &[]);
+    let log = file(&dir, "dmesg.log");
+    std::fs::write(&log, "[ 1.0] target: disagrees about version of symbol printk\n\
+                          [ 1.0] target: no symbol version for kfree\n").unwrap();
+
+    run(&["patch", "-s", &source, &format!("--from-dmesg={}", log), &target]);
+    assert_eq!(list(&target), versions(&[("printk", 0xaa), ("kmalloc", 0x2),
+                                         ("kfree", 0xcc)]));
+
+    std::fs::write(&log, "usb 1-1: new high-speed USB device number 2\n").unwrap();
+    let output = candycorn(&["patch", "-s", &source, &format!("--from-dmesg={}", log),
+                             &target]);
+    assert_eq!(output.status.code(), Some(NOTHING_TO_DO));
+}
+
+#[test]
+fn emitted_patch_applies_to_the_target() {
+    let dir = work_dir("emitted_patch_applies_to_the_target");
+    for (idx, layout) in LAYOUTS.iter().enumerate() {
+        let target = file(&dir, &format!("target{}.ko", idx));
+        let patch = file(&dir, &format!("target{}.json", idx));
+        let patched = file(&dir, &format!("patched{}.ko", idx));
+        testgen(&target, layout, &["printk=0x1", "kmalloc=0x2"], &[]);
+        let original = std::fs::read(&target).unwrap();
+
+        run(&["patch", "--emit-patch", &patch, "--set", "printk=0xaa", &target]);
+        assert_eq!(std::fs::read(&target).unwrap(), original);
+        run(&["apply", "-o", &patched, &patch, &target]);
+        assert_eq!(list(&patched), versions(&[("printk", 0xaa), ("kmalloc", 0x2)]));
+
+        // The patched module no longer has the bytes the patch replaces
+        let output = candycorn(&["apply", "--ignore-hash", &patch, &patched]);
+        assert!(!output.status.success());
+        assert_eq!(list(&patched), versions(&[("printk", 0xaa), ("kmalloc", 0x2)]));
+    }
+}
+
+#[test]
+fn symvers_diff_reports_changed_exports() {
+    let dir = work_dir("symvers_diff_reports_changed_exports");
+    let old = file(&dir, "old.symvers");
+    let new = file(&dir, "new.symvers");
+    std::fs::write(&old, "0x1\tprintk\tvmlinux\tEXPORT_SYMBOL\t\n\
+                          0x2\tkfree\tvmlinux\tEXPORT_SYMBOL\t\n\
+                          0x3\tgone\tvmlinux\tEXPORT_SYMBOL\t\n").unwrap();
+    std::fs::write(&new, "0x1\tprintk\tvmlinux\tEXPORT_SYMBOL\t\n\
+                          0x9\tkfree\tvmlinux\tEXPORT_SYMBOL\t\n\
+                          0x4\tnew\tvmlinux\tEXPORT_SYMBOL\t\n").unwrap();
+
+    let output = run(&["symvers-diff", "--format", "csv", &old, &new]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(),
+               "symbol,crc_old,crc_new\n\
+                gone,0x00000003,\n\
+                kfree,0x00000002,0x00000009\n\
+                new,,0x00000004\n");
+
+    let module = file(&dir, "module.ko");
+    testgen(&module, LAYOUTS[0], &["printk=0x1"], &[]);
+    run(&["symvers-diff", "--module", &module, &old, &new]);
+    testgen(&module, LAYOUTS[0], &["printk=0x1", "gone=0x3"], &[]);
+    let output = candycorn(&["symvers-diff", "--module", &module, &old, &new]);
+    assert!(!output.status.success());
+}